@@ -0,0 +1,194 @@
+//! Packs the bundled m5x7 fonts and every png of a directory into an atlas
+//! with the library API, then renders a small scene using nothing but the
+//! exported metadata and atlas texture, like a game would.
+//!
+//! ```sh
+//! cargo run --example demo_scene -- <sprites dir> [<output dir>]
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use image::{imageops, RgbaImage};
+use texture_packer::{
+    cancellation::CancellationToken, math::IMargins, AtlasMeta, FontIntermediate, Sources,
+    SpriteIntermediate, TextureAtlas,
+};
+
+const FONT_FILES: [&str; 2] = ["assets/m5x7.fnt", "assets/m5x7-color.fnt"];
+const SCENE_TEXT: &str = "Hewwo uwq, gg";
+/// Pixels between the sprites of the scene and above the text.
+const GAP: u32 = 4;
+
+pub struct Demo {
+    pub meta: AtlasMeta,
+    pub texture: RgbaImage,
+    pub scene: RgbaImage,
+}
+
+/// Packs the fonts and the pngs of `sprites_dir` (sorted by file name, named
+/// after their file stem).
+pub fn pack(sprites_dir: &Path) -> anyhow::Result<(AtlasMeta, RgbaImage)> {
+    let mut sources = Sources::new();
+    let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+
+    for font_file in FONT_FILES {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(font_file);
+        let id = sources
+            .try_load_source(&path)
+            .with_context(|| format!("Failed to load font '{}'", path.display()))?;
+
+        atlas.with_font(FontIntermediate::from_fnt(id, &sources)?);
+    }
+
+    let mut sprite_files: Vec<PathBuf> = std::fs::read_dir(sprites_dir)
+        .with_context(|| format!("Failed to read '{}'", sprites_dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    sprite_files.retain(|path| path.extension().is_some_and(|ext| ext == "png"));
+    sprite_files.sort();
+
+    for path in sprite_files.iter() {
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .with_context(|| format!("Invalid sprite file name '{}'", path.display()))?;
+        let id = sources.try_load_source(path)?;
+
+        atlas.with_sprite(SpriteIntermediate::from_image(
+            name.to_string(),
+            id,
+            &sources,
+        )?);
+    }
+
+    let cancel = CancellationToken::new();
+    atlas.load_sizes()?;
+    atlas.pack(&cancel)?;
+
+    let texture = atlas.build_image(&atlas.layout, &sources, &cancel)?;
+    let meta = AtlasMeta::from_texture_atlas(
+        "demo".to_string(),
+        "atlas.png".to_string(),
+        &atlas,
+        &atlas.layout,
+    )?;
+
+    Ok((meta, texture))
+}
+
+/// Draws every named sprite in a row and `text` in the first font below
+/// them.
+pub fn render_scene(
+    meta: &AtlasMeta,
+    texture: &RgbaImage,
+    text: &str,
+) -> anyhow::Result<RgbaImage> {
+    let font = meta.fonts.first().context("The atlas has no font")?;
+    let frame = |index: u32| {
+        let rect = meta.sprites[index as usize];
+        imageops::crop_imm(
+            texture,
+            rect.min.x as u32,
+            rect.min.y as u32,
+            rect.uwidth(),
+            rect.uheight(),
+        )
+        .to_image()
+    };
+
+    let sprites: Vec<RgbaImage> = meta
+        .named_sprites
+        .iter()
+        .map(|sprite| frame(sprite.first_sprite_index))
+        .collect();
+
+    let row_height = sprites
+        .iter()
+        .map(|sprite| sprite.height())
+        .max()
+        .unwrap_or(0);
+    let row_width: u32 = sprites.iter().map(|sprite| sprite.width() + GAP).sum();
+
+    let chars: Vec<_> = text
+        .chars()
+        .filter_map(|c| font.chars.iter().find(|ch| ch.char_code == c as u32))
+        .collect();
+    let text_width: i32 = chars.iter().map(|ch| ch.x_advance).sum();
+
+    let mut scene = RgbaImage::new(
+        row_width.max(text_width.max(0) as u32),
+        row_height + GAP + font.line_height,
+    );
+
+    let mut x = 0;
+    for sprite in sprites.iter() {
+        imageops::overlay(&mut scene, sprite, x, 0);
+        x += (sprite.width() + GAP) as i64;
+    }
+
+    let (mut pen_x, pen_y) = (0, (row_height + GAP) as i32);
+    for ch in chars {
+        if !meta.sprites[ch.first_sprite_index as usize].is_empty() {
+            let glyph = frame(ch.first_sprite_index);
+            imageops::overlay(
+                &mut scene,
+                &glyph,
+                (pen_x + ch.x_offset) as i64,
+                (pen_y + ch.y_offset) as i64,
+            );
+        }
+
+        pen_x += ch.x_advance;
+    }
+
+    Ok(scene)
+}
+
+/// Packs, writes `atlas.png` and `atlas.json` to `out_dir`, reads the
+/// metadata back and renders `scene.png` from it.
+pub fn run(sprites_dir: &Path, out_dir: &Path) -> anyhow::Result<Demo> {
+    let (meta, texture) = pack(sprites_dir)?;
+
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create '{}'", out_dir.display()))?;
+    texture
+        .save(out_dir.join("atlas.png"))
+        .context("Failed to save the atlas texture")?;
+    meta.save(out_dir.join("atlas.json"))?;
+
+    // Only use what a consumer of the files would have
+    let meta = AtlasMeta::load(out_dir.join("atlas.json"))?;
+    let texture = image::open(out_dir.join(&meta.texture_file))
+        .context("Failed to load the atlas texture")?
+        .into_rgba8();
+
+    let scene = render_scene(&meta, &texture, SCENE_TEXT)?;
+    scene
+        .save(out_dir.join("scene.png"))
+        .context("Failed to save the scene")?;
+
+    Ok(Demo {
+        meta,
+        texture,
+        scene,
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let sprites_dir = args
+        .first()
+        .context("Usage: demo_scene <sprites dir> [<output dir>]")?;
+    let out_dir = args.get(1).map_or("demo", |dir| dir);
+
+    let demo = run(Path::new(sprites_dir), Path::new(out_dir))?;
+    println!(
+        "INFO: Rendered a {}x{} scene with {} sprite(s) into '{out_dir}'",
+        demo.scene.width(),
+        demo.scene.height(),
+        demo.meta.named_sprites.len()
+    );
+
+    Ok(())
+}
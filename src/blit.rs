@@ -0,0 +1,233 @@
+use anyhow::Context;
+use image::{GenericImageView, Rgba, RgbaImage, SubImage};
+
+use crate::{
+    error::Ewwow,
+    math::{IMargins, IRect},
+};
+
+/// Copies the pixels of `view` into `output` with its top left corner at
+/// `(x, y)`, without copying the view into its own buffer first.
+///
+/// Whole rows are copied at once, which is a lot faster than
+/// [`image::GenericImage::copy_from`] going pixel by pixel.
+pub fn blit(
+    output: &mut RgbaImage,
+    view: &SubImage<&RgbaImage>,
+    x: u32,
+    y: u32,
+) -> anyhow::Result<()> {
+    let (width, height) = view.dimensions();
+
+    let fits = x
+        .checked_add(width)
+        .zip(y.checked_add(height))
+        .is_some_and(|(max_x, max_y)| max_x <= output.width() && max_y <= output.height());
+
+    if !fits {
+        Ewwow.raise().with_context(|| {
+            format!(
+                "Cannot blit a {width}x{height} image at {x},{y} into a {}x{} image",
+                output.width(),
+                output.height(),
+            )
+        })?;
+    }
+
+    let (src_x, src_y, _, _) = view.bounds();
+    let source = view.inner().as_raw();
+
+    let row_len = width as usize * 4;
+    let src_stride = view.inner().width() as usize * 4;
+    let dst_stride = output.width() as usize * 4;
+
+    let src_start = src_y as usize * src_stride + src_x as usize * 4;
+    let dst_start = y as usize * dst_stride + x as usize * 4;
+
+    let output: &mut [u8] = output;
+
+    for row in 0..height as usize {
+        let src = src_start + row * src_stride;
+        let dst = dst_start + row * dst_stride;
+
+        output[dst..dst + row_len].copy_from_slice(&source[src..src + row_len]);
+    }
+
+    Ok(())
+}
+
+/// Repeats the outermost pixels of `rect` outward by `margins`, clamped to
+/// the image, so filtering past the edge of a sprite reads its own border
+/// instead of its neighbour. The corners repeat the corner pixels.
+pub fn extrude(output: &mut RgbaImage, rect: IRect, margins: IMargins) {
+    if rect.is_empty() {
+        return;
+    }
+
+    let (width, height) = (output.width() as i32, output.height() as i32);
+    let left = margins.left.min(rect.min.x).max(0);
+    let right = margins.right.min(width - rect.max.x).max(0);
+    let top = margins.top.min(rect.min.y).max(0);
+    let bottom = margins.bottom.min(height - rect.max.y).max(0);
+
+    let stride = width as usize * 4;
+    let index = |x: i32, y: i32| y as usize * stride + x as usize * 4;
+    let output: &mut [u8] = output;
+
+    for y in rect.min.y..rect.max.y {
+        let first = index(rect.min.x, y);
+        let last = index(rect.max.x - 1, y);
+
+        for x in rect.min.x - left..rect.min.x {
+            output.copy_within(first..first + 4, index(x, y));
+        }
+        for x in rect.max.x..rect.max.x + right {
+            output.copy_within(last..last + 4, index(x, y));
+        }
+    }
+
+    // Whole rows, including the pixels extruded to the sides
+    let (min_x, max_x) = (rect.min.x - left, rect.max.x + right);
+    let row = |y: i32| index(min_x, y)..index(max_x, y);
+
+    for y in rect.min.y - top..rect.min.y {
+        output.copy_within(row(rect.min.y), index(min_x, y));
+    }
+    for y in rect.max.y..rect.max.y + bottom {
+        output.copy_within(row(rect.max.y - 1), index(min_x, y));
+    }
+}
+
+/// Turns the pixels of `rect` white with their coverage (their luminance
+/// times their alpha) as alpha, so they can be sampled from the alpha
+/// channel alone.
+pub fn coverage_to_alpha(output: &mut RgbaImage, rect: IRect) {
+    let Some((x, y)) = rect.checked_umin() else {
+        return;
+    };
+
+    for y in y..y + rect.uheight() {
+        for x in x..x + rect.uwidth() {
+            let Some(pixel) = output.get_pixel_mut_checked(x, y) else {
+                continue;
+            };
+
+            let [r, g, b, a] = pixel.0.map(|c| c as u32);
+            let luminance = (r * 299 + g * 587 + b * 114) / 1000;
+            *pixel = Rgba([255, 255, 255, (luminance * a / 255) as u8]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImage, GenericImageView, Rgba, RgbaImage};
+
+    use super::{blit, coverage_to_alpha, extrude};
+    use crate::math::{IMargins, IRect};
+
+    #[test]
+    fn test_blit() -> anyhow::Result<()> {
+        let source = RgbaImage::from_fn(8, 8, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let view = source.view(2, 3, 4, 2);
+
+        let mut output = RgbaImage::new(6, 6);
+        blit(&mut output, &view, 1, 4)?;
+
+        let mut expected = RgbaImage::new(6, 6);
+        expected.copy_from(&view.to_image(), 1, 4)?;
+        assert_eq!(output, expected);
+
+        // Empty views are fine anywhere inside the image
+        blit(&mut output, &source.view(8, 8, 0, 0), 6, 6)?;
+        assert_eq!(output, expected);
+
+        assert!(blit(&mut output, &view, 3, 4).is_err());
+        assert!(blit(&mut output, &view, u32::MAX, 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coverage_to_alpha() {
+        let mut output = RgbaImage::from_pixel(3, 1, Rgba([255, 255, 255, 128]));
+        output.put_pixel(1, 0, Rgba([0, 0, 0, 255]));
+        output.put_pixel(2, 0, Rgba([128, 128, 128, 255]));
+
+        coverage_to_alpha(&mut output, IRect::new(0, 0, 2, 1));
+
+        assert_eq!(*output.get_pixel(0, 0), Rgba([255, 255, 255, 128]));
+        assert_eq!(*output.get_pixel(1, 0), Rgba([255, 255, 255, 0]));
+        // Outside of the rect
+        assert_eq!(*output.get_pixel(2, 0), Rgba([128, 128, 128, 255]));
+    }
+
+    #[test]
+    fn test_extrude() {
+        let mut output = RgbaImage::new(5, 4);
+        output.put_pixel(1, 1, Rgba([1, 0, 0, 255]));
+        output.put_pixel(2, 1, Rgba([2, 0, 0, 255]));
+        output.put_pixel(1, 2, Rgba([3, 0, 0, 255]));
+        output.put_pixel(2, 2, Rgba([4, 0, 0, 255]));
+
+        // Clamped to the image on the left and bottom, nothing to the right
+        extrude(
+            &mut output,
+            IRect::new(1, 1, 2, 2),
+            IMargins::new(1, 3, 2, 0),
+        );
+
+        let red = |x, y| output.get_pixel(x, y).0[0];
+        let rows: Vec<Vec<u8>> = (0..4)
+            .map(|y| (0..5).map(|x| red(x, y)).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                [1, 1, 2, 0, 0],
+                [1, 1, 2, 0, 0],
+                [3, 3, 4, 0, 0],
+                [3, 3, 4, 0, 0],
+            ]
+        );
+
+        // Empty rects have no border
+        let before = output.clone();
+        extrude(&mut output, IRect::new(4, 0, 0, 4), IMargins::uniform(1));
+        assert_eq!(output, before);
+    }
+}
+
+#[cfg(test)]
+mod benches {
+    extern crate test;
+
+    use image::{GenericImage, GenericImageView, Rgba, RgbaImage};
+    use test::Bencher;
+
+    use super::blit;
+
+    fn source() -> RgbaImage {
+        RgbaImage::from_fn(1024, 1024, |x, y| Rgba([x as u8, y as u8, 0, 255]))
+    }
+
+    #[bench]
+    fn bench_blit_512(b: &mut Bencher) {
+        let source = source();
+        let mut output = RgbaImage::new(1024, 1024);
+
+        b.iter(|| blit(&mut output, &source.view(100, 200, 512, 512), 300, 400).unwrap());
+    }
+
+    #[bench]
+    fn bench_copy_from_512(b: &mut Bencher) {
+        let source = source();
+        let mut output = RgbaImage::new(1024, 1024);
+
+        b.iter(|| {
+            output
+                .copy_from(&*source.view(100, 200, 512, 512), 300, 400)
+                .unwrap()
+        });
+    }
+}
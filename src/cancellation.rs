@@ -0,0 +1,38 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::error::Cancelled;
+
+/// Lets an embedder abort a running build from another thread, e.g. when the
+/// user changes the settings mid-build. Clones share the same state.
+///
+/// Cancelled operations fail with [`Cancelled`], which can be told apart from
+/// real errors with `err.is::<Cancelled>()`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails with [`Cancelled`] once [`Self::cancel`] has been called.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.is_cancelled() {
+            true => Err(Cancelled),
+            false => Ok(()),
+        }
+    }
+}
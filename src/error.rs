@@ -1,3 +1,50 @@
+use crate::sources::SourceId;
+
+/// Failures library users may want to handle differently, e.g. a missing
+/// source file vs. a malformed one. Functions still return
+/// [`anyhow::Result`], with the error wrapped in context describing what was
+/// being done, so look it up with [`Error::find`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A source file is malformed.
+    #[error("{0}")]
+    ParseError(String),
+    /// A file or alias that isn't loaded into [`crate::sources::Sources`].
+    #[error("Source file '{0}' not found")]
+    SourceNotFound(String),
+    /// A [`SourceId`] of the wrong kind, or one that doesn't exist.
+    #[error("Invalid source id {0:?}")]
+    InvalidSourceId(SourceId),
+    /// Sprites that can't be placed, no matter how large the atlas gets.
+    #[error("Doesn't fit into {limit}: {}", sprites.join(", "))]
+    PackOverflow { sprites: Vec<String>, limit: String },
+    /// Sprites with pixels the declared pixel format can't store, e.g. a
+    /// colored font in an A8 atlas.
+    #[error("Can't be stored as {format}: {}", sprites.join(", "))]
+    IncompatiblePixelFormat {
+        sprites: Vec<String>,
+        format: String,
+    },
+    /// Settings that contradict each other or are out of range.
+    #[error("{0}")]
+    InvalidOption(String),
+    #[error(transparent)]
+    ImageError(#[from] image::ImageError),
+}
+
+impl Error {
+    pub fn raise(self) -> Result<(), Self> {
+        Err(self)
+    }
+
+    /// The first [`Error`] in the chain of `error`, if any.
+    pub fn find(error: &anyhow::Error) -> Option<&Error> {
+        error.chain().find_map(|cause| cause.downcast_ref())
+    }
+}
+
+/// The catch-all for broken invariants, i.e. bugs of the texture packer
+/// itself. Failures library users can handle are [`Error`]s.
 #[derive(Debug, Clone, Copy)]
 pub struct Ewwow;
 
@@ -31,3 +78,16 @@ impl std::fmt::Display for Ewwow {
         write!(f, "OOPSIE WOOPSIE!! uwu We made a fucky wucky!!")
     }
 }
+
+/// The error of operations that were aborted through a
+/// [`crate::cancellation::CancellationToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::error::Error for Cancelled {}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The build was cancelled")
+    }
+}
@@ -1,4 +1,154 @@
+use anyhow::Context;
+
+use crate::error::Ewwow;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum TextCharacterAnimation {
     NoAnimation,
 }
+
+/// The distance field a font's glyphs were rendered as, e.g. by
+/// msdf-bmfont, instead of plain coverage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DistanceField {
+    pub field_type: DistanceFieldType,
+    /// The distance in pixels the field spans, which the shader needs to
+    /// scale it.
+    pub distance_range: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceFieldType {
+    Sdf,
+    Psdf,
+    Msdf,
+    Mtsdf,
+}
+
+impl std::str::FromStr for DistanceFieldType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sdf" => Ok(Self::Sdf),
+            "psdf" => Ok(Self::Psdf),
+            "msdf" => Ok(Self::Msdf),
+            "mtsdf" => Ok(Self::Mtsdf),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Unknown distance field type '{s}', expected sdf, psdf, msdf or mtsdf")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// Vertical metrics measured from the glyph bitmaps, in pixels from the
+/// baseline, since fnt files only carry `line_height` and `base`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FontMetrics {
+    /// How far the highest glyph reaches above the baseline.
+    pub ascent: i32,
+    /// How far the lowest glyph reaches below the baseline.
+    pub descent: i32,
+    /// The height of `H`, if the font has it.
+    pub cap_height: Option<i32>,
+    /// The height of `x`, if the font has it.
+    pub x_height: Option<i32>,
+}
+
+/// Where consumers should draw text decorations, in pixels from the baseline
+/// (positive is below it).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DecorationMetrics {
+    /// The top of the underline.
+    pub underline_position: i32,
+    pub underline_thickness: i32,
+    /// The top of the strikethrough line, it's as thick as the underline.
+    pub strikethrough_position: i32,
+}
+
+impl DecorationMetrics {
+    /// Guesses the decorations: the underline halfway into the descent and
+    /// the strikethrough through the middle of the lowercase letters.
+    pub fn from_metrics(metrics: &FontMetrics) -> Self {
+        let underline_thickness = ((metrics.ascent + metrics.descent) / 10).max(1);
+        let x_height = metrics.x_height.unwrap_or(metrics.ascent * 2 / 3);
+
+        Self {
+            underline_position: ((metrics.descent + 1) / 2).max(1),
+            underline_thickness,
+            strikethrough_position: -(x_height + underline_thickness) / 2,
+        }
+    }
+}
+
+impl std::str::FromStr for DecorationMetrics {
+    type Err = anyhow::Error;
+
+    /// Parses `<underline position>:<thickness>:<strikethrough position>`,
+    /// e.g. `1:1:-3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed: Option<Vec<i32>> = s.split(':').map(|part| part.parse().ok()).collect();
+
+        let Some(&[underline_position, underline_thickness, strikethrough_position]) =
+            parsed.as_deref()
+        else {
+            Ewwow.raise().with_context(|| {
+                format!("Invalid decorations '{s}', expected <underline position>:<thickness>:<strikethrough position>")
+            })?;
+
+            unreachable!()
+        };
+
+        if underline_thickness < 1 {
+            Ewwow.raise().with_context(|| {
+                format!("Invalid decorations '{s}', the thickness must be at least 1")
+            })?;
+        }
+
+        Ok(Self {
+            underline_position,
+            underline_thickness,
+            strikethrough_position,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecorationMetrics, FontMetrics};
+
+    #[test]
+    fn test_decoration_metrics() {
+        let metrics = FontMetrics {
+            ascent: 7,
+            descent: 2,
+            cap_height: Some(7),
+            x_height: Some(5),
+        };
+
+        assert_eq!(
+            DecorationMetrics::from_metrics(&metrics),
+            DecorationMetrics {
+                underline_position: 1,
+                underline_thickness: 1,
+                strikethrough_position: -3,
+            }
+        );
+
+        assert_eq!(
+            "2:1:-4".parse::<DecorationMetrics>().unwrap(),
+            DecorationMetrics {
+                underline_position: 2,
+                underline_thickness: 1,
+                strikethrough_position: -4,
+            }
+        );
+        assert!("2:0:-4".parse::<DecorationMetrics>().is_err());
+        assert!("2:1".parse::<DecorationMetrics>().is_err());
+    }
+}
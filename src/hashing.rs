@@ -0,0 +1,178 @@
+use std::{
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+/// A 64 bit FNV-1a hasher. Unlike `DefaultHasher`, its output is stable
+/// across Rust versions, platforms, and runs, so it can be written to files.
+#[derive(Debug, Clone, Copy)]
+pub struct StableHasher {
+    state: u64,
+}
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    pub fn new() -> Self {
+        Self {
+            state: Self::OFFSET_BASIS,
+        }
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= byte as u64;
+            self.state = self.state.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    // Integers are always hashed as little endian, and `usize` as 64 bits, to
+    // get the same hashes on all platforms.
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+pub fn hash_value<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn to_hex(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+/// Like [`std::fs::write`], but leaves `path` alone if it already has the
+/// same contents, so its modification time doesn't make build systems watching
+/// the outputs rebuild what depends on them for nothing. Missing parent
+/// directories are created.
+pub fn write_if_changed<P: AsRef<Path>, C: AsRef<[u8]>>(
+    path: P,
+    contents: C,
+) -> std::io::Result<()> {
+    let (path, contents) = (path.as_ref(), contents.as_ref());
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let unchanged = std::fs::read(path).is_ok_and(|existing| {
+        existing.len() == contents.len() && hash_bytes(&existing) == hash_bytes(contents)
+    });
+
+    match unchanged {
+        true => Ok(()),
+        false => std::fs::write(path, contents),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{hash_bytes, hash_value, to_hex, write_if_changed};
+
+    #[test]
+    fn test_fnv1a_reference_values() {
+        assert_eq!(hash_bytes(b""), 0xcbf29ce484222325);
+        assert_eq!(hash_bytes(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash_bytes(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_hash_value_is_stable() {
+        assert_eq!(hash_value(&1i32), hash_bytes(&[1, 0, 0, 0]));
+        assert_eq!(hash_value("a"), hash_bytes(&[b'a', 0xff]));
+        assert_eq!(to_hex(0xab), "00000000000000ab");
+        assert_ne!(hash_value(&("ab", "c")), hash_value(&("a", "bc")));
+    }
+
+    #[test]
+    fn test_write_if_changed() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "texture-packer-write-if-changed-{}",
+            std::process::id()
+        ));
+        let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        write_if_changed(&path, "atlas")?;
+        std::fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(past)?;
+
+        write_if_changed(&path, "atlas")?;
+        assert_eq!(std::fs::metadata(&path)?.modified()?, past);
+
+        write_if_changed(&path, "atlas!")?;
+        assert_ne!(std::fs::metadata(&path)?.modified()?, past);
+        assert_eq!(std::fs::read_to_string(&path)?, "atlas!");
+
+        std::fs::remove_file(&path)?;
+
+        // Outputs can go into directories that don't exist yet
+        let dir = path.with_extension("dir");
+        write_if_changed(dir.join("out/atlas.json"), "{}")?;
+        assert_eq!(std::fs::read_to_string(dir.join("out/atlas.json"))?, "{}");
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
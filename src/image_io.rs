@@ -0,0 +1,524 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Context;
+use image::RgbaImage;
+
+use crate::{
+    cancellation::CancellationToken,
+    error::{Error, Ewwow},
+    hashing,
+};
+
+/// A png file that has been read and decoded.
+#[derive(Debug)]
+pub struct DecodedPng {
+    pub content_hash: u64,
+    pub image: RgbaImage,
+}
+
+/// Decodes a png file into RGBA8, using `zune-png` if the `fast-png` feature
+/// is enabled and `image`'s decoder otherwise.
+#[cfg(not(feature = "fast-png"))]
+pub fn decode_png(bytes: &[u8]) -> anyhow::Result<RgbaImage> {
+    let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Png)
+        .map_err(Error::ImageError)?;
+
+    Ok(image.to_rgba8())
+}
+
+/// Decodes a png file into RGBA8, using `zune-png` if the `fast-png` feature
+/// is enabled and `image`'s decoder otherwise.
+#[cfg(feature = "fast-png")]
+pub fn decode_png(bytes: &[u8]) -> anyhow::Result<RgbaImage> {
+    use zune_core::{colorspace::ColorSpace, options::DecoderOptions};
+
+    let options = DecoderOptions::default()
+        .png_set_add_alpha_channel(true)
+        .png_set_strip_to_8bit(true);
+
+    let mut decoder = zune_png::PngDecoder::new_with_options(bytes, options);
+    let pixels = decoder.decode_raw().map_err(|err| {
+        Error::ImageError(image::ImageError::Decoding(
+            image::error::DecodingError::new(image::ImageFormat::Png.into(), format!("{err:?}")),
+        ))
+    })?;
+
+    let (width, height) = decoder
+        .get_dimensions()
+        .context("Png decoder didn't report the image size")?;
+
+    // Grayscale images are decoded as luma + alpha
+    let pixels = match decoder.get_colorspace() {
+        Some(ColorSpace::RGBA) => pixels,
+        Some(ColorSpace::LumaA) => pixels
+            .chunks_exact(2)
+            .flat_map(|la| [la[0], la[0], la[0], la[1]])
+            .collect(),
+        other => anyhow::bail!("Unsupported png color space {other:?}"),
+    };
+
+    RgbaImage::from_raw(width as u32, height as u32, pixels)
+        .context("Png decoder returned too few pixels")
+}
+
+/// Reads and decodes a png file, also hashing its contents.
+pub fn read_png(path: &Path) -> anyhow::Result<DecodedPng> {
+    let _span = tracing::info_span!("read_png", path = %path.display()).entered();
+
+    let file_contents = std::fs::read(path)
+        .with_context(|| format!("Failed to read png image '{}'", path.display()))?;
+
+    let image = decode_png(&file_contents)
+        .with_context(|| format!("Failed to decode png image '{}'", path.display()))?;
+
+    Ok(DecodedPng {
+        content_hash: hashing::hash_bytes(&file_contents),
+        image,
+    })
+}
+
+/// How hard the png encoder tries to compress, see [`PngOptions`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl std::str::FromStr for PngCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Self::Fast),
+            "default" => Ok(Self::Default),
+            "best" => Ok(Self::Best),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Unknown png compression '{s}', expected one of fast, default, best")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// The png row filter, see [`PngOptions`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    /// Picks the best filter for every row
+    #[default]
+    Adaptive,
+}
+
+impl std::str::FromStr for PngFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "sub" => Ok(Self::Sub),
+            "up" => Ok(Self::Up),
+            "avg" => Ok(Self::Avg),
+            "paeth" => Ok(Self::Paeth),
+            "adaptive" => Ok(Self::Adaptive),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Unknown png filter '{s}', expected one of none, sub, up, avg, paeth, adaptive")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// How the final atlas png is encoded. Better compression costs build time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PngOptions {
+    pub compression: PngCompression,
+    pub filter: PngFilter,
+    /// Runs the encoded png through `oxipng` with this preset (0 to 6).
+    /// Needs the `oxipng` feature.
+    pub optimize: Option<u8>,
+}
+
+/// Encodes `image` as a png file.
+pub fn encode_png(image: &RgbaImage, options: &PngOptions) -> anyhow::Result<Vec<u8>> {
+    let _span = tracing::info_span!("encode_png").entered();
+
+    use image::{
+        codecs::png::{CompressionType, FilterType, PngEncoder},
+        ImageEncoder,
+    };
+
+    let compression = match options.compression {
+        PngCompression::Fast => CompressionType::Fast,
+        PngCompression::Default => CompressionType::Default,
+        PngCompression::Best => CompressionType::Best,
+    };
+
+    let filter = match options.filter {
+        PngFilter::None => FilterType::NoFilter,
+        PngFilter::Sub => FilterType::Sub,
+        PngFilter::Up => FilterType::Up,
+        PngFilter::Avg => FilterType::Avg,
+        PngFilter::Paeth => FilterType::Paeth,
+        PngFilter::Adaptive => FilterType::Adaptive,
+    };
+
+    let mut bytes = vec![];
+    PngEncoder::new_with_quality(&mut bytes, compression, filter)
+        .write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            image::ColorType::Rgba8,
+        )
+        .context("Failed to encode png image")?;
+
+    match options.optimize {
+        Some(preset) => optimize_png(&bytes, preset),
+        None => Ok(bytes),
+    }
+}
+
+#[cfg(feature = "oxipng")]
+fn optimize_png(bytes: &[u8], preset: u8) -> anyhow::Result<Vec<u8>> {
+    let _span = tracing::info_span!("optimize_png", preset).entered();
+
+    if preset > 6 {
+        Ewwow
+            .raise()
+            .with_context(|| format!("Invalid oxipng preset {preset}, expected 0 to 6"))?;
+    }
+
+    oxipng::optimize_from_memory(bytes, &oxipng::Options::from_preset(preset))
+        .map_err(|err| anyhow::anyhow!("{err}"))
+        .context("Failed to optimize png image with oxipng")
+}
+
+#[cfg(not(feature = "oxipng"))]
+fn optimize_png(_bytes: &[u8], _preset: u8) -> anyhow::Result<Vec<u8>> {
+    Ewwow
+        .raise()
+        .context("Optimizing png images requires the `oxipng` feature")?;
+
+    unreachable!()
+}
+
+/// Rasterizes an SVG document at its own size, or scaled to be `height`
+/// pixels high.
+#[cfg(feature = "svg")]
+pub fn rasterize_svg(bytes: &[u8], height: Option<u32>) -> anyhow::Result<RgbaImage> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default())
+        .map_err(|err| Error::ParseError(format!("Invalid SVG document: {err}")))?;
+
+    let size = tree.size();
+    let scale = height.map_or(1.0, |height| height as f32 / size.height());
+    let width = (size.width() * scale).ceil().max(1.0) as u32;
+    let height = (size.height() * scale).ceil().max(1.0) as u32;
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or(Ewwow)
+        .with_context(|| format!("Failed to allocate a {width}x{height} SVG raster"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // tiny-skia works with premultiplied alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    Ok(RgbaImage::from_raw(width, height, pixels).unwrap())
+}
+
+#[cfg(not(feature = "svg"))]
+pub fn rasterize_svg(_bytes: &[u8], _height: Option<u32>) -> anyhow::Result<RgbaImage> {
+    Ewwow
+        .raise()
+        .context("Rasterizing SVG images requires the `svg` feature")?;
+
+    unreachable!()
+}
+
+/// Encodes `image` and writes it to `path`. Nothing is written if the build
+/// is cancelled in the meantime.
+pub fn write_png(
+    path: &Path,
+    image: &RgbaImage,
+    options: &PngOptions,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    cancel.check()?;
+
+    let bytes = encode_png(image, options)
+        .with_context(|| format!("Failed to encode png image '{}'", path.display()))?;
+
+    cancel.check()?;
+
+    hashing::write_if_changed(path, bytes)
+        .with_context(|| format!("Failed to write png image '{}'", path.display()))
+}
+
+/// Reads and decodes all `paths` on a pool of worker threads (one per core).
+/// The results are in the same order as `paths`.
+pub fn read_pngs_parallel(paths: &[PathBuf]) -> Vec<anyhow::Result<DecodedPng>> {
+    let num_threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(paths.len());
+
+    if num_threads <= 1 {
+        return paths.iter().map(|path| read_png(path)).collect();
+    }
+
+    let next_index = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<anyhow::Result<DecodedPng>>>> =
+        Mutex::new(paths.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                let index = next_index.fetch_add(1, Ordering::Relaxed);
+
+                let Some(path) = paths.get(index) else {
+                    break;
+                };
+
+                let result = read_png(path);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("Every png is decoded by a worker"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{decode_png, read_pngs_parallel, PngCompression, PngFilter, PngOptions};
+
+    pub(super) fn test_image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                (x * 7) as u8,
+                (y * 13) as u8,
+                (x ^ y) as u8,
+                ((x + y) % 3 * 127) as u8,
+            ])
+        })
+    }
+
+    pub(super) fn encode_png(image: &RgbaImage) -> Vec<u8> {
+        super::encode_png(image, &PngOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_decode_png() -> anyhow::Result<()> {
+        let image = test_image(37, 21);
+
+        assert_eq!(decode_png(&encode_png(&image))?, image);
+
+        // Grayscale pngs are expanded to RGBA
+        let gray = image::DynamicImage::ImageRgba8(image.clone()).to_luma8();
+        let mut bytes = vec![];
+        gray.write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageOutputFormat::Png,
+        )?;
+        let decoded = decode_png(&bytes)?;
+        assert_eq!(decoded.get_pixel(3, 4)[0], gray.get_pixel(3, 4)[0]);
+        assert_eq!(decoded.get_pixel(3, 4)[3], 255);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "svg")]
+    #[test]
+    fn test_rasterize_svg() -> anyhow::Result<()> {
+        let svg = br##"<svg xmlns="http://www.w3.org/2000/svg" width="8" height="4">
+            <rect width="4" height="4" fill="#ff0000"/>
+        </svg>"##;
+
+        let image = super::rasterize_svg(svg, None)?;
+        assert_eq!(image.dimensions(), (8, 4));
+        assert_eq!(*image.get_pixel(1, 1), Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(6, 1)[3], 0);
+
+        // Scaled to the requested height, keeping the aspect ratio
+        assert_eq!(super::rasterize_svg(svg, Some(16))?.dimensions(), (32, 16));
+
+        assert!(super::rasterize_svg(b"<svg", None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_png_options() -> anyhow::Result<()> {
+        let image = test_image(64, 48);
+
+        for compression in ["fast", "default", "best"] {
+            for filter in ["none", "sub", "up", "avg", "paeth", "adaptive"] {
+                let options = PngOptions {
+                    compression: compression.parse()?,
+                    filter: filter.parse()?,
+                    optimize: None,
+                };
+
+                assert_eq!(decode_png(&super::encode_png(&image, &options)?)?, image);
+            }
+        }
+
+        assert!("smallest".parse::<PngCompression>().is_err());
+        assert!("paeth ".parse::<PngFilter>().is_err());
+
+        let optimized = super::encode_png(
+            &image,
+            &PngOptions {
+                optimize: Some(2),
+                ..Default::default()
+            },
+        );
+        if cfg!(feature = "oxipng") {
+            assert_eq!(decode_png(&optimized?)?, image);
+        } else {
+            assert!(optimized.is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_pngs_parallel_keeps_order() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("texture-packer-png-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let mut paths = vec![];
+        for i in 0..9 {
+            let path = dir.join(format!("{i}.png"));
+            std::fs::write(&path, encode_png(&test_image(i + 1, 3)))?;
+            paths.push(path);
+        }
+        paths.push(dir.join("missing.png"));
+
+        let results = read_pngs_parallel(&paths);
+        assert_eq!(results.len(), paths.len());
+
+        for (i, result) in results[..9].iter().enumerate() {
+            assert_eq!(result.as_ref().unwrap().image.width(), i as u32 + 1);
+        }
+        assert!(results[9].is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod benches {
+    extern crate test;
+
+    use std::path::PathBuf;
+
+    use test::Bencher;
+
+    use super::{
+        decode_png, encode_png, read_png, read_pngs_parallel, tests, PngCompression, PngOptions,
+    };
+
+    const NUM_PAGES: usize = 8;
+
+    fn write_pages(name: &str) -> Vec<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("texture-packer-bench-{name}"));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let bytes = tests::encode_png(&tests::test_image(512, 512));
+
+        (0..NUM_PAGES)
+            .map(|i| {
+                let path = dir.join(format!("{i}.png"));
+                std::fs::write(&path, &bytes).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[bench]
+    fn bench_decode_png_512(b: &mut Bencher) {
+        let bytes = tests::encode_png(&tests::test_image(512, 512));
+
+        b.iter(|| decode_png(&bytes).unwrap());
+    }
+
+    #[bench]
+    fn bench_encode_png_512(b: &mut Bencher) {
+        let image = tests::test_image(512, 512);
+
+        b.iter(|| encode_png(&image, &PngOptions::default()).unwrap());
+    }
+
+    #[bench]
+    fn bench_encode_png_512_fast(b: &mut Bencher) {
+        let image = tests::test_image(512, 512);
+        let options = PngOptions {
+            compression: PngCompression::Fast,
+            ..Default::default()
+        };
+
+        b.iter(|| encode_png(&image, &options).unwrap());
+    }
+
+    #[bench]
+    fn bench_read_pages_sequential(b: &mut Bencher) {
+        let paths = write_pages("sequential");
+
+        b.iter(|| {
+            for path in paths.iter() {
+                read_png(path).unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_read_pages_parallel(b: &mut Bencher) {
+        let paths = write_pages("parallel");
+
+        b.iter(|| {
+            for result in read_pngs_parallel(&paths) {
+                result.unwrap();
+            }
+        });
+    }
+}
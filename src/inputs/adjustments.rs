@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::{
+    error::Ewwow, intermediates::effects::ColorAdjustment, outputs::content_filter::ContentFilter,
+};
+
+/// Color tweaks for groups of fonts, labels and sprites, applied before
+/// packing, see
+/// [`crate::intermediates::texture_atlas::TextureAtlas::adjust_colors`].
+///
+/// ```toml
+/// [groups.night]
+/// # A filter like `--thumbnails-include`, see `outputs::content_filter`
+/// include = "props/*,tag:outdoor"
+/// hue = -20         # degrees
+/// saturation = 0.8  # factor
+/// brightness = -0.1 # added to every channel
+/// contrast = 1.1    # factor
+/// ```
+///
+/// Left out values change nothing. Groups are applied in the order of their
+/// names, so something in two groups gets both adjustments.
+#[derive(Debug, Clone, Default)]
+pub struct AdjustmentsFile {
+    pub groups: Vec<AdjustmentGroup>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdjustmentGroup {
+    pub name: String,
+    pub include: ContentFilter,
+    pub adjustment: ColorAdjustment,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawAdjustmentsFile {
+    #[serde(default)]
+    groups: BTreeMap<String, RawAdjustmentGroup>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawAdjustmentGroup {
+    include: String,
+    hue: Option<f32>,
+    saturation: Option<f32>,
+    brightness: Option<f32>,
+    contrast: Option<f32>,
+}
+
+impl AdjustmentsFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let raw: RawAdjustmentsFile =
+            toml::from_str(file_contents).context("Invalid adjustments file")?;
+
+        if raw.groups.is_empty() {
+            Ewwow
+                .raise()
+                .context("The adjustments file has no `[groups.<name>]`")?;
+        }
+
+        let groups = raw
+            .groups
+            .into_iter()
+            .map(|(name, group)| {
+                let include = group
+                    .include
+                    .parse()
+                    .with_context(|| format!("Failed parsing group '{name}'"))?;
+
+                let unchanged = ColorAdjustment::default();
+                let adjustment = ColorAdjustment {
+                    hue: group.hue.unwrap_or(unchanged.hue),
+                    saturation: group.saturation.unwrap_or(unchanged.saturation),
+                    brightness: group.brightness.unwrap_or(unchanged.brightness),
+                    contrast: group.contrast.unwrap_or(unchanged.contrast),
+                };
+
+                Ok(AdjustmentGroup {
+                    name,
+                    include,
+                    adjustment,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { groups })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdjustmentsFile;
+    use crate::intermediates::effects::ColorAdjustment;
+
+    #[test]
+    fn test_parse_adjustments_file() -> anyhow::Result<()> {
+        let file = AdjustmentsFile::try_parse(
+            r#"
+            [groups.night]
+            include = "props/*,tag:outdoor"
+            hue = -20
+            brightness = -0.1
+
+            [groups.faded]
+            include = "fonts"
+            saturation = 0.5
+            "#,
+        )?;
+
+        assert_eq!(file.groups.len(), 2);
+        assert_eq!(file.groups[0].name, "faded");
+        assert!(file.groups[0].include.includes_font("m5x7"));
+        assert_eq!(
+            file.groups[1].adjustment,
+            ColorAdjustment {
+                hue: -20.0,
+                brightness: -0.1,
+                ..Default::default()
+            }
+        );
+
+        assert!(AdjustmentsFile::try_parse("").is_err());
+        assert!(AdjustmentsFile::try_parse("[groups.night]\nhue = 20").is_err());
+        assert!(AdjustmentsFile::try_parse("[groups.night]\ninclude = \"\"").is_err());
+        assert!(AdjustmentsFile::try_parse("[groups.night]\ninclude = \"*\"\nhew = 20").is_err());
+
+        Ok(())
+    }
+}
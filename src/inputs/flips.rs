@@ -0,0 +1,105 @@
+use anyhow::Context;
+
+use crate::{error::Ewwow, intermediates::effects::Flip};
+
+/// The mirrored variants to generate, see
+/// [`crate::intermediates::texture_atlas::TextureAtlas::flip_sprites`].
+///
+/// Every non-empty line that doesn't start with `#` has the form
+/// `<sprite name> <x|y|xy> [baked]`, e.g. `hero x` for `hero#flip-x`. Without
+/// `baked` the variant shares the pixels of the sprite and is only flagged as
+/// flipped in the metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlipsFile {
+    pub flips: Vec<FlipRequest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlipRequest {
+    pub sprite_name: String,
+    pub flip: Flip,
+    pub baked: bool,
+}
+
+impl FlipsFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let mut output = Self::default();
+
+        for (num, line) in file_contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let ctxt = || format!("Failed parsing line {}", num + 1);
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (sprite_name, flip, baked) = match parts[..] {
+                [sprite_name, flip] => (sprite_name, flip, false),
+                [sprite_name, flip, "baked"] => (sprite_name, flip, true),
+                _ => {
+                    Ewwow
+                        .raise()
+                        .with_context(|| {
+                            format!("Expected `<sprite name> <x|y|xy> [baked]` but found `{line}`")
+                        })
+                        .with_context(ctxt)?;
+
+                    unreachable!()
+                }
+            };
+
+            let request = FlipRequest {
+                sprite_name: sprite_name.to_string(),
+                flip: flip.parse().with_context(ctxt)?,
+                baked,
+            };
+
+            if output.flips.iter().any(|existing| {
+                existing.sprite_name == request.sprite_name && existing.flip == request.flip
+            }) {
+                Ewwow
+                    .raise()
+                    .with_context(|| format!("Duplicate flip `{line}`"))
+                    .with_context(ctxt)?;
+            }
+
+            output.flips.push(request);
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlipsFile;
+    use crate::intermediates::effects::Flip;
+
+    #[test]
+    fn test_parse_flips_file() -> anyhow::Result<()> {
+        let contents = "
+# Walk cycles only face right
+hero_walk x
+door      xy baked
+door      y
+";
+
+        let file = FlipsFile::try_parse(contents)?;
+
+        assert_eq!(file.flips.len(), 3);
+        assert_eq!(file.flips[0].sprite_name, "hero_walk");
+        assert_eq!(file.flips[0].flip, Flip { x: true, y: false });
+        assert!(!file.flips[0].baked);
+        assert_eq!(file.flips[1].flip, Flip { x: true, y: true });
+        assert!(file.flips[1].baked);
+
+        assert!(FlipsFile::try_parse("hero").is_err());
+        assert!(FlipsFile::try_parse("hero z").is_err());
+        assert!(FlipsFile::try_parse("hero x cooked").is_err());
+        assert!(FlipsFile::try_parse("hero x\nhero x baked").is_err());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,9 @@
-use std::{fmt::Debug, str::FromStr};
+use std::{collections::BTreeMap, fmt::Debug, str::FromStr};
 
 use anyhow::Context;
 
 use crate::{
-    error::Ewwow,
+    error::Error,
     sources::{SourceSprite, Sources},
 };
 
@@ -20,6 +20,19 @@ pub struct FntFile {
 
     // Chars `char`
     pub chars: Vec<FntChar>,
+
+    // Distance field `distanceField`, written by msdf-bmfont
+    pub distance_field: Option<FntDistanceField>,
+
+    /// Problems the parser glossed over, e.g. a wrong `chars count`.
+    #[serde(skip)]
+    pub parse_warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct FntDistanceField {
+    pub field_type: String,
+    pub distance_range: i32,
 }
 
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
@@ -83,9 +96,7 @@ impl FntPage {
                 "file" => {
                     output.file = parse_string(rhs).context("Failed parsing 'file' attribute")?
                 }
-                _ => Ewwow
-                    .raise()
-                    .with_context(|| format!("Encountered unknown attribute '{lhs}'"))?,
+                _ => Error::ParseError(format!("Encountered unknown attribute '{lhs}'")).raise()?,
             }
 
             Ok(())
@@ -121,15 +132,21 @@ impl FntChar {
                 }
                 "page" => output.page = parse(rhs).context("Failed parsing 'page' attribute")?,
                 "chnl" => output.chnl = parse(rhs).context("Failed parsing 'chnl' attribute")?,
-                _ => Ewwow
-                    .raise()
-                    .with_context(|| format!("Encountered unknown attribute '{lhs}'"))?,
+                _ => Error::ParseError(format!("Encountered unknown attribute '{lhs}'")).raise()?,
             }
 
             Ok(())
         })
         .context("Failed parsing FNT char")?;
 
+        if output.width < 0 || output.height < 0 {
+            Error::ParseError(format!(
+                "Char #{} has a negative size of {}x{}",
+                output.id, output.width, output.height
+            ))
+            .raise()?;
+        }
+
         Ok(output)
     }
 }
@@ -171,9 +188,7 @@ impl FntInfo {
                     output.spacing =
                         parse_array(rhs).context("Failed parsing 'spacing' attribute")?
                 }
-                _ => Ewwow
-                    .raise()
-                    .with_context(|| format!("Encountered unknown attribute '{lhs}'"))?,
+                _ => Error::ParseError(format!("Encountered unknown attribute '{lhs}'")).raise()?,
             }
 
             Ok(())
@@ -208,9 +223,7 @@ impl FntCommon {
                 "packed" => {
                     output.packed = parse(rhs).context("Failed parsing 'packed' attribute")?
                 }
-                _ => Ewwow
-                    .raise()
-                    .with_context(|| format!("Encountered unknown attribute '{lhs}'"))?,
+                _ => Error::ParseError(format!("Encountered unknown attribute '{lhs}'")).raise()?,
             }
 
             Ok(())
@@ -221,10 +234,37 @@ impl FntCommon {
     }
 }
 
+impl FntDistanceField {
+    const KEYWORD: &'static str = "distanceField";
+
+    fn try_parse(line: &str) -> anyhow::Result<Self> {
+        let mut output = Self::default();
+        parse_line(line, |lhs, rhs| {
+            match lhs {
+                // msdf-bmfont doesn't quote it
+                "fieldType" => output.field_type = rhs.trim_matches('"').to_string(),
+                "distanceRange" => {
+                    output.distance_range =
+                        parse(rhs).context("Failed parsing 'distanceRange' attribute")?
+                }
+                _ => Error::ParseError(format!("Encountered unknown attribute '{lhs}'")).raise()?,
+            }
+
+            Ok(())
+        })
+        .context("Failed parsing FNT distance field")?;
+
+        Ok(output)
+    }
+}
+
 impl FntFile {
     pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
         let mut output = Self::default();
 
+        let mut declared_num_chars = None;
+        let mut num_kernings = 0;
+
         for (num, line) in file_contents.lines().enumerate() {
             let (ident, data) = consume_until_space(line);
 
@@ -238,28 +278,108 @@ impl FntFile {
                 FntPage::KEYWORD => output
                     .pages
                     .push(FntPage::try_parse(data).with_context(ctxt)?),
-                "chars" => {} // ignore
+                "chars" => {
+                    // Only used to sanity check the file
+                    parse_line(data, |lhs, rhs| {
+                        if lhs == "count" {
+                            declared_num_chars = Some(
+                                parse::<usize>(rhs).context("Failed parsing 'count' attribute")?,
+                            );
+                        }
+
+                        Ok(())
+                    })
+                    .with_context(ctxt)?
+                }
                 FntChar::KEYWORD => output
                     .chars
                     .push(FntChar::try_parse(data).with_context(ctxt)?),
+                FntDistanceField::KEYWORD => {
+                    output.distance_field =
+                        Some(FntDistanceField::try_parse(data).with_context(ctxt)?)
+                }
                 "kernings" => {} // ignore for now
-                _ => Ewwow
-                    .raise()
-                    .with_context(|| format!("Encountered unknown attribute `{ident}`"))?,
+                "kerning" => num_kernings += 1,
+                _ => {
+                    Error::ParseError(format!("Encountered unknown attribute `{ident}`")).raise()?
+                }
             }
         }
 
+        if let Some(declared) = declared_num_chars.filter(|&n| n != output.chars.len()) {
+            output.parse_warnings.push(format!(
+                "Declares {declared} chars but contains {}",
+                output.chars.len()
+            ));
+        }
+
+        if output.common.num_pages as usize != output.pages.len() {
+            output.parse_warnings.push(format!(
+                "Declares {} pages but contains {}",
+                output.common.num_pages,
+                output.pages.len()
+            ));
+        }
+
+        if num_kernings > 0 {
+            output.parse_warnings.push(format!(
+                "Ignored {num_kernings} kerning pair(s), kerning isn't supported yet"
+            ));
+        }
+
+        output.fill_missing_metrics();
+
         Ok(output)
     }
 
+    /// Some exporters write `lineHeight=0` or leave out `base`, which breaks
+    /// text layout. Both are derived from the glyph boxes instead: the line
+    /// is as high as the lowest reaching glyph, and the base line is where
+    /// most glyphs end.
+    fn fill_missing_metrics(&mut self) {
+        let visible_chars = || self.chars.iter().filter(|ch| ch.height > 0);
+
+        if self.common.line_height <= 0 {
+            if let Some(line_height) = visible_chars().map(|ch| ch.y_offset + ch.height).max() {
+                self.parse_warnings.push(format!(
+                    "Declares a line height of {}, using the height of the glyphs ({line_height}) instead",
+                    self.common.line_height
+                ));
+                self.common.line_height = line_height;
+            }
+        }
+
+        if self.common.base <= 0 {
+            let mut bottoms: BTreeMap<i32, usize> = BTreeMap::new();
+            for ch in visible_chars() {
+                *bottoms.entry(ch.y_offset + ch.height).or_default() += 1;
+            }
+
+            // The highest of the most common bottoms
+            if let Some((base, _)) = bottoms.into_iter().rev().max_by_key(|&(_, count)| count) {
+                self.parse_warnings.push(format!(
+                    "Declares a base of {}, using the line most glyphs end on ({base}) instead",
+                    self.common.base
+                ));
+                self.common.base = base;
+            }
+        }
+    }
+
+    /// Whether the glyphs have soft edges, i.e. were smoothed, supersampled
+    /// (`aa` is the level, 1 is none) or are a distance field.
+    pub fn is_smooth(&self) -> bool {
+        self.info.smooth != 0 || self.info.aa > 1 || self.distance_field.is_some()
+    }
+
     pub fn dependencies(&self) -> Vec<String> {
         self.pages.iter().map(|page| page.file.clone()).collect()
     }
 
-    pub fn get_character_sprite<'s>(
+    pub fn get_character_sprite(
         &self,
         char_code: u32,
-        sources: &'s Sources,
+        sources: &Sources,
     ) -> anyhow::Result<SourceSprite> {
         let self_name = &self.info.face;
 
@@ -267,11 +387,10 @@ impl FntFile {
             .chars
             .iter()
             .find(|&ch| ch.id == char_code)
-            .ok_or(Ewwow)
-            .with_context(|| {
-                format!(
-                "Failed to load char info as font '{self_name}' does not contain char #{char_code}"
-            )
+            .ok_or_else(|| {
+                Error::ParseError(format!(
+                    "Failed to load char info as font '{self_name}' does not contain char #{char_code}"
+                ))
             })?;
 
         let page = fnt_char.page;
@@ -292,11 +411,12 @@ impl FntFile {
 }
 
 fn parse<T: Debug + FromStr>(rhs: &str) -> anyhow::Result<T> {
-    rhs.parse::<T>().map_err(|_| Ewwow).with_context(|| {
-        format!(
+    rhs.parse::<T>().map_err(|_| {
+        Error::ParseError(format!(
             "Failed parsing literal `{rhs}` as {}",
             std::any::type_name::<T>()
-        )
+        ))
+        .into()
     })
 }
 
@@ -317,12 +437,11 @@ fn parse_array<T: FromStr + Default + Copy + Debug, const N: usize>(
     }
 
     if index != N || !rhs.is_empty() {
-        Ewwow.raise().with_context(|| {
-            format!(
-                "Failed parsing literal `{original}` as array {}",
-                std::any::type_name::<[T; N]>()
-            )
-        })?
+        Error::ParseError(format!(
+            "Failed parsing literal `{original}` as array {}",
+            std::any::type_name::<[T; N]>()
+        ))
+        .raise()?
     }
 
     Ok(output)
@@ -349,11 +468,17 @@ where
 fn parse_string(value: &str) -> anyhow::Result<String> {
     Ok(value
         .strip_prefix('"')
-        .ok_or(Ewwow)
-        .with_context(|| format!("Failed to parse `{value}` as a string: misses opening \""))?
+        .ok_or_else(|| {
+            Error::ParseError(format!(
+                "Failed to parse `{value}` as a string: misses opening \""
+            ))
+        })?
         .strip_suffix('"')
-        .ok_or(Ewwow)
-        .with_context(|| format!("Failed to parse `{value}` as a string: misses closing \""))?
+        .ok_or_else(|| {
+            Error::ParseError(format!(
+                "Failed to parse `{value}` as a string: misses closing \""
+            ))
+        })?
         .to_string())
 }
 
@@ -386,6 +511,7 @@ fn try_split_equality(expr: &str) -> Option<(&str, &str)> {
 #[cfg(test)]
 mod tests {
     use super::{consume_until_space, FntFile};
+    use crate::error::Error;
 
     #[test]
     fn test_consume_until_space() {
@@ -419,4 +545,81 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_warnings() -> anyhow::Result<()> {
+        let test_file = include_str!("../../assets/m5x7.fnt");
+        assert!(FntFile::try_parse(test_file)?.parse_warnings.is_empty());
+
+        let file = "common lineHeight=8 base=6 scaleW=8 scaleH=8 pages=2 packed=0\n\
+                    page id=0 file=\"a.png\"\n\
+                    chars count=3\n\
+                    char id=65 x=0 y=0 width=4 height=6 xoffset=0 yoffset=0 xadvance=5 page=0 chnl=15\n\
+                    kernings count=1\n\
+                    kerning first=65 second=65 amount=-1\n";
+
+        assert_eq!(
+            FntFile::try_parse(file)?.parse_warnings,
+            vec![
+                "Declares 3 chars but contains 1",
+                "Declares 2 pages but contains 1",
+                "Ignored 1 kerning pair(s), kerning isn't supported yet",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_distance_field() -> anyhow::Result<()> {
+        let test_file = include_str!("../../assets/m5x7.fnt");
+        let pixel_font = FntFile::try_parse(test_file)?;
+        assert!(pixel_font.distance_field.is_none());
+        assert!(!pixel_font.is_smooth());
+
+        let file = "info face=\"Roboto\" size=42 bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 aa=1 padding=2,2,2,2 spacing=0,0\n\
+                    common lineHeight=49 base=39 scaleW=512 scaleH=512 pages=0 packed=0\n\
+                    distanceField fieldType=msdf distanceRange=4\n";
+        let msdf = FntFile::try_parse(file)?;
+        let field = msdf.distance_field.as_ref().unwrap();
+        assert_eq!(field.field_type, "msdf");
+        assert_eq!(field.distance_range, 4);
+        assert!(msdf.is_smooth());
+
+        assert!(FntFile::try_parse("distanceField fieldType=sdf range=4").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_missing_metrics() -> anyhow::Result<()> {
+        let file = "common lineHeight=0 scaleW=32 scaleH=32 pages=1 packed=0\n\
+                    page id=0 file=\"a.png\"\n\
+                    char id=65 x=0 y=0 width=4 height=7 xoffset=0 yoffset=2 xadvance=5 page=0 chnl=15\n\
+                    char id=66 x=5 y=0 width=4 height=7 xoffset=0 yoffset=2 xadvance=5 page=0 chnl=15\n\
+                    char id=103 x=10 y=0 width=4 height=7 xoffset=0 yoffset=4 xadvance=5 page=0 chnl=15\n\
+                    char id=32 x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=3 page=0 chnl=15\n";
+
+        let fnt = FntFile::try_parse(file)?;
+        assert_eq!(fnt.common.line_height, 11);
+        assert_eq!(fnt.common.base, 9);
+        assert_eq!(fnt.parse_warnings.len(), 2);
+
+        // Declared metrics are kept
+        let m5x7 = FntFile::try_parse(include_str!("../../assets/m5x7.fnt"))?;
+        assert_eq!(m5x7.common.line_height, 13);
+        assert_eq!(m5x7.common.base, 9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_negative_char_size() {
+        let line =
+            "char id=65 x=0 y=0 width=-5 height=9 xoffset=0 yoffset=0 xadvance=6 page=0 chnl=15";
+
+        let err = FntFile::try_parse(line).unwrap_err();
+        assert!(format!("{err:#}").contains("negative size of -5x9"));
+        assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+    }
 }
@@ -0,0 +1,184 @@
+use anyhow::Context;
+
+use crate::{error::Error, intermediates::font};
+
+/// The icons of an icon font, see
+/// [`crate::intermediates::font::FontIntermediate::from_icons`].
+///
+/// Every non-empty line that doesn't start with `#` has the form
+/// `<icon name> <file.svg> [U+<hex>]`, e.g. `close icons/close.svg U+E000`.
+/// Icons without a code point get the one after the previous icon, starting
+/// at [`Self::FIRST_CODE_POINT`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IconFontFile {
+    pub icons: Vec<IconEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconEntry {
+    pub name: String,
+    /// Relative to the icon font file.
+    pub file: String,
+    pub code_point: u32,
+}
+
+/// Which icon a code point of an icon font shows, written next to the atlas
+/// so UI text can refer to icons by name.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct IconTable {
+    pub font: String,
+    pub icons: Vec<IconTableEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct IconTableEntry {
+    pub code_point: u32,
+    pub name: String,
+}
+
+impl IconFontFile {
+    /// The first code point of the Private Use Area.
+    pub const FIRST_CODE_POINT: u32 = 0xe000;
+
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let mut output = Self::default();
+        let mut next_code_point = Self::FIRST_CODE_POINT;
+
+        for (num, line) in file_contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let ctxt = || format!("Failed parsing line {}", num + 1);
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (name, file, code_point) = match parts[..] {
+                [name, file] => (name, file, next_code_point),
+                [name, file, code] => {
+                    let code_point = match font::parse_char_codes(code).with_context(ctxt)?[..] {
+                        [code_point] => code_point,
+                        _ => {
+                            Error::ParseError(format!("Expected one code point, not `{code}`"))
+                                .raise()
+                                .with_context(ctxt)?;
+
+                            unreachable!()
+                        }
+                    };
+
+                    (name, file, code_point)
+                }
+                _ => {
+                    Error::ParseError(format!(
+                        "Expected `<icon name> <file.svg> [U+<hex>]` but found `{line}`"
+                    ))
+                    .raise()
+                    .with_context(ctxt)?;
+
+                    unreachable!()
+                }
+            };
+
+            if !is_private_use(code_point) {
+                Error::ParseError(format!(
+                    "U+{code_point:04X} of icon '{name}' isn't a private use code point"
+                ))
+                .raise()
+                .with_context(ctxt)?;
+            }
+
+            if let Some(existing) = output
+                .icons
+                .iter()
+                .find(|icon| icon.name == name || icon.code_point == code_point)
+            {
+                Error::ParseError(format!(
+                    "Icon '{name}' (U+{code_point:04X}) clashes with icon '{}' (U+{:04X})",
+                    existing.name, existing.code_point
+                ))
+                .raise()
+                .with_context(ctxt)?;
+            }
+
+            output.icons.push(IconEntry {
+                name: name.to_string(),
+                file: file.to_string(),
+                code_point,
+            });
+            next_code_point = code_point + 1;
+        }
+
+        if output.icons.is_empty() {
+            Error::ParseError("The icon font has no icons".to_string()).raise()?;
+        }
+
+        Ok(output)
+    }
+
+    pub fn table(&self, font_name: &str) -> IconTable {
+        IconTable {
+            font: font_name.to_string(),
+            icons: self
+                .icons
+                .iter()
+                .map(|icon| IconTableEntry {
+                    code_point: icon.code_point,
+                    name: icon.name.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Whether `code_point` lies in one of the Private Use Areas of Unicode.
+fn is_private_use(code_point: u32) -> bool {
+    matches!(
+        code_point,
+        0xe000..=0xf8ff | 0xf0000..=0xffffd | 0x100000..=0x10fffd
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IconFontFile;
+    use crate::error::Error;
+
+    #[test]
+    fn test_parse_icon_font_file() -> anyhow::Result<()> {
+        let contents = "
+# Toolbar icons
+close icons/close.svg
+gear  icons/gear.svg  U+E010
+save  icons/save.svg
+";
+
+        let file = IconFontFile::try_parse(contents)?;
+
+        assert_eq!(file.icons.len(), 3);
+        assert_eq!(file.icons[0].name, "close");
+        assert_eq!(file.icons[0].file, "icons/close.svg");
+        assert_eq!(file.icons[0].code_point, 0xe000);
+        assert_eq!(file.icons[1].code_point, 0xe010);
+        assert_eq!(file.icons[2].code_point, 0xe011);
+
+        let table = file.table("icons");
+        assert_eq!(table.font, "icons");
+        assert_eq!(table.icons[2].name, "save");
+        assert_eq!(table.icons[2].code_point, 0xe011);
+
+        assert!(IconFontFile::try_parse("").is_err());
+        assert!(IconFontFile::try_parse("close").is_err());
+        assert!(IconFontFile::try_parse("close close.svg U+41").is_err());
+        assert!(IconFontFile::try_parse("close close.svg U+E000,U+E001").is_err());
+        assert!(IconFontFile::try_parse("close a.svg\nclose b.svg").is_err());
+        assert!(IconFontFile::try_parse("close a.svg U+E001\nopen b.svg U+E001").is_err());
+
+        let err = IconFontFile::try_parse("# icons\nclose a.svg\nclose b.svg").unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        assert!(format!("{err:#}").contains("Failed parsing line 3"));
+
+        Ok(())
+    }
+}
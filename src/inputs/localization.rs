@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::error::Error;
+
+/// A localization file (`<language>.lang`) mapping label keys to the
+/// translated text of one language.
+///
+/// Every non-empty line that doesn't start with `#` has the form
+/// `label.title = "Main Menu"`.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct LocalizationFile {
+    pub language: String,
+    pub entries: BTreeMap<String, String>,
+}
+
+impl LocalizationFile {
+    pub fn try_parse(language: &str, file_contents: &str) -> anyhow::Result<Self> {
+        let mut output = Self {
+            language: language.to_string(),
+            entries: BTreeMap::new(),
+        };
+
+        for (num, line) in file_contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let ctxt = || format!("Failed parsing line {}", num + 1);
+
+            let (key, text) = line
+                .split_once('=')
+                .ok_or_else(|| {
+                    Error::ParseError(format!("Expected `key = \"text\"` but found `{line}`"))
+                })
+                .with_context(ctxt)?;
+
+            let key = key.trim();
+
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                Error::ParseError(format!("Invalid label key `{key}`"))
+                    .raise()
+                    .with_context(ctxt)?;
+            }
+
+            let text = parse_text(text.trim()).with_context(ctxt)?;
+
+            if output.entries.insert(key.to_string(), text).is_some() {
+                Error::ParseError(format!("Duplicate label key `{key}`"))
+                    .raise()
+                    .with_context(ctxt)?;
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+pub(super) fn parse_text(value: &str) -> anyhow::Result<String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| {
+            Error::ParseError(format!(
+                "Failed to parse `{value}` as a string: misses quotes"
+            ))
+        })?;
+
+    let mut output = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('"') => output.push('"'),
+            Some('\\') => output.push('\\'),
+            Some('n') => output.push('\n'),
+            other => Error::ParseError(format!(
+                "Failed to parse `{value}` as a string: invalid escape {other:?}"
+            ))
+            .raise()?,
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalizationFile;
+    use crate::error::Error;
+
+    #[test]
+    fn test_parse_localization_file() -> anyhow::Result<()> {
+        let contents = r#"
+# Main menu
+label.title = "Hauptmenü"
+label.quote   =   "Sag \"Hallo\""
+"#;
+
+        let file = LocalizationFile::try_parse("de", contents)?;
+
+        assert_eq!(file.language, "de");
+        assert_eq!(file.entries.len(), 2);
+        assert_eq!(file.entries["label.title"], "Hauptmenü");
+        assert_eq!(file.entries["label.quote"], "Sag \"Hallo\"");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_localization_file_errors() {
+        for contents in [
+            "label.title \"Title\"",
+            "label.title = Title",
+            "label.title = \"\\t\"",
+            "a = \"x\"\na = \"y\"",
+        ] {
+            let err = LocalizationFile::try_parse("en", contents).unwrap_err();
+            assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        }
+
+        let err = LocalizationFile::try_parse("en", "a = \"x\"\na = \"y\"").unwrap_err();
+        assert!(format!("{err:#}").contains("line 2"));
+    }
+}
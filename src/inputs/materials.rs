@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::error::Ewwow;
+
+/// Blend mode and material hints of sprites, so runtimes can batch draw calls
+/// by material, see
+/// [`crate::intermediates::texture_atlas::TextureAtlas::apply_materials`].
+///
+/// Every non-empty line that doesn't start with `#` has the form
+/// `<material> <sprite name>...`, e.g. `additive spark fire#glow`. The
+/// material names are up to the game, e.g. `additive`, `premultiplied`, `ui`
+/// or `world`. A material may span several lines, but every sprite has at
+/// most one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaterialsFile {
+    pub materials: BTreeMap<String, Vec<String>>,
+}
+
+impl MaterialsFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let mut output = Self::default();
+
+        for (num, line) in file_contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let ctxt = || format!("Failed parsing line {}", num + 1);
+
+            // The line isn't empty, so there's at least the material
+            let mut parts = line.split_whitespace();
+            let material = parts.next().unwrap_or_default();
+            let sprite_names: Vec<&str> = parts.collect();
+
+            if sprite_names.is_empty() {
+                Ewwow
+                    .raise()
+                    .with_context(|| {
+                        format!("Expected `<material> <sprite name>...` but found `{line}`")
+                    })
+                    .with_context(ctxt)?;
+            }
+
+            for sprite_name in sprite_names {
+                if let Some(existing) = output.material_of(sprite_name) {
+                    Ewwow
+                        .raise()
+                        .with_context(|| {
+                            format!("Sprite '{sprite_name}' already has the material '{existing}'")
+                        })
+                        .with_context(ctxt)?;
+                }
+
+                output
+                    .materials
+                    .entry(material.to_string())
+                    .or_default()
+                    .push(sprite_name.to_string());
+            }
+        }
+
+        Ok(output)
+    }
+
+    pub fn material_of(&self, sprite_name: &str) -> Option<&str> {
+        self.materials
+            .iter()
+            .find(|(_, sprite_names)| sprite_names.iter().any(|name| name == sprite_name))
+            .map(|(material, _)| material.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MaterialsFile;
+
+    #[test]
+    fn test_parse_materials_file() -> anyhow::Result<()> {
+        let contents = "
+# Particles are drawn in their own pass
+additive spark fire#glow
+ui       button button#pressed
+additive smoke
+";
+
+        let file = MaterialsFile::try_parse(contents)?;
+
+        assert_eq!(file.materials.len(), 2);
+        assert_eq!(file.materials["additive"], ["spark", "fire#glow", "smoke"]);
+        assert_eq!(file.material_of("button#pressed"), Some("ui"));
+        assert_eq!(file.material_of("hero"), None);
+
+        assert!(MaterialsFile::try_parse("additive").is_err());
+        assert!(MaterialsFile::try_parse("additive spark\nui spark").is_err());
+
+        Ok(())
+    }
+}
@@ -1 +1,21 @@
+pub mod adjustments;
+pub mod flips;
 pub mod fnt;
+pub mod icon_font;
+pub mod localization;
+pub mod materials;
+pub mod palettes;
+pub mod procedural;
+pub mod project;
+pub mod slices;
+pub mod sprite_references;
+pub mod unity_meta;
+pub mod user_data;
+pub mod workspace;
+
+/// The 1-based line of `file_contents` that `byte_offset` is on, for parse
+/// errors of formats whose parsers only report where values start.
+pub(crate) fn line_number(file_contents: &str, byte_offset: usize) -> usize {
+    let before = &file_contents.as_bytes()[..byte_offset.min(file_contents.len())];
+    before.iter().filter(|&&byte| byte == b'\n').count() + 1
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use toml::Spanned;
+
+use crate::{error::Error, intermediates::effects};
+
+use super::line_number;
+
+/// Recolored variants of sprites, e.g. team colors or enemy tiers, see
+/// [`crate::intermediates::texture_atlas::TextureAtlas::swap_palettes`].
+///
+/// ```toml
+/// [palettes.red]
+/// sprites = ["knight", "archer"]
+/// # Colors are `rrggbb`, the alpha of the pixels is kept
+/// colors = { "3050a0" = "a03030", "203070" = "702020" }
+/// # How far every channel may be off to still match, 0 by default
+/// tolerance = 8
+/// # Append the recolored frames to the sprite instead of adding `knight#red`
+/// frames = false
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PalettesFile {
+    /// In the order of their names.
+    pub palettes: Vec<Palette>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub sprites: Vec<String>,
+    /// Source and target color of every mapping.
+    pub colors: Vec<([u8; 3], [u8; 3])>,
+    pub tolerance: u8,
+    pub frames: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPalettesFile {
+    #[serde(default)]
+    palettes: BTreeMap<String, Spanned<RawPalette>>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPalette {
+    sprites: Vec<String>,
+    colors: BTreeMap<String, Spanned<String>>,
+    #[serde(default)]
+    tolerance: u8,
+    #[serde(default)]
+    frames: bool,
+}
+
+impl PalettesFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let raw: RawPalettesFile = toml::from_str(file_contents)
+            .map_err(|err| Error::ParseError(format!("Invalid palettes file: {err}")))?;
+
+        if raw.palettes.is_empty() {
+            Error::ParseError("The palettes file has no `[palettes.<name>]`".to_string())
+                .raise()?;
+        }
+
+        let mut output = Self::default();
+
+        for (name, palette) in raw.palettes {
+            let line = line_number(file_contents, palette.span().start);
+            let ctxt = || format!("Failed parsing palette '{name}' on line {line}");
+            let palette = palette.into_inner();
+
+            if palette.colors.is_empty() {
+                Error::ParseError("No colors to swap".to_string())
+                    .raise()
+                    .with_context(ctxt)?;
+            }
+
+            let colors = palette
+                .colors
+                .iter()
+                .map(|(from, to)| {
+                    let line = line_number(file_contents, to.span().start);
+                    let ctxt = || format!("Failed parsing line {line}");
+                    Ok((
+                        parse_rgb(from).with_context(ctxt)?,
+                        parse_rgb(to.get_ref()).with_context(ctxt)?,
+                    ))
+                })
+                .collect::<anyhow::Result<_>>()
+                .with_context(ctxt)?;
+
+            output.palettes.push(Palette {
+                name,
+                sprites: palette.sprites,
+                colors,
+                tolerance: palette.tolerance,
+                frames: palette.frames,
+            });
+        }
+
+        Ok(output)
+    }
+}
+
+fn parse_rgb(s: &str) -> anyhow::Result<[u8; 3]> {
+    if s.len() != 6 {
+        Error::ParseError(format!("Invalid color '{s}', expected rrggbb")).raise()?;
+    }
+
+    let [r, g, b, _] = effects::parse_hex_color(s)?;
+    Ok([r, g, b])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PalettesFile;
+    use crate::error::Error;
+
+    #[test]
+    fn test_parse_palettes_file() -> anyhow::Result<()> {
+        let file = PalettesFile::try_parse(
+            r#"
+            [palettes.red]
+            sprites = ["knight", "archer"]
+            colors = { "3050a0" = "a03030" }
+            tolerance = 8
+
+            [palettes.elite]
+            sprites = ["slime"]
+            colors = { "00ff00" = "ffd700", "008000" = "b8860b" }
+            frames = true
+            "#,
+        )?;
+
+        assert_eq!(file.palettes.len(), 2);
+
+        let elite = &file.palettes[0];
+        assert_eq!(elite.name, "elite");
+        assert_eq!(
+            elite.colors,
+            [
+                ([0x00, 0x80, 0x00], [0xb8, 0x86, 0x0b]),
+                ([0x00, 0xff, 0x00], [0xff, 0xd7, 0x00])
+            ]
+        );
+        assert_eq!(elite.tolerance, 0);
+        assert!(elite.frames);
+
+        let red = &file.palettes[1];
+        assert_eq!(red.sprites, ["knight", "archer"]);
+        assert_eq!(red.tolerance, 8);
+        assert!(!red.frames);
+
+        assert!(PalettesFile::try_parse("").is_err());
+        assert!(PalettesFile::try_parse("[palettes.red]\nsprites = []\ncolors = {}").is_err());
+        let invalid_color = "[palettes.red]\nsprites = []\ncolors = { \"3050a0ff\" = \"a03030\" }";
+        let err = PalettesFile::try_parse(invalid_color).unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        assert!(format!("{err:#}").contains("Failed parsing line 3"));
+
+        let err = PalettesFile::try_parse("[palettes.red]\nsprites = [").unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        assert!(format!("{err:#}").contains("line 2"));
+
+        Ok(())
+    }
+}
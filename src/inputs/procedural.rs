@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use image::{Rgba, RgbaImage};
+
+use crate::{error::Ewwow, intermediates::effects::parse_hex_color, math::ISize};
+
+use super::user_data::UserDataFile;
+
+/// Sprites generated at pack time instead of being drawn, e.g. the white
+/// pixel and gradient strips UI code needs in the same atlas as everything
+/// else.
+///
+/// Uses the section syntax of [`UserDataFile`], every `[sprite name]`
+/// section needs a `kind` and a `size`:
+///
+/// ```text
+/// [ui/white]
+/// kind = "solid"
+/// size = "1x1"
+/// color = "ffffff"
+///
+/// [ui/fade]
+/// kind = "gradient"
+/// size = "64x1"
+/// from = "ffffffff"
+/// to = "ffffff00"
+/// # `horizontal` (the default) or `vertical`
+/// direction = "horizontal"
+///
+/// [ui/button]
+/// kind = "rounded-rect"
+/// size = "24x12"
+/// radius = "4"
+/// color = "3366cc"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProceduralFile {
+    pub sprites: Vec<ProceduralSprite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProceduralSprite {
+    pub name: String,
+    pub size: ISize,
+    pub shape: ProceduralShape,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProceduralShape {
+    Solid {
+        color: [u8; 4],
+    },
+    Gradient {
+        from: [u8; 4],
+        to: [u8; 4],
+        vertical: bool,
+    },
+    /// Corners are anti-aliased, the rest is transparent.
+    RoundedRect {
+        radius: u32,
+        color: [u8; 4],
+    },
+}
+
+impl ProceduralFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let sections = UserDataFile::try_parse(file_contents)?;
+
+        let sprites = sections
+            .sprites
+            .iter()
+            .map(|(name, entries)| {
+                parse_sprite(name, entries)
+                    .with_context(|| format!("Invalid procedural sprite '{name}'"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { sprites })
+    }
+}
+
+fn parse_sprite(
+    name: &str,
+    entries: &BTreeMap<String, String>,
+) -> anyhow::Result<ProceduralSprite> {
+    let value = |key: &str| {
+        entries
+            .get(key)
+            .map(|value| value.as_str())
+            .ok_or(Ewwow)
+            .with_context(|| format!("Missing `{key}`"))
+    };
+    let color = |key: &str| value(key).and_then(parse_hex_color);
+
+    let size: ISize = value("size")?.parse()?;
+    let shape = match value("kind")? {
+        "solid" => ProceduralShape::Solid {
+            color: color("color")?,
+        },
+        "gradient" => ProceduralShape::Gradient {
+            from: color("from")?,
+            to: color("to")?,
+            vertical: match entries.get("direction").map(|d| d.as_str()) {
+                None | Some("horizontal") => false,
+                Some("vertical") => true,
+                Some(direction) => {
+                    Ewwow.raise().with_context(|| {
+                        format!("Invalid direction '{direction}', expected horizontal or vertical")
+                    })?;
+                    unreachable!()
+                }
+            },
+        },
+        "rounded-rect" => ProceduralShape::RoundedRect {
+            radius: value("radius")?
+                .parse()
+                .context("`radius` expects a number of pixels")?,
+            color: color("color")?,
+        },
+        kind => {
+            Ewwow.raise().with_context(|| {
+                format!("Invalid kind '{kind}', expected solid, gradient or rounded-rect")
+            })?;
+            unreachable!()
+        }
+    };
+
+    Ok(ProceduralSprite {
+        name: name.to_string(),
+        size,
+        shape,
+    })
+}
+
+impl ProceduralSprite {
+    pub fn render(&self) -> RgbaImage {
+        let (width, height) = (self.size.width as u32, self.size.height as u32);
+
+        match self.shape {
+            ProceduralShape::Solid { color } => RgbaImage::from_pixel(width, height, Rgba(color)),
+            ProceduralShape::Gradient { from, to, vertical } => {
+                let steps = match vertical {
+                    true => height,
+                    false => width,
+                };
+
+                RgbaImage::from_fn(width, height, |x, y| {
+                    let step = if vertical { y } else { x };
+                    let t = match steps {
+                        1 => 0.0,
+                        _ => step as f32 / (steps - 1) as f32,
+                    };
+
+                    Rgba(std::array::from_fn(|channel| {
+                        let (from, to) = (from[channel] as f32, to[channel] as f32);
+                        (from + (to - from) * t).round() as u8
+                    }))
+                })
+            }
+            ProceduralShape::RoundedRect { radius, color } => {
+                let radius = radius.min(width / 2).min(height / 2) as f32;
+
+                RgbaImage::from_fn(width, height, |x, y| {
+                    // The distance of the pixel center to the closest point
+                    // that is at least `radius` away from every edge
+                    let closest =
+                        |pos: u32, len: u32| (pos as f32 + 0.5).clamp(radius, len as f32 - radius);
+                    let dx = x as f32 + 0.5 - closest(x, width);
+                    let dy = y as f32 + 0.5 - closest(y, height);
+                    let distance = (dx * dx + dy * dy).sqrt();
+
+                    let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+                    let [r, g, b, a] = color;
+                    Rgba([r, g, b, (a as f32 * coverage).round() as u8])
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::{ProceduralFile, ProceduralShape};
+    use crate::math::ISize;
+
+    #[test]
+    fn test_procedural_sprites() -> anyhow::Result<()> {
+        let file = ProceduralFile::try_parse(
+            r#"
+            [white]
+            kind = "solid"
+            size = "1x1"
+            color = "ffffff"
+
+            [fade]
+            kind = "gradient"
+            size = "1x5"
+            from = "00000000"
+            to = "ff0000ff"
+            direction = "vertical"
+
+            [button]
+            kind = "rounded-rect"
+            size = "12x8"
+            radius = "3"
+            color = "3366cc"
+            "#,
+        )?;
+
+        // Sorted by name like user data sections
+        let names: Vec<_> = file.sprites.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["button", "fade", "white"]);
+        assert_eq!(file.sprites[2].size, ISize::new(1, 1));
+        assert_eq!(
+            file.sprites[2].shape,
+            ProceduralShape::Solid {
+                color: [255, 255, 255, 255]
+            }
+        );
+        assert_eq!(*file.sprites[2].render().get_pixel(0, 0), Rgba([255; 4]));
+
+        let fade = file.sprites[1].render();
+        assert_eq!(fade.dimensions(), (1, 5));
+        assert_eq!(*fade.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+        assert_eq!(*fade.get_pixel(0, 2), Rgba([128, 0, 0, 128]));
+        assert_eq!(*fade.get_pixel(0, 4), Rgba([255, 0, 0, 255]));
+
+        let button = file.sprites[0].render();
+        assert_eq!(button.get_pixel(0, 0)[3], 0);
+        assert!(button.get_pixel(0, 1)[3] < 255);
+        assert_eq!(*button.get_pixel(6, 0), Rgba([0x33, 0x66, 0xcc, 255]));
+        assert_eq!(*button.get_pixel(0, 4), Rgba([0x33, 0x66, 0xcc, 255]));
+
+        let invalid = [
+            "[a]\nkind = \"solid\"\nsize = \"1x1\"",
+            "[a]\nkind = \"circle\"\nsize = \"1x1\"",
+            "[a]\nkind = \"solid\"\nsize = \"0x1\"\ncolor = \"ffffff\"",
+            "[a]\nkind = \"gradient\"\nsize = \"4x1\"\nfrom = \"ffffff\"\nto = \"000000\"\ndirection = \"up\"",
+        ];
+        for contents in invalid {
+            assert!(ProceduralFile::try_parse(contents).is_err(), "{contents}");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::{error::Ewwow, math::IMargins};
+
+/// A `texture-packer.toml` describing the atlases of a project, so they can
+/// be built with one command instead of a shell script per atlas.
+///
+/// ```toml
+/// [atlases.ui]
+/// inputs = ["fonts/m5x7.fnt", "icons/close.png"]
+/// padding = { top = 1, bottom = 2 }
+/// spacing = 1
+/// out = "build/ui.png"
+/// meta = ["build/ui.json", "build/ui.rmp"]
+/// pixel_format = "rgba4444"
+/// # Any other command line options
+/// args = ["--white-pixel"]
+///
+/// # Only with `--target mobile`
+/// [atlases.ui.target.mobile]
+/// exclude = ["icons/close.png"]
+/// out = "build/mobile/ui.png"
+///
+/// # Only with `--feature halloween_event`
+/// [atlases.ui.feature.halloween_event]
+/// inputs = ["icons/pumpkin.png"]
+/// ```
+///
+/// Paths are relative to the project file.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectFile {
+    #[serde(default)]
+    pub atlases: BTreeMap<String, AtlasBuild>,
+}
+
+/// One atlas of a [`ProjectFile`], every field left out keeps the default of
+/// its command line option.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AtlasBuild {
+    /// Fonts (`.fnt`) and single frame sprites (`.png`), like `--input`.
+    pub inputs: Vec<String>,
+    pub padding: Option<Padding>,
+    pub spacing: Option<i32>,
+    /// The atlas texture, like `--out`.
+    pub out: Option<String>,
+    /// One file per metadata format, `.json` or `.rmp`, like `--meta`.
+    pub meta: Vec<String>,
+    /// Like `--pixel-format`.
+    pub pixel_format: Option<String>,
+    /// Passed on as they are, for everything without a field of its own.
+    pub args: Vec<String>,
+    /// Changes when building for a platform, see [`Self::resolve`].
+    pub target: BTreeMap<String, Conditional>,
+    /// Changes when building with a feature flag, see [`Self::resolve`].
+    pub feature: BTreeMap<String, Conditional>,
+}
+
+/// A `target` or `feature` section of an [`AtlasBuild`].
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Conditional {
+    /// Added to the inputs.
+    pub inputs: Vec<String>,
+    /// Removed from the inputs.
+    pub exclude: Vec<String>,
+    pub padding: Option<Padding>,
+    pub spacing: Option<i32>,
+    pub out: Option<String>,
+    /// Replaces the metadata files, unless empty.
+    pub meta: Vec<String>,
+    pub pixel_format: Option<String>,
+    /// Added to the other options.
+    pub args: Vec<String>,
+}
+
+/// Either the same margin on every side, or a table of the sides that have
+/// one.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Padding {
+    Uniform(i32),
+    Sides(IMargins),
+}
+
+impl ProjectFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let project: Self = toml::from_str(file_contents).context("Invalid project file")?;
+
+        if project.atlases.is_empty() {
+            Ewwow
+                .raise()
+                .context("The project file has no `[atlases.<name>]`")?;
+        }
+
+        Ok(project)
+    }
+
+    /// Whether any atlas has a section for the platform `target`.
+    pub fn has_target(&self, target: &str) -> bool {
+        self.atlases
+            .values()
+            .any(|build| build.target.contains_key(target))
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.atlases
+            .values()
+            .any(|build| build.feature.contains_key(feature))
+    }
+}
+
+impl AtlasBuild {
+    /// The build with the section of `target` and then those of `features`
+    /// (in their order) applied. Sections the atlas doesn't have are
+    /// skipped.
+    pub fn resolve(&self, target: Option<&str>, features: &[&str]) -> anyhow::Result<Self> {
+        let mut build = Self {
+            target: BTreeMap::new(),
+            feature: BTreeMap::new(),
+            ..self.clone()
+        };
+
+        let target = target.and_then(|name| Some(("target", name, self.target.get(name)?)));
+        let features = features
+            .iter()
+            .filter_map(|&name| Some(("feature", name, self.feature.get(name)?)));
+
+        for (kind, name, section) in target.into_iter().chain(features) {
+            for exclude in section.exclude.iter() {
+                let Some(index) = build.inputs.iter().position(|input| input == exclude) else {
+                    Ewwow.raise().with_context(|| {
+                        format!("Can't exclude '{exclude}' for {kind} '{name}', it isn't an input")
+                    })?;
+
+                    unreachable!()
+                };
+                build.inputs.remove(index);
+            }
+            build.inputs.extend(section.inputs.iter().cloned());
+
+            build.padding = section.padding.or(build.padding);
+            build.spacing = section.spacing.or(build.spacing);
+            build.out = section.out.clone().or(build.out);
+            if !section.meta.is_empty() {
+                build.meta = section.meta.clone();
+            }
+            build.pixel_format = section.pixel_format.clone().or(build.pixel_format);
+            build.args.extend(section.args.iter().cloned());
+        }
+
+        Ok(build)
+    }
+
+    /// The command line that builds this atlas, run from the directory of the
+    /// project file. `name` keeps the list of written files apart from the
+    /// other atlases of the project.
+    pub fn to_args(&self, name: &str) -> Vec<String> {
+        let mut args = vec![];
+        let mut push = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+
+        for input in self.inputs.iter() {
+            push("--input", input.clone());
+        }
+        if let Some(padding) = self.padding {
+            let padding = match padding {
+                Padding::Uniform(px) => IMargins::uniform(px),
+                Padding::Sides(sides) => sides,
+            };
+            push(
+                "--padding",
+                format!(
+                    "{},{},{},{}",
+                    padding.top, padding.bottom, padding.left, padding.right
+                ),
+            );
+        }
+        if let Some(spacing) = self.spacing {
+            push("--spacing", spacing.to_string());
+        }
+        if let Some(out) = &self.out {
+            push("--out", out.clone());
+        }
+        for meta in self.meta.iter() {
+            push("--meta", meta.clone());
+        }
+        if let Some(pixel_format) = &self.pixel_format {
+            push("--pixel-format", pixel_format.clone());
+        }
+        push("--pack-manifest", format!("{name}.pack-manifest.json"));
+
+        args.extend(self.args.iter().cloned());
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AtlasBuild, Conditional, Padding, ProjectFile};
+    use crate::math::IMargins;
+
+    #[test]
+    fn test_project_file() -> anyhow::Result<()> {
+        let project = ProjectFile::try_parse(
+            r#"
+            [atlases.ui]
+            inputs = ["fonts/m5x7.fnt", "icons/close.png"]
+            padding = { top = 1, right = 2 }
+            out = "build/ui.png"
+            meta = ["build/ui.json", "build/ui.rmp"]
+            pixel_format = "rgba4444"
+            args = ["--white-pixel"]
+
+            [atlases.game]
+            padding = 2
+            spacing = 1
+            "#,
+        )?;
+
+        assert_eq!(project.atlases.keys().collect::<Vec<_>>(), ["game", "ui"]);
+
+        let ui = &project.atlases["ui"];
+        assert_eq!(ui.padding, Some(Padding::Sides(IMargins::new(1, 0, 0, 2))));
+        assert_eq!(
+            ui.to_args("ui"),
+            [
+                "--input",
+                "fonts/m5x7.fnt",
+                "--input",
+                "icons/close.png",
+                "--padding",
+                "1,0,0,2",
+                "--out",
+                "build/ui.png",
+                "--meta",
+                "build/ui.json",
+                "--meta",
+                "build/ui.rmp",
+                "--pixel-format",
+                "rgba4444",
+                "--pack-manifest",
+                "ui.pack-manifest.json",
+                "--white-pixel",
+            ]
+        );
+
+        let game = &project.atlases["game"];
+        assert_eq!(
+            game,
+            &AtlasBuild {
+                padding: Some(Padding::Uniform(2)),
+                spacing: Some(1),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            &game.to_args("game")[..4],
+            ["--padding", "2,2,2,2", "--spacing", "1"]
+        );
+
+        assert!(ProjectFile::try_parse("").is_err());
+        assert!(ProjectFile::try_parse("[atlases.ui.target.mobile]\ninput = []").is_err());
+        assert!(ProjectFile::try_parse("[atlases.ui]\nimputs = []").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_conditional_sections() -> anyhow::Result<()> {
+        let project = ProjectFile::try_parse(
+            r#"
+            [atlases.ui]
+            inputs = ["close.png", "hover.png"]
+            padding = 1
+            out = "build/ui.png"
+            meta = ["build/ui.json"]
+
+            [atlases.ui.target.mobile]
+            exclude = ["hover.png"]
+            padding = 2
+            out = "build/mobile/ui.png"
+            pixel_format = "rgba4444"
+
+            [atlases.ui.feature.halloween_event]
+            inputs = ["pumpkin.png"]
+            meta = ["build/halloween.json"]
+            args = ["--white-pixel"]
+            "#,
+        )?;
+        let ui = &project.atlases["ui"];
+        assert!(project.has_target("mobile") && !project.has_target("desktop"));
+        assert!(project.has_feature("halloween_event"));
+
+        assert_eq!(
+            ui.target["mobile"],
+            Conditional {
+                exclude: vec!["hover.png".to_string()],
+                padding: Some(Padding::Uniform(2)),
+                out: Some("build/mobile/ui.png".to_string()),
+                pixel_format: Some("rgba4444".to_string()),
+                ..Default::default()
+            }
+        );
+
+        // Nothing selected, or only sections the atlas doesn't have
+        let desktop = ui.resolve(Some("desktop"), &["christmas"])?;
+        assert_eq!(desktop.inputs, ["close.png", "hover.png"]);
+        assert!(desktop.target.is_empty());
+        assert_eq!(ui.resolve(None, &[])?.to_args("ui"), desktop.to_args("ui"));
+
+        let mobile = ui.resolve(Some("mobile"), &["halloween_event"])?;
+        assert_eq!(mobile.inputs, ["close.png", "pumpkin.png"]);
+        assert_eq!(mobile.padding, Some(Padding::Uniform(2)));
+        assert_eq!(mobile.out.as_deref(), Some("build/mobile/ui.png"));
+        assert_eq!(mobile.meta, ["build/halloween.json"]);
+        assert_eq!(mobile.pixel_format.as_deref(), Some("rgba4444"));
+        assert_eq!(mobile.args, ["--white-pixel"]);
+
+        let mut broken = ui.clone();
+        broken.inputs.clear();
+        assert!(broken.resolve(Some("mobile"), &[]).is_err());
+
+        Ok(())
+    }
+}
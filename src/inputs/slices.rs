@@ -0,0 +1,175 @@
+use anyhow::Context;
+use glam::Vec2;
+use serde_json::value::RawValue;
+
+use crate::{
+    error::Error,
+    math::{IMargins, IRect},
+};
+
+use super::line_number;
+
+/// The regions of an image as art tools export them, read from a side-car
+/// file next to it, e.g. `hero.slices.json` for `hero.png`, and turned into
+/// one sprite per slice.
+///
+/// ```json
+/// {
+///   "slices": [
+///     { "name": "hero_idle", "rect": { "x": 0, "y": 0, "w": 16, "h": 24 } },
+///     {
+///       "name": "panel",
+///       "rect": { "x": 16, "y": 0, "w": 32, "h": 32 },
+///       "pivot": [0.5, 1.0],
+///       "border": { "top": 4, "bottom": 4, "left": 6, "right": 6 }
+///     }
+///   ]
+/// }
+/// ```
+///
+/// Rects are in pixels with y pointing down from the top edge of the image.
+/// The pivot is relative to the size of the rect, (0, 0) being the top left
+/// corner, and the 9-slice `border` is in pixels. Both are optional, slices
+/// without either are plain sprites.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlicesFile {
+    pub slices: Vec<Slice>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Slice {
+    pub name: String,
+    pub rect: IRect,
+    #[serde(default)]
+    pub pivot: Option<Vec2>,
+    #[serde(default)]
+    pub border: Option<IMargins>,
+}
+
+/// The slices as they are written, to point errors at their lines.
+#[derive(serde::Deserialize)]
+struct RawSlicesFile<'a> {
+    #[serde(borrow)]
+    slices: Vec<&'a RawValue>,
+}
+
+impl SlicesFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let file: Self = serde_json::from_str(file_contents)
+            .map_err(|err| Error::ParseError(format!("Invalid slices file: {err}")))?;
+        let raw: RawSlicesFile =
+            serde_json::from_str(file_contents).context("Invalid slices file")?;
+
+        if file.slices.is_empty() {
+            Error::ParseError("The slices file has no `slices`".to_string()).raise()?;
+        }
+
+        for (index, (slice, raw)) in file.slices.iter().zip(raw.slices).enumerate() {
+            let offset = raw.get().as_ptr() as usize - file_contents.as_ptr() as usize;
+            let ctxt = || format!("Failed parsing line {}", line_number(file_contents, offset));
+
+            if file.slices[..index]
+                .iter()
+                .any(|other| other.name == slice.name)
+            {
+                Error::ParseError(format!("Slice '{}' is defined twice", slice.name))
+                    .raise()
+                    .with_context(ctxt)?;
+            }
+
+            if slice.rect.is_empty() {
+                Error::ParseError(format!("Slice '{}' has an empty rect", slice.name))
+                    .raise()
+                    .with_context(ctxt)?;
+            }
+
+            let border = slice.border.unwrap_or_default();
+            let borders_fit = [border.top, border.bottom, border.left, border.right]
+                .iter()
+                .all(|&side| side >= 0)
+                && border.hori() <= slice.rect.width()
+                && border.vert() <= slice.rect.height();
+            if !borders_fit {
+                Error::ParseError(format!(
+                    "The border of slice '{}' doesn't fit its rect",
+                    slice.name
+                ))
+                .raise()
+                .with_context(ctxt)?;
+            }
+        }
+
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::SlicesFile;
+    use crate::{
+        error::Error,
+        math::{IMargins, IRect},
+    };
+
+    #[test]
+    fn test_parse_slices_file() -> anyhow::Result<()> {
+        let file = SlicesFile::try_parse(
+            r#"{
+                "slices": [
+                    { "name": "hero_idle", "rect": { "x": 0, "y": 0, "w": 16, "h": 24 } },
+                    {
+                        "name": "panel",
+                        "rect": { "x": 16, "y": 0, "w": 32, "h": 32 },
+                        "pivot": [0.5, 1.0],
+                        "border": { "top": 4, "bottom": 4, "left": 6 }
+                    }
+                ]
+            }"#,
+        )?;
+
+        assert_eq!(file.slices.len(), 2);
+        assert_eq!(file.slices[0].name, "hero_idle");
+        assert_eq!(file.slices[0].rect, IRect::new(0, 0, 16, 24));
+        assert_eq!(file.slices[0].pivot, None);
+        assert_eq!(file.slices[0].border, None);
+        assert_eq!(file.slices[1].pivot, Some(Vec2::new(0.5, 1.0)));
+        assert_eq!(file.slices[1].border, Some(IMargins::new(4, 4, 6, 0)));
+
+        let rect = r#""rect": { "x": 0, "y": 0, "w": 4, "h": 4 }"#;
+        assert!(SlicesFile::try_parse(r#"{ "slices": [] }"#).is_err());
+        assert!(SlicesFile::try_parse(&format!(
+            r#"{{ "slices": [{{ "name": "a", {rect} }}, {{ "name": "a", {rect} }}] }}"#
+        ))
+        .is_err());
+        assert!(SlicesFile::try_parse(&format!(
+            r#"{{ "slices": [{{ "name": "a", {rect}, "border": {{ "left": 3, "right": 2 }} }}] }}"#
+        ))
+        .is_err());
+        assert!(SlicesFile::try_parse(
+            r#"{ "slices": [{ "name": "a", "rect": { "x": 0, "y": 0, "w": 0, "h": 4 } }] }"#
+        )
+        .is_err());
+        assert!(SlicesFile::try_parse(&format!(
+            r#"{{ "slices": [{{ "name": "a", {rect}, "tags": [] }}] }}"#
+        ))
+        .is_err());
+
+        let err = SlicesFile::try_parse(&format!(
+            "{{\n  \"slices\": [\n    {{ \"name\": \"a\", {rect} }},\n    {{ \"name\": \"a\", {rect} }}\n  ]\n}}"
+        ))
+        .unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        assert!(format!("{err:#}").contains("Failed parsing line 4"));
+
+        let err =
+            SlicesFile::try_parse("{\n  \"slices\": [\n    { \"name\": 3 }\n  ]\n}").unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        assert!(format!("{err:#}").contains("line 3"));
+
+        Ok(())
+    }
+}
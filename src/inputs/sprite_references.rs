@@ -0,0 +1,50 @@
+use std::collections::HashSet;
+
+/// The sprite names the game actually references, e.g. from a codegen report
+/// or plain `grep` output. Every word-like token in the file counts as a
+/// reference, so the exact format doesn't matter.
+#[derive(Debug, Clone, Default)]
+pub struct SpriteReferences {
+    pub names: HashSet<String>,
+}
+
+impl SpriteReferences {
+    pub fn parse(file_contents: &str) -> Self {
+        let is_name_char =
+            |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '#' | '/' | '@');
+
+        let names = file_contents
+            .split(|c: char| !is_name_char(c))
+            .map(|token| token.trim_matches('.'))
+            .filter(|token| !token.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        Self { names }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpriteReferences;
+
+    #[test]
+    fn test_parse_references() {
+        let refs = SpriteReferences::parse(
+            "hero_idle\n\
+             src/ui.rs:12:    draw_sprite(\"ui/button#pressed\");\n\
+             src/menu.rs:3:    label(\"label.title\").\n",
+        );
+
+        assert!(refs.contains("hero_idle"));
+        assert!(refs.contains("ui/button#pressed"));
+        assert!(refs.contains("label.title"));
+        assert!(refs.contains("draw_sprite"));
+        assert!(!refs.contains("hero"));
+        assert!(!refs.contains("label.title."));
+    }
+}
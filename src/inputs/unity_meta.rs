@@ -0,0 +1,479 @@
+use anyhow::Context;
+use glam::Vec2;
+
+use crate::{
+    error::Error,
+    math::{IMargins, IRect},
+};
+
+/// The sprite slicing of a Unity texture `.meta` file, e.g. `hero.png.meta`
+/// next to `hero.png`, so projects moving away from Unity keep their
+/// slices.
+///
+/// Only the parts of the YAML the texture importer writes are understood.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitySpriteSheet {
+    pub sprites: Vec<UnitySprite>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitySprite {
+    pub name: String,
+    /// In Unity's coordinates, where y points up from the bottom edge of the
+    /// texture.
+    pub rect: IRect,
+    /// Relative to the size of the rect, (0, 0) is the bottom left corner.
+    pub pivot: Vec2,
+    /// The 9-slice borders in pixels.
+    pub border: IMargins,
+}
+
+impl UnitySpriteSheet {
+    /// Reads the slices of a multiple sprite texture, or the single sprite
+    /// covering all of a `texture_width` x `texture_height` texture named
+    /// `texture_name` otherwise.
+    pub fn try_parse(
+        file_contents: &str,
+        texture_name: &str,
+        texture_width: i32,
+        texture_height: i32,
+    ) -> anyhow::Result<Self> {
+        let root = Yaml::parse(file_contents);
+        let importer = root.get("TextureImporter").ok_or_else(|| {
+            Error::ParseError(
+                "Expected a `TextureImporter`, is this the .meta file of a texture?".to_string(),
+            )
+        })?;
+
+        let sheet_sprites = importer
+            .get("spriteSheet")
+            .and_then(|sheet| sheet.get("sprites"))
+            .map_or(&[][..], |sprites| sprites.items());
+
+        // `spriteMode: 2` is a sheet of multiple sprites
+        if importer.scalar("spriteMode") == Some("2") {
+            let sprites = sheet_sprites
+                .iter()
+                .enumerate()
+                .map(|(index, sprite)| {
+                    parse_sprite(sprite).with_context(|| {
+                        format!(
+                            "Invalid sprite #{index} on line {}",
+                            sprite.first_line().unwrap_or_default()
+                        )
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            return Ok(Self { sprites });
+        }
+
+        Ok(Self {
+            sprites: vec![UnitySprite {
+                name: texture_name.to_string(),
+                rect: IRect::new(0, 0, texture_width, texture_height),
+                pivot: alignment_pivot(importer, "alignment", "spritePivot")?,
+                border: parse_border(importer.scalar("spriteBorder"))
+                    .with_context(|| line_context(importer, "spriteBorder"))?,
+            }],
+        })
+    }
+}
+
+impl UnitySprite {
+    /// The rect with y pointing down from the top edge of a texture that is
+    /// `texture_height` pixels high.
+    pub fn top_down_rect(&self, texture_height: i32) -> IRect {
+        IRect::new(
+            self.rect.min.x,
+            texture_height - self.rect.max.y,
+            self.rect.width(),
+            self.rect.height(),
+        )
+    }
+
+    /// The pivot with (0, 0) in the top left corner.
+    pub fn top_down_pivot(&self) -> Vec2 {
+        Vec2::new(self.pivot.x, 1.0 - self.pivot.y)
+    }
+}
+
+fn parse_sprite(sprite: &Yaml) -> anyhow::Result<UnitySprite> {
+    let name = sprite
+        .scalar("name")
+        .ok_or_else(|| Error::ParseError("Missing `name`".to_string()))?;
+
+    let rect = sprite
+        .get("rect")
+        .ok_or_else(|| Error::ParseError(format!("Sprite '{name}' has no `rect`")))?;
+    let number = |key: &str| -> anyhow::Result<i32> {
+        let value = rect.scalar(key).unwrap_or("0");
+        // Unity writes whole pixels, but as floats sometimes
+        let value: f32 = value
+            .parse()
+            .map_err(|_| {
+                Error::ParseError(format!("Invalid `rect.{key}` '{value}' of sprite '{name}'"))
+            })
+            .with_context(|| line_context(rect, key))?;
+        Ok(value.round() as i32)
+    };
+
+    Ok(UnitySprite {
+        name: name.to_string(),
+        rect: IRect::new(
+            number("x")?,
+            number("y")?,
+            number("width")?,
+            number("height")?,
+        ),
+        pivot: alignment_pivot(sprite, "alignment", "pivot")
+            .with_context(|| format!("Invalid pivot of sprite '{name}'"))?,
+        border: parse_border(sprite.scalar("border"))
+            .with_context(|| line_context(sprite, "border"))
+            .with_context(|| format!("Invalid border of sprite '{name}'"))?,
+    })
+}
+
+/// The pivot of Unity's `SpriteAlignment`, where 9 means the custom pivot.
+fn alignment_pivot(node: &Yaml, alignment_key: &str, pivot_key: &str) -> anyhow::Result<Vec2> {
+    let pivot = match node.scalar(alignment_key).unwrap_or("0") {
+        "0" => Vec2::new(0.5, 0.5),
+        "1" => Vec2::new(0.0, 1.0),
+        "2" => Vec2::new(0.5, 1.0),
+        "3" => Vec2::new(1.0, 1.0),
+        "4" => Vec2::new(0.0, 0.5),
+        "5" => Vec2::new(1.0, 0.5),
+        "6" => Vec2::new(0.0, 0.0),
+        "7" => Vec2::new(0.5, 0.0),
+        "8" => Vec2::new(1.0, 0.0),
+        "9" => {
+            let pivot = node.scalar(pivot_key).unwrap_or("{x: 0.5, y: 0.5}");
+            let [x, y] = parse_flow_numbers(pivot, ["x", "y"])
+                .with_context(|| line_context(node, pivot_key))?;
+            Vec2::new(x, y)
+        }
+        alignment => {
+            Error::ParseError(format!("Unknown sprite alignment '{alignment}'"))
+                .raise()
+                .with_context(|| line_context(node, alignment_key))?;
+            unreachable!()
+        }
+    };
+
+    Ok(pivot)
+}
+
+/// Parses `{x: <left>, y: <bottom>, z: <right>, w: <top>}`.
+fn parse_border(border: Option<&str>) -> anyhow::Result<IMargins> {
+    let Some(border) = border else {
+        return Ok(IMargins::default());
+    };
+
+    let [left, bottom, right, top] = parse_flow_numbers(border, ["x", "y", "z", "w"])?;
+
+    Ok(IMargins::new(
+        top.round() as i32,
+        bottom.round() as i32,
+        left.round() as i32,
+        right.round() as i32,
+    ))
+}
+
+/// Parses the numbers of a flow mapping like `{x: 0.5, y: 1}`, missing keys
+/// are 0.
+fn parse_flow_numbers<const N: usize>(s: &str, keys: [&str; N]) -> anyhow::Result<[f32; N]> {
+    let inner = s
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or_else(|| Error::ParseError(format!("Expected `{{key: value, ...}}`, found '{s}'")))?;
+
+    let mut values = [0.0; N];
+
+    for entry in inner.split(',').map(|entry| entry.trim()) {
+        if entry.is_empty() {
+            continue;
+        }
+
+        let (key, value) = entry
+            .split_once(':')
+            .ok_or_else(|| Error::ParseError(format!("Expected `key: value`, found '{entry}'")))?;
+
+        if let Some(index) = keys.iter().position(|k| *k == key.trim()) {
+            values[index] = value
+                .trim()
+                .parse()
+                .map_err(|_| Error::ParseError(format!("Invalid number '{}'", value.trim())))?;
+        }
+    }
+
+    Ok(values)
+}
+
+/// Points errors in the value of `key` at its line.
+fn line_context(node: &Yaml, key: &str) -> String {
+    match node.line(key) {
+        Some(line) => format!("Failed parsing line {line}"),
+        None => format!("Failed parsing `{key}`"),
+    }
+}
+
+/// The block style YAML Unity writes: nested mappings and sequences, where
+/// flow collections like `{x: 1, y: 2}` and everything else are kept as
+/// scalars. Map entries know the line of their key.
+#[derive(Debug, Clone, PartialEq)]
+enum Yaml {
+    Map(Vec<(String, usize, Yaml)>),
+    Seq(Vec<Yaml>),
+    Scalar(String),
+}
+
+struct Line<'a> {
+    num: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+impl Yaml {
+    fn parse(s: &str) -> Self {
+        let mut lines: Vec<Line> = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                let trimmed = line.trim();
+                !trimmed.is_empty()
+                    && !trimmed.starts_with('#')
+                    && !trimmed.starts_with('%')
+                    && !trimmed.starts_with("---")
+            })
+            .map(|(index, line)| Line {
+                num: index + 1,
+                indent: line.len() - line.trim_start().len(),
+                text: line.trim(),
+            })
+            .collect();
+
+        let mut pos = 0;
+        let indent = lines.first().map_or(0, |line| line.indent);
+
+        Self::parse_block(&mut lines, &mut pos, indent)
+    }
+
+    fn parse_block(lines: &mut [Line], pos: &mut usize, indent: usize) -> Self {
+        if lines.get(*pos).is_some_and(|line| is_seq_item(line.text)) {
+            let mut items = vec![];
+
+            while let Some(line) = lines.get_mut(*pos) {
+                if line.indent != indent || !is_seq_item(line.text) {
+                    break;
+                }
+
+                // Parse the rest of `- key: value` as if it were on a line
+                // of its own, indented like the following keys of the item
+                let rest = line.text[1..].trim_start();
+                if rest.is_empty() {
+                    *pos += 1;
+                    match lines.get(*pos) {
+                        Some(next) if next.indent > indent => {
+                            let next_indent = next.indent;
+                            items.push(Self::parse_block(lines, pos, next_indent));
+                        }
+                        _ => items.push(Yaml::Scalar(String::new())),
+                    }
+                } else {
+                    let item_indent = indent + (line.text.len() - rest.len());
+                    *line = Line {
+                        num: line.num,
+                        indent: item_indent,
+                        text: rest,
+                    };
+                    items.push(Self::parse_block(lines, pos, item_indent));
+                }
+            }
+
+            return Yaml::Seq(items);
+        }
+
+        let mut entries = vec![];
+
+        while let Some(line) = lines.get(*pos) {
+            if line.indent < indent || (line.indent == indent && is_seq_item(line.text)) {
+                break;
+            }
+
+            // Continuation lines of multi-line scalars aren't supported
+            if line.indent > indent {
+                *pos += 1;
+                continue;
+            }
+
+            let (key, value) = match line.text.split_once(": ") {
+                Some((key, value)) => (key, value.trim()),
+                None => (line.text.trim_end_matches(':'), ""),
+            };
+            let num = line.num;
+            *pos += 1;
+
+            let value = match lines.get(*pos) {
+                _ if !value.is_empty() => Yaml::Scalar(value.to_string()),
+                // Unity writes the items of a sequence at the indent of its key
+                Some(next)
+                    if next.indent > indent
+                        || (next.indent == indent && is_seq_item(next.text)) =>
+                {
+                    let next_indent = next.indent;
+                    Self::parse_block(lines, pos, next_indent)
+                }
+                _ => Yaml::Scalar(String::new()),
+            };
+
+            entries.push((key.to_string(), num, value));
+        }
+
+        Yaml::Map(entries)
+    }
+
+    fn get(&self, key: &str) -> Option<&Yaml> {
+        match self {
+            Yaml::Map(entries) => entries.iter().find(|(k, ..)| k == key).map(|(.., v)| v),
+            _ => None,
+        }
+    }
+
+    fn line(&self, key: &str) -> Option<usize> {
+        match self {
+            Yaml::Map(entries) => entries
+                .iter()
+                .find(|(k, ..)| k == key)
+                .map(|&(_, line, _)| line),
+            _ => None,
+        }
+    }
+
+    fn first_line(&self) -> Option<usize> {
+        match self {
+            Yaml::Map(entries) => entries.first().map(|&(_, line, _)| line),
+            _ => None,
+        }
+    }
+
+    fn scalar(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            Yaml::Scalar(value) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    fn items(&self) -> &[Yaml] {
+        match self {
+            Yaml::Seq(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+fn is_seq_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::UnitySpriteSheet;
+    use crate::{
+        error::Error,
+        math::{IMargins, IRect},
+    };
+
+    const SHEET_META: &str = "\
+fileFormatVersion: 2
+guid: 3f1c2e0d9b5a4c7e8f6a1b2c3d4e5f60
+TextureImporter:
+  internalIDToNameTable:
+  - first:
+      213: 21300000
+    second: hero_0
+  serializedVersion: 12
+  spriteMode: 2
+  alignment: 0
+  spritePivot: {x: 0.5, y: 0.5}
+  spriteBorder: {x: 0, y: 0, z: 0, w: 0}
+  spriteSheet:
+    serializedVersion: 2
+    sprites:
+    - serializedVersion: 2
+      name: hero_0
+      rect:
+        serializedVersion: 2
+        x: 0
+        y: 48
+        width: 16
+        height: 16
+      alignment: 7
+      pivot: {x: 0.5, y: 0}
+      border: {x: 0, y: 0, z: 0, w: 0}
+      outline:
+      - - {x: -8, y: 8}
+        - {x: 8, y: 8}
+      physicsShape: []
+      tessellationDetail: 0
+    - serializedVersion: 2
+      name: panel
+      rect:
+        serializedVersion: 2
+        x: 16
+        y: 32
+        width: 32
+        height: 24
+      alignment: 9
+      pivot: {x: 0.25, y: 0.75}
+      border: {x: 4, y: 3, z: 5, w: 6}
+      outline: []
+    outline: []
+  spritePackingTag:
+  userData:
+";
+
+    #[test]
+    fn test_unity_sprite_sheet() -> anyhow::Result<()> {
+        let sheet = UnitySpriteSheet::try_parse(SHEET_META, "hero", 64, 64)?;
+        assert_eq!(sheet.sprites.len(), 2);
+
+        let hero = &sheet.sprites[0];
+        assert_eq!(hero.name, "hero_0");
+        assert_eq!(hero.rect, IRect::new(0, 48, 16, 16));
+        assert_eq!(hero.top_down_rect(64), IRect::new(0, 0, 16, 16));
+        // Bottom center
+        assert_eq!(hero.top_down_pivot(), Vec2::new(0.5, 1.0));
+
+        let panel = &sheet.sprites[1];
+        assert_eq!(panel.top_down_rect(64), IRect::new(16, 8, 32, 24));
+        assert_eq!(panel.pivot, Vec2::new(0.25, 0.75));
+        assert_eq!(panel.border, IMargins::new(6, 3, 4, 5));
+
+        // A single sprite covers the whole texture
+        let single = SHEET_META
+            .replace("spriteMode: 2", "spriteMode: 1")
+            .replace("  alignment: 0", "  alignment: 9")
+            .replace("spritePivot: {x: 0.5, y: 0.5}", "spritePivot: {x: 0, y: 1}")
+            .replace(
+                "spriteBorder: {x: 0, y: 0, z: 0, w: 0}",
+                "spriteBorder: {x: 2, y: 2, z: 2, w: 2}",
+            );
+        let sheet = UnitySpriteSheet::try_parse(&single, "hero", 64, 32)?;
+        assert_eq!(sheet.sprites.len(), 1);
+        assert_eq!(sheet.sprites[0].name, "hero");
+        assert_eq!(sheet.sprites[0].rect, IRect::new(0, 0, 64, 32));
+        assert_eq!(sheet.sprites[0].top_down_pivot(), Vec2::new(0.0, 0.0));
+        assert_eq!(sheet.sprites[0].border, IMargins::uniform(2));
+
+        assert!(UnitySpriteSheet::try_parse("fileFormatVersion: 2\n", "hero", 1, 1).is_err());
+        let broken = SHEET_META.replace("x: 16", "x: sixteen");
+        let err = UnitySpriteSheet::try_parse(&broken, "hero", 64, 64).unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        assert!(format!("{err:#}").contains("Failed parsing line 36"));
+
+        Ok(())
+    }
+}
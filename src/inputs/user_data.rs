@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::error::Error;
+
+use super::localization::parse_text;
+
+/// Game specific attributes of sprites, which are passed through verbatim to
+/// the sprite metadata.
+///
+/// A `[sprite name]` line starts the section of a sprite, every following
+/// non-empty line that doesn't start with `#` has the form
+/// `material = "metal"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserDataFile {
+    pub sprites: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl UserDataFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let mut output = Self::default();
+        let mut current: Option<String> = None;
+
+        for (num, line) in file_contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let ctxt = || format!("Failed parsing line {}", num + 1);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+
+                if name.is_empty() || output.sprites.contains_key(name) {
+                    Error::ParseError(format!("Invalid or duplicate sprite section `{line}`"))
+                        .raise()
+                        .with_context(ctxt)?;
+                }
+
+                output.sprites.insert(name.to_string(), BTreeMap::new());
+                current = Some(name.to_string());
+                continue;
+            }
+
+            let Some(sprite) = current.as_ref() else {
+                Error::ParseError(format!("Expected a `[sprite name]` line before `{line}`"))
+                    .raise()
+                    .with_context(ctxt)?;
+
+                unreachable!()
+            };
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| {
+                    Error::ParseError(format!("Expected `key = \"value\"` but found `{line}`"))
+                })
+                .with_context(ctxt)?;
+
+            let key = key.trim();
+
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                Error::ParseError(format!("Invalid user data key `{key}`"))
+                    .raise()
+                    .with_context(ctxt)?;
+            }
+
+            let value = parse_text(value.trim()).with_context(ctxt)?;
+
+            let entries = output.sprites.get_mut(sprite).unwrap();
+            if entries.insert(key.to_string(), value).is_some() {
+                Error::ParseError(format!("Duplicate user data key `{key}` of `{sprite}`"))
+                    .raise()
+                    .with_context(ctxt)?;
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserDataFile;
+    use crate::error::Error;
+
+    #[test]
+    fn test_parse_user_data_file() -> anyhow::Result<()> {
+        let contents = r#"
+# Hit boxes
+[hero]
+damage_zone = "head"
+material    = "metal"
+
+[ui/button#pressed]
+sound = "click"
+"#;
+
+        let file = UserDataFile::try_parse(contents)?;
+
+        assert_eq!(file.sprites.len(), 2);
+        assert_eq!(file.sprites["hero"]["damage_zone"], "head");
+        assert_eq!(file.sprites["hero"]["material"], "metal");
+        assert_eq!(file.sprites["ui/button#pressed"]["sound"], "click");
+
+        for contents in [
+            "material = \"metal\"",
+            "[hero]\na = \"x\"\na = \"y\"",
+            "[hero]\n[hero]",
+            "[hero]\nmaterial = metal",
+            "[hero]\nmaterial \"metal\"",
+        ] {
+            let err = UserDataFile::try_parse(contents).unwrap_err();
+            assert!(matches!(Error::find(&err), Some(Error::ParseError(_))));
+        }
+
+        let err = UserDataFile::try_parse("[hero]\n\na = \"x\"\na = \"y\"").unwrap_err();
+        assert!(format!("{err:#}").contains("line 4"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Context;
+
+use crate::{error::Ewwow, inputs::project::ProjectFile};
+
+/// A `texture-packer-workspace.toml` combining several project files, e.g.
+/// one per content pack or DLC, each built on its own.
+///
+/// ```toml
+/// [projects]
+/// base = "base/texture-packer.toml"
+/// forest = "dlc/forest/texture-packer.toml"
+///
+/// # Optionally, one atlas with everything in it
+/// [master]
+/// out = "build/master.png"
+/// meta = ["build/master.json"]
+/// # Any other command line options
+/// args = ["--packer", "skyline"]
+/// ```
+///
+/// The master atlas is merged from the exported atlases of the projects, so
+/// every atlas of them needs a `meta`. Sprites, fonts and labels are renamed
+/// to `<project>/<name>`, e.g. `forest/tree`. Paths are relative to the
+/// workspace file.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceFile {
+    #[serde(default)]
+    pub projects: BTreeMap<String, String>,
+    pub master: Option<MasterBuild>,
+}
+
+/// The combined atlas of a [`WorkspaceFile`].
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MasterBuild {
+    /// The atlas texture, like `--out`.
+    pub out: Option<String>,
+    /// One file per metadata format, `.json` or `.rmp`, like `--meta`.
+    pub meta: Vec<String>,
+    /// Passed on as they are.
+    pub args: Vec<String>,
+}
+
+impl WorkspaceFile {
+    pub fn try_parse(file_contents: &str) -> anyhow::Result<Self> {
+        let workspace: Self = toml::from_str(file_contents).context("Invalid workspace file")?;
+
+        if workspace.projects.is_empty() {
+            Ewwow
+                .raise()
+                .context("The workspace file has no `[projects]`")?;
+        }
+
+        if let Some(name) = workspace.projects.keys().find(|name| name.contains('/')) {
+            Ewwow
+                .raise()
+                .with_context(|| format!("Project name '{name}' can't contain a '/'"))?;
+        }
+
+        Ok(workspace)
+    }
+}
+
+impl MasterBuild {
+    /// The command line that builds the master atlas, run from the directory
+    /// of the workspace file. `projects` are the parsed project files with
+    /// their name and path relative to the workspace.
+    pub fn to_args(&self, projects: &[(&str, &Path, ProjectFile)]) -> anyhow::Result<Vec<String>> {
+        let mut args = vec![];
+        let mut push = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+
+        for (name, path, project) in projects.iter() {
+            let dir = path.parent().unwrap_or(Path::new(""));
+
+            for (atlas_name, build) in project.atlases.iter() {
+                // Any format will do, they all describe the same atlas
+                let meta = build.meta.first().with_context(|| {
+                    format!("Atlas '{atlas_name}' of project '{name}' needs a `meta` to be merged")
+                })?;
+
+                push("--merge", format!("{name}={}", dir.join(meta).display()));
+            }
+        }
+
+        if let Some(out) = &self.out {
+            push("--out", out.clone());
+        }
+        for meta in self.meta.iter() {
+            push("--meta", meta.clone());
+        }
+        push("--pack-manifest", "master.pack-manifest.json".to_string());
+
+        args.extend(self.args.iter().cloned());
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::WorkspaceFile;
+    use crate::inputs::project::ProjectFile;
+
+    #[test]
+    fn test_workspace_file() -> anyhow::Result<()> {
+        let workspace = WorkspaceFile::try_parse(
+            r#"
+            [projects]
+            base = "base/texture-packer.toml"
+            forest = "dlc/forest/texture-packer.toml"
+
+            [master]
+            out = "build/master.png"
+            meta = ["build/master.json"]
+            args = ["--white-pixel"]
+            "#,
+        )?;
+
+        assert_eq!(
+            workspace.projects.keys().collect::<Vec<_>>(),
+            ["base", "forest"]
+        );
+
+        let base = ProjectFile::try_parse(
+            r#"
+            [atlases.ui]
+            meta = ["build/ui.rmp", "build/ui.json"]
+            [atlases.world]
+            meta = ["build/world.json"]
+            "#,
+        )?;
+        let forest = ProjectFile::try_parse("[atlases.trees]\nmeta = [\"trees.json\"]")?;
+
+        let master = workspace.master.as_ref().unwrap();
+        let projects = [
+            ("base", Path::new("base/texture-packer.toml"), base),
+            (
+                "forest",
+                Path::new("dlc/forest/texture-packer.toml"),
+                forest,
+            ),
+        ];
+        assert_eq!(
+            master.to_args(&projects)?,
+            [
+                "--merge",
+                "base=base/build/ui.rmp",
+                "--merge",
+                "base=base/build/world.json",
+                "--merge",
+                "forest=dlc/forest/trees.json",
+                "--out",
+                "build/master.png",
+                "--meta",
+                "build/master.json",
+                "--pack-manifest",
+                "master.pack-manifest.json",
+                "--white-pixel",
+            ]
+        );
+
+        let no_meta = ProjectFile::try_parse("[atlases.ui]\nspacing = 1")?;
+        assert!(master
+            .to_args(&[("base", Path::new("texture-packer.toml"), no_meta)])
+            .is_err());
+
+        assert!(WorkspaceFile::try_parse("").is_err());
+        assert!(WorkspaceFile::try_parse("[projects]\n\"a/b\" = \"a.toml\"").is_err());
+        assert!(WorkspaceFile::try_parse("[projects]\na = \"a.toml\"\n[master]\nout = 1").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use crate::math::{IMargins, IRect, ISize};
+
+/// The result of packing a texture atlas: where every sprite of every asset
+/// ends up in the final image. It does not reference any image data, so it
+/// can be cached, compared, or reused for companion atlases.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AtlasLayout {
+    pub size: ISize,
+    /// Empty pixels along the edges of the image that no sprite reaches into.
+    pub margin: i32,
+    pub placements: Vec<SpritePlacement>,
+    /// The blocks assets were packed into as a whole, e.g. every font with
+    /// [`super::texture_atlas::TextureAtlas::font_regions`].
+    #[serde(default)]
+    pub regions: Vec<AssetRegion>,
+    /// The number of equally sized layers (of `size`) the sprites were packed
+    /// into by [`super::texture_atlas::TextureAtlas::pack_layers`], 0 for a
+    /// single image.
+    #[serde(default)]
+    pub layer_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SpritePlacement {
+    pub asset_id: usize,
+    pub sprite_id: usize,
+    pub bounds: IRect,
+    #[serde(default)]
+    pub layer: u32,
+}
+
+/// The area of the atlas that only the (padded) sprites of one asset occupy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AssetRegion {
+    pub asset_id: usize,
+    pub bounds: IRect,
+}
+
+impl AtlasLayout {
+    pub fn new(size: ISize) -> Self {
+        Self {
+            size,
+            margin: 0,
+            placements: vec![],
+            regions: vec![],
+            layer_count: 0,
+        }
+    }
+
+    pub fn place(&mut self, asset_id: usize, sprite_id: usize, bounds: IRect) {
+        self.place_in_layer(asset_id, sprite_id, bounds, 0);
+    }
+
+    pub fn place_in_layer(&mut self, asset_id: usize, sprite_id: usize, bounds: IRect, layer: u32) {
+        self.placements.push(SpritePlacement {
+            asset_id,
+            sprite_id,
+            bounds,
+            layer,
+        });
+    }
+
+    /// A single image layout of the placements in `layer`.
+    pub fn layer(&self, layer: u32) -> AtlasLayout {
+        AtlasLayout {
+            size: self.size,
+            margin: self.margin,
+            placements: self
+                .placements
+                .iter()
+                .filter(|p| p.layer == layer)
+                .map(|&p| SpritePlacement { layer: 0, ..p })
+                .collect(),
+            regions: vec![],
+            layer_count: 0,
+        }
+    }
+
+    /// The smallest image size the placements (with their `padding`) and the
+    /// margin would fit into, without moving any of them.
+    pub fn used_size(&self, padding: IMargins) -> ISize {
+        let max = self
+            .placements
+            .iter()
+            .map(|p| p.bounds.grow(padding).max)
+            .fold(glam::IVec2::ZERO, |a, b| a.max(b));
+
+        ISize::new(max.x + self.margin, max.y + self.margin)
+    }
+
+    pub fn region(&self, asset_id: usize) -> Option<IRect> {
+        self.regions
+            .iter()
+            .find(|region| region.asset_id == asset_id)
+            .map(|region| region.bounds)
+    }
+
+    pub fn placement_map(&self) -> HashMap<(usize, usize), SpritePlacement> {
+        self.placements
+            .iter()
+            .map(|&p| ((p.asset_id, p.sprite_id), p))
+            .collect()
+    }
+
+    pub fn bounds_map(&self) -> HashMap<(usize, usize), IRect> {
+        self.placements
+            .iter()
+            .map(|p| ((p.asset_id, p.sprite_id), p.bounds))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtlasLayout;
+    use crate::math::{IRect, ISize};
+
+    #[test]
+    fn test_layout_serde_round_trip() -> anyhow::Result<()> {
+        let mut layout = AtlasLayout::new(ISize::new(64, 32));
+        layout.place(0, 0, IRect::new(0, 0, 7, 13));
+        layout.place(1, 3, IRect::new(7, 0, 12, 4));
+
+        let json = serde_json::to_string(&layout)?;
+        let loaded: AtlasLayout = serde_json::from_str(&json)?;
+
+        assert_eq!(layout, loaded);
+        assert_eq!(loaded.bounds_map()[&(1, 3)], IRect::new(7, 0, 12, 4));
+
+        Ok(())
+    }
+}
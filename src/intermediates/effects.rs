@@ -0,0 +1,598 @@
+use anyhow::Context;
+use image::{GenericImageView, Rgba, RgbaImage};
+
+use crate::error::{Error, Ewwow};
+
+/// An effect baked into additional frames of a font, see
+/// [`super::font::FontIntermediate::with_effect_frames`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum GlyphEffect {
+    /// Draws `color` around every opaque pixel, `thickness` pixels wide.
+    Outline { thickness: u32, color: [u8; 4] },
+}
+
+impl GlyphEffect {
+    /// How many pixels the effect grows a glyph by on every side.
+    pub fn margin(&self) -> u32 {
+        match *self {
+            GlyphEffect::Outline { thickness, .. } => thickness,
+        }
+    }
+
+    /// `glyph` with the effect applied, grown by [`Self::margin`] on every
+    /// side.
+    pub fn apply<I>(&self, glyph: &I) -> RgbaImage
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        match *self {
+            GlyphEffect::Outline { thickness, color } => outline(glyph, thickness, color, true),
+        }
+    }
+}
+
+impl std::str::FromStr for GlyphEffect {
+    type Err = anyhow::Error;
+
+    /// Parses `outline:<thickness>:<rrggbb[aa]>`, e.g. `outline:1:000000`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+
+        match parts.as_slice() {
+            ["outline", thickness, color] => Ok(GlyphEffect::Outline {
+                thickness: thickness
+                    .parse()
+                    .with_context(|| format!("Invalid outline thickness '{thickness}'"))?,
+                color: parse_hex_color(color)?,
+            }),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Unknown glyph effect '{s}', expected outline:<thickness>:<rrggbb[aa]>")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// Generates a new sprite from every frame of a selected sprite, see
+/// [`super::sprite::SpriteIntermediate::derive`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum SpriteDerivation {
+    /// The silhouette of the sprite in `color`, blurred by `blur_radius`.
+    Shadow { blur_radius: u32, color: [u8; 4] },
+    /// Only the outline around the sprite, e.g. for selection highlights.
+    Outline { thickness: u32, color: [u8; 4] },
+    /// The mirrored sprite, for engines that can't flip sprites when drawing.
+    Flip(Flip),
+}
+
+/// Which axes a sprite is mirrored along.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(default)]
+pub struct Flip {
+    /// Mirrored horizontally, left is right.
+    pub x: bool,
+    /// Mirrored vertically, top is bottom.
+    pub y: bool,
+}
+
+impl Flip {
+    pub fn is_none(&self) -> bool {
+        !self.x && !self.y
+    }
+
+    /// The name suffix of flipped sprites, e.g. `hero#flip-x`.
+    pub fn suffix(&self) -> &'static str {
+        match (self.x, self.y) {
+            (true, true) => "flip-xy",
+            (true, false) => "flip-x",
+            (false, true) => "flip-y",
+            (false, false) => "flip-none",
+        }
+    }
+}
+
+impl std::str::FromStr for Flip {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x" => Ok(Flip { x: true, y: false }),
+            "y" => Ok(Flip { x: false, y: true }),
+            "xy" => Ok(Flip { x: true, y: true }),
+            _ => {
+                Ewwow
+                    .raise()
+                    .with_context(|| format!("Invalid flip '{s}', expected x, y or xy"))?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl SpriteDerivation {
+    /// Parses `<blur radius>:<rrggbb[aa]>`, e.g. `2:00000080`.
+    pub fn parse_shadow(s: &str) -> anyhow::Result<Self> {
+        let (blur_radius, color) = parse_width_and_color(s).with_context(|| {
+            format!("Invalid shadow style '{s}', expected <blur radius>:<rrggbb[aa]>")
+        })?;
+
+        Ok(SpriteDerivation::Shadow { blur_radius, color })
+    }
+
+    /// Parses `<thickness>:<rrggbb[aa]>`, e.g. `1:ffffff`.
+    pub fn parse_outline(s: &str) -> anyhow::Result<Self> {
+        let (thickness, color) = parse_width_and_color(s).with_context(|| {
+            format!("Invalid outline style '{s}', expected <thickness>:<rrggbb[aa]>")
+        })?;
+
+        Ok(SpriteDerivation::Outline { thickness, color })
+    }
+
+    /// The name suffix of derived sprites, e.g. `hero#shadow`.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            SpriteDerivation::Shadow { .. } => "shadow",
+            SpriteDerivation::Outline { .. } => "outline",
+            SpriteDerivation::Flip(flip) => flip.suffix(),
+        }
+    }
+
+    /// How many pixels the derived frames are larger on every side.
+    pub fn margin(&self) -> u32 {
+        match *self {
+            SpriteDerivation::Shadow { blur_radius, .. } => blur_radius,
+            SpriteDerivation::Outline { thickness, .. } => thickness,
+            SpriteDerivation::Flip(_) => 0,
+        }
+    }
+
+    pub fn apply<I>(&self, frame: &I) -> RgbaImage
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        match *self {
+            SpriteDerivation::Shadow { blur_radius, color } => shadow(frame, blur_radius, color),
+            SpriteDerivation::Outline { thickness, color } => {
+                outline(frame, thickness, color, false)
+            }
+            SpriteDerivation::Flip(flip) => {
+                let mut flipped =
+                    RgbaImage::from_fn(frame.width(), frame.height(), |x, y| frame.get_pixel(x, y));
+                if flip.x {
+                    image::imageops::flip_horizontal_in_place(&mut flipped);
+                }
+                if flip.y {
+                    image::imageops::flip_vertical_in_place(&mut flipped);
+                }
+                flipped
+            }
+        }
+    }
+}
+
+fn parse_width_and_color(s: &str) -> anyhow::Result<(u32, [u8; 4])> {
+    let (width, color) = s.split_once(':').ok_or(Ewwow)?;
+
+    Ok((width.parse()?, parse_hex_color(color)?))
+}
+
+pub(crate) fn parse_hex_color(s: &str) -> anyhow::Result<[u8; 4]> {
+    let channel = |i: usize| {
+        s.get(i * 2..i * 2 + 2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+    };
+
+    let color = match s.len() {
+        6 => channel(0)
+            .zip(channel(1))
+            .zip(channel(2))
+            .map(|((r, g), b)| [r, g, b, 255]),
+        8 => channel(0)
+            .zip(channel(1))
+            .zip(channel(2))
+            .zip(channel(3))
+            .map(|(((r, g), b), a)| [r, g, b, a]),
+        _ => None,
+    };
+
+    color.ok_or_else(|| {
+        Error::ParseError(format!("Invalid color '{s}', expected rrggbb or rrggbbaa")).into()
+    })
+}
+
+/// Pixels within `thickness` (in both axes) of an opaque pixel get `color`,
+/// with the alpha of the most opaque of those pixels. The glyph is drawn on
+/// top if `with_glyph` is set, otherwise it is cut out of the outline.
+fn outline<I>(glyph: &I, thickness: u32, color: [u8; 4], with_glyph: bool) -> RgbaImage
+where
+    I: GenericImageView<Pixel = Rgba<u8>>,
+{
+    let (width, height) = glyph.dimensions();
+    let t = thickness as i64;
+
+    let mut output = RgbaImage::new(width + 2 * thickness, height + 2 * thickness);
+
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let mut coverage = 0;
+
+        for gy in (y as i64 - 2 * t).max(0)..=(y as i64).min(height as i64 - 1) {
+            for gx in (x as i64 - 2 * t).max(0)..=(x as i64).min(width as i64 - 1) {
+                coverage = coverage.max(glyph.get_pixel(gx as u32, gy as u32)[3]);
+            }
+        }
+
+        let mut alpha = coverage as u32 * color[3] as u32 / 255;
+
+        let inside = (x as i64 - t, y as i64 - t);
+        if !with_glyph
+            && (0..width as i64).contains(&inside.0)
+            && (0..height as i64).contains(&inside.1)
+        {
+            let glyph_alpha = glyph.get_pixel(inside.0 as u32, inside.1 as u32)[3] as u32;
+            alpha = alpha * (255 - glyph_alpha) / 255;
+        }
+
+        *pixel = Rgba([color[0], color[1], color[2], alpha as u8]);
+    }
+
+    if with_glyph {
+        image::imageops::overlay(&mut output, glyph, t, t);
+    }
+
+    output
+}
+
+/// The silhouette of `image` in `color`, grown by `blur_radius` on every side
+/// and blurred with two box blur passes.
+fn shadow<I>(image: &I, blur_radius: u32, color: [u8; 4]) -> RgbaImage
+where
+    I: GenericImageView<Pixel = Rgba<u8>>,
+{
+    let (width, height) = image.dimensions();
+    let r = blur_radius as usize;
+    let (out_width, out_height) = (width as usize + 2 * r, height as usize + 2 * r);
+
+    let mut alpha = vec![0u32; out_width * out_height];
+    for (x, y, pixel) in image.pixels() {
+        alpha[(y as usize + r) * out_width + x as usize + r] =
+            pixel[3] as u32 * color[3] as u32 / 255;
+    }
+
+    // Horizontal, then vertical
+    let alpha = box_blur(&alpha, out_width, out_height, r, 1, out_width);
+    let alpha = box_blur(&alpha, out_height, out_width, r, out_width, 1);
+
+    RgbaImage::from_fn(out_width as u32, out_height as u32, |x, y| {
+        let a = alpha[y as usize * out_width + x as usize];
+        Rgba([color[0], color[1], color[2], a as u8])
+    })
+}
+
+/// Averages every value with the `r` values before and after it along lines
+/// of `len` values, which are `step` apart. Consecutive lines start
+/// `line_step` apart.
+fn box_blur(
+    values: &[u32],
+    len: usize,
+    num_lines: usize,
+    r: usize,
+    step: usize,
+    line_step: usize,
+) -> Vec<u32> {
+    let mut blurred = vec![0; values.len()];
+    let window = 2 * r as u32 + 1;
+
+    for line in 0..num_lines {
+        let at = |i: usize| line * line_step + i * step;
+
+        for i in 0..len {
+            let sum: u32 = (i.saturating_sub(r)..(i + r + 1).min(len))
+                .map(|j| values[at(j)])
+                .sum();
+
+            blurred[at(i)] = sum / window;
+        }
+    }
+
+    blurred
+}
+
+/// Batch color tweaks, see [`crate::inputs::adjustments::AdjustmentsFile`].
+/// The default changes nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorAdjustment {
+    /// Rotates the hue, in degrees.
+    pub hue: f32,
+    /// Multiplies the saturation (of HSV).
+    pub saturation: f32,
+    /// Added to every channel, -1 turns everything black.
+    pub brightness: f32,
+    /// Scales the distance of every channel from mid gray.
+    pub contrast: f32,
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+        }
+    }
+}
+
+impl ColorAdjustment {
+    /// `frame` with the hue and saturation adjusted first, then the
+    /// brightness and the contrast. Alpha is kept.
+    pub fn apply<I>(&self, frame: &I) -> RgbaImage
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        RgbaImage::from_fn(frame.width(), frame.height(), |x, y| {
+            let pixel = frame.get_pixel(x, y);
+            if pixel[3] == 0 {
+                return pixel;
+            }
+
+            let rgb = [0, 1, 2].map(|c| pixel[c] as f32 / 255.0);
+            let (hue, saturation, value) = rgb_to_hsv(rgb);
+            let rgb = hsv_to_rgb(
+                (hue + self.hue).rem_euclid(360.0),
+                (saturation * self.saturation).clamp(0.0, 1.0),
+                value,
+            );
+
+            let [r, g, b] = rgb.map(|channel| {
+                let channel = (channel + self.brightness - 0.5) * self.contrast + 0.5;
+                (channel.clamp(0.0, 1.0) * 255.0).round() as u8
+            });
+
+            Rgba([r, g, b, pixel[3]])
+        })
+    }
+}
+
+/// Hue in degrees, saturation and value in 0..=1.
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = match delta {
+        0.0 => 0.0,
+        _ if max == r => 60.0 * ((g - b) / delta).rem_euclid(6.0),
+        _ if max == g => 60.0 * ((b - r) / delta + 2.0),
+        _ => 60.0 * ((r - g) / delta + 4.0),
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    (hue, saturation, max)
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [f32; 3] {
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = value - chroma;
+
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    [r + m, g + m, b + m]
+}
+
+/// `frame` with every pixel within `tolerance` (in every channel) of the
+/// source color of one of `colors` recolored to its target color, keeping
+/// its alpha. The closest source color wins.
+pub fn swap_palette<I>(frame: &I, colors: &[([u8; 3], [u8; 3])], tolerance: u8) -> RgbaImage
+where
+    I: GenericImageView<Pixel = Rgba<u8>>,
+{
+    RgbaImage::from_fn(frame.width(), frame.height(), |x, y| {
+        let pixel = frame.get_pixel(x, y);
+        let distance = |color: &[u8; 3]| (0..3).map(|c| pixel[c].abs_diff(color[c])).max();
+
+        let target = colors
+            .iter()
+            .filter(|(from, _)| distance(from) <= Some(tolerance))
+            .min_by_key(|(from, _)| distance(from))
+            .map(|&(_, to)| to);
+
+        match target {
+            Some([r, g, b]) => Rgba([r, g, b, pixel[3]]),
+            None => pixel,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{swap_palette, ColorAdjustment, Flip, GlyphEffect, SpriteDerivation};
+
+    #[test]
+    fn test_outline_only() -> anyhow::Result<()> {
+        let outline = SpriteDerivation::parse_outline("1:ffffff")?;
+        assert_eq!(outline.suffix(), "outline");
+        assert!(SpriteDerivation::parse_outline("x:ffffff").is_err());
+
+        let sprite = RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        let derived = outline.apply(&sprite);
+        assert_eq!(derived.dimensions(), (4, 4));
+
+        let alphas: Vec<Vec<u8>> = (0..4)
+            .map(|y| (0..4).map(|x| derived.get_pixel(x, y)[3]).collect())
+            .collect();
+        assert_eq!(
+            alphas,
+            vec![
+                vec![255, 255, 255, 255],
+                vec![255, 0, 0, 255],
+                vec![255, 0, 0, 255],
+                vec![255, 255, 255, 255],
+            ]
+        );
+        assert_eq!(*derived.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shadow() -> anyhow::Result<()> {
+        let shadow = SpriteDerivation::parse_shadow("1:00000080")?;
+        assert_eq!(
+            shadow,
+            SpriteDerivation::Shadow {
+                blur_radius: 1,
+                color: [0, 0, 0, 128]
+            }
+        );
+        assert!(SpriteDerivation::parse_shadow("1").is_err());
+
+        let sprite = RgbaImage::from_pixel(3, 3, Rgba([255, 0, 0, 255]));
+        let derived = shadow.apply(&sprite);
+        assert_eq!(derived.dimensions(), (5, 5));
+
+        // The center is fully covered, the corners only by a single pixel
+        assert_eq!(*derived.get_pixel(2, 2), Rgba([0, 0, 0, 128]));
+        assert_eq!(derived.get_pixel(0, 0)[3], 128 / 9);
+        assert_eq!(derived.get_pixel(0, 2)[3], 128 / 3);
+
+        // The blur is symmetric
+        for (x, y, pixel) in derived.enumerate_pixels() {
+            assert_eq!(pixel, derived.get_pixel(4 - x, 4 - y));
+            assert_eq!(pixel, derived.get_pixel(y, x));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flip() -> anyhow::Result<()> {
+        let flip = SpriteDerivation::Flip("xy".parse()?);
+        assert_eq!(flip.suffix(), "flip-xy");
+        assert_eq!(flip.margin(), 0);
+        assert!("z".parse::<Flip>().is_err());
+
+        let sprite = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let flipped = flip.apply(&sprite);
+        assert_eq!(flipped.dimensions(), (3, 2));
+        assert_eq!(*flipped.get_pixel(0, 0), Rgba([2, 1, 0, 255]));
+
+        let flipped = SpriteDerivation::Flip("x".parse()?).apply(&sprite);
+        assert_eq!(*flipped.get_pixel(0, 1), Rgba([2, 1, 0, 255]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_outline() -> anyhow::Result<()> {
+        let effect: GlyphEffect = "outline:1:ff000080".parse()?;
+        assert_eq!(
+            effect,
+            GlyphEffect::Outline {
+                thickness: 1,
+                color: [255, 0, 0, 128]
+            }
+        );
+        assert!("outline:1:red".parse::<GlyphEffect>().is_err());
+        assert!("shadow:1:000000".parse::<GlyphEffect>().is_err());
+
+        // A 3x1 glyph with only its middle pixel set
+        let mut glyph = RgbaImage::new(3, 1);
+        glyph.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+
+        let outlined = effect.apply(&glyph);
+        assert_eq!(outlined.dimensions(), (5, 3));
+
+        let alphas: Vec<Vec<u8>> = (0..3)
+            .map(|y| (0..5).map(|x| outlined.get_pixel(x, y)[3]).collect())
+            .collect();
+        assert_eq!(
+            alphas,
+            vec![
+                vec![0, 128, 128, 128, 0],
+                vec![0, 128, 255, 128, 0],
+                vec![0, 128, 128, 128, 0],
+            ]
+        );
+        assert_eq!(*outlined.get_pixel(2, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(*outlined.get_pixel(1, 1), Rgba([255, 0, 0, 128]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_palette() {
+        let sprite = RgbaImage::from_fn(4, 1, |x, _| match x {
+            0 => Rgba([48, 80, 160, 255]),
+            1 => Rgba([52, 76, 160, 128]),
+            2 => Rgba([60, 80, 160, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        let colors = [([48, 80, 160], [160, 48, 48]), ([50, 78, 160], [0, 0, 0])];
+
+        let swapped = swap_palette(&sprite, &colors, 4);
+        assert_eq!(*swapped.get_pixel(0, 0), Rgba([160, 48, 48, 255]));
+        // Closer to the second color, keeps its alpha
+        assert_eq!(*swapped.get_pixel(1, 0), Rgba([0, 0, 0, 128]));
+        // Too far off both
+        assert_eq!(*swapped.get_pixel(2, 0), Rgba([60, 80, 160, 255]));
+        assert_eq!(*swapped.get_pixel(3, 0), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_color_adjustment() {
+        let sprite = RgbaImage::from_fn(3, 1, |x, _| match x {
+            0 => Rgba([255, 0, 0, 255]),
+            1 => Rgba([128, 64, 32, 100]),
+            _ => Rgba([10, 20, 30, 0]),
+        });
+
+        let unchanged = ColorAdjustment::default().apply(&sprite);
+        assert_eq!(unchanged, sprite);
+
+        let adjustment = ColorAdjustment {
+            hue: 120.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            *adjustment.apply(&sprite).get_pixel(0, 0),
+            Rgba([0, 255, 0, 255])
+        );
+
+        let adjustment = ColorAdjustment {
+            saturation: 0.0,
+            brightness: -0.25,
+            ..Default::default()
+        };
+        let adjusted = adjustment.apply(&sprite);
+        // Gray at the value of the color, then darker
+        assert_eq!(*adjusted.get_pixel(0, 0), Rgba([191, 191, 191, 255]));
+        assert_eq!(*adjusted.get_pixel(1, 0), Rgba([64, 64, 64, 100]));
+        // Fully transparent pixels are left alone
+        assert_eq!(*adjusted.get_pixel(2, 0), Rgba([10, 20, 30, 0]));
+
+        let adjustment = ColorAdjustment {
+            contrast: 2.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            *adjustment.apply(&sprite).get_pixel(1, 0),
+            Rgba([129, 0, 0, 100])
+        );
+    }
+}
@@ -1,14 +1,22 @@
+use std::collections::HashMap;
+
 use anyhow::Context;
-use image::{Rgba, SubImage};
+use image::{GenericImageView, Rgba, SubImage};
 
 use crate::{
-    error::Ewwow,
-    font_shared,
+    error::{Error, Ewwow},
+    font_shared, image_io,
     math::ISize,
+    sampling::Filtering,
     sources::{SourceId, SourceSprite, Sources},
+    warnings::Warning,
 };
 
-use super::texture_atlas::Atlasable;
+use super::{
+    effects::GlyphEffect,
+    sprite::{SpriteFrame, SpriteIntermediate},
+    texture_atlas::{Atlasable, SpriteTexture},
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct CharacterSprite {
@@ -18,19 +26,201 @@ pub struct CharacterSprite {
     pub x_offset: i32,
     pub y_offset: i32,
     pub x_advance: i32,
+    /// Pixels generated from `sprite`, e.g. by an effect, which are used
+    /// instead of it.
+    pub baked: Option<image::RgbaImage>,
+}
+
+/// Empty cells reserved in the atlas next to the glyphs of a font, so
+/// runtimes can rasterize rare glyphs on demand into known free space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DynamicSlots {
+    pub count: u32,
+    pub cell_size: ISize,
 }
 
+impl std::str::FromStr for DynamicSlots {
+    type Err = anyhow::Error;
+
+    /// Parses `<count>:<width>x<height>`, e.g. `64:8x12`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s.split_once(':').and_then(|(count, cell_size)| {
+            let (width, height) = cell_size.split_once('x')?;
+
+            Some((
+                count.parse().ok()?,
+                width.parse().ok()?,
+                height.parse().ok()?,
+            ))
+        });
+
+        let Some((count, width, height)) = parsed.filter(|&(_, w, h)| w > 0 && h > 0) else {
+            Error::InvalidOption(format!(
+                "Invalid dynamic slots '{s}', expected <count>:<width>x<height>"
+            ))
+            .raise()?;
+
+            unreachable!()
+        };
+
+        Ok(Self {
+            count,
+            cell_size: ISize::new(width, height),
+        })
+    }
+}
+
+/// A bitmap font on its way into an atlas, usually read from a fnt file with
+/// [`Self::from_fnt`].
 #[derive(Debug, Clone)]
 pub struct FontIntermediate {
+    /// The logical name the font is exported as, e.g. `ui_small`. Defaults to
+    /// `family`, see [`Self::with_name`].
     pub name: String,
+    /// The face name from the fnt file.
+    pub family: String,
     pub animation: font_shared::TextCharacterAnimation,
     pub num_frames: u32,
     pub line_height: i32,
     pub base: i32,
     pub chars: Vec<CharacterSprite>,
+    /// Packed as additional empty sprites after all `chars`.
+    pub dynamic_slots: Option<DynamicSlots>,
+    /// If set, the second half of the frames of every char has this effect
+    /// applied, see [`Self::with_effect_frames`].
+    pub effect: Option<GlyphEffect>,
+    /// The advance of every char if the font is monospaced, see
+    /// [`Self::with_monospace`].
+    pub cell_width: Option<i32>,
+    pub metrics: font_shared::FontMetrics,
+    pub decorations: font_shared::DecorationMetrics,
+    /// How chars with fewer than `num_frames` frames are padded, see
+    /// [`Self::with_ragged_frames`].
+    pub ragged_frames: RaggedFrames,
+    /// Packs the glyphs into rows of exactly `line_height`, see
+    /// [`Self::with_line_shelves`].
+    pub line_shelves: bool,
+    /// How the runtime should sample the glyphs: smoothed, antialiased and
+    /// distance field fonts need linear filtering, pixel fonts nearest.
+    pub filtering_hint: Filtering,
+    pub distance_field: Option<font_shared::DistanceField>,
+}
+
+/// How [`FontIntermediate::with_monospace`] treats chars of different widths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Monospace {
+    /// Fail unless every char already has the same advance.
+    Validate,
+    /// Use the largest advance for every char, centering the narrower ones.
+    Force,
+}
+
+/// What to do with chars that have fewer frames than the font, e.g. when only
+/// the letters of an animated font are animated. Padding frames reuse the
+/// sprites of the existing ones.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum RaggedFrames {
+    /// Fail, every char needs every frame.
+    #[default]
+    Error,
+    /// Repeat the last frame of the char.
+    RepeatLast,
+    /// Start over with the first frame of the char.
+    Loop,
+}
+
+impl RaggedFrames {
+    /// The frame of a char with `num_frames` frames that is shown as `frame`.
+    pub fn source_frame(self, frame: u32, num_frames: u32) -> u32 {
+        match self {
+            RaggedFrames::Error | RaggedFrames::RepeatLast => frame.min(num_frames - 1),
+            RaggedFrames::Loop => frame % num_frames,
+        }
+    }
+}
+
+impl std::str::FromStr for RaggedFrames {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "repeat-last" => Ok(Self::RepeatLast),
+            "loop" => Ok(Self::Loop),
+            _ => {
+                Error::InvalidOption(format!(
+                    "Invalid ragged frames policy '{s}', expected error, repeat-last or loop"
+                ))
+                .raise()?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// How [`FontIntermediate::check_frame_metrics`] treats chars whose frames
+/// disagree on their offsets or advance.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetricConflicts {
+    /// Fail, naming the char and the values that differ.
+    #[default]
+    Error,
+    /// Use the metrics of the first frame for every frame, with a warning.
+    FirstFrame,
+}
+
+impl std::str::FromStr for MetricConflicts {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "first-frame" => Ok(Self::FirstFrame),
+            _ => {
+                Error::InvalidOption(format!(
+                    "Invalid metric conflict policy '{s}', expected error or first-frame"
+                ))
+                .raise()?;
+
+                unreachable!()
+            }
+        }
+    }
 }
 
 impl FontIntermediate {
+    /// A still font of `chars` named after its family, without effects,
+    /// dynamic slots or measured metrics.
+    pub fn from_chars(
+        name: String,
+        line_height: i32,
+        base: i32,
+        chars: Vec<CharacterSprite>,
+    ) -> Self {
+        Self {
+            family: name.clone(),
+            name,
+            animation: font_shared::TextCharacterAnimation::NoAnimation,
+            num_frames: 1,
+            line_height,
+            base,
+            chars,
+            dynamic_slots: None,
+            effect: None,
+            cell_width: None,
+            metrics: Default::default(),
+            decorations: Default::default(),
+            ragged_frames: Default::default(),
+            line_shelves: false,
+            filtering_hint: Default::default(),
+            distance_field: None,
+        }
+    }
+
     pub fn from_fnt(fnt_src_id: SourceId, srcs: &Sources) -> anyhow::Result<Self> {
         let fnt = srcs
             .get_fnt(fnt_src_id)
@@ -46,31 +236,401 @@ impl FontIntermediate {
                         "Failed to generate source sprite of character '{}' (#{}) for font {}",
                         char_code_as_printable(c.id),
                         c.id,
-                        &fnt.info.face,
+                        fnt.info.face,
                     )
                 })?,
                 frame: 0,
                 x_offset: c.x_offset,
                 y_offset: c.y_offset,
                 x_advance: c.x_advance,
+                baked: None,
             });
         }
 
-        Ok(Self {
-            name: fnt.info.face.clone(),
-            animation: font_shared::TextCharacterAnimation::NoAnimation,
-            num_frames: 1,
-            line_height: fnt.common.line_height,
-            base: fnt.common.base,
-            chars,
+        let distance_field = fnt
+            .distance_field
+            .as_ref()
+            .map(|field| {
+                anyhow::Ok(font_shared::DistanceField {
+                    field_type: field.field_type.parse()?,
+                    distance_range: field.distance_range,
+                })
+            })
+            .transpose()
+            .with_context(|| format!("Invalid distance field of font {}", fnt.info.face))?;
+
+        let filtering_hint = match fnt.is_smooth() {
+            true => Filtering::Linear,
+            false => Filtering::Nearest,
+        };
+
+        let mut font = Self {
+            filtering_hint,
+            distance_field,
+            ..Self::from_chars(
+                fnt.info.face.clone(),
+                fnt.common.line_height,
+                fnt.common.base,
+                chars,
+            )
+        };
+        font.metrics = font.measure_metrics(srcs)?;
+        font.decorations = font_shared::DecorationMetrics::from_metrics(&font.metrics);
+
+        Ok(font)
+    }
+
+    /// An icon font with one glyph per `(svg image, code point)` of `icons`.
+    /// The SVGs are rasterized again from their files to be `size` pixels
+    /// high, and sit on the base line.
+    pub fn from_icons(
+        name: String,
+        icons: &[(SourceId, u32)],
+        size: u32,
+        srcs: &Sources,
+    ) -> anyhow::Result<Self> {
+        let mut chars: Vec<CharacterSprite> = Vec::with_capacity(icons.len());
+
+        for &(id, char_code) in icons.iter() {
+            let path = srcs.get_path(id)?;
+            let image = std::fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| image_io::rasterize_svg(&bytes, Some(size)))
+                .with_context(|| format!("Failed to rasterize icon '{}'", path.display()))?;
+            let source = srcs.get_image(id)?;
+
+            chars.push(CharacterSprite {
+                char_code,
+                sprite: SourceSprite {
+                    image_source_id: id,
+                    x: 0,
+                    y: 0,
+                    width: source.width() as i32,
+                    height: source.height() as i32,
+                },
+                frame: 0,
+                x_offset: 0,
+                y_offset: 0,
+                x_advance: image.width() as i32,
+                baked: Some(image),
+            });
+        }
+
+        let mut font = Self {
+            // Rasterizing antialiases the edges
+            filtering_hint: Filtering::Linear,
+            ..Self::from_chars(name, size as i32, size as i32, chars)
+        };
+        font.metrics = font.measure_metrics(srcs)?;
+        font.decorations = font_shared::DecorationMetrics::from_metrics(&font.metrics);
+
+        Ok(font)
+    }
+
+    /// Measures the metrics from the opaque rows of the glyphs of the first
+    /// frame.
+    pub fn measure_metrics(&self, srcs: &Sources) -> anyhow::Result<font_shared::FontMetrics> {
+        let mut metrics = font_shared::FontMetrics::default();
+
+        for ch in self.chars.iter().filter(|ch| ch.frame == 0) {
+            let glyph = ch.get_sprite_texture_view(srcs).with_context(|| {
+                format!(
+                    "Failed to get the glyph of character #{} of font '{}'",
+                    ch.char_code, self.name
+                )
+            })?;
+
+            let is_opaque_row = |y: &u32| (0..glyph.width()).any(|x| glyph.get_pixel(x, *y)[3] > 0);
+            let (Some(top), Some(bottom)) = (
+                (0..glyph.height()).find(is_opaque_row),
+                (0..glyph.height()).rev().find(is_opaque_row),
+            ) else {
+                continue;
+            };
+
+            let ascent = self.base - (ch.y_offset + top as i32);
+            let descent = ch.y_offset + bottom as i32 + 1 - self.base;
+
+            metrics.ascent = metrics.ascent.max(ascent);
+            metrics.descent = metrics.descent.max(descent);
+
+            match char::from_u32(ch.char_code) {
+                Some('H') => metrics.cap_height = Some(ascent),
+                Some('x') => metrics.x_height = Some(ascent),
+                _ => {}
+            }
+        }
+
+        Ok(metrics)
+    }
+
+    /// Doubles the frames of every char: the first half are the plain glyphs,
+    /// the second half the same glyphs with `effect` applied, so the effect
+    /// can be switched at runtime by picking the frame.
+    ///
+    /// Both frames of a char have the same size and offsets, so the plain
+    /// glyphs get an empty border as wide as the effect grows the glyphs.
+    pub fn with_effect_frames(
+        mut self,
+        effect: GlyphEffect,
+        srcs: &Sources,
+    ) -> anyhow::Result<Self> {
+        if let Some(existing) = self.effect {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Font '{}' already has effect frames for {existing:?}",
+                    self.name
+                )
+            })?;
+        }
+
+        let margin = effect.margin();
+        let mut effect_chars = Vec::with_capacity(self.chars.len());
+
+        for ch in self.chars.iter_mut() {
+            // Empty glyphs stay metric-only in both frames
+            if ch.size().is_empty() {
+                effect_chars.push(CharacterSprite {
+                    frame: ch.frame + self.num_frames,
+                    ..ch.clone()
+                });
+                continue;
+            }
+
+            let glyph = ch.get_sprite_texture_view(srcs).with_context(|| {
+                format!(
+                    "Failed to get the glyph of character #{} of font '{}'",
+                    ch.char_code, self.name
+                )
+            })?;
+
+            let effect_image = effect.apply(&*glyph);
+
+            let mut plain = image::RgbaImage::new(effect_image.width(), effect_image.height());
+            image::imageops::overlay(&mut plain, &*glyph, margin as i64, margin as i64);
+
+            ch.baked = Some(plain);
+            ch.x_offset -= margin as i32;
+            ch.y_offset -= margin as i32;
+
+            effect_chars.push(CharacterSprite {
+                frame: ch.frame + self.num_frames,
+                baked: Some(effect_image),
+                ..ch.clone()
+            });
+        }
+
+        self.chars.extend(effect_chars);
+        self.num_frames *= 2;
+        self.effect = Some(effect);
+
+        Ok(self)
+    }
+
+    /// Makes sure every frame of a char has the same offsets and advance,
+    /// which the exported metadata only stores once per char. Returns a
+    /// warning for every frame `policy` overwrote.
+    pub fn check_frame_metrics(&mut self, policy: MetricConflicts) -> anyhow::Result<Vec<Warning>> {
+        let metrics = |ch: &CharacterSprite| (ch.x_offset, ch.y_offset, ch.x_advance);
+
+        // The metrics of the first frame of every char
+        let mut first_frames: HashMap<u32, (u32, (i32, i32, i32))> = HashMap::new();
+        for ch in self.chars.iter() {
+            let first = first_frames
+                .entry(ch.char_code)
+                .or_insert((ch.frame, metrics(ch)));
+            if ch.frame < first.0 {
+                *first = (ch.frame, metrics(ch));
+            }
+        }
+
+        let mut warnings = vec![];
+
+        for ch in self.chars.iter_mut() {
+            let (first_frame, expected) = first_frames[&ch.char_code];
+            if metrics(ch) == expected {
+                continue;
+            }
+
+            match policy {
+                MetricConflicts::Error => {
+                    Ewwow.raise().with_context(|| {
+                        format!(
+                            "Frame {} of character '{}' (#{}) of font '{}' has offset ({}, {}) and advance {}, but frame {first_frame} has offset ({}, {}) and advance {}",
+                            ch.frame,
+                            char_code_as_printable(ch.char_code),
+                            ch.char_code,
+                            self.name,
+                            ch.x_offset,
+                            ch.y_offset,
+                            ch.x_advance,
+                            expected.0,
+                            expected.1,
+                            expected.2,
+                        )
+                    })?;
+                }
+                MetricConflicts::FirstFrame => {
+                    (ch.x_offset, ch.y_offset, ch.x_advance) = expected;
+                    warnings.push(Warning::MetricConflict {
+                        font_name: self.name.clone(),
+                        char_code: ch.char_code,
+                        frame: ch.frame,
+                    });
+                }
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// Removes control characters, byte order marks and zero-width code
+    /// points, which never show up in rendered text, unless they are in
+    /// `keep` (e.g. for a visible newline symbol). Returns a warning listing
+    /// the removed chars, if there were any.
+    pub fn skip_invisible_chars(&mut self, keep: &[u32]) -> Option<Warning> {
+        let mut skipped: Vec<u32> = self
+            .chars
+            .iter()
+            .map(|ch| ch.char_code)
+            .filter(|&code| is_invisible(code) && !keep.contains(&code))
+            .collect();
+
+        if skipped.is_empty() {
+            return None;
+        }
+
+        self.chars.retain(|ch| !skipped.contains(&ch.char_code));
+
+        skipped.sort_unstable();
+        skipped.dedup();
+
+        Some(Warning::SkippedInvisibleChars {
+            font_name: self.name.clone(),
+            char_codes: skipped,
         })
     }
 
+    /// Allows chars with fewer than `num_frames` frames, padded as `policy`
+    /// says in the exported metadata.
+    pub fn with_ragged_frames(mut self, policy: RaggedFrames) -> Self {
+        self.ragged_frames = policy;
+        self
+    }
+
+    /// Exports the font as `name`, e.g. to tell two sizes of the same face
+    /// apart.
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Gives every char the same advance for terminal-style renderers.
+    pub fn with_monospace(mut self, mode: Monospace) -> anyhow::Result<Self> {
+        let Some(cell_width) = self.chars.iter().map(|ch| ch.x_advance).max() else {
+            return Ok(self);
+        };
+
+        if mode == Monospace::Validate {
+            if let Some(ch) = self.chars.iter().find(|ch| ch.x_advance != cell_width) {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "Font '{}' isn't monospaced: character #{} advances by {} instead of {cell_width}",
+                        self.name, ch.char_code, ch.x_advance
+                    )
+                })?;
+            }
+        }
+
+        for ch in self.chars.iter_mut() {
+            ch.x_offset += (cell_width - ch.x_advance) / 2;
+            ch.x_advance = cell_width;
+        }
+
+        self.cell_width = Some(cell_width);
+
+        Ok(self)
+    }
+
+    /// Packs the glyphs into shelves of exactly `line_height`, so a runtime
+    /// can treat the block of the font as rows, e.g. for uploading glyphs a
+    /// row at a time. Fails if a glyph is taller than a line.
+    pub fn with_line_shelves(mut self) -> anyhow::Result<Self> {
+        if let Some(ch) = self
+            .chars
+            .iter()
+            .find(|ch| ch.size().height > self.line_height)
+        {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Font '{}' can't be packed into line shelves: character #{} is {} pixels high, but lines only {}",
+                    self.name,
+                    ch.char_code,
+                    ch.size().height,
+                    self.line_height
+                )
+            })?;
+        }
+
+        self.line_shelves = true;
+
+        Ok(self)
+    }
+
+    /// Overrides the decorations guessed from the metrics.
+    pub fn with_decorations(mut self, decorations: font_shared::DecorationMetrics) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    /// A white sprite named `<name>#line`, one pixel wide and as high as the
+    /// underline is thick, for consumers to stretch into underlines and
+    /// strikethroughs.
+    pub fn line_sprite(&self) -> SpriteIntermediate {
+        let thickness = self.decorations.underline_thickness.max(1) as u32;
+
+        SpriteIntermediate::from_frames(
+            format!("{}#line", self.name),
+            vec![SpriteFrame::Baked(image::RgbaImage::from_pixel(
+                1,
+                thickness,
+                Rgba([255, 255, 255, 255]),
+            ))],
+        )
+    }
+
+    pub fn with_dynamic_slots(mut self, dynamic_slots: DynamicSlots) -> Self {
+        self.dynamic_slots = Some(dynamic_slots);
+        self
+    }
+
+    /// The sprite index of the first dynamic slot, they come after the chars.
+    pub fn first_dynamic_slot_index(&self) -> usize {
+        self.chars.len()
+    }
+
     pub fn render_text(&self, text: &str, srcs: &Sources) -> anyhow::Result<image::RgbaImage> {
+        self.render_text_impl(text, srcs, true)
+    }
+
+    /// Renders `text` like [`Self::render_text`] but without the debug base
+    /// line, so the result can be packed as a sprite.
+    pub fn bake_text(&self, text: &str, srcs: &Sources) -> anyhow::Result<image::RgbaImage> {
+        self.render_text_impl(text, srcs, false)
+    }
+
+    fn render_text_impl(
+        &self,
+        text: &str,
+        srcs: &Sources,
+        draw_base_line: bool,
+    ) -> anyhow::Result<image::RgbaImage> {
         let mut curr_x = 0;
-        let mut min_y = 0;
-        let mut max_y = 0;
+        let mut min_x = 0;
         let mut max_x = 0;
+        // Start with the line box, glyphs can reach above or below it
+        let mut min_y = 0;
+        let mut max_y = self.line_height;
 
         // Determine the bounds
         for ch in text.chars() {
@@ -82,30 +642,38 @@ impl FontIntermediate {
                 .ok_or(Ewwow)
                 .with_context(|| format!(
                     "Failed to render '{text}' as font '{}' does not have a sprite for '{ch}' (char code #{char_code})",
-                    &self.name
+                    self.name
                 ))?;
 
             let curr_min_y = char_info.y_offset;
-            let curr_max_y = char_info.y_offset + char_info.sprite.height;
-            let curr_max_x = curr_x + char_info.x_offset + char_info.sprite.width;
+            let curr_max_y = char_info.y_offset + char_info.size().height;
+            let curr_min_x = curr_x + char_info.x_offset;
+            let curr_max_x = curr_min_x + char_info.size().width;
 
             min_y = min_y.min(curr_min_y);
             max_y = max_y.max(curr_max_y);
+            min_x = min_x.min(curr_min_x);
             max_x = max_x.max(curr_max_x);
 
             curr_x += char_info.x_advance;
         }
 
-        // Make the image buffer
+        // Make the image buffer, the line box starts at `origin`
         let mut buffer: image::ImageBuffer<image::Rgba<u8>, Vec<u8>> =
-            image::RgbaImage::new(max_x as u32, self.line_height as u32);
+            image::RgbaImage::new((max_x - min_x) as u32, (max_y - min_y) as u32);
+        let origin = (-min_x, -min_y);
 
         // Draw base line
-        let base_line_color = Rgba::<u8>([128, 128, 128, 255]);
+        if draw_base_line {
+            let base_line_color = Rgba::<u8>([128, 128, 128, 255]);
+            let base_line_y = origin.1 + self.base;
 
-        for x in 0..max_x as u32 {
-            if x % 3 != 2 {
-                buffer.put_pixel(x, self.base as u32, base_line_color);
+            if (0..buffer.height() as i32).contains(&base_line_y) {
+                for x in 0..buffer.width() {
+                    if x % 3 != 2 {
+                        buffer.put_pixel(x, base_line_y as u32, base_line_color);
+                    }
+                }
             }
         }
 
@@ -120,14 +688,14 @@ impl FontIntermediate {
                 .find(|&cs| cs.char_code == char_code)
                 .unwrap();
 
-            let x = (curr_x + char_info.x_offset) as i64;
-            let y = char_info.y_offset as i64;
+            let x = (origin.0 + curr_x + char_info.x_offset) as i64;
+            let y = (origin.1 + char_info.y_offset) as i64;
 
-            let character_img = char_info.sprite
-                .get_image(srcs)
+            let character_img = char_info
+                .get_sprite_texture_view(srcs)
                 .with_context(|| format!(
                     "Failed to retrieve character sprite image for '{ch}' (code ${char_code}) for font '{}'",
-                    &self.name
+                    self.name
                 ))?;
 
             image::imageops::overlay(&mut buffer, &character_img.to_image(), x, y);
@@ -139,6 +707,39 @@ impl FontIntermediate {
     }
 }
 
+/// Whether `code` is a control character, a byte order mark, a zero-width
+/// code point or no valid char at all.
+fn is_invisible(code: u32) -> bool {
+    char::from_u32(code).is_none_or(|c| {
+        c.is_control()
+            || matches!(
+                c,
+                '\u{feff}' | '\u{200b}'..='\u{200f}' | '\u{2060}'..='\u{2064}' | '\u{180e}'
+            )
+    })
+}
+
+/// Parses a comma separated list of char codes, each either decimal or
+/// `U+<hex>`, e.g. `10,U+2424`.
+pub fn parse_char_codes(s: &str) -> anyhow::Result<Vec<u32>> {
+    s.split(',')
+        .map(|code| {
+            let code = code.trim();
+            let parsed = match code.strip_prefix("U+").or(code.strip_prefix("u+")) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => code.parse(),
+            };
+
+            parsed.map_err(|_| {
+                Error::ParseError(format!(
+                    "Invalid char code '{code}', expected a decimal number or U+<hex>"
+                ))
+                .into()
+            })
+        })
+        .collect()
+}
+
 fn char_code_as_printable(code: u32) -> char {
     let c = char::from_u32(code).unwrap_or(0 as char);
 
@@ -151,30 +752,360 @@ fn char_code_as_printable(code: u32) -> char {
 
 impl Atlasable for FontIntermediate {
     fn get_sprite_sizes(&self) -> Vec<ISize> {
-        self.chars
-            .iter()
-            .map(|ch| ISize::new(ch.sprite.width, ch.sprite.height))
-            .collect()
+        let slots = self
+            .dynamic_slots
+            .map(|slots| vec![slots.cell_size; slots.count as usize])
+            .unwrap_or_default();
+
+        self.chars.iter().map(|ch| ch.size()).chain(slots).collect()
     }
 
     fn get_sprite_texture(&self, index: usize, srcs: &Sources) -> anyhow::Result<image::RgbaImage> {
+        if let (Some(slots), true) = (self.dynamic_slots, index >= self.chars.len()) {
+            let (width, height) = slots.cell_size.checked_u32().unwrap_or_default();
+            return Ok(image::RgbaImage::new(width, height));
+        }
+
         Ok(self.chars[index]
             .get_sprite_texture_view(srcs)
             .with_context(|| {
                 format!(
                     "Failed to get the texture view of a character sprite #{} of font '{}'",
-                    self.chars[index].char_code, &self.name
+                    self.chars[index].char_code, self.name
                 )
             })?
             .to_image())
     }
+
+    fn get_sprite_view<'s>(
+        &'s self,
+        index: usize,
+        srcs: &'s Sources,
+    ) -> anyhow::Result<SpriteTexture<'s>> {
+        if index >= self.chars.len() {
+            return Ok(SpriteTexture::Owned(self.get_sprite_texture(index, srcs)?));
+        }
+
+        let view = self.chars[index]
+            .get_sprite_texture_view(srcs)
+            .with_context(|| {
+                format!(
+                    "Failed to get the texture view of a character sprite #{} of font '{}'",
+                    self.chars[index].char_code, self.name
+                )
+            })?;
+
+        Ok(SpriteTexture::View(view))
+    }
+
+    fn get_sprite_source(&self, index: usize) -> Option<SourceId> {
+        let ch = self.chars.get(index)?;
+
+        ch.baked.is_none().then_some(ch.sprite.image_source_id)
+    }
 }
 
 impl CharacterSprite {
+    pub fn size(&self) -> ISize {
+        match self.baked.as_ref() {
+            Some(image) => ISize::new(image.width() as i32, image.height() as i32),
+            None => self.sprite.size(),
+        }
+    }
+
     pub fn get_sprite_texture_view<'s>(
-        &self,
+        &'s self,
         srcs: &'s Sources,
     ) -> anyhow::Result<SubImage<&'s image::RgbaImage>> {
-        self.sprite.get_image(srcs)
+        match self.baked.as_ref() {
+            Some(image) => Ok(image.view(0, 0, image.width(), image.height())),
+            None => self.sprite.get_image(srcs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{
+        parse_char_codes, CharacterSprite, DynamicSlots, FontIntermediate, MetricConflicts,
+        Monospace,
+    };
+    use crate::{
+        font_shared::{DecorationMetrics, FontMetrics},
+        intermediates::{effects::GlyphEffect, texture_atlas::Atlasable},
+        math::ISize,
+        sources::{SourceId, SourceSprite, Sources},
+        warnings::Warning,
+    };
+
+    fn char_sprite(char_code: u32, width: u32, height: u32) -> CharacterSprite {
+        CharacterSprite {
+            char_code,
+            sprite: SourceSprite {
+                image_source_id: SourceId::Image(0),
+                x: 0,
+                y: 0,
+                width: width as i32,
+                height: height as i32,
+            },
+            frame: 0,
+            x_offset: 1,
+            y_offset: 2,
+            x_advance: 4,
+            baked: Some(RgbaImage::from_pixel(
+                width,
+                height,
+                Rgba([255, 255, 255, 255]),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_measure_metrics() -> anyhow::Result<()> {
+        let mut x = char_sprite(120, 3, 5);
+        for pixel in x.baked.as_mut().unwrap().rows_mut().take(2).flatten() {
+            *pixel = Rgba([0, 0, 0, 0]);
+        }
+        let g = CharacterSprite {
+            y_offset: 4,
+            ..char_sprite(103, 3, 5)
+        };
+
+        let font = FontIntermediate::from_chars(
+            "test".to_string(),
+            8,
+            6,
+            vec![char_sprite(72, 3, 4), x, g, char_sprite(32, 0, 0)],
+        );
+
+        assert_eq!(
+            font.measure_metrics(&Sources::new())?,
+            FontMetrics {
+                ascent: 4,
+                descent: 3,
+                cap_height: Some(4),
+                x_height: Some(2),
+            }
+        );
+
+        let line = font
+            .with_decorations(DecorationMetrics {
+                underline_position: 1,
+                underline_thickness: 2,
+                strikethrough_position: -2,
+            })
+            .line_sprite();
+        assert_eq!(line.name, "test#line");
+        assert_eq!(line.frames[0].size(), ISize::new(1, 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_invisible_chars() -> anyhow::Result<()> {
+        let mut font = FontIntermediate::from_chars(
+            "test".to_string(),
+            8,
+            6,
+            [0xfeff, 65, 10, 0x200b, 9, 0x2424]
+                .map(|code| char_sprite(code, 3, 4))
+                .to_vec(),
+        );
+
+        let keep = parse_char_codes("10, U+2424")?;
+        assert_eq!(keep, [10, 0x2424]);
+        assert!(parse_char_codes("newline").is_err());
+
+        let warning = font.skip_invisible_chars(&keep);
+        assert_eq!(
+            warning,
+            Some(Warning::SkippedInvisibleChars {
+                font_name: "test".to_string(),
+                char_codes: vec![9, 0x200b, 0xfeff],
+            })
+        );
+
+        let codes: Vec<_> = font.chars.iter().map(|ch| ch.char_code).collect();
+        assert_eq!(codes, [65, 10, 0x2424]);
+        assert_eq!(font.skip_invisible_chars(&keep), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_text_bounds() -> anyhow::Result<()> {
+        // An accented capital reaching 3px above the line box and a
+        // descender reaching below it
+        let e_acute = CharacterSprite {
+            y_offset: -3,
+            ..char_sprite('É' as u32, 3, 10)
+        };
+        let g = CharacterSprite {
+            y_offset: 4,
+            ..char_sprite('g' as u32, 3, 6)
+        };
+        let font = FontIntermediate::from_chars(
+            "test".to_string(),
+            8,
+            6,
+            vec![e_acute, char_sprite('A' as u32, 3, 4), g],
+        );
+        let srcs = Sources::new();
+
+        let image = font.bake_text("ÉAg", &srcs)?;
+        assert_eq!((image.width(), image.height()), (12, 13));
+
+        // Nothing is clipped
+        let opaque = image.pixels().filter(|pixel| pixel[3] > 0).count();
+        assert_eq!(opaque, 3 * 10 + 3 * 4 + 3 * 6);
+        assert_eq!(image.get_pixel(1, 0)[3], 255);
+        assert_eq!(image.get_pixel(9, 12)[3], 255);
+
+        // The base line moves down with the line box
+        let image = font.render_text("ÉAg", &srcs)?;
+        assert_eq!(*image.get_pixel(0, 9), Rgba([128, 128, 128, 255]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_monospace() -> anyhow::Result<()> {
+        let wide = CharacterSprite {
+            x_advance: 7,
+            ..char_sprite(87, 6, 5)
+        };
+        let font = FontIntermediate::from_chars(
+            "test".to_string(),
+            8,
+            6,
+            vec![char_sprite(105, 1, 5), wide, char_sprite(32, 0, 0)],
+        );
+
+        assert!(font.clone().with_monospace(Monospace::Validate).is_err());
+
+        let font = font.with_monospace(Monospace::Force)?;
+        assert_eq!(font.cell_width, Some(7));
+        assert!(font.chars.iter().all(|ch| ch.x_advance == 7));
+        // Narrower chars are centered in the cell
+        assert_eq!(font.chars[0].x_offset, 2);
+        assert_eq!(font.chars[1].x_offset, 1);
+
+        // Already monospaced now
+        font.with_monospace(Monospace::Validate)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_frame_metrics() -> anyhow::Result<()> {
+        let shifted = CharacterSprite {
+            frame: 1,
+            y_offset: 3,
+            ..char_sprite(65, 3, 5)
+        };
+        let mut font = FontIntermediate {
+            num_frames: 2,
+            ..FontIntermediate::from_chars(
+                "test".to_string(),
+                8,
+                6,
+                vec![
+                    char_sprite(65, 3, 5),
+                    shifted,
+                    char_sprite(66, 3, 5),
+                    CharacterSprite {
+                        frame: 1,
+                        ..char_sprite(66, 3, 5)
+                    },
+                ],
+            )
+        };
+
+        let error = font
+            .clone()
+            .check_frame_metrics(MetricConflicts::Error)
+            .unwrap_err();
+        assert!(format!("{error:#}").contains(
+            "Frame 1 of character 'A' (#65) of font 'test' has offset (1, 3) and advance 4, but frame 0 has offset (1, 2)"
+        ));
+
+        let warnings = font.check_frame_metrics(MetricConflicts::FirstFrame)?;
+        assert_eq!(warnings.len(), 1);
+        assert!(font.chars.iter().all(|ch| ch.y_offset == 2));
+
+        // Nothing left to resolve
+        assert!(font.check_frame_metrics(MetricConflicts::Error)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_effect_frames() -> anyhow::Result<()> {
+        let font = FontIntermediate::from_chars(
+            "test".to_string(),
+            8,
+            6,
+            vec![char_sprite(65, 3, 5), char_sprite(32, 0, 0)],
+        );
+
+        let effect = GlyphEffect::Outline {
+            thickness: 1,
+            color: [0, 0, 0, 255],
+        };
+        let srcs = Sources::new();
+        let font = font.with_effect_frames(effect, &srcs)?;
+
+        assert_eq!(font.num_frames, 2);
+        assert_eq!(
+            font.get_sprite_sizes(),
+            vec![
+                ISize::new(5, 7),
+                ISize::new(0, 0),
+                ISize::new(5, 7),
+                ISize::new(0, 0)
+            ]
+        );
+
+        let frames: Vec<_> = font
+            .chars
+            .iter()
+            .map(|ch| (ch.char_code, ch.frame, ch.x_offset, ch.y_offset))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![(65, 0, 0, 1), (32, 0, 1, 2), (65, 1, 0, 1), (32, 1, 1, 2)]
+        );
+
+        // The plain frame only got an empty border, the effect frame an outline
+        let plain = font.get_sprite_texture(0, &srcs)?;
+        let outlined = font.get_sprite_texture(2, &srcs)?;
+        assert_eq!(plain.get_pixel(0, 0)[3], 0);
+        assert_eq!(*plain.get_pixel(1, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(*outlined.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*outlined.get_pixel(1, 1), Rgba([255, 255, 255, 255]));
+
+        assert!(font.with_effect_frames(effect, &srcs).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_dynamic_slots() -> anyhow::Result<()> {
+        assert_eq!(
+            "64:8x12".parse::<DynamicSlots>()?,
+            DynamicSlots {
+                count: 64,
+                cell_size: ISize::new(8, 12),
+            }
+        );
+
+        assert!("64".parse::<DynamicSlots>().is_err());
+        assert!("64:8".parse::<DynamicSlots>().is_err());
+        assert!("64:0x12".parse::<DynamicSlots>().is_err());
+        assert!("-1:8x12".parse::<DynamicSlots>().is_err());
+
+        Ok(())
     }
 }
@@ -0,0 +1,194 @@
+use anyhow::Context;
+use image::GenericImageView;
+
+use crate::{
+    math::ISize,
+    sources::{SourceId, Sources},
+};
+
+use super::{
+    font::FontIntermediate,
+    texture_atlas::{Atlasable, SpriteTexture},
+};
+
+/// A piece of text pre-rendered with a font for one language.
+#[derive(Debug, Clone)]
+pub struct BakedLabel {
+    pub key: String,
+    pub language: String,
+    pub text: String,
+    pub image: image::RgbaImage,
+}
+
+/// All localized variants of a set of labels, rendered with the same font.
+#[derive(Debug, Clone)]
+pub struct LabelSetIntermediate {
+    pub font_name: String,
+    pub labels: Vec<BakedLabel>,
+}
+
+impl BakedLabel {
+    /// The name of the sprite of this variant, e.g. `label.title.en`.
+    pub fn sprite_name(&self) -> String {
+        format!("{}.{}", self.key, self.language)
+    }
+}
+
+impl LabelSetIntermediate {
+    pub fn from_localizations(
+        font: &FontIntermediate,
+        localization_src_ids: &[SourceId],
+        srcs: &Sources,
+    ) -> anyhow::Result<Self> {
+        let mut labels = vec![];
+
+        for &src_id in localization_src_ids {
+            let localization = srcs
+                .get_localization(src_id)
+                .with_context(|| format!("Failed to load localization file {src_id:?}"))?;
+
+            for (key, text) in localization.entries.iter() {
+                let image = font.bake_text(text, srcs).with_context(|| {
+                    format!(
+                        "Failed to bake label '{key}' for language '{}' with font '{}'",
+                        localization.language, font.name,
+                    )
+                })?;
+
+                labels.push(BakedLabel {
+                    key: key.clone(),
+                    language: localization.language.clone(),
+                    text: text.clone(),
+                    image,
+                });
+            }
+        }
+
+        Ok(Self {
+            font_name: font.name.clone(),
+            labels,
+        })
+    }
+}
+
+impl Atlasable for LabelSetIntermediate {
+    fn get_sprite_sizes(&self) -> Vec<ISize> {
+        self.labels
+            .iter()
+            .map(|label| ISize::new(label.image.width() as i32, label.image.height() as i32))
+            .collect()
+    }
+
+    fn get_sprite_texture(
+        &self,
+        index: usize,
+        _srcs: &Sources,
+    ) -> anyhow::Result<image::RgbaImage> {
+        Ok(self.labels[index].image.clone())
+    }
+
+    fn get_sprite_view<'s>(
+        &'s self,
+        index: usize,
+        _srcs: &'s Sources,
+    ) -> anyhow::Result<SpriteTexture<'s>> {
+        let image = &self.labels[index].image;
+
+        Ok(SpriteTexture::View(image.view(
+            0,
+            0,
+            image.width(),
+            image.height(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use image::{Rgba, RgbaImage};
+
+    use super::LabelSetIntermediate;
+    use crate::{
+        intermediates::font::{CharacterSprite, FontIntermediate},
+        sources::{SharedSources, SourceId, SourceSprite},
+    };
+
+    fn test_font() -> FontIntermediate {
+        let glyph = |ch: char, width: u32| CharacterSprite {
+            char_code: ch as u32,
+            sprite: SourceSprite {
+                image_source_id: SourceId::Image(0),
+                x: 0,
+                y: 0,
+                width: width as i32,
+                height: 5,
+            },
+            frame: 0,
+            x_offset: 0,
+            y_offset: 1,
+            x_advance: width as i32 + 1,
+            baked: Some(RgbaImage::from_pixel(width, 5, Rgba([255, 255, 255, 255]))),
+        };
+
+        FontIntermediate::from_chars(
+            "small".to_string(),
+            8,
+            6,
+            vec![glyph('A', 3), glyph('B', 4), glyph(' ', 0)],
+        )
+    }
+
+    #[test]
+    fn test_from_localizations() -> anyhow::Result<()> {
+        let srcs = SharedSources::default();
+        let localization_ids = [
+            ("en.lang", "label.start = \"A B\"\nlabel.back = \"B\""),
+            ("de.lang", "label.start = \"AA\""),
+        ]
+        .map(|(file_name, contents)| {
+            srcs.try_load_source_bytes(
+                PathBuf::from(file_name),
+                file_name.to_string(),
+                contents.as_bytes(),
+            )
+        });
+        let localization_ids = localization_ids
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let srcs = srcs.into_inner();
+
+        let font = test_font();
+        let label_set = LabelSetIntermediate::from_localizations(&font, &localization_ids, &srcs)?;
+        assert_eq!(label_set.font_name, "small");
+
+        // Every entry of every language in order, keys sorted per file
+        let names: Vec<_> = label_set
+            .labels
+            .iter()
+            .map(|label| label.sprite_name())
+            .collect();
+        assert_eq!(names, ["label.back.en", "label.start.en", "label.start.de"]);
+        assert_eq!(label_set.labels[2].text, "AA");
+
+        // The labels are baked like the font renders them
+        for label in label_set.labels.iter() {
+            assert_eq!(label.image, font.bake_text(&label.text, &srcs)?);
+        }
+        assert_eq!(label_set.labels[1].image.width(), 9);
+
+        // A char the font doesn't have names the label
+        let srcs = SharedSources::default();
+        let id = srcs.try_load_source_bytes(
+            PathBuf::from("fr.lang"),
+            "fr.lang".to_string(),
+            "label.start = \"C\"".as_bytes(),
+        )?;
+        let err =
+            LabelSetIntermediate::from_localizations(&font, &[id], &srcs.into_inner()).unwrap_err();
+        assert!(format!("{err:#}").contains("label 'label.start' for language 'fr'"));
+
+        Ok(())
+    }
+}
@@ -1,2 +1,7 @@
+pub mod atlas_layout;
+pub mod effects;
 pub mod font;
+pub mod label;
+pub mod resample;
+pub mod sprite;
 pub mod texture_atlas;
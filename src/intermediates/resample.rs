@@ -0,0 +1,130 @@
+use anyhow::Context;
+use image::{imageops, GenericImageView, Rgba, RgbaImage};
+
+use crate::error::Ewwow;
+
+/// The alpha above which a pixel counts as covered, for
+/// [`Resampler::Coverage`].
+const COVERAGE_THRESHOLD: u8 = 128;
+
+/// How sprites are scaled to another design scale, see
+/// [`super::sprite::SpriteIntermediate::with_target_scale`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Resampler {
+    /// Bilinear filtering.
+    #[default]
+    Linear,
+    /// Sharper, but can ring around hard edges.
+    Lanczos,
+    /// Bilinear, then alpha is scaled so the same share of pixels is above
+    /// half alpha as before. Keeps thin, partly transparent details like
+    /// foliage or hair from thinning out when downscaled.
+    Coverage,
+}
+
+impl std::str::FromStr for Resampler {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "lanczos" => Ok(Self::Lanczos),
+            "coverage" => Ok(Self::Coverage),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Invalid resampler '{s}', expected linear, lanczos or coverage")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl Resampler {
+    pub fn resize<I>(self, image: &I, width: u32, height: u32) -> RgbaImage
+    where
+        I: GenericImageView<Pixel = Rgba<u8>>,
+    {
+        match self {
+            Resampler::Linear => {
+                imageops::resize(image, width, height, imageops::FilterType::Triangle)
+            }
+            Resampler::Lanczos => {
+                imageops::resize(image, width, height, imageops::FilterType::Lanczos3)
+            }
+            Resampler::Coverage => {
+                let target = coverage(image.pixels().map(|(_, _, pixel)| pixel[3]), 1.0);
+
+                let mut resized =
+                    imageops::resize(image, width, height, imageops::FilterType::Triangle);
+                let alphas: Vec<u8> = resized.pixels().map(|pixel| pixel[3]).collect();
+
+                // Coverage only grows with the alpha scale, so search for the
+                // smallest scale that reaches the original coverage
+                let (mut low, mut high) = (0.0f32, 4.0f32);
+                for _ in 0..16 {
+                    let mid = (low + high) / 2.0;
+                    match coverage(alphas.iter().copied(), mid) < target {
+                        true => low = mid,
+                        false => high = mid,
+                    }
+                }
+
+                for pixel in resized.pixels_mut() {
+                    pixel[3] = (pixel[3] as f32 * high).round().min(255.0) as u8;
+                }
+
+                resized
+            }
+        }
+    }
+}
+
+/// The share of `alphas` that are above [`COVERAGE_THRESHOLD`] after scaling
+/// them by `scale`.
+fn coverage(alphas: impl Iterator<Item = u8>, scale: f32) -> f32 {
+    let (mut covered, mut total) = (0usize, 0usize);
+
+    for alpha in alphas {
+        total += 1;
+        if (alpha as f32 * scale).round() >= COVERAGE_THRESHOLD as f32 {
+            covered += 1;
+        }
+    }
+
+    match total {
+        0 => 0.0,
+        _ => covered as f32 / total as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{coverage, Resampler};
+
+    #[test]
+    fn test_coverage_resampler() {
+        // Sparse single pixels, like the tips of leaves
+        let image = RgbaImage::from_fn(32, 32, |x, y| match (x * 7 + y * 13) % 5 {
+            0 => Rgba([40, 30, 20, 255]),
+            _ => Rgba([40, 30, 20, 0]),
+        });
+        let original = coverage(image.pixels().map(|pixel| pixel[3]), 1.0);
+
+        let linear = Resampler::Linear.resize(&image, 16, 16);
+        let preserved = Resampler::Coverage.resize(&image, 16, 16);
+
+        let covered = |image: &RgbaImage| coverage(image.pixels().map(|pixel| pixel[3]), 1.0);
+        // Linear filtering blurs almost every pixel below half alpha
+        assert!(covered(&linear) < original / 2.0);
+        assert!((covered(&preserved) - original).abs() < 0.1);
+
+        // Colors are untouched
+        assert!(preserved.pixels().all(|pixel| pixel.0[..3] == [40, 30, 20]));
+
+        assert!("box".parse::<Resampler>().is_err());
+    }
+}
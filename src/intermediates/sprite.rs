@@ -0,0 +1,576 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use glam::{IVec2, Vec2};
+use image::{GenericImageView, RgbaImage, SubImage};
+
+use crate::{
+    error::Ewwow,
+    inputs::{procedural::ProceduralSprite, slices::SlicesFile, unity_meta::UnitySpriteSheet},
+    math::{IMargins, IRect, ISize, RoundingPolicy},
+    sources::{SourceId, SourceSprite, Sources},
+};
+
+use super::{
+    effects::{Flip, SpriteDerivation},
+    resample::Resampler,
+    texture_atlas::{Atlasable, SpriteTexture},
+};
+
+/// A named sprite with one or more animation frames.
+#[derive(Debug, Clone)]
+pub struct SpriteIntermediate {
+    pub name: String,
+    pub frames: Vec<SpriteFrame>,
+    pub derived_from: Option<DerivedFrom>,
+    /// The design scale the frames were drawn at, e.g. 2 for `hero@2x.png`.
+    /// Their logical size is their pixel size divided by it.
+    pub scale: f32,
+    /// Game specific attributes, exported verbatim, see
+    /// [`crate::inputs::user_data::UserDataFile`].
+    pub user_data: BTreeMap<String, String>,
+    pub slicing: Option<SpriteSlicing>,
+    /// Set if the transparent borders of the frames were cut off, see
+    /// [`Self::trimmed`].
+    pub trim: Option<SpriteTrim>,
+}
+
+/// Where a sprite is anchored and how it stretches as a 9-slice.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SpriteSlicing {
+    /// Relative to the frame size, (0, 0) is the top left corner.
+    pub pivot: Vec2,
+    /// The borders that don't stretch, in pixels.
+    pub border: IMargins,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpriteFrame {
+    Source(SourceSprite),
+    /// Pixels generated during the build, e.g. by a [`SpriteDerivation`].
+    Baked(RgbaImage),
+}
+
+/// Where a derived sprite like `hero#shadow` came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedFrom {
+    pub sprite_name: String,
+    pub derivation: SpriteDerivation,
+    /// The position of the top left corner of every frame relative to the
+    /// one of the same frame of the original sprite.
+    pub offset: IVec2,
+}
+
+/// How [`SpriteIntermediate::trimmed`] cuts off the fully transparent borders
+/// of frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrimMode {
+    /// Every frame to its own bounds. Smallest, but animations jitter unless
+    /// they are drawn with the offset of every frame.
+    #[default]
+    PerFrame,
+    /// All frames of a sprite to the union of their bounds, so they keep a
+    /// common size and offset and animations stay put.
+    Group,
+}
+
+impl std::str::FromStr for TrimMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-frame" => Ok(Self::PerFrame),
+            "group" => Ok(Self::Group),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Invalid trim mode '{s}', expected per-frame or group")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// What was cut off the frames of a trimmed sprite, to draw them where the
+/// untrimmed frames would have been.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SpriteTrim {
+    /// The size of the untrimmed frames.
+    pub source_size: ISize,
+    /// Where the top left corner of the trimmed frames was in the untrimmed
+    /// ones, shared by all frames.
+    pub offset: IVec2,
+    /// The offset of every frame instead, if they were trimmed with
+    /// [`TrimMode::PerFrame`] to different bounds. Empty otherwise.
+    pub frame_offsets: Vec<IVec2>,
+}
+
+/// A mirrored variant like `hero#flip-x` that shares the frames of its
+/// sprite instead of baking new ones, engines flip it when drawing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlippedSprite {
+    pub name: String,
+    pub sprite_name: String,
+    pub flip: Flip,
+}
+
+impl SpriteFrame {
+    pub fn size(&self) -> ISize {
+        match self {
+            SpriteFrame::Source(sprite) => sprite.size(),
+            SpriteFrame::Baked(image) => ISize::new(image.width() as i32, image.height() as i32),
+        }
+    }
+
+    pub fn get_image<'s>(&'s self, srcs: &'s Sources) -> anyhow::Result<SubImage<&'s RgbaImage>> {
+        match self {
+            SpriteFrame::Source(sprite) => sprite.get_image(srcs),
+            SpriteFrame::Baked(image) => Ok(image.view(0, 0, image.width(), image.height())),
+        }
+    }
+}
+
+impl SpriteIntermediate {
+    /// A sprite of `frames` as they are: not derived, unscaled and without
+    /// user data, slicing or trimming.
+    pub fn from_frames(name: String, frames: Vec<SpriteFrame>) -> Self {
+        Self {
+            name,
+            frames,
+            derived_from: None,
+            scale: 1.0,
+            user_data: BTreeMap::new(),
+            slicing: None,
+            trim: None,
+        }
+    }
+
+    /// A single frame sprite showing the whole image.
+    pub fn from_image(
+        name: String,
+        image_src_id: SourceId,
+        srcs: &Sources,
+    ) -> anyhow::Result<Self> {
+        let image = srcs
+            .get_image(image_src_id)
+            .with_context(|| format!("Failed to load image of sprite '{name}'"))?;
+
+        Ok(Self::from_frames(
+            name,
+            vec![SpriteFrame::Source(SourceSprite {
+                image_source_id: image_src_id,
+                x: 0,
+                y: 0,
+                width: image.width() as i32,
+                height: image.height() as i32,
+            })],
+        ))
+    }
+
+    /// A single frame sprite with the generated pixels of `sprite`.
+    pub fn from_procedural(sprite: &ProceduralSprite) -> Self {
+        Self::from_frames(
+            sprite.name.clone(),
+            vec![SpriteFrame::Baked(sprite.render())],
+        )
+    }
+
+    /// One single frame sprite per slice of `sheet`, cut from the image the
+    /// `.meta` file belongs to.
+    pub fn from_unity_sheet(
+        sheet: &UnitySpriteSheet,
+        image_src_id: SourceId,
+        srcs: &Sources,
+    ) -> anyhow::Result<Vec<Self>> {
+        let image = srcs
+            .get_image(image_src_id)
+            .context("Failed to load image of Unity sprite sheet")?;
+        let image_height = image.height() as i32;
+
+        sheet
+            .sprites
+            .iter()
+            .map(|sprite| {
+                Self::from_slice(
+                    &sprite.name,
+                    image_src_id,
+                    sprite.top_down_rect(image_height),
+                    Some(SpriteSlicing {
+                        pivot: sprite.top_down_pivot(),
+                        border: sprite.border,
+                    }),
+                    srcs,
+                )
+            })
+            .collect()
+    }
+
+    /// One single frame sprite per slice of `file`, cut from the image it
+    /// belongs to.
+    pub fn from_slices(
+        file: &SlicesFile,
+        image_src_id: SourceId,
+        srcs: &Sources,
+    ) -> anyhow::Result<Vec<Self>> {
+        file.slices
+            .iter()
+            .map(|slice| {
+                let slicing =
+                    (slice.pivot.is_some() || slice.border.is_some()).then(|| SpriteSlicing {
+                        pivot: slice.pivot.unwrap_or(Vec2::splat(0.5)),
+                        border: slice.border.unwrap_or_default(),
+                    });
+
+                Self::from_slice(&slice.name, image_src_id, slice.rect, slicing, srcs)
+            })
+            .collect()
+    }
+
+    /// A single frame sprite of the pixels in `rect` of an image, which has
+    /// to be inside of it.
+    fn from_slice(
+        name: &str,
+        image_src_id: SourceId,
+        rect: IRect,
+        slicing: Option<SpriteSlicing>,
+        srcs: &Sources,
+    ) -> anyhow::Result<Self> {
+        let image = srcs.get_image(image_src_id)?;
+        let (image_width, image_height) = (image.width() as i32, image.height() as i32);
+
+        if rect.min.x < 0 || rect.min.y < 0 || rect.max.x > image_width || rect.max.y > image_height
+        {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Sprite '{name}' at {rect:?} is outside of the {image_width}x{image_height} image"
+                )
+            })?;
+        }
+
+        Ok(Self {
+            slicing,
+            ..Self::from_frames(
+                name.to_string(),
+                vec![SpriteFrame::Source(SourceSprite {
+                    image_source_id: image_src_id,
+                    x: rect.min.x,
+                    y: rect.min.y,
+                    width: rect.width(),
+                    height: rect.height(),
+                })],
+            )
+        })
+    }
+
+    /// The sprite with the fully transparent borders of its frames cut off,
+    /// `None` if there's nothing to cut or it can't be trimmed: frames of
+    /// different sizes have no common source size, and 9-slice borders need
+    /// the whole frame.
+    pub fn trimmed(&self, mode: TrimMode, srcs: &Sources) -> anyhow::Result<Option<Self>> {
+        let Some(source_size) = self.frames.first().map(|frame| frame.size()) else {
+            return Ok(None);
+        };
+        if self.slicing.is_some()
+            || self.trim.is_some()
+            || self.frames.iter().any(|frame| frame.size() != source_size)
+        {
+            return Ok(None);
+        }
+
+        let mut bounds = Vec::with_capacity(self.frames.len());
+        for (index, frame) in self.frames.iter().enumerate() {
+            let image = frame.get_image(srcs).with_context(|| {
+                format!("Failed to get frame #{index} of sprite '{}'", self.name)
+            })?;
+
+            // Fully transparent frames are kept whole
+            bounds.push(
+                opaque_bounds(&*image).unwrap_or(IRect::from_min_size(IVec2::ZERO, source_size)),
+            );
+        }
+
+        if mode == TrimMode::Group {
+            let union = bounds.iter().copied().reduce(IRect::union);
+            bounds.fill(union.unwrap_or_default());
+        }
+
+        if bounds.iter().all(|rect| rect.size() == source_size) {
+            return Ok(None);
+        }
+
+        let frames = self
+            .frames
+            .iter()
+            .zip(bounds.iter())
+            .map(|(frame, rect)| match frame {
+                SpriteFrame::Source(sprite) => SpriteFrame::Source(SourceSprite {
+                    x: sprite.x + rect.min.x,
+                    y: sprite.y + rect.min.y,
+                    width: rect.width(),
+                    height: rect.height(),
+                    ..*sprite
+                }),
+                SpriteFrame::Baked(image) => SpriteFrame::Baked(
+                    image
+                        .view(
+                            rect.min.x as u32,
+                            rect.min.y as u32,
+                            rect.uwidth(),
+                            rect.uheight(),
+                        )
+                        .to_image(),
+                ),
+            })
+            .collect();
+
+        let shared = bounds.iter().all(|rect| rect.min == bounds[0].min);
+        let trim = SpriteTrim {
+            source_size,
+            offset: match shared {
+                true => bounds[0].min,
+                false => IVec2::ZERO,
+            },
+            frame_offsets: match shared {
+                true => vec![],
+                false => bounds.iter().map(|rect| rect.min).collect(),
+            },
+        };
+
+        Ok(Some(Self {
+            frames,
+            trim: Some(trim),
+            ..self.clone()
+        }))
+    }
+
+    /// Splits a design scale suffix off a sprite name, e.g. `hero@2x` is
+    /// `hero` at scale 2. Names without one are at scale 1.
+    pub fn split_scale_suffix(name: &str) -> (&str, f32) {
+        name.rsplit_once('@')
+            .and_then(|(base, suffix)| {
+                let scale: f32 = suffix.strip_suffix('x')?.parse().ok()?;
+                (scale > 0.0).then_some((base, scale))
+            })
+            .unwrap_or((name, 1.0))
+    }
+
+    /// Downscales every frame from the design scale of the sprite to
+    /// `target_scale` with `resampler`. Sprites that are already at or below
+    /// it are returned unchanged, upscaling would only blur them.
+    pub fn with_target_scale(
+        self,
+        target_scale: f32,
+        resampler: Resampler,
+        srcs: &Sources,
+    ) -> anyhow::Result<Self> {
+        if self.scale <= target_scale {
+            return Ok(self);
+        }
+
+        let factor = target_scale / self.scale;
+        let rounding = RoundingPolicy::default();
+        let mut frames = Vec::with_capacity(self.frames.len());
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            let image = frame.get_image(srcs).with_context(|| {
+                format!("Failed to get frame #{index} of sprite '{}'", self.name)
+            })?;
+            let size = frame.size().scaled(factor, rounding);
+
+            frames.push(SpriteFrame::Baked(resampler.resize(
+                &*image,
+                size.width as u32,
+                size.height as u32,
+            )));
+        }
+
+        // Pivots are relative and stay put, borders shrink with the frames
+        let slicing = self.slicing.map(|slicing| SpriteSlicing {
+            border: slicing.border.scaled(factor, rounding),
+            ..slicing
+        });
+
+        Ok(Self {
+            frames,
+            scale: target_scale,
+            slicing,
+            ..self
+        })
+    }
+
+    /// A new sprite named `<name>#<suffix>` with `derivation` applied to every
+    /// frame, e.g. `hero#shadow`.
+    pub fn derive(&self, derivation: SpriteDerivation, srcs: &Sources) -> anyhow::Result<Self> {
+        let mut frames = Vec::with_capacity(self.frames.len());
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            let image = frame.get_image(srcs).with_context(|| {
+                format!("Failed to get frame #{index} of sprite '{}'", self.name)
+            })?;
+
+            frames.push(SpriteFrame::Baked(derivation.apply(&*image)));
+        }
+
+        let margin = derivation.margin() as i32;
+
+        Ok(Self {
+            name: format!("{}#{}", self.name, derivation.suffix()),
+            frames,
+            derived_from: Some(DerivedFrom {
+                sprite_name: self.name.clone(),
+                derivation,
+                offset: IVec2::splat(-margin),
+            }),
+            scale: self.scale,
+            user_data: BTreeMap::new(),
+            slicing: None,
+            trim: None,
+        })
+    }
+}
+
+/// The smallest rect around all pixels of `image` that aren't fully
+/// transparent, `None` if there are none.
+fn opaque_bounds<I>(image: &I) -> Option<IRect>
+where
+    I: GenericImageView<Pixel = image::Rgba<u8>>,
+{
+    image
+        .pixels()
+        .filter(|(_, _, pixel)| pixel[3] > 0)
+        .map(|(x, y, _)| IRect::new(x as i32, y as i32, 1, 1))
+        .reduce(IRect::union)
+}
+
+impl Atlasable for SpriteIntermediate {
+    fn get_sprite_sizes(&self) -> Vec<ISize> {
+        self.frames.iter().map(|frame| frame.size()).collect()
+    }
+
+    fn get_sprite_texture(&self, index: usize, srcs: &Sources) -> anyhow::Result<image::RgbaImage> {
+        Ok(self.frames[index]
+            .get_image(srcs)
+            .with_context(|| format!("Failed to get frame #{index} of sprite '{}'", self.name))?
+            .to_image())
+    }
+
+    fn get_sprite_view<'s>(
+        &'s self,
+        index: usize,
+        srcs: &'s Sources,
+    ) -> anyhow::Result<SpriteTexture<'s>> {
+        let view = self.frames[index]
+            .get_image(srcs)
+            .with_context(|| format!("Failed to get frame #{index} of sprite '{}'", self.name))?;
+
+        Ok(SpriteTexture::View(view))
+    }
+
+    fn get_sprite_source(&self, index: usize) -> Option<SourceId> {
+        match self.frames.get(index)? {
+            SpriteFrame::Source(sprite) => Some(sprite.image_source_id),
+            SpriteFrame::Baked(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::IVec2;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    use super::{SpriteFrame, SpriteIntermediate, TrimMode};
+    use crate::{intermediates::resample::Resampler, math::ISize, sources::Sources};
+
+    #[test]
+    fn test_target_scale() -> anyhow::Result<()> {
+        assert_eq!(
+            SpriteIntermediate::split_scale_suffix("hero@2x"),
+            ("hero", 2.0)
+        );
+        assert_eq!(
+            SpriteIntermediate::split_scale_suffix("icon@1.5x"),
+            ("icon", 1.5)
+        );
+        for name in ["hero", "mail@home", "hero@x", "hero@0x"] {
+            assert_eq!(SpriteIntermediate::split_scale_suffix(name), (name, 1.0));
+        }
+
+        let sprite = SpriteIntermediate {
+            scale: 2.0,
+            ..SpriteIntermediate::from_frames(
+                "hero".to_string(),
+                vec![
+                    SpriteFrame::Baked(RgbaImage::from_pixel(8, 6, Rgba([255, 0, 0, 255]))),
+                    SpriteFrame::Baked(RgbaImage::from_pixel(5, 5, Rgba([255, 0, 0, 255]))),
+                ],
+            )
+        };
+
+        let srcs = Sources::new();
+        let normalized = sprite
+            .clone()
+            .with_target_scale(1.0, Resampler::Linear, &srcs)?;
+        assert_eq!(normalized.scale, 1.0);
+        let sizes: Vec<_> = normalized.frames.iter().map(|frame| frame.size()).collect();
+        assert_eq!(sizes, [ISize::new(4, 3), ISize::new(3, 3)]);
+
+        // Never upscaled
+        let kept = sprite
+            .clone()
+            .with_target_scale(3.0, Resampler::Coverage, &srcs)?;
+        assert_eq!(kept.scale, 2.0);
+        assert_eq!(kept.frames, sprite.frames);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim() -> anyhow::Result<()> {
+        // A 2x2 dot moving right inside 8x8 frames
+        let frame = |x: u32| {
+            SpriteFrame::Baked(RgbaImage::from_fn(8, 8, |px, py| {
+                match (x..x + 2).contains(&px) && (3..5).contains(&py) {
+                    true => Rgba([255, 255, 255, 255]),
+                    false => Rgba([0, 0, 0, 0]),
+                }
+            }))
+        };
+        let sprite = SpriteIntermediate::from_frames("dot".to_string(), vec![frame(1), frame(4)]);
+        let srcs = Sources::new();
+
+        let per_frame = sprite.trimmed(TrimMode::PerFrame, &srcs)?.unwrap();
+        let sizes: Vec<_> = per_frame.frames.iter().map(|frame| frame.size()).collect();
+        assert_eq!(sizes, [ISize::new(2, 2), ISize::new(2, 2)]);
+        let trim = per_frame.trim.unwrap();
+        assert_eq!(trim.source_size, ISize::new(8, 8));
+        assert_eq!(trim.frame_offsets, [IVec2::new(1, 3), IVec2::new(4, 3)]);
+
+        let group = sprite.trimmed(TrimMode::Group, &srcs)?.unwrap();
+        let sizes: Vec<_> = group.frames.iter().map(|frame| frame.size()).collect();
+        assert_eq!(sizes, [ISize::new(5, 2), ISize::new(5, 2)]);
+        let trim = group.trim.clone().unwrap();
+        assert_eq!(trim.offset, IVec2::new(1, 3));
+        assert!(trim.frame_offsets.is_empty());
+        // The dot is still where it was relative to the shared offset
+        let image = group.frames[1].get_image(&srcs)?;
+        assert_eq!(image.get_pixel(3, 0)[3], 255);
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+
+        // Nothing to cut, or already cut
+        let opaque = SpriteIntermediate {
+            frames: vec![SpriteFrame::Baked(RgbaImage::from_pixel(
+                2,
+                2,
+                Rgba([255, 255, 255, 255]),
+            ))],
+            ..sprite.clone()
+        };
+        assert!(opaque.trimmed(TrimMode::PerFrame, &srcs)?.is_none());
+        assert!(group.trimmed(TrimMode::Group, &srcs)?.is_none());
+        assert!("all".parse::<TrimMode>().is_err());
+
+        Ok(())
+    }
+}
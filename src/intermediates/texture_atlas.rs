@@ -1,16 +1,305 @@
+use std::collections::{BTreeMap, HashMap};
+
 use anyhow::Context;
-use image::{GenericImage, RgbaImage};
+use image::{GenericImageView, RgbaImage, SubImage};
+use rayon::prelude::*;
+
+use crate::{
+    blit::{blit, coverage_to_alpha, extrude},
+    cancellation::CancellationToken,
+    error::Error,
+    hashing,
+    inputs::{
+        adjustments::AdjustmentsFile, flips::FlipsFile, materials::MaterialsFile,
+        palettes::PalettesFile, sprite_references::SpriteReferences, user_data::UserDataFile,
+    },
+    math::*,
+    packing::{self, PackAlgorithm, PackConfig},
+    sources::{SourceId, Sources},
+    warnings::Warning,
+};
+
+use super::{
+    atlas_layout::{AssetRegion, AtlasLayout},
+    effects::{self, SpriteDerivation},
+    font::FontIntermediate,
+    label::LabelSetIntermediate,
+    resample::Resampler,
+    sprite::{FlippedSprite, SpriteFrame, SpriteIntermediate, TrimMode},
+};
+
+/// The name of the sprite added by [`TextureAtlas::inject_white_pixel`].
+pub const WHITE_PIXEL_SPRITE: &str = "white_pixel";
+
+/// What [`TextureAtlas::prune`] removed.
+#[derive(Debug, Clone, Default)]
+pub struct PruneReport {
+    pub removed: Vec<String>,
+    /// The number of pixels the removed sprites would have taken up,
+    /// including their padding.
+    pub saved_area: i64,
+}
+
+/// How much texture memory rounding the atlas up to a power of two costs,
+/// see [`TextureAtlas::pot_cost_report`].
+#[derive(Debug, Clone, Default)]
+pub struct PotCostReport {
+    pub size: ISize,
+    /// The size the packed sprites actually reach out to.
+    pub used_size: ISize,
+    /// The area of all padded sprites.
+    pub sprite_area: i64,
+    /// The bytes of RGBA8 texture memory outside of `used_size`.
+    pub wasted_bytes: i64,
+    pub suggestions: Vec<String>,
+}
+
+/// How much of the packed atlas the sprites cover and where the rest went,
+/// see [`TextureAtlas::occupancy_report`].
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct OccupancyReport {
+    pub size: ISize,
+    /// 1 if the sprites weren't packed into layers.
+    pub layers: u32,
+    /// The area of all sprites, without their padding.
+    pub sprite_area: i64,
+    /// The area of the padding around the sprites.
+    pub padding_area: i64,
+    /// `sprite_area` over the area of all layers, from 0 to 1.
+    pub occupancy: f64,
+    /// The padded sprites grouped into rows by their top edge, like
+    /// [`PackAlgorithm::Rows`] places them.
+    pub rows: Vec<RowWaste>,
+    /// The largest rect that no padded sprite reaches into, and its layer.
+    pub largest_empty: (u32, IRect),
+    pub suggestions: Vec<String>,
+}
+
+/// The padded sprites of an [`OccupancyReport`] starting at the same `y`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct RowWaste {
+    pub layer: u32,
+    pub y: i32,
+    /// The height of the tallest padded sprite.
+    pub height: i32,
+    pub sprites: usize,
+    /// The pixels of the row from margin to margin that no padded sprite
+    /// covers.
+    pub empty_area: i64,
+}
+
+/// What [`TextureAtlas::clustering`] places next to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Clustering {
+    /// Sprites cut from the same source image, e.g. the page of a font.
+    SourceSheet,
+    /// Sprites of a similar average color.
+    AverageColor,
+}
+
+impl std::str::FromStr for Clustering {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sheet" => Ok(Self::SourceSheet),
+            "color" => Ok(Self::AverageColor),
+            _ => {
+                Error::InvalidOption(format!("Invalid clustering '{s}', expected sheet or color"))
+                    .raise()?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// How [`TextureAtlas::pack`] grows the atlas until everything fits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AtlasGrowth {
+    /// Doubles the width, then the height, so the atlas is either square or
+    /// twice as wide as high.
+    #[default]
+    Square,
+    /// Tries every power of two shape of the same area, widest first, before
+    /// doubling the area, e.g. 2048x512 for a strip of wide sprites.
+    Free,
+}
+
+impl std::str::FromStr for AtlasGrowth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Self::Square),
+            "free" => Ok(Self::Free),
+            _ => {
+                Error::InvalidOption(format!(
+                    "Invalid atlas growth '{s}', expected square or free"
+                ))
+                .raise()?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// Which image sizes [`TextureAtlas::pack`] may produce.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SizeConstraint {
+    /// A power of two on both sides.
+    #[default]
+    ForcePot,
+    /// The same power of two on both sides.
+    ForceSquare,
+    /// Whatever the sprites need: packed like `ForcePot`, then cropped to the
+    /// area they actually use.
+    AnySize,
+}
 
-use crate::{error::Ewwow, math::*, sources::Sources};
+impl std::str::FromStr for SizeConstraint {
+    type Err = anyhow::Error;
 
-use super::font::FontIntermediate;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pot" => Ok(Self::ForcePot),
+            "square" => Ok(Self::ForceSquare),
+            "any" => Ok(Self::AnySize),
+            _ => {
+                Error::InvalidOption(format!(
+                    "Invalid size constraint '{s}', expected pot, square or any"
+                ))
+                .raise()?;
 
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// A target platform whose certification limits the size of its textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    Handheld,
+    Console,
+    Pc,
+}
+
+impl Platform {
+    /// The longest side a texture may have on the platform.
+    pub fn max_texture_size(self) -> i32 {
+        match self {
+            Platform::Handheld => 2048,
+            Platform::Console => 4096,
+            Platform::Pc => 8192,
+        }
+    }
+}
+
+impl std::str::FromStr for Platform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "handheld" => Ok(Self::Handheld),
+            "console" => Ok(Self::Console),
+            "pc" => Ok(Self::Pc),
+            _ => {
+                Error::InvalidOption(format!(
+                    "Invalid platform '{s}', expected handheld, console or pc"
+                ))
+                .raise()?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Platform::Handheld => write!(f, "handheld"),
+            Platform::Console => write!(f, "console"),
+            Platform::Pc => write!(f, "pc"),
+        }
+    }
+}
+
+/// The fonts, labels and sprites that go into one atlas, and how they are
+/// packed.
+///
+/// Add assets with [`Self::with_font`], [`Self::with_sprite`] and
+/// [`Self::with_label_set`], then call [`Self::load_sizes`] and
+/// [`Self::pack`]. Afterwards [`Self::layout`] says where everything went,
+/// [`Self::build_image`] draws the texture and
+/// [`crate::outputs::atlas_meta::AtlasMeta::from_texture_atlas`] exports the
+/// metadata.
 pub struct TextureAtlas {
     pub fonts: Vec<FontIntermediate>,
+    pub label_sets: Vec<LabelSetIntermediate>,
+    pub sprites: Vec<SpriteIntermediate>,
+    /// Variants that share the frames of a sprite in `sprites`, see
+    /// [`Self::flip_sprites`].
+    pub flipped_sprites: Vec<FlippedSprite>,
+    /// The names of the sprites of every material, see
+    /// [`Self::apply_materials`].
+    pub materials: BTreeMap<String, Vec<String>>,
+    /// Packs the sprites of every material into layers of their own, so a
+    /// layer never needs more than one material. Only supported by
+    /// [`Self::pack_layers`].
+    pub material_pages: bool,
     pub sprite_sizes: Vec<(usize, usize, ISize)>,
-    pub sprite_bounds: Vec<(usize, usize, IRect)>,
+    /// Sprites left out of `sprite_sizes` because they are pixel-identical
+    /// to another one, with the asset and sprite id of the duplicate and of
+    /// the sprite it shares the rect of, see [`Self::dedup_sprites`].
+    pub duplicate_sprites: Vec<((usize, usize), (usize, usize))>,
+    /// Empty pixels reserved around every sprite, see [`PackConfig`].
     pub padding: IMargins,
-    pub final_image_bounds: ISize,
+    /// Empty pixels between the padded cells of neighbouring sprites.
+    pub spacing: i32,
+    /// How far [`Self::build_image`] repeats the border pixels of every
+    /// sprite outward into its padding, so linear filtering doesn't bleed in
+    /// the neighbours. At most the padding of every side.
+    pub extrude: i32,
+    /// Empty pixels along the edges of the atlas, on top of the padding.
+    pub atlas_margin: i32,
+    /// The longest the long side of the atlas may be compared to its short
+    /// side, see [`ISize::aspect_ratio`]. At least 1.
+    pub max_aspect_ratio: Option<f32>,
+    /// The longest side [`Self::pack`] grows the atlas to, a power of two.
+    pub max_size: i32,
+    /// The platform whose texture size limit `max_size` may not exceed.
+    pub platform: Option<Platform>,
+    /// Which sides [`Self::pack`] grows.
+    pub growth: AtlasGrowth,
+    /// Which sizes [`Self::pack`] may end up with. Layers always have the
+    /// size they are packed into.
+    pub size_constraint: SizeConstraint,
+    /// Packs the glyphs of every font into their own contiguous block of the
+    /// atlas, e.g. for engines that stream fonts separately.
+    pub font_regions: bool,
+    /// Stores the coverage of glyphs in the alpha channel of white pixels,
+    /// filling the rest of every font region with transparent white, so fonts
+    /// can be sampled from the alpha channel alone. Colors of glyphs are
+    /// lost.
+    pub alpha_glyphs: bool,
+    /// Spills into pages of `max_size` with [`Self::pack_layers`] once the
+    /// sprites don't fit into a single one anymore.
+    pub pages: bool,
+    /// Packs similar sprites next to each other, which helps png compression
+    /// at the cost of some occupancy. Needs [`Self::load_clusters`]. Ignored
+    /// with `font_regions` or fonts with line shelves and by
+    /// [`Self::pack_layers`].
+    pub clustering: Option<Clustering>,
+    /// How the sprites are arranged. Clustered packing and line shelves
+    /// always use rows.
+    pub pack_algorithm: PackAlgorithm,
+    /// The cluster key of every sprite in `sprite_sizes`.
+    sprite_clusters: Vec<u64>,
+    pub layout: AtlasLayout,
+    pub warnings: Vec<Warning>,
     image_side_len_guess: u32,
 }
 
@@ -18,10 +307,30 @@ impl TextureAtlas {
     pub fn new(padding: IMargins) -> Self {
         Self {
             fonts: vec![],
+            label_sets: vec![],
+            sprites: vec![],
+            flipped_sprites: vec![],
+            materials: BTreeMap::new(),
+            material_pages: false,
             sprite_sizes: Vec::new(),
-            sprite_bounds: Vec::new(),
+            duplicate_sprites: vec![],
             padding,
-            final_image_bounds: ISize::default(),
+            spacing: 0,
+            extrude: 0,
+            atlas_margin: 0,
+            max_aspect_ratio: None,
+            max_size: 1024,
+            platform: None,
+            growth: AtlasGrowth::default(),
+            size_constraint: SizeConstraint::default(),
+            font_regions: false,
+            alpha_glyphs: false,
+            pages: false,
+            clustering: None,
+            pack_algorithm: PackAlgorithm::default(),
+            sprite_clusters: vec![],
+            layout: AtlasLayout::default(),
+            warnings: vec![],
             image_side_len_guess: 1,
         }
     }
@@ -30,221 +339,2858 @@ impl TextureAtlas {
         self.fonts.push(font);
     }
 
-    pub fn load_sizes(&mut self) {
-        self.sprite_sizes.clear();
+    pub fn with_label_set(&mut self, label_set: LabelSetIntermediate) {
+        self.label_sets.push(label_set);
+    }
 
-        let mut area = 0;
+    pub fn with_sprite(&mut self, sprite: SpriteIntermediate) {
+        self.sprites.push(sprite);
+    }
 
-        // Font asset indices start at 0
-        let mut font_sizes: Vec<_> = self
-            .fonts
-            .iter()
-            .enumerate()
-            .flat_map(|(asset_index, font)| {
-                font.get_sprite_sizes()
+    /// A new atlas with only the font `name` and the packing settings of this
+    /// one, for engines that stream fonts independently of the merged atlas.
+    /// It still needs to be sized and packed.
+    pub fn standalone_font(&self, name: &str) -> Option<TextureAtlas> {
+        let font = self.fonts.iter().find(|font| font.name == name)?;
+
+        let mut atlas = TextureAtlas::new(self.padding);
+        atlas.spacing = self.spacing;
+        atlas.extrude = self.extrude;
+        atlas.alpha_glyphs = self.alpha_glyphs;
+        atlas.atlas_margin = self.atlas_margin;
+        atlas.max_aspect_ratio = self.max_aspect_ratio;
+        atlas.max_size = self.max_size;
+        atlas.platform = self.platform;
+        atlas.with_font(font.clone());
+
+        Some(atlas)
+    }
+
+    /// Adds a sprite with `derivation` applied (e.g. `hero#shadow`) for every
+    /// sprite that `selection` references. Returns the names of the new
+    /// sprites.
+    pub fn derive_sprites(
+        &mut self,
+        derivation: SpriteDerivation,
+        selection: &SpriteReferences,
+        srcs: &Sources,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut derived = vec![];
+
+        for sprite in self.sprites.iter() {
+            if sprite.derived_from.is_none() && selection.contains(&sprite.name) {
+                derived.push(sprite.derive(derivation, srcs).with_context(|| {
+                    format!(
+                        "Failed to generate the {} of sprite '{}'",
+                        derivation.suffix(),
+                        sprite.name
+                    )
+                })?);
+            }
+        }
+
+        let names = derived.iter().map(|sprite| sprite.name.clone()).collect();
+        self.sprites.extend(derived);
+
+        Ok(names)
+    }
+
+    /// Cuts the fully transparent borders off the frames of every sprite, see
+    /// [`SpriteIntermediate::trimmed`]. Returns how many pixels of frames were
+    /// cut off.
+    pub fn trim_sprites(&mut self, mode: TrimMode, srcs: &Sources) -> anyhow::Result<i64> {
+        let mut saved_area = 0;
+
+        for sprite in self.sprites.iter_mut() {
+            let Some(trimmed) = sprite
+                .trimmed(mode, srcs)
+                .with_context(|| format!("Failed to trim sprite '{}'", sprite.name))?
+            else {
+                continue;
+            };
+
+            let area = |sprite: &SpriteIntermediate| -> i64 {
+                sprite
+                    .frames
                     .iter()
-                    .enumerate()
-                    .map(|(sprite_index, size)| {
-                        area += size.area();
+                    .map(|frame| frame.size().area() as i64)
+                    .sum()
+            };
+            saved_area += area(sprite) - area(&trimmed);
 
-                        (asset_index, sprite_index, *size)
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .collect();
+            *sprite = trimmed;
+        }
 
-        // The next type of asset's asset indices start at `fonts.len()`
+        Ok(saved_area)
+    }
 
-        self.sprite_sizes.append(&mut font_sizes);
+    /// Adds the mirrored variants `flips` asks for, e.g. `hero#flip-x`, either
+    /// baked into new sprites or sharing the frames of their sprite. Returns
+    /// the names of the new variants.
+    pub fn flip_sprites(
+        &mut self,
+        flips: &FlipsFile,
+        srcs: &Sources,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut names = vec![];
 
-        // Get a guess for what the size of the atlas should be
-        let area_sqrt = (area as f32).sqrt();
+        for request in flips.flips.iter() {
+            let sprite = self
+                .sprites
+                .iter()
+                .find(|sprite| sprite.name == request.sprite_name)
+                .ok_or_else(|| {
+                    Error::InvalidOption(format!(
+                        "Can't flip unknown sprite '{}'",
+                        request.sprite_name
+                    ))
+                })?;
+            let name = format!("{}#{}", sprite.name, request.flip.suffix());
 
-        self.image_side_len_guess = (area_sqrt.ceil() as u32).next_power_of_two() / 2;
+            match request.baked {
+                true => {
+                    let flipped = sprite
+                        .derive(SpriteDerivation::Flip(request.flip), srcs)
+                        .with_context(|| format!("Failed to generate '{name}'"))?;
+                    self.sprites.push(flipped);
+                }
+                false => self.flipped_sprites.push(FlippedSprite {
+                    name: name.clone(),
+                    sprite_name: request.sprite_name.clone(),
+                    flip: request.flip,
+                }),
+            }
 
-        println!(
-            "Loaded {} sprite sizes. Guess for image side len is {}.",
-            self.sprite_sizes.len(),
-            self.image_side_len_guess,
-        );
+            names.push(name);
+        }
+
+        Ok(names)
     }
 
-    pub fn pack(&mut self) {
-        let mut width = self.image_side_len_guess as i32;
-        let mut height = self.image_side_len_guess as i32;
+    /// Adds a recolored variant like `knight#red` of every sprite a palette
+    /// selects, or appends the recolored frames to the sprite for palettes
+    /// with `frames` set. Returns the names of the new sprites.
+    pub fn swap_palettes(
+        &mut self,
+        palettes: &PalettesFile,
+        srcs: &Sources,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut names = vec![];
+
+        for palette in palettes.palettes.iter() {
+            for sprite_name in palette.sprites.iter() {
+                let sprite = self
+                    .sprites
+                    .iter_mut()
+                    .find(|sprite| &sprite.name == sprite_name)
+                    .ok_or_else(|| {
+                        Error::InvalidOption(format!(
+                            "Can't recolor unknown sprite '{sprite_name}'"
+                        ))
+                    })?;
+
+                let mut frames = Vec::with_capacity(sprite.frames.len());
+                for (index, frame) in sprite.frames.iter().enumerate() {
+                    let image = frame.get_image(srcs).with_context(|| {
+                        format!("Failed to get frame #{index} of sprite '{sprite_name}'")
+                    })?;
+
+                    frames.push(SpriteFrame::Baked(effects::swap_palette(
+                        &*image,
+                        &palette.colors,
+                        palette.tolerance,
+                    )));
+                }
+
+                if palette.frames {
+                    // The recolored frames have the same bounds as the originals
+                    if let Some(trim) = sprite.trim.as_mut() {
+                        trim.frame_offsets.extend_from_within(..);
+                    }
+                    sprite.frames.extend(frames);
+                    continue;
+                }
 
-        loop {
-            if width > 1024 {
-                panic!("Not terminating");
+                let recolored = SpriteIntermediate {
+                    scale: sprite.scale,
+                    slicing: sprite.slicing,
+                    trim: sprite.trim.clone(),
+                    ..SpriteIntermediate::from_frames(
+                        format!("{sprite_name}#{}", palette.name),
+                        frames,
+                    )
+                };
+                names.push(recolored.name.clone());
+                self.sprites.push(recolored);
             }
+        }
 
-            if !self.try_pack(width, height) {
-                if width == height {
-                    width *= 2;
-                } else {
-                    height *= 2;
+        Ok(names)
+    }
+
+    /// Applies the color adjustment of every group to the glyphs, labels and
+    /// sprite frames it includes. Returns how many fonts, labels and sprites
+    /// were adjusted, counting those in several groups once per group.
+    pub fn adjust_colors(
+        &mut self,
+        adjustments: &AdjustmentsFile,
+        srcs: &Sources,
+    ) -> anyhow::Result<usize> {
+        let mut adjusted = 0;
+
+        for group in adjustments.groups.iter() {
+            let adjustment = group.adjustment;
+
+            for font in self.fonts.iter_mut() {
+                if !group.include.includes_font(&font.name) {
+                    continue;
                 }
 
-                assert!(width >= height);
-                continue;
+                for ch in font.chars.iter_mut() {
+                    let glyph = ch.get_sprite_texture_view(srcs).with_context(|| {
+                        format!(
+                            "Failed to get the glyph of character #{} of font '{}'",
+                            ch.char_code, font.name
+                        )
+                    })?;
+                    ch.baked = Some(adjustment.apply(&*glyph));
+                }
+                adjusted += 1;
             }
 
-            self.final_image_bounds = ISize::new(width as i32, height as i32);
+            for label_set in self.label_sets.iter_mut() {
+                for label in label_set.labels.iter_mut() {
+                    if group.include.includes_label(&label.key) {
+                        label.image = adjustment.apply(&label.image);
+                        adjusted += 1;
+                    }
+                }
+            }
 
-            println!("Final image size is {width}x{height}");
+            for sprite in self.sprites.iter_mut() {
+                if !group
+                    .include
+                    .includes_sprite_named(&sprite.name, &sprite.user_data)
+                {
+                    continue;
+                }
 
-            break;
+                for (index, frame) in sprite.frames.iter_mut().enumerate() {
+                    let image = frame.get_image(srcs).with_context(|| {
+                        format!("Failed to get frame #{index} of sprite '{}'", sprite.name)
+                    })?;
+                    *frame = SpriteFrame::Baked(adjustment.apply(&*image));
+                }
+                adjusted += 1;
+            }
         }
+
+        Ok(adjusted)
     }
 
-    fn get_asset_sprite_texture(
-        &self,
-        asset_id: usize,
-        sprite_id: usize,
+    /// Downscales every sprite drawn at a higher design scale than
+    /// `target_scale` to it, with the resampler `resamplers` names for it or
+    /// the default one. Sprites at a lower scale are reported as warnings.
+    pub fn normalize_scales(
+        &mut self,
+        target_scale: f32,
+        resamplers: &[(&str, Resampler)],
         srcs: &Sources,
-    ) -> anyhow::Result<image::RgbaImage> {
-        // The first `self.fonts.len()` asset ids refer to fonts
-        if asset_id < self.fonts.len() {
-            return self.fonts[asset_id]
-                .get_sprite_texture(sprite_id, srcs)
-                .with_context(|| {
-                    format!(
-                        "Failed to get sprite #{sprite_id} from font '{}'",
-                        &self.fonts[asset_id].name
-                    )
+    ) -> anyhow::Result<()> {
+        for sprite in std::mem::take(&mut self.sprites) {
+            if sprite.scale < target_scale {
+                self.warnings.push(Warning::BelowTargetScale {
+                    sprite_name: sprite.name.clone(),
+                    scale: sprite.scale,
+                    target_scale,
                 });
+            }
+
+            let name = sprite.name.clone();
+            let resampler = resamplers
+                .iter()
+                .find(|(sprite_name, _)| *sprite_name == name)
+                .map_or(Resampler::default(), |&(_, resampler)| resampler);
+
+            self.sprites.push(
+                sprite
+                    .with_target_scale(target_scale, resampler, srcs)
+                    .with_context(|| format!("Failed to scale sprite '{name}'"))?,
+            );
         }
 
-        Ewwow.raise()
-            .with_context(|| format!("Failed to get sprite texture from asset #{asset_id} as this asset id does not exist"))?;
+        Ok(())
+    }
 
-        unreachable!()
+    /// Attaches the user data of every sprite in `user_data` to it. Sprites
+    /// that aren't in the atlas are reported as warnings.
+    pub fn apply_user_data(&mut self, user_data: &UserDataFile) {
+        for (sprite_name, entries) in user_data.sprites.iter() {
+            match self
+                .sprites
+                .iter_mut()
+                .find(|sprite| &sprite.name == sprite_name)
+            {
+                Some(sprite) => sprite.user_data.extend(entries.clone()),
+                None => self.warnings.push(Warning::UnknownUserDataSprite {
+                    sprite_name: sprite_name.clone(),
+                }),
+            }
+        }
     }
 
-    pub fn build_image(&self, srcs: &Sources) -> anyhow::Result<image::RgbaImage> {
-        let mut output = RgbaImage::new(
-            self.final_image_bounds.width as u32,
-            self.final_image_bounds.height as u32,
-        );
+    /// Tags the sprites of `materials` (and their flipped variants) with
+    /// their material. Sprites that aren't in the atlas are reported as
+    /// warnings.
+    pub fn apply_materials(&mut self, materials: &MaterialsFile) {
+        for (material, sprite_names) in materials.materials.iter() {
+            for sprite_name in sprite_names.iter() {
+                let known = self
+                    .sprites
+                    .iter()
+                    .any(|sprite| &sprite.name == sprite_name)
+                    || self
+                        .flipped_sprites
+                        .iter()
+                        .any(|flipped| &flipped.name == sprite_name);
 
-        for &(asset_id, sprite_id, bounds) in self.sprite_bounds.iter() {
-            let sprite_texture = self
-                .get_asset_sprite_texture(asset_id, sprite_id, srcs)
-                .with_context(|| {
-                    format!("Failed to retrieve sprite #{sprite_id} of asset #{asset_id}")
-                })?;
+                match known {
+                    true => self
+                        .materials
+                        .entry(material.clone())
+                        .or_default()
+                        .push(sprite_name.clone()),
+                    false => self.warnings.push(Warning::UnknownMaterialSprite {
+                        sprite_name: sprite_name.clone(),
+                    }),
+                }
+            }
+        }
+    }
 
-            assert!(sprite_texture.width() == bounds.uwidth());
-            assert!(sprite_texture.height() == bounds.uheight());
+    /// Adds every asset of `other` with its name prefixed by `<namespace>/`,
+    /// e.g. to combine the atlases of several content packs into one without
+    /// their names clashing. References between the assets (labels, derived
+    /// and flipped sprites, materials) are renamed along with them. Needs to
+    /// be called before [`Self::load_sizes`].
+    pub fn merge(&mut self, other: TextureAtlas, namespace: &str) {
+        let rename = |name: &mut String| *name = format!("{namespace}/{name}");
 
-            let x = bounds.min.x as u32;
-            let y = bounds.min.y as u32;
+        for mut font in other.fonts {
+            rename(&mut font.name);
+            self.with_font(font);
+        }
 
-            output.copy_from(&sprite_texture, x, y).with_context(|| {
-                format!("Failed to copy sprite #{sprite_id} of asset #{asset_id} into final image")
-            })?;
+        for mut label_set in other.label_sets {
+            rename(&mut label_set.font_name);
+            self.with_label_set(label_set);
         }
 
-        Ok(output)
+        for mut sprite in other.sprites {
+            rename(&mut sprite.name);
+            if let Some(derived_from) = sprite.derived_from.as_mut() {
+                rename(&mut derived_from.sprite_name);
+            }
+            self.with_sprite(sprite);
+        }
+
+        for mut flipped in other.flipped_sprites {
+            rename(&mut flipped.name);
+            rename(&mut flipped.sprite_name);
+            self.flipped_sprites.push(flipped);
+        }
+
+        for (material, mut sprite_names) in other.materials {
+            sprite_names.iter_mut().for_each(rename);
+            self.materials
+                .entry(material)
+                .or_default()
+                .extend(sprite_names);
+        }
+
+        self.warnings.extend(other.warnings);
+    }
+
+    /// The material of the sprite `asset_id`, if it's a sprite and has one.
+    fn asset_material(&self, asset_id: usize) -> Option<&str> {
+        let index = asset_id.checked_sub(self.get_sprite_asset_id(0))?;
+        let sprite = self.sprites.get(index)?;
+
+        self.materials
+            .iter()
+            .find(|(_, sprite_names)| sprite_names.contains(&sprite.name))
+            .map(|(material, _)| material.as_str())
     }
 
-    fn try_pack(&mut self, width: i32, height: i32) -> bool {
-        self.sprite_bounds.clear();
+    /// Removes and returns all warnings collected so far.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
 
-        // Sort the sprites by height
-        let mut sprite_sizes = self.sprite_sizes.clone();
+    /// The source images pixels are copied from when building the atlas
+    /// image. Baked pixels (labels, effects, derived sprites) were generated
+    /// from the sources earlier and don't count, so they go stale if one of
+    /// them changes.
+    pub fn source_image_ids(&self) -> std::collections::HashSet<SourceId> {
+        let glyphs = self
+            .fonts
+            .iter()
+            .flat_map(|font| font.chars.iter())
+            .filter(|char| char.baked.is_none())
+            .map(|char| char.sprite.image_source_id);
 
-        sprite_sizes
-            .sort_by(|&(_, _, a_size), &(_, _, b_size)| {
-                // Use reverse cmp to get decreasing heights
-                b_size.height.cmp(&a_size.height)
+        let frames = self
+            .sprites
+            .iter()
+            .flat_map(|sprite| sprite.frames.iter())
+            .filter_map(|frame| match frame {
+                SpriteFrame::Source(source) => Some(source.image_source_id),
+                SpriteFrame::Baked(_) => None,
             });
 
-        let mut current_x: i32 = 0;
-        let mut current_y: i32 = 0;
-        let mut next_y: i32 = 0;
+        glyphs.chain(frames).collect()
+    }
+
+    /// Adds an opaque white sprite named [`WHITE_PIXEL_SPRITE`] for batched
+    /// renderers to draw untextured quads with. It's 2x2, so bilinear sampling
+    /// at its center only ever reads white texels. Replaces a sprite of the
+    /// same name.
+    pub fn inject_white_pixel(&mut self) {
+        self.sprites
+            .retain(|sprite| sprite.name != WHITE_PIXEL_SPRITE);
+        self.with_sprite(SpriteIntermediate::from_frames(
+            WHITE_PIXEL_SPRITE.to_string(),
+            vec![SpriteFrame::Baked(RgbaImage::from_pixel(
+                2,
+                2,
+                image::Rgba([255, 255, 255, 255]),
+            ))],
+        ));
+    }
 
-        let mut index = 0;
+    /// Removes all sprites and label variants that aren't referenced by name.
+    /// Labels also count as referenced if their key is. Fonts are always kept.
+    ///
+    /// Needs to be called before [`Self::load_sizes`].
+    pub fn prune(&mut self, references: &SpriteReferences) -> PruneReport {
+        let mut report = PruneReport::default();
+        let padding = self.padding;
 
-        let pad_h = self.padding.hori();
-        let pad_v = self.padding.vert();
+        let mut record = |name: String, size: ISize| {
+            if !size.is_empty() {
+                report.saved_area += size.grow(padding).area() as i64;
+            }
+            report.removed.push(name);
+        };
 
-        while index < sprite_sizes.len() {
-            let (i1, i2, size) = sprite_sizes[index];
+        let flipped_sprites = &self.flipped_sprites;
+        self.sprites.retain(|sprite| {
+            // Derived sprites like `hero#shadow` are kept with their original,
+            // sprites with a referenced flipped variant for its frames
+            let keep = references.contains(&sprite.name)
+                || sprite
+                    .derived_from
+                    .as_ref()
+                    .is_some_and(|derived| references.contains(&derived.sprite_name))
+                || flipped_sprites.iter().any(|flipped| {
+                    flipped.sprite_name == sprite.name && references.contains(&flipped.name)
+                });
 
-            // Sanity check -- if we didn't check this we could get an endless loop
-            if size.width > width {
-                return false;
+            if !keep {
+                for frame in sprite.frames.iter() {
+                    record(sprite.name.clone(), frame.size());
+                }
             }
 
-            // Start of a new row
-            if current_x == 0 {
-                // Check that the sprites actually fit in the row
-                if current_y + size.height + pad_v > height {
-                    return false;
+            keep
+        });
+
+        let sprites = &self.sprites;
+        self.flipped_sprites.retain(|flipped| {
+            sprites
+                .iter()
+                .any(|sprite| sprite.name == flipped.sprite_name)
+        });
+
+        let flipped_sprites = &self.flipped_sprites;
+        for sprite_names in self.materials.values_mut() {
+            sprite_names.retain(|name| {
+                sprites.iter().any(|sprite| &sprite.name == name)
+                    || flipped_sprites.iter().any(|flipped| &flipped.name == name)
+            });
+        }
+        self.materials
+            .retain(|_, sprite_names| !sprite_names.is_empty());
+
+        for label_set in self.label_sets.iter_mut() {
+            label_set.labels.retain(|label| {
+                let keep =
+                    references.contains(&label.key) || references.contains(&label.sprite_name());
+
+                if !keep {
+                    let size = ISize::new(label.image.width() as i32, label.image.height() as i32);
+                    record(label.sprite_name(), size);
                 }
 
-                next_y = current_y + size.height + pad_v;
-            }
+                keep
+            });
+        }
 
-            // Check that this sprite still fits in the row
-            if current_x + pad_h + size.width > width {
+        report.removed.dedup();
 
-                // The sprite doesn't fit anymore. See if we can fit a later sprite in
-                if let Some((other_index_offset, &(j1, j2, other_size))) = sprite_sizes[index+1..]
-                    .iter()
-                    .enumerate()
-                    .find(|(_, (_, _, other_size))| {
-                        current_x + pad_h + other_size.width <= width
-                    })
-                {
-                    // The sprite fits!
-                    let bounds = IRect::new(
-                        current_x + self.padding.left,
-                        current_y + self.padding.top,
-                        other_size.width,
-                        other_size.height,
-                    );
+        report
+    }
+
+    pub fn load_sizes(&mut self) -> anyhow::Result<()> {
+        self.sprite_sizes.clear();
+        self.duplicate_sprites.clear();
 
-                    self.sprite_bounds.push((j1, j2, bounds));
+        let mut area = 0;
+        let mut sprite_sizes = vec![];
+        let mut warnings = vec![];
 
-                    current_x += other_size.width + pad_h;
+        for (asset_id, asset) in self.assets() {
+            let mut num_empty = 0;
 
-                    // Delete the sprite from the vector
-                    sprite_sizes.remove(index + 1 + other_index_offset);
+            for (sprite_id, size) in asset.get_sprite_sizes().into_iter().enumerate() {
+                // Negative sizes can only come from malformed inputs
+                if size.width < 0 || size.height < 0 {
+                    Error::ParseError(format!(
+                        "Sprite #{sprite_id} of {} has a negative size of {}x{}",
+                        self.get_asset_name(asset_id),
+                        size.width,
+                        size.height,
+                    ))
+                    .raise()?;
+                }
 
+                // Empty sprites (e.g. the space character) don't need to be
+                // packed; they are exported as metric-only entries.
+                if size.is_empty() {
+                    num_empty += 1;
                     continue;
                 }
 
-                current_x = 0;
-                current_y = next_y;
+                area += size.area();
+
+                sprite_sizes.push((asset_id, sprite_id, size));
+            }
+
+            if num_empty > 0 {
+                warnings.push(Warning::SkippedEmptySprites {
+                    asset: self.get_asset_name(asset_id),
+                    count: num_empty,
+                });
+            }
+        }
+
+        self.sprite_sizes = sprite_sizes;
+        self.warnings.extend(warnings);
+
+        // Get a guess for what the size of the atlas should be
+        let area_sqrt = (area as f32).sqrt();
+
+        self.image_side_len_guess = ((area_sqrt.ceil() as u32).next_power_of_two() / 2).max(1);
+
+        println!(
+            "Loaded {} sprite sizes. Guess for image side len is {}.",
+            self.sprite_sizes.len(),
+            self.image_side_len_guess,
+        );
+
+        Ok(())
+    }
+
+    /// Packs pixel-identical sprites, e.g. blank glyphs or repeated tiles,
+    /// only once and lets the duplicates share their rect. Needs to be called
+    /// after [`Self::load_sizes`] and before [`Self::load_clusters`], returns
+    /// the number of duplicates.
+    ///
+    /// Sprites are only shared within a font with font regions or line
+    /// shelves, and within a material with `material_pages`.
+    pub fn dedup_sprites(&mut self, srcs: &Sources) -> anyhow::Result<usize> {
+        let assets = self.assets();
+        let regions = self.font_regions || self.fonts.iter().any(|font| font.line_shelves);
+
+        let pixels = |asset_id: usize, sprite_id: usize| {
+            let (_, asset) = assets[asset_id];
+            let texture = asset.get_sprite_view(sprite_id, srcs).with_context(|| {
+                format!(
+                    "Failed to get sprite #{sprite_id} of {}",
+                    self.get_asset_name(asset_id)
+                )
+            })?;
+
+            anyhow::Ok(texture.view().to_image())
+        };
+
+        let mut originals: HashMap<_, Vec<(usize, usize)>> = HashMap::new();
+        let mut unique = vec![];
+        let mut duplicates = vec![];
+
+        for &(asset_id, sprite_id, size) in self.sprite_sizes.iter() {
+            // Dynamic slots are blank, but each one is filled at runtime
+            if self
+                .fonts
+                .get(asset_id)
+                .is_some_and(|font| sprite_id >= font.chars.len())
+            {
+                unique.push((asset_id, sprite_id, size));
                 continue;
             }
 
-            // The sprite fits!
-            let bounds = IRect::new(
-                current_x + self.padding.left,
-                current_y + self.padding.top,
-                size.width,
-                size.height,
-            );
+            let image = pixels(asset_id, sprite_id)?;
 
-            self.sprite_bounds.push((i1, i2, bounds));
+            let key = (
+                size,
+                hashing::hash_bytes(image.as_raw()),
+                (regions && asset_id < self.fonts.len()).then_some(asset_id),
+                self.material_pages
+                    .then(|| self.asset_material(asset_id))
+                    .flatten(),
+            );
+            let candidates = originals.entry(key).or_default();
 
-            current_x += size.width + pad_h;
+            // Equal hashes are compared pixel by pixel to rule out collisions
+            let mut original = None;
+            for &(original_asset_id, original_sprite_id) in candidates.iter() {
+                if pixels(original_asset_id, original_sprite_id)? == image {
+                    original = Some((original_asset_id, original_sprite_id));
+                    break;
+                }
+            }
 
-            index += 1;
+            match original {
+                Some(original) => duplicates.push(((asset_id, sprite_id), original)),
+                None => {
+                    candidates.push((asset_id, sprite_id));
+                    unique.push((asset_id, sprite_id, size));
+                }
+            }
         }
 
-        true
+        let num_duplicates = duplicates.len();
+        self.sprite_sizes = unique;
+        self.duplicate_sprites = duplicates;
+
+        Ok(num_duplicates)
     }
 
-    pub fn get_font_asset_id(&self, font_index: usize) -> usize {
-        font_index
+    /// Places the [`Self::duplicate_sprites`] onto the sprites they share
+    /// their pixels with.
+    fn place_duplicates(&mut self) {
+        let placements = self.layout.placement_map();
+
+        for &((asset_id, sprite_id), original) in self.duplicate_sprites.iter() {
+            if let Some(placement) = placements.get(&original) {
+                self.layout
+                    .place_in_layer(asset_id, sprite_id, placement.bounds, placement.layer);
+            }
+        }
     }
-}
 
-pub trait Atlasable {
-    fn get_sprite_sizes(&self) -> Vec<ISize>;
-    fn get_sprite_texture(&self, index: usize, srcs: &Sources) -> anyhow::Result<image::RgbaImage>;
+    /// Assigns every sprite to a cluster for [`Self::clustering`]. Needs to
+    /// be called after [`Self::load_sizes`], returns the number of distinct
+    /// clusters.
+    pub fn load_clusters(&mut self, srcs: &Sources) -> anyhow::Result<usize> {
+        let Some(clustering) = self.clustering else {
+            self.sprite_clusters.clear();
+            return Ok(0);
+        };
+
+        let assets = self.assets();
+        let mut clusters = Vec::with_capacity(self.sprite_sizes.len());
+
+        for &(asset_id, sprite_id, _) in self.sprite_sizes.iter() {
+            let (_, asset) = assets[asset_id];
+
+            let key = match clustering {
+                // Generated pixels all end up in cluster 0
+                Clustering::SourceSheet => asset
+                    .get_sprite_source(sprite_id)
+                    .map_or(0, |id| hashing::hash_value(&id).max(1)),
+                Clustering::AverageColor => {
+                    let texture = asset.get_sprite_view(sprite_id, srcs).with_context(|| {
+                        format!(
+                            "Failed to get sprite #{sprite_id} of {}",
+                            self.get_asset_name(asset_id)
+                        )
+                    })?;
+
+                    average_color_key(&texture.view())
+                }
+            };
+
+            clusters.push(key);
+        }
+
+        let mut distinct = clusters.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+
+        self.sprite_clusters = clusters;
+
+        Ok(distinct.len())
+    }
+
+    pub fn pack(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        if self.clustering.is_some() && self.sprite_clusters.len() != self.sprite_sizes.len() {
+            Error::InvalidOption(
+                "Clustered packing needs the clusters to be loaded after the sizes".to_string(),
+            )
+            .raise()?;
+        }
+
+        if let Some(max_aspect_ratio) = self
+            .max_aspect_ratio
+            .filter(|ratio| ratio.is_nan() || *ratio < 1.0)
+        {
+            Error::InvalidOption(format!(
+                "The max aspect ratio needs to be at least 1, not {max_aspect_ratio}"
+            ))
+            .raise()?;
+        }
+
+        if self.spacing < 0 {
+            Error::InvalidOption(format!(
+                "The spacing needs to be at least 0, not {}",
+                self.spacing
+            ))
+            .raise()?;
+        }
+
+        if self.max_size < 1 || !(self.max_size as u32).is_power_of_two() {
+            Error::InvalidOption(format!(
+                "The max atlas size needs to be a power of two, not {}",
+                self.max_size
+            ))
+            .raise()?;
+        }
+
+        if let Some(platform) = self
+            .platform
+            .filter(|platform| self.max_size > platform.max_texture_size())
+        {
+            Error::InvalidOption(format!(
+                "The max atlas size {} exceeds the {platform} limit of {}",
+                self.max_size,
+                platform.max_texture_size()
+            ))
+            .raise()?;
+        }
+
+        if self.material_pages && !self.materials.is_empty() {
+            Error::InvalidOption(
+                "Packing materials into pages of their own needs a texture array".to_string(),
+            )
+            .raise()?;
+        }
+
+        // These would never fit, no matter how large the atlas gets
+        if let Some(&(asset_id, sprite_id, size)) =
+            self.sprite_sizes.iter().find(|&&(asset_id, _, size)| {
+                self.fonts
+                    .get(asset_id)
+                    .is_some_and(|font| font.line_shelves && size.height > font.line_height)
+            })
+        {
+            Error::PackOverflow {
+                sprites: vec![format!(
+                    "Sprite #{sprite_id} of {} ({} pixels high)",
+                    self.get_asset_name(asset_id),
+                    size.height
+                )],
+                limit: "its line shelves".to_string(),
+            }
+            .raise()?;
+        }
+
+        // Every size the atlas may grow to, smallest first
+        let guess = self.image_side_len_guess as i32;
+        let mut candidates = vec![];
+
+        if self.growth == AtlasGrowth::Free {
+            let mut area = guess as i64 * guess as i64;
+
+            while area <= self.max_size as i64 * self.max_size as i64 {
+                // Every power of two shape of this area, widest first
+                candidates.extend(
+                    (0..=self.max_size.ilog2())
+                        .rev()
+                        .map(|power| 1 << power)
+                        .filter(|&width| area % width == 0 && area / width <= self.max_size as i64)
+                        .map(|width| ISize::new(width as i32, (area / width) as i32))
+                        .filter(|&size| self.allows_size(size)),
+                );
+
+                area *= 2;
+            }
+        } else {
+            let mut width = guess;
+            let mut height = guess;
+
+            while width <= self.max_size {
+                candidates.push(ISize::new(width, height));
+
+                if width == height {
+                    width *= 2;
+
+                    // Grow both sides at once, a 2:1 atlas would be too wide
+                    if !self.allows_size(ISize::new(width, height)) {
+                        height *= 2;
+                    }
+                } else {
+                    height *= 2;
+                }
+
+                assert!(width >= height);
+            }
+        }
+
+        // The attempts run in parallel, but the smallest size that fits wins
+        // like it would one after the other
+        let packed = candidates
+            .par_iter()
+            .find_map_first(|&size| match cancel.is_cancelled() {
+                true => None,
+                false => self.try_pack(size).map(|packed| (size, packed)),
+            });
+        cancel.check()?;
+
+        let Some((size, (rects, regions))) = packed else {
+            return self.spill_or_overflow(cancel);
+        };
+
+        self.place_packed(size, rects, regions);
+        self.apply_size_constraint();
+
+        Ok(())
+    }
+
+    /// Crops the packed image to the area the sprites and regions use with
+    /// [`SizeConstraint::AnySize`].
+    fn apply_size_constraint(&mut self) {
+        if self.size_constraint == SizeConstraint::AnySize {
+            let used_size = self.layout.used_size(self.padding);
+            let regions = self
+                .layout
+                .regions
+                .iter()
+                .map(|region| region.bounds.max + self.atlas_margin)
+                .fold(glam::IVec2::new(1, 1), |a, b| a.max(b));
+
+            self.layout.size = ISize::new(
+                used_size.width.max(regions.x),
+                used_size.height.max(regions.y),
+            );
+        }
+
+        let size = self.layout.size;
+        println!("Final image size is {}x{}", size.width, size.height);
+    }
+
+    /// Spills into pages once the atlas would outgrow `max_size`, or fails
+    /// with the sprites that don't fit anymore.
+    fn spill_or_overflow(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let max_size = ISize::new(self.max_size, self.max_size);
+
+        if self.pages {
+            self.pack_layers(max_size, cancel)?;
+            return Ok(());
+        }
+
+        let limit = format!("{}x{}", max_size.width, max_size.height);
+        Error::PackOverflow {
+            sprites: self.overflowing_sprites(max_size),
+            limit: match self.platform {
+                Some(platform) if self.max_size == platform.max_texture_size() => {
+                    format!("{limit}, the {platform} limit")
+                }
+                _ => limit,
+            },
+        }
+        .raise()?;
+
+        unreachable!()
+    }
+
+    /// The sprites that don't fit into `size` anymore, tallest first: after
+    /// the largest set of taller ones that does, every smaller one that still
+    /// fits in between is left out.
+    fn overflowing_sprites(&self, size: ISize) -> Vec<String> {
+        let config = PackConfig::new(size.width, size.height, self.padding)
+            .with_spacing(self.spacing)
+            .with_margin(self.atlas_margin);
+
+        let mut order: Vec<usize> = (0..self.sprite_sizes.len()).collect();
+        order.sort_by_key(|&index| std::cmp::Reverse(self.sprite_sizes[index].2.height));
+
+        let fits = |indices: &[usize]| {
+            let sizes: Vec<ISize> = indices
+                .iter()
+                .map(|&index| self.sprite_sizes[index].2)
+                .collect();
+            self.pack_algorithm.pack(&sizes, &config).is_ok()
+        };
+
+        // Binary search for the longest prefix that still fits
+        let (mut fitting, mut overflowing) = (0, order.len());
+        while fitting + 1 < overflowing {
+            let count = (fitting + overflowing) / 2;
+            match fits(&order[..count]) {
+                true => fitting = count,
+                false => overflowing = count,
+            }
+        }
+        // Only the font regions or clusters didn't fit
+        if fits(&order[..overflowing]) {
+            return vec![format!(
+                "{} sprites in their regions or clusters",
+                order.len()
+            )];
+        }
+
+        let mut packed = order[..fitting].to_vec();
+        let mut overflow = vec![];
+        for &index in order[fitting..].iter() {
+            packed.push(index);
+            if !fits(&packed) {
+                packed.pop();
+                overflow.push(index);
+            }
+        }
+
+        overflow
+            .into_iter()
+            .map(|index| {
+                let (asset_id, sprite_id, size) = self.sprite_sizes[index];
+                format!(
+                    "Sprite #{sprite_id} of {} ({}x{})",
+                    self.get_asset_name(asset_id),
+                    size.width,
+                    size.height
+                )
+            })
+            .collect()
+    }
+
+    /// Packs the sprites into as many `layer_size` layers as needed instead
+    /// of growing a single image, e.g. for texture arrays. Returns the number
+    /// of layers, see [`AtlasLayout::layer_count`].
+    pub fn pack_layers(
+        &mut self,
+        layer_size: ISize,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<u32> {
+        if let Some(font) = self.fonts.iter().find(|font| font.dynamic_slots.is_some()) {
+            Error::InvalidOption(format!(
+                "Font '{}' has dynamic slots, which aren't supported in layers",
+                font.name
+            ))
+            .raise()?;
+        }
+
+        if let Some(font) = self.fonts.iter().find(|font| font.line_shelves) {
+            Error::InvalidOption(format!(
+                "Font '{}' is packed into line shelves, which aren't supported in layers",
+                font.name
+            ))
+            .raise()?;
+        }
+
+        let config = PackConfig::new(layer_size.width, layer_size.height, self.padding)
+            .with_spacing(self.spacing)
+            .with_margin(self.atlas_margin);
+
+        // Tallest first, like the packer itself
+        let mut order: Vec<usize> = (0..self.sprite_sizes.len()).collect();
+        order.sort_by_key(|&index| std::cmp::Reverse(self.sprite_sizes[index].2.height));
+
+        // The indices into `sprite_sizes` and the packed rects of every layer,
+        // and the material of its sprites with `material_pages`
+        let mut layers: Vec<(Vec<usize>, Vec<IRect>, Option<String>)> = vec![];
+
+        for index in order {
+            cancel.check()?;
+
+            let material = match self.material_pages {
+                true => self
+                    .asset_material(self.sprite_sizes[index].0)
+                    .map(str::to_string),
+                false => None,
+            };
+
+            let fits = layers.iter_mut().any(|(indices, rects, layer_material)| {
+                if *layer_material != material {
+                    return false;
+                }
+
+                let sizes: Vec<ISize> = indices
+                    .iter()
+                    .chain([&index])
+                    .map(|&i| self.sprite_sizes[i].2)
+                    .collect();
+
+                match self.pack_algorithm.pack(&sizes, &config) {
+                    Ok(packed) => {
+                        indices.push(index);
+                        *rects = packed;
+                        true
+                    }
+                    Err(_) => false,
+                }
+            });
+
+            if !fits {
+                let rects =
+                    packing::pack(&[self.sprite_sizes[index].2], &config).map_err(|_| {
+                        let (asset_id, sprite_id, _) = self.sprite_sizes[index];
+                        Error::PackOverflow {
+                            sprites: vec![format!(
+                                "Sprite #{sprite_id} of {}",
+                                self.get_asset_name(asset_id)
+                            )],
+                            limit: format!("a {}x{} layer", layer_size.width, layer_size.height),
+                        }
+                    })?;
+
+                layers.push((vec![index], rects, material));
+            }
+        }
+
+        self.layout = AtlasLayout::new(layer_size);
+        self.layout.margin = self.atlas_margin;
+        self.layout.layer_count = layers.len().max(1) as u32;
+
+        for (layer, (indices, rects, _)) in layers.into_iter().enumerate() {
+            for (index, bounds) in indices.into_iter().zip(rects) {
+                let (asset_id, sprite_id, _) = self.sprite_sizes[index];
+                self.layout
+                    .place_in_layer(asset_id, sprite_id, bounds, layer as u32);
+            }
+        }
+        self.place_duplicates();
+
+        Ok(self.layout.layer_count)
+    }
+
+    fn allows_size(&self, size: ISize) -> bool {
+        if self.size_constraint == SizeConstraint::ForceSquare && size.width != size.height {
+            return false;
+        }
+
+        self.max_aspect_ratio
+            .is_none_or(|max_aspect_ratio| size.aspect_ratio() <= max_aspect_ratio)
+    }
+
+    /// Compares the packed power of two size with the size the sprites
+    /// actually need. Needs to be called after [`Self::pack`].
+    pub fn pot_cost_report(&self) -> PotCostReport {
+        let size = self.layout.size;
+        let used_size = self.layout.used_size(self.padding);
+
+        let sprite_area: i64 = self
+            .sprite_sizes
+            .iter()
+            .map(|&(_, _, size)| size.grow(self.padding).area() as i64)
+            .sum();
+
+        let wasted_bytes = (size.area() as i64 - used_size.area() as i64) * 4;
+
+        let mut suggestions = vec![];
+
+        // The atlas grew because of a comparatively small overflow
+        if used_size.area() as i64 * 4 < size.area() as i64 * 3 {
+            let margin = 2 * self.atlas_margin;
+            let cells: Vec<ISize> = self
+                .sprite_sizes
+                .iter()
+                .map(|&(_, _, size)| size.grow(self.padding))
+                .collect();
+
+            // Pages of half the size, that every sprite fits into upright
+            let half = [
+                ISize::new(size.width / 2, size.height),
+                ISize::new(size.width, size.height / 2),
+            ]
+            .into_iter()
+            .filter(|&half| self.allows_size(half))
+            .filter(|half| {
+                cells.iter().all(|cell| {
+                    cell.width + margin <= half.width && cell.height + margin <= half.height
+                })
+            })
+            .min_by_key(|half| (sprite_area + half.area() as i64 - 1) / half.area() as i64);
+
+            if let Some(half) = half {
+                let half_area = half.area() as i64;
+                let num_pages = (sprite_area + half_area - 1) / half_area;
+
+                if num_pages * half_area < size.area() as i64 {
+                    suggestions.push(format!(
+                        "The sprites need at least {num_pages} page(s) of {}x{}, multiple pages would save up to {} KiB",
+                        half.width,
+                        half.height,
+                        (size.area() as i64 - num_pages * half_area) * 4 / 1024,
+                    ));
+                }
+
+                if num_pages == 1 {
+                    suggestions.push(format!(
+                        "The sprites cover only {sprite_area} px, tighter packing (e.g. with rotated sprites) might fit them into {}x{}",
+                        half.width, half.height,
+                    ));
+                }
+            }
+        }
+
+        PotCostReport {
+            size,
+            used_size,
+            sprite_area,
+            wasted_bytes,
+            suggestions,
+        }
+    }
+
+    /// Measures how much of the atlas the sprites and their padding cover,
+    /// how much every row wastes and how large the biggest empty spot is.
+    /// Needs to be called after [`Self::pack`].
+    pub fn occupancy_report(&self) -> OccupancyReport {
+        let size = self.layout.size;
+        let layers = self.layout.layer_count.max(1);
+        let inner =
+            IRect::new(0, 0, size.width, size.height).shrink(IMargins::uniform(self.layout.margin));
+
+        let sprite_area: i64 = self
+            .sprite_sizes
+            .iter()
+            .map(|&(_, _, size)| size.area() as i64)
+            .sum();
+        let padded_area: i64 = self
+            .sprite_sizes
+            .iter()
+            .map(|&(_, _, size)| size.grow(self.padding).area() as i64)
+            .sum();
+        let padding_area = padded_area - sprite_area;
+
+        // Duplicates share the cell of their original
+        let mut cells: Vec<(u32, IRect)> = self
+            .layout
+            .placements
+            .iter()
+            .filter(|placement| !placement.bounds.is_empty())
+            .map(|placement| (placement.layer, placement.bounds.grow(self.padding)))
+            .collect();
+        cells.sort_by_key(|&(layer, cell)| (layer, cell.min.y, cell.min.x));
+        cells.dedup();
+
+        let mut rows: Vec<RowWaste> = vec![];
+        for &(layer, cell) in cells.iter() {
+            let row = match rows.last_mut() {
+                Some(row) if row.layer == layer && row.y == cell.min.y => row,
+                _ => {
+                    rows.push(RowWaste {
+                        layer,
+                        y: cell.min.y,
+                        ..Default::default()
+                    });
+                    rows.last_mut().unwrap()
+                }
+            };
+
+            row.height = row.height.max(cell.height());
+            row.sprites += 1;
+            row.empty_area -= cell.size().area() as i64;
+        }
+        for row in rows.iter_mut() {
+            row.empty_area += inner.width() as i64 * row.height as i64;
+        }
+
+        let largest_empty = (0..layers)
+            .map(|layer| {
+                let occupied: Vec<IRect> = cells
+                    .iter()
+                    .filter(|&&(cell_layer, _)| cell_layer == layer)
+                    .map(|&(_, cell)| cell)
+                    .collect();
+                (layer, packing::largest_empty_rect(inner, &occupied))
+            })
+            .max_by_key(|&(layer, rect)| (rect.size().area(), std::cmp::Reverse(layer)))
+            .unwrap_or_default();
+
+        let mut suggestions = vec![];
+
+        // Smaller sizes the sprites alone would cover, but not with padding
+        let margin = 2 * self.layout.margin;
+        let usable_area =
+            |size: ISize| (size.width - margin) as i64 * (size.height - margin) as i64;
+        let half = [
+            ISize::new(size.width / 2, size.height),
+            ISize::new(size.width, size.height / 2),
+        ]
+        .into_iter()
+        .find(|&half| self.allows_size(half));
+
+        if let Some(half) = half.filter(|&half| {
+            layers == 1 && sprite_area <= usable_area(half) && padded_area > usable_area(half)
+        }) {
+            suggestions.push(format!(
+                "The sprites alone would cover {:.1}% of {}x{}, but not with the {padding_area} px of padding around them",
+                sprite_area as f64 / usable_area(half) as f64 * 100.0,
+                half.width,
+                half.height,
+            ));
+        }
+
+        let total_area = size.area() as i64 * layers as i64;
+
+        OccupancyReport {
+            size,
+            layers,
+            sprite_area,
+            padding_area,
+            occupancy: match total_area {
+                0 => 0.0,
+                _ => sprite_area as f64 / total_area as f64,
+            },
+            rows,
+            largest_empty,
+            suggestions,
+        }
+    }
+
+    fn get_asset_sprite_view<'s>(
+        &'s self,
+        asset_id: usize,
+        sprite_id: usize,
+        srcs: &'s Sources,
+    ) -> anyhow::Result<SpriteTexture<'s>> {
+        let Some((_, asset)) = self.assets().into_iter().find(|&(id, _)| id == asset_id) else {
+            Error::InvalidOption(format!(
+                "Failed to get sprite texture from asset #{asset_id} as this asset id does not exist"
+            ))
+            .raise()?;
+
+            unreachable!()
+        };
+
+        asset.get_sprite_view(sprite_id, srcs).with_context(|| {
+            format!(
+                "Failed to get sprite #{sprite_id} from {}",
+                self.get_asset_name(asset_id)
+            )
+        })
+    }
+
+    pub fn build_image(
+        &self,
+        layout: &AtlasLayout,
+        srcs: &Sources,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let _span = tracing::info_span!("build_image").entered();
+
+        let mut output = RgbaImage::new(layout.size.width as u32, layout.size.height as u32);
+        let extrusion = IMargins::new(
+            self.extrude.min(self.padding.top),
+            self.extrude.min(self.padding.bottom),
+            self.extrude.min(self.padding.left),
+            self.extrude.min(self.padding.right),
+        );
+
+        // The padding and gaps between glyphs are transparent white too
+        let font_regions = layout
+            .regions
+            .iter()
+            .filter(|region| self.alpha_glyphs && region.asset_id < self.fonts.len());
+        for region in font_regions {
+            let bounds = region.bounds;
+
+            for y in bounds.min.y.max(0)..bounds.max.y {
+                for x in bounds.min.x.max(0)..bounds.max.x {
+                    if let Some(pixel) = output.get_pixel_mut_checked(x as u32, y as u32) {
+                        *pixel = image::Rgba([255, 255, 255, 0]);
+                    }
+                }
+            }
+        }
+
+        for placement in layout.placements.iter() {
+            cancel.check()?;
+
+            let (asset_id, sprite_id, bounds) =
+                (placement.asset_id, placement.sprite_id, placement.bounds);
+
+            let sprite_texture = self
+                .get_asset_sprite_view(asset_id, sprite_id, srcs)
+                .with_context(|| {
+                    format!("Failed to retrieve sprite #{sprite_id} of asset #{asset_id}")
+                })?;
+            let sprite_texture = sprite_texture.view();
+
+            // The layout might be stale if it was cached
+            if sprite_texture.width() != bounds.uwidth()
+                || sprite_texture.height() != bounds.uheight()
+            {
+                Error::InvalidOption(format!(
+                    "Sprite #{sprite_id} of asset #{asset_id} is {}x{} but the layout expects {}x{}",
+                    sprite_texture.width(),
+                    sprite_texture.height(),
+                    bounds.uwidth(),
+                    bounds.uheight(),
+                ))
+                .raise()?;
+            }
+
+            let (x, y) = bounds.checked_umin().ok_or_else(|| {
+                Error::InvalidOption(format!(
+                    "Sprite #{sprite_id} of asset #{asset_id} is placed at negative coordinates"
+                ))
+            })?;
+
+            blit(&mut output, &sprite_texture, x, y).with_context(|| {
+                format!("Failed to copy sprite #{sprite_id} of asset #{asset_id} into final image")
+            })?;
+
+            if self.alpha_glyphs && asset_id < self.fonts.len() {
+                coverage_to_alpha(&mut output, bounds);
+            }
+            if self.extrude > 0 {
+                extrude(&mut output, bounds, extrusion);
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// The packed rects of the sprites, in the order of `sprite_sizes`, and
+    /// the regions of the fonts if everything fits into `size`.
+    fn try_pack(&self, size: ISize) -> Option<(Vec<IRect>, Vec<AssetRegion>)> {
+        let _span = tracing::info_span!("try_pack", size.width, size.height).entered();
+
+        let sizes: Vec<ISize> = self.sprite_sizes.iter().map(|&(_, _, size)| size).collect();
+        let config = PackConfig::new(size.width, size.height, self.padding)
+            .with_spacing(self.spacing)
+            .with_margin(self.atlas_margin);
+
+        // Shelves are packed as blocks of their own too
+        let regions = self.font_regions || self.fonts.iter().any(|font| font.line_shelves);
+
+        let packed = match (regions, self.clustering) {
+            (true, _) => self.pack_font_regions(&config),
+            (false, Some(_)) => packing::pack_clustered(&sizes, &self.sprite_clusters, &config)
+                .map(|rects| (rects, vec![])),
+            (false, None) => self
+                .pack_algorithm
+                .pack(&sizes, &config)
+                .map(|rects| (rects, vec![])),
+        };
+        packed.ok()
+    }
+
+    /// Lays out the sprites as [`Self::try_pack`] packed them into `size`.
+    fn place_packed(&mut self, size: ISize, rects: Vec<IRect>, regions: Vec<AssetRegion>) {
+        self.layout = AtlasLayout::new(size);
+        self.layout.margin = self.atlas_margin;
+        self.layout.regions = regions;
+
+        for (&(asset_id, sprite_id, _), bounds) in self.sprite_sizes.iter().zip(rects) {
+            self.layout.place(asset_id, sprite_id, bounds);
+        }
+        self.place_duplicates();
+    }
+
+    /// Packs the sprites of every font into a block of their own first,
+    /// which is then packed like a single sprite along with everything else.
+    fn pack_font_regions(
+        &self,
+        config: &PackConfig,
+    ) -> anyhow::Result<(Vec<IRect>, Vec<AssetRegion>)> {
+        let padding = config.padding;
+
+        // Indices into `sprite_sizes`, grouped by font
+        let mut blocks: Vec<(Option<usize>, Vec<usize>)> = vec![];
+        for (index, &(asset_id, _, _)) in self.sprite_sizes.iter().enumerate() {
+            let font = (asset_id < self.fonts.len()).then_some(asset_id);
+
+            match blocks
+                .iter_mut()
+                .find(|(id, _)| font.is_some() && *id == font)
+            {
+                Some((_, indices)) => indices.push(index),
+                None => blocks.push((font, vec![index])),
+            }
+        }
+
+        // Where the sprites are inside of their block
+        let mut block_rects = vec![IRect::default(); self.sprite_sizes.len()];
+        let mut block_sizes = Vec::with_capacity(blocks.len());
+
+        for (font, indices) in blocks.iter() {
+            let sizes: Vec<ISize> = indices.iter().map(|&i| self.sprite_sizes[i].2).collect();
+
+            // Aim for a roughly square block
+            let cells = sizes.iter().map(|size| size.grow(padding));
+            let area: i64 = cells.clone().map(|cell| cell.area() as i64).sum();
+            let widest = cells.map(|cell| cell.width).max().unwrap_or(0);
+            let block_width = widest.max((area as f64).sqrt().ceil() as i32);
+
+            let block_config =
+                PackConfig::new(block_width, config.height - 2 * config.margin, padding)
+                    .with_spacing(config.spacing);
+            let shelf_font = font
+                .map(|font| &self.fonts[font])
+                .filter(|font| font.line_shelves);
+            let rects = match shelf_font {
+                Some(font) => packing::pack_shelves(&sizes, font.line_height, &block_config)
+                    .with_context(|| format!("Failed to pack font '{}'", font.name))?,
+                None => self.pack_algorithm.pack(&sizes, &block_config)?,
+            };
+
+            let mut max = rects
+                .iter()
+                .map(|rect| rect.grow(padding).max)
+                .fold(glam::IVec2::ZERO, |a, b| a.max(b));
+
+            // Even the last shelf is a full line high
+            if let Some(stride) = shelf_font.and_then(|font| self.shelf_stride(font)) {
+                let shelves = (max.y + config.spacing + stride - 1) / stride;
+                max.y = shelves * stride - config.spacing;
+            }
+
+            for (&index, rect) in indices.iter().zip(rects) {
+                block_rects[index] = rect;
+            }
+
+            // The packer grows the block by the padding again
+            block_sizes.push(ISize::new(max.x - padding.hori(), max.y - padding.vert()));
+        }
+
+        let mut rects = vec![IRect::default(); self.sprite_sizes.len()];
+        let mut regions = vec![];
+
+        for ((font, indices), block) in blocks
+            .iter()
+            .zip(self.pack_algorithm.pack(&block_sizes, config)?)
+        {
+            let region = block.grow(padding);
+
+            for &index in indices.iter() {
+                rects[index] = block_rects[index].translate(region.min);
+            }
+
+            if let Some(asset_id) = *font {
+                regions.push(AssetRegion {
+                    asset_id,
+                    bounds: region,
+                });
+            }
+        }
+
+        Ok((rects, regions))
+    }
+
+    /// The distance between the shelves of `font` if it's packed into line
+    /// shelves, see [`packing::pack_shelves`].
+    pub fn shelf_stride(&self, font: &FontIntermediate) -> Option<i32> {
+        font.line_shelves
+            .then_some(font.line_height + self.padding.vert() + self.spacing)
+    }
+
+    /// A hash of all settings that influence the packing and the final image.
+    pub fn settings_hash(&self) -> u64 {
+        let dynamic_slots: Vec<_> = self.fonts.iter().map(|font| font.dynamic_slots).collect();
+        let line_shelves: Vec<_> = self.fonts.iter().map(|font| font.line_shelves).collect();
+
+        // Tuples only implement `Hash` up to 12 elements
+        let size = (
+            self.max_size,
+            self.max_aspect_ratio.map(f32::to_bits),
+            self.platform,
+            self.growth,
+            self.size_constraint,
+            self.pages,
+            self.material_pages,
+        );
+        let layout = (
+            self.padding,
+            self.spacing,
+            self.atlas_margin,
+            dynamic_slots,
+            line_shelves,
+            self.font_regions,
+            self.clustering,
+            self.pack_algorithm,
+            &self.materials,
+        );
+        let image = (self.extrude, self.alpha_glyphs);
+
+        hashing::hash_value(&(size, layout, image))
+    }
+
+    /// All assets in asset id order.
+    fn assets(&self) -> Vec<(usize, &dyn Atlasable)> {
+        // Font asset indices start at 0
+        let fonts = self
+            .fonts
+            .iter()
+            .enumerate()
+            .map(|(index, font)| (self.get_font_asset_id(index), font as &dyn Atlasable));
+
+        // The next type of asset's asset indices start at `fonts.len()`
+        let label_sets = self
+            .label_sets
+            .iter()
+            .enumerate()
+            .map(|(index, label_set)| {
+                (
+                    self.get_label_set_asset_id(index),
+                    label_set as &dyn Atlasable,
+                )
+            });
+
+        // Sprites come after the label sets
+        let sprites = self
+            .sprites
+            .iter()
+            .enumerate()
+            .map(|(index, sprite)| (self.get_sprite_asset_id(index), sprite as &dyn Atlasable));
+
+        fonts.chain(label_sets).chain(sprites).collect()
+    }
+
+    pub fn get_asset_name(&self, asset_id: usize) -> String {
+        if let Some(font) = self.fonts.get(asset_id) {
+            return format!("font '{}'", font.name);
+        }
+
+        let index = asset_id - self.fonts.len();
+        if let Some(label_set) = self.label_sets.get(index) {
+            return format!("labels of font '{}'", label_set.font_name);
+        }
+
+        let index = index - self.label_sets.len();
+        if let Some(sprite) = self.sprites.get(index) {
+            return format!("sprite '{}'", sprite.name);
+        }
+
+        format!("asset #{asset_id}")
+    }
+
+    pub fn get_font_asset_id(&self, font_index: usize) -> usize {
+        font_index
+    }
+
+    pub fn get_label_set_asset_id(&self, label_set_index: usize) -> usize {
+        self.fonts.len() + label_set_index
+    }
+
+    pub fn get_sprite_asset_id(&self, sprite_index: usize) -> usize {
+        self.fonts.len() + self.label_sets.len() + sprite_index
+    }
+}
+
+pub trait Atlasable {
+    fn get_sprite_sizes(&self) -> Vec<ISize>;
+    fn get_sprite_texture(&self, index: usize, srcs: &Sources) -> anyhow::Result<image::RgbaImage>;
+
+    /// Like [`Self::get_sprite_texture`], but borrows the pixels if they
+    /// already exist somewhere, so they can be blitted without a copy.
+    fn get_sprite_view<'s>(
+        &'s self,
+        index: usize,
+        srcs: &'s Sources,
+    ) -> anyhow::Result<SpriteTexture<'s>> {
+        Ok(SpriteTexture::Owned(self.get_sprite_texture(index, srcs)?))
+    }
+
+    /// The source image the sprite is cut from, `None` if its pixels are
+    /// generated.
+    fn get_sprite_source(&self, _index: usize) -> Option<SourceId> {
+        None
+    }
+}
+
+/// Quantizes the alpha weighted average color of `image` to 2 bits per
+/// channel, so sprites of roughly the same color share a key. Neighbouring
+/// keys are similar in red. Fully transparent images get 0.
+fn average_color_key(image: &SubImage<&RgbaImage>) -> u64 {
+    let mut sums = [0u64; 3];
+    let mut alpha = 0u64;
+
+    for (_, _, pixel) in image.pixels() {
+        let a = pixel[3] as u64;
+        for (sum, &channel) in sums.iter_mut().zip(pixel.0.iter()) {
+            *sum += channel as u64 * a;
+        }
+        alpha += a;
+    }
+
+    if alpha == 0 {
+        return 0;
+    }
+
+    let [r, g, b] = sums.map(|sum| (sum / alpha) >> 6);
+
+    1 + ((r << 4) | (g << 2) | b)
+}
+
+/// The pixels of a sprite, either borrowed from a source image or an
+/// intermediate, or rendered on demand.
+pub enum SpriteTexture<'s> {
+    View(SubImage<&'s RgbaImage>),
+    Owned(RgbaImage),
+}
+
+impl SpriteTexture<'_> {
+    pub fn view(&self) -> SubImage<&RgbaImage> {
+        match self {
+            SpriteTexture::View(view) => {
+                let (width, height) = view.dimensions();
+                view.view(0, 0, width, height)
+            }
+            SpriteTexture::Owned(image) => image.view(0, 0, image.width(), image.height()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use image::Rgba;
+
+    use image::RgbaImage;
+
+    use super::{
+        AtlasGrowth, Atlasable, Clustering, Platform, SizeConstraint, TextureAtlas,
+        WHITE_PIXEL_SPRITE,
+    };
+    use crate::{
+        cancellation::CancellationToken,
+        error::{Cancelled, Error},
+        inputs::{
+            adjustments::AdjustmentsFile, materials::MaterialsFile, palettes::PalettesFile,
+            sprite_references::SpriteReferences, user_data::UserDataFile,
+        },
+        intermediates::{
+            effects::{Flip, SpriteDerivation},
+            font::{CharacterSprite, DynamicSlots, FontIntermediate},
+            label::{BakedLabel, LabelSetIntermediate},
+            sprite::{FlippedSprite, SpriteFrame, SpriteIntermediate},
+        },
+        math::{IMargins, IRect, ISize},
+        outputs::atlas_meta::AtlasMeta,
+        packing::PackAlgorithm,
+        sources::{SourceId, SourceSprite, Sources},
+        warnings::Warning,
+    };
+
+    fn opaque_label_set(sizes: &[(u32, u32)]) -> LabelSetIntermediate {
+        LabelSetIntermediate {
+            font_name: "test".to_string(),
+            labels: sizes
+                .iter()
+                .enumerate()
+                .map(|(i, &(w, h))| BakedLabel {
+                    key: format!("label.{i}"),
+                    language: "en".to_string(),
+                    text: String::new(),
+                    image: image::RgbaImage::from_pixel(w, h, Rgba([255, 255, 255, 255])),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_label_set(opaque_label_set(&[(4, 2), (3, 3), (0, 0)]));
+
+        let references = SpriteReferences::parse("label.0\nlabel.2.en\n");
+        let report = atlas.prune(&references);
+
+        assert_eq!(report.removed, vec!["label.1.en".to_string()]);
+        assert_eq!(report.saved_area, 25);
+
+        let keys: Vec<_> = atlas.label_sets[0].labels.iter().map(|l| &l.key).collect();
+        assert_eq!(keys, vec!["label.0", "label.2"]);
+    }
+
+    #[test]
+    fn test_empty_sprites_warning() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_label_set(opaque_label_set(&[(4, 2), (0, 0), (3, 0)]));
+        atlas.load_sizes()?;
+
+        assert_eq!(atlas.sprite_sizes.len(), 1);
+        assert_eq!(
+            atlas.take_warnings(),
+            vec![Warning::SkippedEmptySprites {
+                asset: "labels of font 'test'".to_string(),
+                count: 2,
+            }]
+        );
+        assert!(atlas.warnings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inject_white_pixel() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_label_set(opaque_label_set(&[(5, 3)]));
+        atlas.inject_white_pixel();
+        atlas.inject_white_pixel();
+        assert_eq!(atlas.sprites.len(), 1);
+
+        let cancel = CancellationToken::new();
+        atlas.load_sizes()?;
+        atlas.pack(&cancel)?;
+        let image = atlas.build_image(&atlas.layout, &Sources::new(), &cancel)?;
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.png".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+        let sprite = meta
+            .named_sprites
+            .iter()
+            .find(|sprite| sprite.name == WHITE_PIXEL_SPRITE)
+            .unwrap();
+        let rect = meta.sprites[sprite.first_sprite_index as usize];
+        assert_eq!(rect.size(), ISize::new(2, 2));
+
+        let (x, y) = rect.checked_umin().unwrap();
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert_eq!(*image.get_pixel(x + dx, y + dy), Rgba([255; 4]));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_label_set(opaque_label_set(&[(4, 2), (3, 3)]));
+        atlas.load_sizes()?;
+
+        let cancel = CancellationToken::new();
+        cancel.clone().cancel();
+
+        let err = atlas.pack(&cancel).unwrap_err();
+        assert!(err.is::<Cancelled>());
+
+        atlas.pack(&CancellationToken::new())?;
+        let err = atlas
+            .build_image(&atlas.layout, &Sources::new(), &cancel)
+            .unwrap_err();
+        assert!(err.is::<Cancelled>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pot_cost_report() -> anyhow::Result<()> {
+        // Three 9x9 sprites don't fit into 16x16, the 32x16 atlas is half empty
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_label_set(opaque_label_set(&[(9, 9), (9, 9), (9, 9)]));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let report = atlas.pot_cost_report();
+        assert_eq!(report.size, ISize::new(32, 16));
+        assert_eq!(report.used_size, ISize::new(27, 9));
+        assert_eq!(report.sprite_area, 243);
+        assert_eq!(report.wasted_bytes, (32 * 16 - 27 * 9) * 4);
+        assert_eq!(report.suggestions.len(), 2);
+        assert!(report.suggestions[0].contains("1 page(s) of 16x16"));
+        assert!(report.suggestions[1].contains("fit them into 16x16"));
+
+        // A snug atlas gets no suggestions
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_label_set(opaque_label_set(&[(8, 8), (8, 8), (8, 8), (8, 8)]));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let report = atlas.pot_cost_report();
+        assert_eq!(report.wasted_bytes, 0);
+        assert!(report.suggestions.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_occupancy_report() -> anyhow::Result<()> {
+        // Five 6x6 sprites would fit into 16x16, but not with their padding
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_label_set(opaque_label_set(&[(6, 6); 5]));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let report = atlas.occupancy_report();
+        assert_eq!(report.size, ISize::new(32, 16));
+        assert_eq!(report.sprite_area, 180);
+        assert_eq!(report.padding_area, 140);
+        assert_eq!(report.occupancy, 180.0 / 512.0);
+
+        let rows: Vec<_> = report
+            .rows
+            .iter()
+            .map(|row| (row.y, row.height, row.sprites, row.empty_area))
+            .collect();
+        assert_eq!(rows, [(0, 8, 4, 0), (8, 8, 1, 192)]);
+        assert_eq!(report.largest_empty, (0, IRect::new(8, 8, 24, 8)));
+
+        assert_eq!(report.suggestions.len(), 1);
+        assert!(report.suggestions[0].contains("70.3% of 16x16"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_derived_sprites_and_user_data() -> anyhow::Result<()> {
+        let sprite = |name: &str| {
+            SpriteIntermediate::from_frames(
+                name.to_string(),
+                vec![
+                    SpriteFrame::Baked(RgbaImage::from_pixel(4, 4, Rgba([255, 255, 255, 255]))),
+                    SpriteFrame::Baked(RgbaImage::from_pixel(2, 3, Rgba([255, 255, 255, 255]))),
+                ],
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_sprite(sprite("hero"));
+        atlas.with_sprite(sprite("tree"));
+
+        let shadow = SpriteDerivation::Shadow {
+            blur_radius: 2,
+            color: [0, 0, 0, 128],
+        };
+        let srcs = Sources::new();
+        let derived = atlas.derive_sprites(shadow, &SpriteReferences::parse("hero\n"), &srcs)?;
+        assert_eq!(derived, vec!["hero#shadow".to_string()]);
+        assert_eq!(
+            atlas.sprites[2].get_sprite_sizes(),
+            vec![ISize::new(8, 8), ISize::new(6, 7)]
+        );
+
+        // Shadows stay as long as their sprite does
+        atlas.prune(&SpriteReferences::parse("hero\n"));
+        assert_eq!(atlas.sprites.len(), 2);
+
+        atlas.apply_user_data(&UserDataFile::try_parse(
+            "[hero]\nmaterial = \"metal\"\n[ghost]\nmaterial = \"ectoplasm\"",
+        )?);
+        assert_eq!(
+            atlas.take_warnings(),
+            vec![Warning::UnknownUserDataSprite {
+                sprite_name: "ghost".to_string()
+            }]
+        );
+
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.png".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+
+        let hero = &meta.named_sprites[0];
+        let hero_shadow = &meta.named_sprites[1];
+        assert_eq!(hero.derived, vec!["hero#shadow".to_string()]);
+        assert_eq!(hero_shadow.derived_from.as_deref(), Some("hero"));
+        assert_eq!(hero_shadow.offset, glam::IVec2::new(-2, -2));
+        assert_eq!(hero_shadow.num_frames, 2);
+        assert_eq!(hero.user_data["material"], "metal");
+        assert!(hero_shadow.user_data.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_aspect_ratio() -> anyhow::Result<()> {
+        let sizes = [(9, 9), (9, 9), (9, 9)];
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_label_set(opaque_label_set(&sizes));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(32, 16));
+
+        atlas.max_aspect_ratio = Some(1.5);
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(32, 32));
+        assert!(atlas.pot_cost_report().suggestions.is_empty());
+
+        atlas.max_aspect_ratio = Some(0.5);
+        assert!(atlas.pack(&CancellationToken::new()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_layers() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_label_set(opaque_label_set(&[(9, 9); 6]));
+        atlas.load_sizes()?;
+
+        assert_eq!(
+            atlas.pack_layers(ISize::new(20, 20), &CancellationToken::new())?,
+            2
+        );
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.ktx2".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+        meta.verify()?;
+        assert_eq!((meta.width, meta.height, meta.layer_count), (20, 20, 2));
+        assert_eq!(meta.layers.iter().filter(|&&layer| layer == 0).count(), 4);
+
+        for layer in 0..2 {
+            let image = atlas.build_image(
+                &atlas.layout.layer(layer),
+                &Sources::new(),
+                &CancellationToken::new(),
+            )?;
+            assert_eq!(image.dimensions(), (20, 20));
+        }
+
+        // Too large for any layer
+        atlas.with_label_set(opaque_label_set(&[(21, 1)]));
+        atlas.load_sizes()?;
+        assert!(atlas
+            .pack_layers(ISize::new(20, 20), &CancellationToken::new())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_material_pages() -> anyhow::Result<()> {
+        let sprite = |name: &str| {
+            SpriteIntermediate::from_frames(
+                name.to_string(),
+                vec![SpriteFrame::Baked(RgbaImage::from_pixel(
+                    4,
+                    4,
+                    Rgba([255, 255, 255, 255]),
+                ))],
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        for name in ["hero", "spark", "smoke", "tree"] {
+            atlas.with_sprite(sprite(name));
+        }
+        atlas.apply_materials(&MaterialsFile::try_parse("additive spark smoke ghost")?);
+        assert_eq!(
+            atlas.take_warnings(),
+            vec![Warning::UnknownMaterialSprite {
+                sprite_name: "ghost".to_string()
+            }]
+        );
+
+        // Everything fits into one layer, unless the materials are kept apart
+        atlas.load_sizes()?;
+        assert_eq!(
+            atlas.pack_layers(ISize::new(16, 16), &CancellationToken::new())?,
+            1
+        );
+
+        atlas.material_pages = true;
+        assert!(atlas.pack(&CancellationToken::new()).is_err());
+        assert_eq!(
+            atlas.pack_layers(ISize::new(16, 16), &CancellationToken::new())?,
+            2
+        );
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.ktx2".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+        let layer_of = |name: &str| {
+            let sprite = meta.named_sprites.iter().find(|s| s.name == name).unwrap();
+            meta.layers[sprite.first_sprite_index as usize]
+        };
+        assert_eq!(meta.materials["additive"], ["spark", "smoke"]);
+        assert_eq!(layer_of("spark"), layer_of("smoke"));
+        assert_eq!(layer_of("hero"), layer_of("tree"));
+        assert_ne!(layer_of("hero"), layer_of("spark"));
+
+        // Pruned sprites leave their material
+        atlas.prune(&SpriteReferences::parse("hero tree smoke"));
+        assert_eq!(atlas.materials["additive"], ["smoke"]);
+        atlas.prune(&SpriteReferences::parse("hero tree"));
+        assert!(atlas.materials.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> anyhow::Result<()> {
+        let sprite = |name: &str| {
+            SpriteIntermediate::from_frames(
+                name.to_string(),
+                vec![SpriteFrame::Baked(RgbaImage::new(2, 2))],
+            )
+        };
+
+        let mut base = TextureAtlas::new(IMargins::uniform(0));
+        base.with_sprite(sprite("hero"));
+
+        let mut forest = TextureAtlas::new(IMargins::uniform(0));
+        forest.with_sprite(sprite("hero"));
+        forest.flipped_sprites.push(FlippedSprite {
+            name: "hero#flip-x".to_string(),
+            sprite_name: "hero".to_string(),
+            flip: Flip { x: true, y: false },
+        });
+        forest.with_label_set(LabelSetIntermediate {
+            font_name: "m5x7".to_string(),
+            labels: vec![],
+        });
+        forest.apply_materials(&MaterialsFile::try_parse("additive hero#flip-x")?);
+
+        base.merge(forest, "forest");
+
+        let names: Vec<_> = base.sprites.iter().map(|sprite| &sprite.name).collect();
+        assert_eq!(names, ["hero", "forest/hero"]);
+        assert_eq!(base.flipped_sprites[0].name, "forest/hero#flip-x");
+        assert_eq!(base.flipped_sprites[0].sprite_name, "forest/hero");
+        assert_eq!(base.label_sets[0].font_name, "forest/m5x7");
+        assert_eq!(base.materials["additive"], ["forest/hero#flip-x"]);
+
+        base.load_sizes()?;
+        base.pack(&CancellationToken::new())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_size() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        for index in 0..3 {
+            atlas.with_sprite(SpriteIntermediate::from_frames(
+                format!("crate_{index}"),
+                vec![SpriteFrame::Baked(RgbaImage::new(40, 40))],
+            ));
+        }
+        atlas.load_sizes()?;
+
+        // Only one of them fits
+        atlas.max_size = 64;
+        let err = atlas.pack(&CancellationToken::new()).unwrap_err();
+        match Error::find(&err) {
+            Some(Error::PackOverflow { sprites, limit }) => {
+                assert_eq!(sprites.len(), 2);
+                assert_eq!(sprites[0], "Sprite #0 of sprite 'crate_1' (40x40)");
+                assert_eq!(limit, "64x64");
+            }
+            other => panic!("Expected a pack overflow, got {other:?}"),
+        }
+
+        atlas.max_size = 100;
+        assert!(matches!(
+            Error::find(&atlas.pack(&CancellationToken::new()).unwrap_err()),
+            Some(Error::InvalidOption(_))
+        ));
+
+        atlas.max_size = 128;
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(128, 64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_platform_limits() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        for index in 0..2 {
+            atlas.with_sprite(SpriteIntermediate::from_frames(
+                format!("backdrop_{index}"),
+                vec![SpriteFrame::Baked(RgbaImage::new(1100, 1100))],
+            ));
+        }
+        atlas.load_sizes()?;
+
+        atlas.platform = Some("handheld".parse()?);
+        atlas.max_size = 4096;
+        assert!(matches!(
+            Error::find(&atlas.pack(&CancellationToken::new()).unwrap_err()),
+            Some(Error::InvalidOption(_))
+        ));
+
+        atlas.max_size = 2048;
+        match Error::find(&atlas.pack(&CancellationToken::new()).unwrap_err()) {
+            Some(Error::PackOverflow { limit, .. }) => {
+                assert_eq!(limit, "2048x2048, the handheld limit")
+            }
+            other => panic!("Expected a pack overflow, got {other:?}"),
+        }
+
+        atlas.platform = Some(Platform::Console);
+        atlas.max_size = Platform::Console.max_texture_size();
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(4096, 2048));
+
+        assert!("switch".parse::<Platform>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_free_growth() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        for index in 0..3 {
+            atlas.with_sprite(SpriteIntermediate::from_frames(
+                format!("banner_{index}"),
+                vec![SpriteFrame::Baked(RgbaImage::new(300, 20))],
+            ));
+        }
+        atlas.load_sizes()?;
+
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(512, 256));
+
+        atlas.growth = AtlasGrowth::Free;
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(1024, 32));
+
+        atlas.max_aspect_ratio = Some(4.0);
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(512, 128));
+
+        atlas.max_size = 256;
+        assert!(matches!(
+            Error::find(&atlas.pack(&CancellationToken::new()).unwrap_err()),
+            Some(Error::PackOverflow { .. })
+        ));
+
+        assert!("wide".parse::<AtlasGrowth>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_constraint() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.atlas_margin = 2;
+        for (index, width) in [30, 20].into_iter().enumerate() {
+            atlas.with_sprite(SpriteIntermediate::from_frames(
+                format!("door_{index}"),
+                vec![SpriteFrame::Baked(RgbaImage::new(width, 10))],
+            ));
+        }
+        atlas.load_sizes()?;
+
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(64, 32));
+
+        atlas.size_constraint = SizeConstraint::ForceSquare;
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(64, 64));
+
+        atlas.growth = AtlasGrowth::Free;
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(64, 64));
+
+        // Both padded sprites next to each other, plus the margin
+        atlas.growth = AtlasGrowth::Square;
+        atlas.size_constraint = SizeConstraint::AnySize;
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.size, ISize::new(2 + 32 + 22 + 2, 2 + 12 + 2));
+        assert_eq!(atlas.layout.used_size(atlas.padding), atlas.layout.size);
+
+        assert!("npot".parse::<SizeConstraint>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_sprites() -> anyhow::Result<()> {
+        let red = RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]));
+        let sprite = |name: &str, frames: Vec<RgbaImage>| {
+            SpriteIntermediate::from_frames(
+                name.to_string(),
+                frames.into_iter().map(SpriteFrame::Baked).collect(),
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_sprite(sprite("blink", vec![red.clone(), red.clone()]));
+        atlas.with_sprite(sprite("stop", vec![red.clone()]));
+        atlas.with_sprite(sprite(
+            "go",
+            vec![RgbaImage::from_pixel(4, 4, Rgba([0, 255, 0, 255]))],
+        ));
+        atlas.load_sizes()?;
+
+        assert_eq!(atlas.dedup_sprites(&Sources::new())?, 2);
+        assert_eq!(atlas.sprite_sizes.len(), 2);
+
+        atlas.pack(&CancellationToken::new())?;
+        let bounds = atlas.layout.bounds_map();
+        assert_eq!(bounds.len(), 4);
+        assert_eq!(bounds[&(0, 0)], bounds[&(0, 1)]);
+        assert_eq!(bounds[&(0, 0)], bounds[&(1, 0)]);
+        assert_ne!(bounds[&(0, 0)], bounds[&(2, 0)]);
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.png".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+        assert_eq!(meta.duplicate_of, BTreeMap::from([(1, 0), (2, 0)]));
+        meta.verify()?;
+
+        // Sizes are loaded from scratch
+        atlas.load_sizes()?;
+        assert!(atlas.duplicate_sprites.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pages() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        for index in 0..3 {
+            atlas.with_sprite(SpriteIntermediate::from_frames(
+                format!("backdrop_{index}"),
+                vec![SpriteFrame::Baked(RgbaImage::new(600, 600))],
+            ));
+        }
+        atlas.pages = true;
+
+        // Only one fits into every 1024x1024 page
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+        assert_eq!(atlas.layout.layer_count, 3);
+        assert_eq!(atlas.layout.size, ISize::new(1024, 1024));
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test-0.png".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+        let mut pages = meta.layers.clone();
+        pages.sort();
+        assert_eq!(pages, [0, 1, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_palettes() -> anyhow::Result<()> {
+        let blue = Rgba([48, 80, 160, 255]);
+        let sprite = |name: &str, frames: usize| SpriteIntermediate {
+            scale: 2.0,
+            ..SpriteIntermediate::from_frames(
+                name.to_string(),
+                vec![SpriteFrame::Baked(RgbaImage::from_pixel(2, 2, blue)); frames],
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_sprite(sprite("knight", 1));
+        atlas.with_sprite(sprite("slime", 2));
+
+        let palettes = PalettesFile::try_parse(
+            r#"
+            [palettes.red]
+            sprites = ["knight"]
+            colors = { "3050a0" = "a03030" }
+
+            [palettes.elite]
+            sprites = ["slime"]
+            colors = { "3050a0" = "ffd700" }
+            frames = true
+            "#,
+        )?;
+        let names = atlas.swap_palettes(&palettes, &Sources::new())?;
+        assert_eq!(names, ["knight#red"]);
+
+        let frame_color = |sprite: &SpriteIntermediate, index: usize| match &sprite.frames[index] {
+            SpriteFrame::Baked(image) => *image.get_pixel(0, 0),
+            SpriteFrame::Source(_) => unreachable!(),
+        };
+
+        let knight_red = &atlas.sprites[2];
+        assert_eq!(knight_red.name, "knight#red");
+        assert_eq!(knight_red.scale, 2.0);
+        assert_eq!(frame_color(knight_red, 0), Rgba([160, 48, 48, 255]));
+        assert_eq!(frame_color(&atlas.sprites[0], 0), blue);
+
+        let slime = &atlas.sprites[1];
+        assert_eq!(slime.frames.len(), 4);
+        assert_eq!(frame_color(slime, 1), blue);
+        assert_eq!(frame_color(slime, 2), Rgba([255, 215, 0, 255]));
+
+        let unknown = PalettesFile::try_parse(
+            "[palettes.red]\nsprites = [\"ghost\"]\ncolors = { \"3050a0\" = \"a03030\" }",
+        )?;
+        assert!(matches!(
+            Error::find(&atlas.swap_palettes(&unknown, &Sources::new()).unwrap_err()),
+            Some(Error::InvalidOption(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_adjust_colors() -> anyhow::Result<()> {
+        let red = Rgba([255, 0, 0, 255]);
+        let sprite = |name: &str| {
+            SpriteIntermediate::from_frames(
+                name.to_string(),
+                vec![SpriteFrame::Baked(RgbaImage::from_pixel(2, 2, red))],
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_sprite(sprite("props/tree"));
+        atlas.with_sprite(sprite("hero"));
+        atlas.with_label_set(LabelSetIntermediate {
+            font_name: "m5x7".to_string(),
+            labels: vec![BakedLabel {
+                key: "label.title".to_string(),
+                language: "en".to_string(),
+                text: "Title".to_string(),
+                image: RgbaImage::from_pixel(4, 2, red),
+            }],
+        });
+
+        let adjustments = AdjustmentsFile::try_parse(
+            r#"
+            [groups.night]
+            include = "props/*,labels"
+            hue = 120
+
+            [groups.dark]
+            include = "props/*"
+            brightness = -0.5
+            "#,
+        )?;
+        assert_eq!(atlas.adjust_colors(&adjustments, &Sources::new())?, 3);
+
+        let frame_color = |sprite: &SpriteIntermediate| match &sprite.frames[0] {
+            SpriteFrame::Baked(image) => *image.get_pixel(0, 0),
+            SpriteFrame::Source(_) => unreachable!(),
+        };
+        // Darkened first (`dark` comes before `night`), then turned green
+        assert_eq!(frame_color(&atlas.sprites[0]), Rgba([0, 128, 0, 255]));
+        assert_eq!(frame_color(&atlas.sprites[1]), red);
+        assert_eq!(
+            *atlas.label_sets[0].labels[0].image.get_pixel(0, 0),
+            Rgba([0, 255, 0, 255])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_font_regions() -> anyhow::Result<()> {
+        let slot_font = |name: &str, count: u32, cell_size: ISize| FontIntermediate {
+            dynamic_slots: Some(DynamicSlots { count, cell_size }),
+            ..FontIntermediate::from_chars(
+                name.to_string(),
+                cell_size.height,
+                cell_size.height,
+                vec![],
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.spacing = 1;
+        atlas.font_regions = true;
+        atlas.with_font(slot_font("small", 9, ISize::new(3, 5)));
+        atlas.with_font(slot_font("large", 4, ISize::new(6, 9)));
+        atlas.with_label_set(opaque_label_set(&[(7, 4), (2, 11), (5, 5)]));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        assert_eq!(atlas.layout.regions.len(), 2);
+
+        for placement in atlas.layout.placements.iter() {
+            let cell = placement.bounds.grow(atlas.padding);
+
+            for (font_asset_id, font) in atlas.fonts.iter().enumerate() {
+                let region = atlas.layout.region(font_asset_id).unwrap();
+
+                // Every glyph is inside of the region of its font and
+                // nothing else reaches into it
+                if placement.asset_id == font_asset_id {
+                    assert!(region.contains(cell), "{} outside {region:?}", font.name);
+                } else {
+                    assert!(!region.intersects(cell), "{cell:?} inside {}", font.name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_alpha_glyphs() -> anyhow::Result<()> {
+        let glyph = |char_code: u32, color: [u8; 4]| CharacterSprite {
+            char_code,
+            sprite: SourceSprite {
+                image_source_id: SourceId::Image(0),
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 3,
+            },
+            frame: 0,
+            x_offset: 0,
+            y_offset: 0,
+            x_advance: 3,
+            baked: Some(RgbaImage::from_pixel(2, 3, Rgba(color))),
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.font_regions = true;
+        atlas.alpha_glyphs = true;
+        atlas.with_font(FontIntermediate::from_chars(
+            "ui".to_string(),
+            4,
+            3,
+            vec![glyph(65, [255, 255, 255, 200]), glyph(66, [0, 0, 0, 255])],
+        ));
+        atlas.with_label_set(opaque_label_set(&[(3, 3)]));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let image = atlas.build_image(&atlas.layout, &Sources::new(), &CancellationToken::new())?;
+        let pixel_at = |asset_id: usize, sprite_id: usize| {
+            let placement = atlas.layout.placement_map()[&(asset_id, sprite_id)];
+            let (x, y) = placement.bounds.checked_umin().unwrap();
+            *image.get_pixel(x, y)
+        };
+
+        // Black is no coverage, labels keep their colors
+        assert_eq!(pixel_at(0, 0), Rgba([255, 255, 255, 200]));
+        assert_eq!(pixel_at(0, 1), Rgba([255, 255, 255, 0]));
+        assert_eq!(pixel_at(1, 0), Rgba([255, 255, 255, 255]));
+
+        let region = atlas.layout.region(0).unwrap();
+        let (x, y) = region.checked_umin().unwrap();
+        assert_eq!(*image.get_pixel(x, y), Rgba([255, 255, 255, 0]));
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.png".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+        assert!(meta.fonts[0].alpha_only);
+        assert_eq!(meta.fonts[0].region, Some(region));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_standalone_font() -> anyhow::Result<()> {
+        let font = |name: &str| FontIntermediate {
+            family: "test".to_string(),
+            dynamic_slots: Some(DynamicSlots {
+                count: 4,
+                cell_size: ISize::new(3, 5),
+            }),
+            ..FontIntermediate::from_chars(name.to_string(), 5, 5, vec![])
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.spacing = 2;
+        atlas.with_font(font("small"));
+        atlas.with_font(font("large"));
+        atlas.with_label_set(opaque_label_set(&[(7, 4)]));
+
+        assert!(atlas.standalone_font("medium").is_none());
+
+        let mut standalone = atlas.standalone_font("large").unwrap();
+        assert_eq!(standalone.spacing, 2);
+        assert!(standalone.label_sets.is_empty());
+        let names: Vec<_> = standalone.fonts.iter().map(|font| &font.name).collect();
+        assert_eq!(names, ["large"]);
+
+        standalone.load_sizes()?;
+        standalone.pack(&CancellationToken::new())?;
+        assert_eq!(standalone.layout.placements.len(), 4);
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "font-large".to_string(),
+            "font-large.png".to_string(),
+            &standalone,
+            &standalone.layout,
+        )?;
+        meta.verify()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_shelves() -> anyhow::Result<()> {
+        let glyph = |char_code: u32, width: u32, height: u32| CharacterSprite {
+            char_code,
+            sprite: SourceSprite {
+                image_source_id: SourceId::Image(0),
+                x: 0,
+                y: 0,
+                width: width as i32,
+                height: height as i32,
+            },
+            frame: 0,
+            x_offset: 0,
+            y_offset: 0,
+            x_advance: width as i32,
+            baked: Some(RgbaImage::from_pixel(
+                width,
+                height,
+                Rgba([255, 255, 255, 255]),
+            )),
+        };
+        let font = FontIntermediate::from_chars(
+            "test".to_string(),
+            7,
+            6,
+            (0..20)
+                .map(|i| glyph(65 + i, 2 + i % 4, 3 + i % 5))
+                .collect(),
+        );
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.spacing = 1;
+        atlas.with_font(font.clone().with_line_shelves()?);
+        atlas.with_label_set(opaque_label_set(&[(7, 4), (2, 11)]));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.png".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+        meta.verify()?;
+
+        let stride = meta.fonts[0].shelf_stride.unwrap() as i32;
+        let region = meta.fonts[0].region.unwrap();
+        assert_eq!(stride, 7 + 2 + 1);
+
+        // Every glyph is inside of one shelf of the region
+        for ch in meta.fonts[0].chars.iter() {
+            let cell = meta.sprites[ch.first_sprite_index as usize].grow(atlas.padding);
+            assert!(region.contains(cell));
+
+            let shelf = (cell.min.y - region.min.y) / stride;
+            assert_eq!(cell.min.y, region.min.y + shelf * stride);
+            assert!(cell.max.y <= region.min.y + (shelf + 1) * stride - atlas.spacing);
+        }
+        assert_eq!((region.height() + 1) % stride, 0);
+
+        // Taller glyphs don't fit into a line
+        let tall = FontIntermediate {
+            chars: vec![glyph(65, 2, 8)],
+            ..font
+        };
+        assert!(tall.clone().with_line_shelves().is_err());
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_font(FontIntermediate {
+            line_shelves: true,
+            ..tall
+        });
+        atlas.load_sizes()?;
+        assert!(atlas.pack(&CancellationToken::new()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings_hash() {
+        type Change = (&'static str, fn(&mut TextureAtlas));
+        let changes: Vec<Change> = vec![
+            ("padding", |atlas| atlas.padding = IMargins::uniform(2)),
+            ("spacing", |atlas| atlas.spacing = 1),
+            ("extrude", |atlas| atlas.extrude = 1),
+            ("atlas_margin", |atlas| atlas.atlas_margin = 1),
+            ("max_aspect_ratio", |atlas| {
+                atlas.max_aspect_ratio = Some(2.0)
+            }),
+            ("max_size", |atlas| atlas.max_size = 2048),
+            ("platform", |atlas| {
+                atlas.platform = Some(Platform::Handheld)
+            }),
+            ("growth", |atlas| atlas.growth = AtlasGrowth::Free),
+            ("size_constraint", |atlas| {
+                atlas.size_constraint = SizeConstraint::AnySize
+            }),
+            ("font_regions", |atlas| atlas.font_regions = true),
+            ("alpha_glyphs", |atlas| atlas.alpha_glyphs = true),
+            ("pages", |atlas| atlas.pages = true),
+            ("material_pages", |atlas| atlas.material_pages = true),
+            ("materials", |atlas| {
+                atlas
+                    .materials
+                    .insert("additive".to_string(), vec!["spark".to_string()]);
+            }),
+            ("clustering", |atlas| {
+                atlas.clustering = Some(Clustering::AverageColor)
+            }),
+            ("pack_algorithm", |atlas| {
+                atlas.pack_algorithm = PackAlgorithm::Skyline
+            }),
+            ("dynamic_slots", |atlas| {
+                atlas.fonts[0].dynamic_slots = Some(DynamicSlots {
+                    count: 2,
+                    cell_size: ISize::new(4, 4),
+                })
+            }),
+            ("line_shelves", |atlas| atlas.fonts[0].line_shelves = true),
+        ];
+
+        let base = || {
+            let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+            atlas.with_font(FontIntermediate::from_chars(
+                "test".to_string(),
+                8,
+                6,
+                vec![],
+            ));
+            atlas
+        };
+        let base_hash = base().settings_hash();
+        assert_eq!(base().settings_hash(), base_hash);
+
+        for (name, change) in changes {
+            let mut atlas = base();
+            change(&mut atlas);
+            assert_ne!(atlas.settings_hash(), base_hash, "{name} isn't hashed");
+        }
+    }
+
+    #[test]
+    fn test_clustering() -> anyhow::Result<()> {
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 255]];
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        for index in 0..4 {
+            atlas.with_sprite(SpriteIntermediate::from_frames(
+                format!("sprite_{index}"),
+                vec![SpriteFrame::Baked(RgbaImage::from_pixel(
+                    2,
+                    2,
+                    Rgba(colors[index % 2]),
+                ))],
+            ));
+        }
+
+        atlas.clustering = Some(Clustering::AverageColor);
+        atlas.load_sizes()?;
+
+        // The clusters have to be loaded first
+        assert!(matches!(
+            Error::find(&atlas.pack(&CancellationToken::new()).unwrap_err()),
+            Some(Error::InvalidOption(_))
+        ));
+
+        atlas.load_clusters(&Sources::new())?;
+        atlas.pack(&CancellationToken::new())?;
+
+        // Sprites of the same color share a row
+        let bounds = atlas.layout.bounds_map();
+        assert_eq!(bounds[&(0, 0)].min.y, bounds[&(2, 0)].min.y);
+        assert_eq!(bounds[&(1, 0)].min.y, bounds[&(3, 0)].min.y);
+        assert_ne!(bounds[&(0, 0)].min.y, bounds[&(1, 0)].min.y);
+
+        // Baked sprites don't come from any sheet
+        atlas.clustering = Some(Clustering::SourceSheet);
+        assert_eq!(atlas.load_clusters(&Sources::new())?, 1);
+        assert_eq!(atlas.sprite_clusters, vec![0; 4]);
+
+        assert_eq!("color".parse::<Clustering>()?, Clustering::AverageColor);
+        assert!("hue".parse::<Clustering>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extrude() -> anyhow::Result<()> {
+        let mut atlas = TextureAtlas::new(IMargins::new(1, 1, 2, 1));
+        // More than the padding only fills the padding
+        atlas.extrude = 2;
+        for (name, color) in [("red", [255, 0, 0, 255]), ("blue", [0, 0, 255, 255])] {
+            atlas.with_sprite(SpriteIntermediate::from_frames(
+                name.to_string(),
+                vec![SpriteFrame::Baked(RgbaImage::from_pixel(3, 2, Rgba(color)))],
+            ));
+        }
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let image = atlas.build_image(&atlas.layout, &Sources::new(), &CancellationToken::new())?;
+
+        for placement in atlas.layout.placements.iter() {
+            let (x, y) = placement.bounds.checked_umin().unwrap();
+            let color = *image.get_pixel(x, y);
+
+            let cell = placement.bounds.grow(atlas.padding);
+            for y in cell.min.y..cell.max.y {
+                for x in cell.min.x..cell.max.x {
+                    assert_eq!(*image.get_pixel(x as u32, y as u32), color, "{x},{y}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_padding_and_spacing_gaps() -> anyhow::Result<()> {
+        let sizes = [
+            (5, 9),
+            (3, 3),
+            (7, 2),
+            (1, 1),
+            (4, 6),
+            (6, 6),
+            (2, 8),
+            (9, 1),
+        ];
+
+        for (padding, spacing, margin) in [
+            (IMargins::uniform(0), 0, 0),
+            (IMargins::uniform(1), 0, 0),
+            (IMargins::new(1, 2, 3, 4), 0, 0),
+            (IMargins::uniform(0), 2, 0),
+            (IMargins::new(2, 0, 1, 0), 1, 0),
+            (IMargins::uniform(0), 0, 1),
+            (IMargins::uniform(1), 1, 2),
+        ] {
+            let mut atlas = TextureAtlas::new(padding);
+            atlas.spacing = spacing;
+            atlas.atlas_margin = margin;
+            atlas.with_label_set(opaque_label_set(&sizes));
+            atlas.load_sizes()?;
+            atlas.pack(&CancellationToken::new())?;
+
+            let image =
+                atlas.build_image(&atlas.layout, &Sources::new(), &CancellationToken::new())?;
+            let image_bounds = IRect::new(0, 0, image.width() as i32, image.height() as i32)
+                .shrink(IMargins::uniform(margin));
+
+            let rects: Vec<IRect> = atlas.layout.placements.iter().map(|p| p.bounds).collect();
+            assert_eq!(rects.len(), sizes.len());
+
+            for &rect in rects.iter() {
+                let cell = rect.grow(padding);
+                assert!(image_bounds.contains(cell));
+
+                // Everything around the sprite within its padding is empty
+                for y in cell.min.y..cell.max.y {
+                    for x in cell.min.x..cell.max.x {
+                        let alpha = image.get_pixel(x as u32, y as u32)[3];
+                        let inside = rect.contains_point(glam::IVec2::new(x, y));
+
+                        assert_eq!(alpha, if inside { 255 } else { 0 }, "{padding:?} {x},{y}");
+                    }
+                }
+
+                // No other sprite gets closer than the spacing
+                let spaced = cell.grow(IMargins::uniform(spacing));
+                for &other in rects.iter().filter(|&&other| other != rect) {
+                    assert!(!spaced.intersects(other.grow(padding)));
+                }
+            }
+        }
+
+        // Negative spacing would overlap the padding of neighbours
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.spacing = -1;
+        atlas.with_label_set(opaque_label_set(&sizes));
+        atlas.load_sizes()?;
+        let err = atlas.pack(&CancellationToken::new()).unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::InvalidOption(_))));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod benches {
+    extern crate test;
+
+    use image::{GenericImage, RgbaImage};
+    use test::Bencher;
+
+    use super::TextureAtlas;
+    use crate::{
+        cancellation::CancellationToken, intermediates::font::FontIntermediate, math::IMargins,
+        sources::Sources,
+    };
+
+    fn bundled_fonts_atlas() -> (TextureAtlas, Sources) {
+        let mut srcs = Sources::new();
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+
+        for path in ["assets/m5x7.fnt", "assets/m5x7-color.fnt"] {
+            let id = srcs.try_load_source(path).unwrap();
+            atlas.with_font(FontIntermediate::from_fnt(id, &srcs).unwrap());
+        }
+
+        atlas.load_sizes().unwrap();
+        atlas.pack(&CancellationToken::new()).unwrap();
+
+        (atlas, srcs)
+    }
+
+    #[bench]
+    fn bench_build_image_bundled_fonts(b: &mut Bencher) {
+        let (atlas, srcs) = bundled_fonts_atlas();
+
+        b.iter(|| {
+            atlas
+                .build_image(&atlas.layout, &srcs, &CancellationToken::new())
+                .unwrap()
+        });
+    }
+
+    /// What `build_image` used to do, copying every sprite into its own
+    /// buffer first.
+    #[bench]
+    fn bench_build_image_bundled_fonts_to_image(b: &mut Bencher) {
+        let (atlas, srcs) = bundled_fonts_atlas();
+        let layout = &atlas.layout;
+
+        b.iter(|| {
+            let mut output = RgbaImage::new(layout.size.width as u32, layout.size.height as u32);
+
+            for placement in layout.placements.iter() {
+                let texture = atlas
+                    .get_asset_sprite_view(placement.asset_id, placement.sprite_id, &srcs)
+                    .unwrap()
+                    .view()
+                    .to_image();
+                let (x, y) = placement.bounds.checked_umin().unwrap();
+
+                output.copy_from(&texture, x, y).unwrap();
+            }
+
+            output
+        });
+    }
 }
@@ -0,0 +1,86 @@
+//! Packs bitmap fonts, localized text labels and sprites into texture
+//! atlases, and exports their metadata. The `texture-packer` binary is a
+//! command line front end for this library.
+//!
+//! A build loads its files into [`Sources`], collects fonts
+//! ([`FontIntermediate`]), sprites ([`SpriteIntermediate`]) and labels in a
+//! [`TextureAtlas`], packs it and exports an [`AtlasMeta`] next to the
+//! texture:
+//!
+//! ```no_run
+//! use texture_packer::{
+//!     cancellation::CancellationToken, math::IMargins, AtlasMeta, FontIntermediate,
+//!     SpriteIntermediate, Sources, TextureAtlas,
+//! };
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut sources = Sources::new();
+//! let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+//!
+//! let font = sources.try_load_source("fonts/m5x7.fnt")?;
+//! atlas.with_font(FontIntermediate::from_fnt(font, &sources)?);
+//! let hero = sources.try_load_source("sprites/hero.png")?;
+//! atlas.with_sprite(SpriteIntermediate::from_image("hero".to_string(), hero, &sources)?);
+//!
+//! let cancel = CancellationToken::new();
+//! atlas.load_sizes()?;
+//! atlas.pack(&cancel)?;
+//!
+//! atlas
+//!     .build_image(&atlas.layout, &sources, &cancel)?
+//!     .save("atlas.png")?;
+//! AtlasMeta::from_texture_atlas("ui".to_string(), "atlas.png".to_string(), &atlas, &atlas.layout)?
+//!     .save("atlas.json")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`outputs::texture_files`] writes texture arrays and reduced pixel formats
+//! like the binary does, [`AtlasMeta::load_atlas`] reads an exported atlas
+//! back. [`build`] runs everything the binary does for a set of
+//! [`PackOptions`], whose `Default` matches the command line, and returns
+//! the warnings of the build for the caller to show.
+//!
+//! The default `cli` feature builds the binary, parses [`PackOptions`] with
+//! `clap` and adds `build_project` and `build_workspace` for project files,
+//! which are parsed like the command line. Embedders that don't need those
+//! can turn it off to leave out `clap`.
+//!
+//! Errors are [`anyhow::Error`]s, [`error::Error::find`] tells e.g. a missing
+//! source file apart from a malformed one.
+
+#![feature(error_generic_member_access)]
+#![cfg_attr(test, feature(test))]
+
+pub mod blit;
+pub mod cancellation;
+pub mod error;
+pub mod font_shared;
+pub mod hashing;
+pub mod image_io;
+pub mod inputs;
+pub mod intermediates;
+pub mod math;
+pub mod outputs;
+pub mod packing;
+pub mod pipeline;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "remote")]
+pub mod remote_cache;
+pub mod sampling;
+pub mod sources;
+pub mod timings;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod warnings;
+pub mod watch;
+
+pub use intermediates::{
+    font::FontIntermediate, sprite::SpriteIntermediate, texture_atlas::TextureAtlas,
+};
+pub use outputs::atlas_meta::AtlasMeta;
+pub use pipeline::{build, repack, watch, Build, PackOptions, RepackOptions};
+#[cfg(feature = "cli")]
+pub use pipeline::{build_project, build_workspace};
+pub use sources::{SourceId, Sources};
@@ -1,67 +1,186 @@
-#![allow(dead_code)]
-#![feature(error_generic_member_access)]
+use std::path::{Path, PathBuf};
 
+#[cfg(not(feature = "tui"))]
 use anyhow::Context;
-use intermediates::{font, texture_atlas::TextureAtlas};
-use outputs::atlas_meta::AtlasMeta;
-
-mod error;
-mod font_shared;
-mod inputs;
-mod intermediates;
-mod math;
-mod outputs;
-mod sources;
-
-fn main() -> anyhow::Result<()> {
-    let mut sources = sources::Sources::new();
-
-    let m5x7_id = sources
-        .try_load_source("assets/m5x7.fnt")
-        .with_context(|| format!("Failed to load 'm5x7.fnt'"))?;
-    let font = font::FontIntermediate::from_fnt(m5x7_id, &sources)?;
-
-    let test_text = font.render_text("Hewwo uwq, gg", &sources)?;
-    test_text
-        .save("test-text.png")
-        .context("Failed to save test text rendering")?;
+use clap::{Parser, Subcommand};
+#[cfg(not(feature = "tui"))]
+use texture_packer::error;
+use texture_packer::{
+    cancellation::CancellationToken,
+    outputs::report::{DiffReport, InspectReport, StatsReport},
+    timings, AtlasMeta, Build, PackOptions, RepackOptions,
+};
+#[cfg(feature = "tui")]
+use texture_packer::{outputs, tui};
+
+/// Packs the fonts and sprites of every --input into one atlas texture (--out)
+/// with metadata (--meta, a .rmp extension writes MessagePack).
+///
+/// Instead of packing, --verify, --unpack and --preview-font work on a
+/// previously exported atlas meta. The other commands report on or edit
+/// exported atlases.
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// `pack` is the default, so its options also work without it.
+    #[command(flatten)]
+    pack: PackOptions,
+}
 
-    let m5x7_color_id = sources
-        .try_load_source("assets/m5x7-color.fnt")
-        .with_context(|| format!("Failed to load 'm5x7-color.fnt'"))?;
-    let font_color = font::FontIntermediate::from_fnt(m5x7_color_id, &sources)?;
+#[derive(Subcommand)]
+enum Command {
+    /// Packs fonts and sprites into an atlas, the default.
+    Pack(Box<PackOptions>),
+    /// Redraws sprites in the texture of an exported atlas without moving
+    /// anything else.
+    Repack(RepackOptions),
+    /// Prints a summary of the fonts, labels and sprites of an exported
+    /// atlas.
+    Inspect(ReportArgs),
+    /// Prints statistics of an exported atlas.
+    Stats(ReportArgs),
+    /// Prints what changed between two exported atlases.
+    Diff {
+        /// The meta of the older atlas.
+        old_meta: PathBuf,
+        /// The meta of the newer atlas.
+        new_meta: PathBuf,
+        #[command(flatten)]
+        format: ReportFormat,
+    },
+    /// Browses the packed layout of an exported atlas in the terminal (needs
+    /// the `tui` feature).
+    Tui {
+        /// The meta of the atlas.
+        meta: PathBuf,
+    },
+}
 
-    let mut atlas = TextureAtlas::new(math::IMargins::uniform(0));
-    atlas.with_font(font);
-    atlas.with_font(font_color);
+#[derive(clap::Args)]
+struct ReportArgs {
+    /// The meta of the atlas.
+    meta: PathBuf,
+    #[command(flatten)]
+    format: ReportFormat,
+}
 
-    atlas.load_sizes();
-    atlas.pack();
+#[derive(clap::Args)]
+struct ReportFormat {
+    /// Prints the report in the versioned JSON schema of `outputs::report`.
+    #[arg(long)]
+    json: bool,
+}
 
-    let atlas_image = atlas
-        .build_image(&sources)
-        .context("Failed to build atlas image")?;
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    // The CLI never cancels, embedders keep a clone of this to abort the build
+    let cancel = CancellationToken::new();
+
+    match cli.command {
+        Some(Command::Pack(options)) => pack(&options, &cancel),
+        Some(Command::Repack(options)) => texture_packer::repack(&options, &cancel),
+        Some(Command::Inspect(args)) => {
+            let report = InspectReport::new(&AtlasMeta::load(&args.meta)?);
+            args.format.print(&report, || report.to_json())
+        }
+        Some(Command::Stats(args)) => {
+            let report = StatsReport::new(&AtlasMeta::load(&args.meta)?);
+            args.format.print(&report, || report.to_json())
+        }
+        Some(Command::Diff {
+            old_meta,
+            new_meta,
+            format,
+        }) => {
+            let report =
+                DiffReport::new(&AtlasMeta::load(&old_meta)?, &AtlasMeta::load(&new_meta)?);
+            format.print(&report, || report.to_json())
+        }
+        Some(Command::Tui { meta }) => browse_layout(&meta),
+        None => pack(&cli.pack, &cancel),
+    }
+}
 
-    atlas_image
-        .save("atlas.png")
-        .context("Failed to save atlas image")?;
+/// Builds what `options` asks for, then keeps rebuilding it with `--watch`.
+fn pack(options: &PackOptions, cancel: &CancellationToken) -> anyhow::Result<()> {
+    let tracing_guard = timings::init(options.timings, options.trace_chrome.as_deref())?;
+
+    let target = options.target.as_deref();
+    let features: Vec<&str> = options.features.iter().map(String::as_str).collect();
+
+    let atlas_warnings = match (&options.project, &options.workspace) {
+        (Some(project_file), _) => Some(texture_packer::build_project(
+            &options.path(project_file),
+            target,
+            &features,
+            cancel,
+        )?),
+        (None, Some(workspace_file)) => Some(texture_packer::build_workspace(
+            &options.path(workspace_file),
+            target,
+            &features,
+            cancel,
+        )?),
+        (None, None) => None,
+    };
+    if let Some(atlas_warnings) = atlas_warnings {
+        for (atlas, warning) in atlas_warnings.iter() {
+            println!("WARNING: Atlas '{atlas}': {warning}");
+        }
+        return Ok(());
+    }
+
+    let Some(build) = texture_packer::build(options, cancel)? else {
+        return Ok(());
+    };
+    tracing_guard.finish();
+    print_warnings(&build);
+
+    if options.watch {
+        texture_packer::watch(options, build, cancel, print_warnings)?;
+    }
 
-    let atlas_meta = AtlasMeta::from_texture_atlas(
-        "font-atlas".to_string(),
-        "atlas.png".to_string(),
-        &atlas
-    ).context("Failed to generate AtlasMeta from texture atlas")?;
+    Ok(())
+}
 
-    let atlas_meta_json = serde_json::to_string_pretty(&atlas_meta)
-        .context("Failed to JSON serialize atlas meta")?;
+fn print_warnings(build: &Build) {
+    for warning in build.warnings.iter() {
+        println!("WARNING: {warning}");
+    }
+}
 
-    std::fs::write("font.json", atlas_meta_json)
-        .context("Failed to write JSON file")?;
+impl ReportFormat {
+    /// Prints `report`, or its JSON with `--json`.
+    fn print(
+        &self,
+        report: &impl std::fmt::Display,
+        to_json: impl FnOnce() -> anyhow::Result<String>,
+    ) -> anyhow::Result<()> {
+        match self.json {
+            true => println!("{}", to_json()?),
+            false => println!("{report}"),
+        }
+
+        Ok(())
+    }
+}
 
-    let atlas_meta_rmp = rmp_serde::to_vec(&atlas_meta).unwrap();
+/// Shows the fonts, labels and sprites of an exported atlas as a tree next
+/// to the page they're packed on, with the occupancy of every page and asset.
+#[cfg(feature = "tui")]
+fn browse_layout(meta_file: &Path) -> anyhow::Result<()> {
+    let meta = AtlasMeta::load(meta_file)?;
+    tui::run(outputs::layout_browser::LayoutBrowser::new(&meta))
+}
 
-    std::fs::write("atlas.rmp", atlas_meta_rmp)
-        .context("Failed to write RMP file")?;
+#[cfg(not(feature = "tui"))]
+fn browse_layout(_meta_file: &Path) -> anyhow::Result<()> {
+    error::Ewwow
+        .raise()
+        .context("tui requires the `tui` feature")?;
 
     Ok(())
 }
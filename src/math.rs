@@ -1,6 +1,10 @@
 use glam::IVec2;
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+use crate::error::Error;
+
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
 pub struct ISize {
     pub width: i32,
     pub height: i32,
@@ -18,20 +22,159 @@ impl ISize {
         self.width * self.height
     }
 
+    /// The longer side divided by the shorter one, so never less than 1.
+    pub fn aspect_ratio(self) -> f32 {
+        self.width.max(self.height) as f32 / self.width.min(self.height).max(1) as f32
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
     pub fn grow(self, padding: IMargins) -> Self {
         Self::new(
             self.width + padding.left + padding.right,
             self.height + padding.top + padding.bottom,
         )
     }
+
+    /// The size at `scale`, e.g. for an @0.5x variant, see [`RoundingPolicy`].
+    pub fn scaled(self, scale: f32, rounding: RoundingPolicy) -> Self {
+        Self::new(
+            rounding.scale_len(self.width, scale),
+            rounding.scale_len(self.height, scale),
+        )
+    }
+
+    /// The size as `(width, height)` if neither of them is negative.
+    pub fn checked_u32(self) -> Option<(u32, u32)> {
+        Some((
+            u32::try_from(self.width).ok()?,
+            u32::try_from(self.height).ok()?,
+        ))
+    }
+}
+
+impl From<IVec2> for ISize {
+    fn from(value: IVec2) -> Self {
+        Self::new(value.x, value.y)
+    }
+}
+
+impl From<ISize> for IVec2 {
+    fn from(value: ISize) -> Self {
+        IVec2::new(value.width, value.height)
+    }
+}
+
+impl std::str::FromStr for ISize {
+    type Err = anyhow::Error;
+
+    /// Parses `<width>x<height>`, e.g. `512x256`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+
+        let Some((width, height)) = parsed.filter(|&(w, h)| w > 0 && h > 0) else {
+            Error::InvalidOption(format!("Invalid size '{s}', expected <width>x<height>"))
+                .raise()?;
+
+            unreachable!()
+        };
+
+        Ok(ISize::new(width, height))
+    }
 }
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+/// Serialized as `{ x, y, w, h }`, or as `{ min, max }` inside of
+/// [`with_rect_layout`]. Both layouts are accepted when deserializing.
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(from = "RectRepr", into = "RectRepr")]
 pub struct IRect {
     pub min: IVec2,
     pub max: IVec2,
 }
 
+/// How [`IRect`]s are serialized, see [`with_rect_layout`].
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RectLayout {
+    /// `{ x, y, w, h }`
+    #[default]
+    Sized,
+    /// `{ min, max }`, like older versions wrote them.
+    MinMax,
+}
+
+thread_local! {
+    static RECT_LAYOUT: std::cell::Cell<RectLayout> = const { std::cell::Cell::new(RectLayout::Sized) };
+}
+
+/// Runs `f`, serializing all [`IRect`]s on this thread with `layout`. Serde
+/// can't pass options down to nested values, so e.g. an exported meta picks
+/// the layout of all its rects this way.
+pub fn with_rect_layout<R>(layout: RectLayout, f: impl FnOnce() -> R) -> R {
+    let previous = RECT_LAYOUT.replace(layout);
+    let result = f();
+    RECT_LAYOUT.set(previous);
+
+    result
+}
+
+// Separate structs instead of struct variants, untagged struct variants
+// can't be deserialized from the arrays of `rmp_serde`
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum RectRepr {
+    Sized(SizedRect),
+    MinMax(MinMaxRect),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SizedRect {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MinMaxRect {
+    min: IVec2,
+    max: IVec2,
+}
+
+impl From<IRect> for RectRepr {
+    fn from(rect: IRect) -> Self {
+        match RECT_LAYOUT.get() {
+            RectLayout::Sized => RectRepr::Sized(SizedRect {
+                x: rect.min.x,
+                y: rect.min.y,
+                w: rect.width(),
+                h: rect.height(),
+            }),
+            RectLayout::MinMax => RectRepr::MinMax(MinMaxRect {
+                min: rect.min,
+                max: rect.max,
+            }),
+        }
+    }
+}
+
+impl From<RectRepr> for IRect {
+    fn from(repr: RectRepr) -> Self {
+        match repr {
+            RectRepr::Sized(SizedRect { x, y, w, h }) => IRect::new(x, y, w, h),
+            RectRepr::MinMax(MinMaxRect { min, max }) => IRect { min, max },
+        }
+    }
+}
+
 impl IRect {
     pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
         IRect {
@@ -40,10 +183,73 @@ impl IRect {
         }
     }
 
+    pub fn from_min_size(min: IVec2, size: ISize) -> Self {
+        Self {
+            min,
+            max: min + IVec2::from(size),
+        }
+    }
+
     pub fn shrink(self, padding: IMargins) -> Self {
         Self {
             min: IVec2::new(self.min.x + padding.left, self.min.y + padding.top),
-            max: IVec2::new(self.max.x - padding.right, self.max.y - padding.right),
+            max: IVec2::new(self.max.x - padding.right, self.max.y - padding.bottom),
+        }
+    }
+
+    pub fn grow(self, padding: IMargins) -> Self {
+        Self {
+            min: IVec2::new(self.min.x - padding.left, self.min.y - padding.top),
+            max: IVec2::new(self.max.x + padding.right, self.max.y + padding.bottom),
+        }
+    }
+
+    pub fn size(self) -> ISize {
+        ISize::new(self.width(), self.height())
+    }
+
+    pub fn translate(self, offset: IVec2) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.size().is_empty()
+    }
+
+    /// Whether the two rects share at least one pixel. Empty rects never
+    /// intersect anything.
+    pub fn intersects(self, other: IRect) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.min.x < other.max.x
+            && other.min.x < self.max.x
+            && self.min.y < other.max.y
+            && other.min.y < self.max.y
+    }
+
+    /// Whether `other` lies completely inside of this rect.
+    pub fn contains(self, other: IRect) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && other.max.x <= self.max.x
+            && other.max.y <= self.max.y
+    }
+
+    pub fn contains_point(self, point: IVec2) -> bool {
+        self.min.x <= point.x
+            && point.x < self.max.x
+            && self.min.y <= point.y
+            && point.y < self.max.y
+    }
+
+    /// The smallest rect containing both rects.
+    pub fn union(self, other: IRect) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
         }
     }
 
@@ -62,9 +268,36 @@ impl IRect {
     pub fn uheight(self) -> u32 {
         (self.max.y - self.min.y) as u32
     }
+
+    /// The position of the top left corner as `(x, y)` if it isn't negative.
+    pub fn checked_umin(self) -> Option<(u32, u32)> {
+        Some((
+            u32::try_from(self.min.x).ok()?,
+            u32::try_from(self.min.y).ok()?,
+        ))
+    }
+
+    pub fn checked_usize(self) -> Option<(u32, u32)> {
+        self.size().checked_u32()
+    }
+
+    /// The rect at `scale`. Its size is always `self.size().scaled(..)`, so a
+    /// sprite cut out with the scaled rect is exactly as large as the scaled
+    /// sprite size says.
+    pub fn scaled(self, scale: f32, rounding: RoundingPolicy) -> Self {
+        let min = IVec2::new(
+            rounding.scale_coord(self.min.x, scale),
+            rounding.scale_coord(self.min.y, scale),
+        );
+
+        Self::from_min_size(min, self.size().scaled(scale, rounding))
+    }
 }
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(default)]
 pub struct IMargins {
     pub top: i32,
     pub bottom: i32,
@@ -93,4 +326,306 @@ impl IMargins {
     pub fn hori(self) -> i32 {
         self.left + self.right
     }
+
+    /// The margins at `scale`. Margins are lengths, so they are rounded like
+    /// sprite sizes.
+    pub fn scaled(self, scale: f32, rounding: RoundingPolicy) -> Self {
+        Self::new(
+            rounding.scale_len(self.top, scale),
+            rounding.scale_len(self.bottom, scale),
+            rounding.scale_len(self.left, scale),
+            rounding.scale_len(self.right, scale),
+        )
+    }
+}
+
+impl std::str::FromStr for IMargins {
+    type Err = anyhow::Error;
+
+    /// Parses `<px>` for the same margin on every side, or
+    /// `<top>,<bottom>,<left>,<right>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed: Option<Vec<i32>> = s.split(',').map(|px| px.trim().parse().ok()).collect();
+
+        match parsed.as_deref() {
+            Some(&[m]) if m >= 0 => Ok(IMargins::uniform(m)),
+            Some(&[t, b, l, r]) if t >= 0 && b >= 0 && l >= 0 && r >= 0 => {
+                Ok(IMargins::new(t, b, l, r))
+            }
+            _ => {
+                Error::InvalidOption(format!(
+                    "Invalid margins '{s}', expected <px> or <top>,<bottom>,<left>,<right>"
+                ))
+                .raise()?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// How fractional pixels are rounded when sprites, rects or margins are
+/// scaled, see [`RoundingPolicy`].
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum RoundingMode {
+    Floor,
+    Ceil,
+    /// Halves are rounded up, also for negative values, so that the rounding
+    /// doesn't depend on which side of the origin something is on.
+    #[default]
+    Round,
+}
+
+/// The one place that decides how scaled pixel values are rounded, so every
+/// size, rect and margin of a scaled atlas agrees with the others.
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct RoundingPolicy {
+    pub mode: RoundingMode,
+    /// Rounds odd lengths up to the next even one, so the result can be
+    /// halved again without any rounding, e.g. for @0.5x from @1x from @2x.
+    pub keep_even: bool,
+}
+
+impl RoundingPolicy {
+    pub fn new(mode: RoundingMode) -> Self {
+        Self {
+            mode,
+            keep_even: false,
+        }
+    }
+
+    pub fn with_keep_even(mut self, keep_even: bool) -> Self {
+        self.keep_even = keep_even;
+        self
+    }
+
+    pub fn round(self, value: f32) -> i32 {
+        match self.mode {
+            RoundingMode::Floor => value.floor() as i32,
+            RoundingMode::Ceil => value.ceil() as i32,
+            RoundingMode::Round => (value + 0.5).floor() as i32,
+        }
+    }
+
+    /// Scales a position. Positions are never made even.
+    pub fn scale_coord(self, coord: i32, scale: f32) -> i32 {
+        self.round(coord as f32 * scale)
+    }
+
+    /// Scales a length, e.g. a width or a margin. Lengths that weren't zero
+    /// stay at least one pixel long, so no sprite disappears.
+    pub fn scale_len(self, len: i32, scale: f32) -> i32 {
+        if len <= 0 {
+            return 0;
+        }
+
+        let mut scaled = self.round(len as f32 * scale).max(1);
+
+        if self.keep_even && scaled % 2 != 0 {
+            scaled += 1;
+        }
+
+        scaled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::IVec2;
+
+    use super::{
+        with_rect_layout, IMargins, IRect, ISize, RectLayout, RoundingMode, RoundingPolicy,
+    };
+    use crate::error::Error;
+
+    #[test]
+    fn test_rect_serde() {
+        let rect = IRect::new(1, 2, 3, 4);
+
+        assert_eq!(
+            serde_json::to_string(&rect).unwrap(),
+            r#"{"x":1,"y":2,"w":3,"h":4}"#
+        );
+        assert_eq!(
+            with_rect_layout(RectLayout::MinMax, || serde_json::to_string(&rect)).unwrap(),
+            r#"{"min":[1,2],"max":[4,6]}"#
+        );
+        // The layout only applies inside of `with_rect_layout`
+        assert!(serde_json::to_string(&rect).unwrap().contains(r#""w":3"#));
+
+        for json in [
+            r#"{"x":1,"y":2,"w":3,"h":4}"#,
+            r#"{"min":[1,2],"max":[4,6]}"#,
+        ] {
+            assert_eq!(serde_json::from_str::<IRect>(json).unwrap(), rect);
+        }
+
+        let rmp = rmp_serde::to_vec(&rect).unwrap();
+        assert_eq!(rmp_serde::from_slice::<IRect>(&rmp).unwrap(), rect);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!("512x256".parse::<ISize>().unwrap(), ISize::new(512, 256));
+        assert!("512".parse::<ISize>().is_err());
+        assert!("0x256".parse::<ISize>().is_err());
+
+        assert_eq!("2".parse::<IMargins>().unwrap(), IMargins::uniform(2));
+        assert_eq!(
+            "1, 2,3,4".parse::<IMargins>().unwrap(),
+            IMargins::new(1, 2, 3, 4)
+        );
+        assert!("1,2".parse::<IMargins>().is_err());
+        assert!("-1".parse::<IMargins>().is_err());
+
+        let err = "0x256".parse::<ISize>().unwrap_err();
+        assert!(matches!(Error::find(&err), Some(Error::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_aspect_ratio() {
+        assert_eq!(ISize::new(64, 64).aspect_ratio(), 1.0);
+        assert_eq!(ISize::new(128, 64).aspect_ratio(), 2.0);
+        assert_eq!(ISize::new(128, 512).aspect_ratio(), 4.0);
+        assert_eq!(ISize::new(7, 0).aspect_ratio(), 7.0);
+    }
+
+    #[test]
+    fn test_rounding_policy() {
+        let floor = RoundingPolicy::new(RoundingMode::Floor);
+        let ceil = RoundingPolicy::new(RoundingMode::Ceil);
+        let round = RoundingPolicy::new(RoundingMode::Round);
+
+        // 7 px at @0.5x
+        assert_eq!(floor.scale_len(7, 0.5), 3);
+        assert_eq!(ceil.scale_len(7, 0.5), 4);
+        assert_eq!(round.scale_len(7, 0.5), 4);
+        assert_eq!(floor.with_keep_even(true).scale_len(7, 0.5), 4);
+        assert_eq!(round.with_keep_even(true).scale_len(10, 0.5), 6);
+
+        // Nothing disappears, empty stays empty
+        assert_eq!(floor.scale_len(1, 0.25), 1);
+        assert_eq!(ceil.scale_len(0, 0.5), 0);
+        assert_eq!(round.scale_len(-3, 0.5), 0);
+
+        // Halves round the same way on both sides of the origin
+        assert_eq!(round.scale_coord(-3, 0.5), -1);
+        assert_eq!(round.scale_coord(3, 0.5), 2);
+        assert_eq!(floor.scale_coord(-3, 0.5), -2);
+    }
+
+    #[test]
+    fn test_scaled() {
+        for mode in [RoundingMode::Floor, RoundingMode::Ceil, RoundingMode::Round] {
+            for keep_even in [false, true] {
+                let rounding = RoundingPolicy::new(mode).with_keep_even(keep_even);
+
+                for rect in [
+                    IRect::new(0, 0, 7, 5),
+                    IRect::new(3, 9, 1, 1),
+                    IRect::new(-5, 2, 13, 0),
+                ] {
+                    for scale in [0.5, 0.25, 1.5, 2.0] {
+                        let scaled = rect.scaled(scale, rounding);
+                        assert_eq!(scaled.size(), rect.size().scaled(scale, rounding));
+
+                        if keep_even && !rect.is_empty() {
+                            assert_eq!(scaled.width() % 2, 0);
+                            assert_eq!(scaled.height() % 2, 0);
+                        }
+                    }
+                }
+            }
+        }
+
+        let rounding = RoundingPolicy::default();
+        assert_eq!(
+            IRect::new(4, 6, 8, 2).scaled(0.5, rounding),
+            IRect::new(2, 3, 4, 1)
+        );
+        assert_eq!(
+            IMargins::new(1, 2, 3, 0).scaled(0.5, rounding),
+            IMargins::new(1, 1, 2, 0)
+        );
+        assert_eq!(
+            IRect::new(1, 1, 3, 3).scaled(1.0, rounding),
+            IRect::new(1, 1, 3, 3)
+        );
+    }
+
+    #[test]
+    fn test_shrink_and_grow() {
+        let rect = IRect::new(10, 20, 30, 40);
+        let margins = IMargins::new(1, 2, 3, 4);
+
+        let shrunk = rect.shrink(margins);
+        assert_eq!(shrunk, IRect::new(13, 21, 23, 37));
+        assert_eq!(shrunk.grow(margins), rect);
+        assert_eq!(rect.size().grow(margins), rect.grow(margins).size());
+    }
+
+    #[test]
+    fn test_intersects() {
+        let a = IRect::new(0, 0, 4, 4);
+
+        assert!(a.intersects(a));
+        assert!(a.intersects(IRect::new(3, 3, 4, 4)));
+        assert!(a.intersects(IRect::new(1, 1, 1, 1)));
+
+        // Touching edges and corners don't count
+        assert!(!a.intersects(IRect::new(4, 0, 4, 4)));
+        assert!(!a.intersects(IRect::new(0, 4, 4, 4)));
+        assert!(!a.intersects(IRect::new(4, 4, 1, 1)));
+
+        // Empty rects never intersect
+        assert!(!a.intersects(IRect::new(2, 2, 0, 0)));
+        assert!(!IRect::new(2, 2, 0, 3).intersects(a));
+    }
+
+    #[test]
+    fn test_contains() {
+        let a = IRect::new(0, 0, 4, 4);
+
+        assert!(a.contains(a));
+        assert!(a.contains(IRect::new(1, 1, 2, 2)));
+        assert!(a.contains(IRect::new(4, 4, 0, 0)));
+        assert!(!a.contains(IRect::new(1, 1, 4, 2)));
+        assert!(!a.contains(IRect::new(-1, 0, 2, 2)));
+
+        assert!(a.contains_point(IVec2::new(0, 0)));
+        assert!(a.contains_point(IVec2::new(3, 3)));
+        assert!(!a.contains_point(IVec2::new(4, 3)));
+        assert!(!a.contains_point(IVec2::new(-1, 0)));
+    }
+
+    #[test]
+    fn test_union() {
+        let a = IRect::new(0, 0, 4, 4);
+        let b = IRect::new(-2, 3, 1, 5);
+
+        assert_eq!(a.union(b), IRect::new(-2, 0, 6, 8));
+        assert_eq!(a.union(b), b.union(a));
+        assert_eq!(a.union(a), a);
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(ISize::from(IVec2::new(3, 4)), ISize::new(3, 4));
+        assert_eq!(IVec2::from(ISize::new(3, 4)), IVec2::new(3, 4));
+        assert_eq!(
+            IRect::from_min_size(IVec2::new(1, 2), ISize::new(3, 4)),
+            IRect::new(1, 2, 3, 4)
+        );
+
+        assert_eq!(ISize::new(3, 4).checked_u32(), Some((3, 4)));
+        assert_eq!(ISize::new(-3, 4).checked_u32(), None);
+        assert_eq!(IRect::new(1, 2, 3, 4).checked_umin(), Some((1, 2)));
+        assert_eq!(IRect::new(-1, 2, 3, 4).checked_umin(), None);
+        assert_eq!(IRect::new(-1, 2, 3, 4).checked_usize(), Some((3, 4)));
+        assert_eq!(IRect::new(1, 2, -3, 4).checked_usize(), None);
+    }
 }
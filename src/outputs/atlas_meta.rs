@@ -1,38 +1,227 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
 
 use anyhow::Context;
+use glam::{IVec2, Vec2};
+use image::GenericImageView;
 
 use crate::{
-    font_shared,
-    intermediates::{font::FontIntermediate, texture_atlas::TextureAtlas},
-    math::IRect,
+    error::{Error, Ewwow},
+    font_shared, hashing,
+    intermediates::{
+        atlas_layout::{AtlasLayout, SpritePlacement},
+        effects::{Flip, GlyphEffect, SpriteDerivation},
+        font::{CharacterSprite, DynamicSlots, FontIntermediate, RaggedFrames},
+        label::{BakedLabel, LabelSetIntermediate},
+        sprite::{
+            DerivedFrom, FlippedSprite, SpriteFrame, SpriteIntermediate, SpriteSlicing, SpriteTrim,
+        },
+        texture_atlas::TextureAtlas,
+    },
+    math::{self, IMargins, IRect, ISize, RectLayout},
+    sampling::Filtering,
+    sources::{SourceId, SourceSprite, Sources},
 };
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+use super::{build_info::BuildInfo, pixel_format::PixelFormat};
+
+/// The file formats of an [`AtlasMeta`], picked by the extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaFormat {
+    Json,
+    /// `.rmp`
+    MessagePack,
+}
+
+impl MetaFormat {
+    pub const ALL: [Self; 2] = [Self::Json, Self::MessagePack];
+
+    /// MessagePack for `.rmp` files, JSON for everything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().is_some_and(|ext| ext == "rmp") {
+            true => Self::MessagePack,
+            false => Self::Json,
+        }
+    }
+}
+
+impl std::fmt::Display for MetaFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json => write!(f, "JSON"),
+            Self::MessagePack => write!(f, "MessagePack"),
+        }
+    }
+}
+
+/// What a game needs to draw from a packed atlas: the rect of every sprite,
+/// glyph and label, and how they are named. Saved as JSON or MessagePack.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct AtlasMeta {
     pub atlas_name: String,
     pub texture_file: String,
     pub width: u32,
     pub height: u32,
+    /// Empty pixels along the edges of the texture.
+    pub atlas_margin: u32,
+    /// The format the texture's colors were reduced to, see
+    /// [`super::pixel_format::reduce`].
+    #[serde(default)]
+    pub pixel_format: PixelFormat,
+    /// How the rects in this file are written, see [`Self::to_bytes`].
+    #[serde(default)]
+    pub rect_layout: RectLayout,
 
     // Sprites
     pub sprites: Vec<IRect>,
+    /// The normalized UV rects of `sprites`, in the same order. They are
+    /// shrunk by `uv_inset` texels on every side, the pixel rects are not.
+    pub uvs: Vec<UvRect>,
+    pub uv_inset: f32,
+    /// Where every sprite was in its frame before the transparent borders
+    /// were cut off, in the same order as `sprites`. Empty if no sprite was
+    /// trimmed.
+    #[serde(default)]
+    pub trims: Vec<TrimRect>,
+
+    // Texture arrays and pages
+    /// The number of layers of a texture array or pages of an atlas that
+    /// didn't fit into one texture, 0 for a single texture.
+    #[serde(default)]
+    pub layer_count: u32,
+    /// The layer (or page) of every sprite, in the same order as `sprites`.
+    /// Empty for a single texture.
+    #[serde(default)]
+    pub layers: Vec<u32>,
+    /// One image per layer, if the layers aren't stored in `texture_file`.
+    #[serde(default)]
+    pub layer_files: Vec<String>,
+    /// Sprites that are pixel-identical to an earlier one and share its
+    /// rect, by their index and the index of the earlier one.
+    #[serde(default)]
+    pub duplicate_of: BTreeMap<u32, u32>,
 
     // Fonts
     pub fonts: Vec<FontMeta>,
+
+    // Labels
+    pub labels: Vec<LabelMeta>,
+
+    // Named sprites
+    pub named_sprites: Vec<SpriteMeta>,
+    /// The named sprites of every material, e.g. `additive`, so they can be
+    /// drawn in one batch per material.
+    #[serde(default)]
+    pub materials: BTreeMap<String, Vec<String>>,
+
+    // Build info
+    pub build_info: Option<BuildInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UvRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl UvRect {
+    /// The UV rect of `rect` in a `width` x `height` texture, shrunk by
+    /// `inset` texels on every side. Rects too small for the inset collapse
+    /// to their center.
+    pub fn from_rect(rect: IRect, width: u32, height: u32, inset: f32) -> Self {
+        if rect.is_empty() {
+            return Self::default();
+        }
+
+        let texture_size = Vec2::new(width as f32, height as f32);
+        let min = rect.min.as_vec2();
+        let max = rect.max.as_vec2();
+        let inset = Vec2::splat(inset).min((max - min) / 2.0);
+
+        Self {
+            min: (min + inset) / texture_size,
+            max: (max - inset) / texture_size,
+        }
+    }
+}
+
+/// The offset of a trimmed sprite in its untrimmed frame and the size of
+/// that frame, to draw it where the whole frame would have been.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TrimRect {
+    pub source_x: i32,
+    pub source_y: i32,
+    pub original_w: i32,
+    pub original_h: i32,
+}
+
+impl TrimRect {
+    /// The rect of an untrimmed sprite of `size`.
+    pub fn untrimmed(size: ISize) -> Self {
+        Self {
+            source_x: 0,
+            source_y: 0,
+            original_w: size.width,
+            original_h: size.height,
+        }
+    }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct FontMeta {
     pub name: String,
+    /// The face name from the fnt file, `name` may be a logical name instead.
+    #[serde(default)]
+    pub family: String,
     pub animation: font_shared::TextCharacterAnimation,
     pub num_animation_frames: u32,
     pub line_height: u32,
     pub base_line_y: u32,
     pub chars: Vec<CharMeta>,
+    /// If set, the second half of the frames of every char shows the glyph
+    /// with this effect applied, the first half shows the plain glyph.
+    #[serde(default)]
+    pub effect: Option<GlyphEffect>,
+    /// Empty cells in the texture reserved for glyphs rasterized at runtime.
+    /// They are not part of `sprites`.
+    #[serde(default)]
+    pub dynamic_slots: Vec<IRect>,
+    /// The advance of every char if the font is monospaced.
+    #[serde(default)]
+    pub cell_width: Option<u32>,
+    #[serde(default)]
+    pub metrics: font_shared::FontMetrics,
+    #[serde(default)]
+    pub decorations: font_shared::DecorationMetrics,
+    /// The block of the atlas the glyphs were packed into, if fonts were
+    /// packed into regions of their own.
+    #[serde(default)]
+    pub region: Option<IRect>,
+    /// The glyphs are white with their coverage as alpha, like the rest of
+    /// `region`, so the font can be sampled from the alpha channel alone.
+    #[serde(default)]
+    pub alpha_only: bool,
+    /// How chars with fewer frames than the font were padded, see
+    /// [`CharMeta::num_frames`].
+    #[serde(default)]
+    pub ragged_frames: RaggedFrames,
+    /// Set if the glyphs were packed into shelves of `line_height`: shelf `i`
+    /// starts `i * shelf_stride` pixels below the top of `region`. Padding is
+    /// part of the shelves.
+    #[serde(default)]
+    pub shelf_stride: Option<u32>,
+    /// `linear` for smoothed, antialiased and distance field fonts, so the
+    /// runtime can pick the sampler per font.
+    #[serde(default)]
+    pub filtering_hint: Filtering,
+    /// Set for distance field fonts, which need a shader to be drawn.
+    #[serde(default)]
+    pub distance_field: Option<font_shared::DistanceField>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct CharMeta {
     /// The first sprite index (in the sprites vector of the altas).
     /// The following `num_animation_frames - 1` sprites are the other
@@ -42,6 +231,76 @@ pub struct CharMeta {
     pub x_offset: i32,
     pub y_offset: i32,
     pub x_advance: i32,
+    /// Set if the char has fewer distinct frames than the font. The sprites
+    /// of the remaining frames repeat them as `ragged_frames` of the font
+    /// says.
+    #[serde(default)]
+    pub num_frames: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LabelMeta {
+    /// The localization key of the label, e.g. `label.title`.
+    pub key: String,
+    pub font: String,
+    pub variants: Vec<LabelVariantMeta>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LabelVariantMeta {
+    pub language: String,
+    /// The sprite name of this variant, e.g. `label.title.en`.
+    pub sprite_name: String,
+    pub sprite_index: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SpriteMeta {
+    pub name: String,
+    /// The sprite index of the first frame. The following `num_frames - 1`
+    /// sprites are the other animation frames.
+    pub first_sprite_index: u32,
+    pub num_frames: u32,
+    /// The sprite this one was generated from, e.g. `hero` for `hero#shadow`.
+    #[serde(default)]
+    pub derived_from: Option<String>,
+    /// Where the frames are relative to the same frames of `derived_from`.
+    #[serde(default)]
+    pub offset: IVec2,
+    /// How this sprite was generated from `derived_from`.
+    #[serde(default)]
+    pub derivation: Option<SpriteDerivation>,
+    /// The sprites generated from this one, e.g. `hero#shadow`.
+    #[serde(default)]
+    pub derived: Vec<String>,
+    /// Pixels per logical unit of the packed frames, e.g. 2 for an `@2x`
+    /// sprite that wasn't downscaled to the target scale.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// The size of the first frame in logical units, its pixel size divided
+    /// by `scale`.
+    #[serde(default)]
+    pub logical_size: Vec2,
+    /// Game specific attributes from the user data file, e.g.
+    /// `material = "metal"`.
+    #[serde(default)]
+    pub user_data: BTreeMap<String, String>,
+    /// The pivot and 9-slice borders, e.g. imported from a Unity `.meta`
+    /// file.
+    #[serde(default)]
+    pub slicing: Option<SpriteSlicing>,
+    /// Set for variants like `hero#flip-x` that share the frames of
+    /// `derived_from` and have to be drawn mirrored along these axes.
+    #[serde(default)]
+    pub flip: Flip,
+    /// Set if the transparent borders of the frames were cut off before
+    /// packing.
+    #[serde(default)]
+    pub trim: Option<SpriteTrim>,
+}
+
+fn default_scale() -> f32 {
+    1.0
 }
 
 impl AtlasMeta {
@@ -49,47 +308,666 @@ impl AtlasMeta {
         name: String,
         texture_file: String,
         atlas: &TextureAtlas,
+        layout: &AtlasLayout,
     ) -> anyhow::Result<Self> {
         let mut builder = Self {
             atlas_name: name,
             texture_file,
-            width: atlas.final_image_bounds.width as u32,
-            height: atlas.final_image_bounds.height as u32,
+            width: layout.size.width as u32,
+            height: layout.size.height as u32,
+            atlas_margin: layout.margin as u32,
+            pixel_format: PixelFormat::default(),
+            rect_layout: RectLayout::default(),
             sprites: vec![],
+            uvs: vec![],
+            uv_inset: 0.0,
+            trims: vec![],
+            layer_count: layout.layer_count,
+            layers: vec![],
+            layer_files: vec![],
+            duplicate_of: BTreeMap::new(),
             fonts: vec![],
+            labels: vec![],
+            named_sprites: vec![],
+            materials: atlas.materials.clone(),
+            build_info: None,
         };
 
-        let mut bounds_map: HashMap<(usize, usize), IRect> =
-            HashMap::with_capacity(atlas.sprite_bounds.len());
-
-        for &(asset_id, sprite_id, bounds) in atlas.sprite_bounds.iter() {
-            bounds_map.insert((asset_id, sprite_id), bounds);
-        }
+        let placements = layout.placement_map();
 
         // Insert fonts
         for (index, font) in atlas.fonts.iter().enumerate() {
             let asset_id = atlas.get_font_asset_id(index);
             builder
-                .insert_font(font, asset_id, &bounds_map)
-                .with_context(|| format!("Failed to insert font #{index} '{}'", &font.name))?;
+                .insert_font(font, asset_id, &placements)
+                .with_context(|| format!("Failed to insert font #{index} '{}'", font.name))?;
+
+            if let Some(font_meta) = builder.fonts.last_mut() {
+                font_meta.region = layout.region(asset_id);
+                font_meta.alpha_only = atlas.alpha_glyphs;
+                font_meta.shelf_stride = atlas.shelf_stride(font).map(|stride| stride as u32);
+            }
         }
 
+        // Insert labels
+        for (index, label_set) in atlas.label_sets.iter().enumerate() {
+            let asset_id = atlas.get_label_set_asset_id(index);
+            builder
+                .insert_label_set(label_set, asset_id, &placements)
+                .with_context(|| {
+                    format!(
+                        "Failed to insert label set #{index} of font '{}'",
+                        label_set.font_name
+                    )
+                })?;
+        }
+
+        // Insert sprites
+        for (index, sprite) in atlas.sprites.iter().enumerate() {
+            let asset_id = atlas.get_sprite_asset_id(index);
+            builder
+                .insert_sprite(sprite, asset_id, &placements)
+                .with_context(|| format!("Failed to insert sprite '{}'", sprite.name))?;
+        }
+
+        // Trimmed frames remember where they were cut from
+        let trimmed = builder
+            .named_sprites
+            .iter()
+            .filter_map(|sprite| Some((sprite, sprite.trim.as_ref()?)))
+            .collect::<Vec<_>>();
+
+        if !trimmed.is_empty() {
+            let mut trims: Vec<TrimRect> = builder
+                .sprites
+                .iter()
+                .map(|rect| TrimRect::untrimmed(rect.size()))
+                .collect();
+
+            for (sprite, trim) in trimmed {
+                for frame in 0..sprite.num_frames {
+                    let offset = trim
+                        .frame_offsets
+                        .get(frame as usize)
+                        .copied()
+                        .unwrap_or(trim.offset);
+
+                    trims[(sprite.first_sprite_index + frame) as usize] = TrimRect {
+                        source_x: offset.x,
+                        source_y: offset.y,
+                        original_w: trim.source_size.width,
+                        original_h: trim.source_size.height,
+                    };
+                }
+            }
+
+            builder.trims = trims;
+        }
+
+        // Deduplicated sprites share the rect of the first one. Packed sprites
+        // don't overlap, so every sprite with that rect is one of them.
+        let shared: HashSet<(IRect, u32)> = atlas
+            .duplicate_sprites
+            .iter()
+            .filter_map(|(_, original)| placements.get(original))
+            .map(|placement| (placement.bounds, placement.layer))
+            .collect();
+        let mut first_indices: HashMap<(IRect, u32), u32> = HashMap::new();
+
+        for (index, &rect) in builder.sprites.iter().enumerate() {
+            let layer = builder.layers.get(index).copied().unwrap_or(0);
+            if !shared.contains(&(rect, layer)) {
+                continue;
+            }
+
+            match first_indices.get(&(rect, layer)) {
+                Some(&first) => {
+                    builder.duplicate_of.insert(index as u32, first);
+                }
+                None => {
+                    first_indices.insert((rect, layer), index as u32);
+                }
+            }
+        }
+
+        // Flipped variants share the frames of their sprite
+        for flipped in atlas.flipped_sprites.iter() {
+            let sprite = builder
+                .named_sprites
+                .iter()
+                .find(|sprite| sprite.name == flipped.sprite_name)
+                .ok_or(Ewwow)
+                .with_context(|| {
+                    format!(
+                        "Sprite '{}' of flipped sprite '{}' is missing",
+                        flipped.sprite_name, flipped.name
+                    )
+                })?;
+
+            builder.named_sprites.push(SpriteMeta {
+                name: flipped.name.clone(),
+                derived_from: Some(flipped.sprite_name.clone()),
+                offset: IVec2::ZERO,
+                derivation: Some(SpriteDerivation::Flip(flipped.flip)),
+                derived: vec![],
+                flip: flipped.flip,
+                ..sprite.clone()
+            });
+        }
+
+        // Link the original sprites to the ones derived from them
+        let links: Vec<(String, String)> = builder
+            .named_sprites
+            .iter()
+            .filter_map(|sprite| Some((sprite.derived_from.clone()?, sprite.name.clone())))
+            .collect();
+
+        for (original, derived) in links {
+            if let Some(sprite) = builder
+                .named_sprites
+                .iter_mut()
+                .find(|sprite| sprite.name == original)
+            {
+                sprite.derived.push(derived);
+            }
+        }
+
+        builder.set_uv_inset(0.0)?;
+
         Ok(builder)
     }
 
+    /// Shrinks the exported UV rects by `inset` texels on every side, e.g.
+    /// half a texel for engines that bleed neighbouring sprites otherwise.
+    /// Neither the packing nor the pixel rects change.
+    ///
+    /// Fails for negative insets. Sprites less than twice the inset wide or
+    /// high collapse to their center, returns how many there are.
+    pub fn set_uv_inset(&mut self, inset: f32) -> anyhow::Result<usize> {
+        if !(inset.is_finite() && inset >= 0.0) {
+            Error::InvalidOption(format!("The UV inset needs to be at least 0, not {inset}"))
+                .raise()?;
+        }
+
+        self.uv_inset = inset;
+        self.uvs = self
+            .sprites
+            .iter()
+            .map(|&rect| UvRect::from_rect(rect, self.width, self.height, inset))
+            .collect();
+
+        let num_clamped = self
+            .sprites
+            .iter()
+            .filter(|rect| inset > 0.0 && !rect.is_empty())
+            .filter(|rect| 2.0 * inset >= rect.width().min(rect.height()) as f32)
+            .count();
+
+        Ok(num_clamped)
+    }
+
+    /// Loads metadata written by [`Self::save`], as JSON or as MessagePack if
+    /// the extension is `.rmp`.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read atlas meta '{}'", path.display()))?;
+
+        Self::from_bytes(&bytes, MetaFormat::from_path(path))
+            .with_context(|| format!("Failed to parse atlas meta '{}'", path.display()))
+    }
+
+    pub fn from_bytes(bytes: &[u8], format: MetaFormat) -> anyhow::Result<Self> {
+        match format {
+            MetaFormat::Json => serde_json::from_slice(bytes).map_err(anyhow::Error::from),
+            MetaFormat::MessagePack => rmp_serde::from_slice(bytes).map_err(anyhow::Error::from),
+        }
+    }
+
+    /// Loads an exported atlas meta and its texture, which is expected next to
+    /// it, and rebuilds the intermediates.
+    pub fn load_atlas<P: AsRef<Path>>(
+        path: P,
+        sources: &mut Sources,
+    ) -> anyhow::Result<(Self, TextureAtlas)> {
+        let path = path.as_ref();
+        let meta = Self::load(path)?;
+
+        let texture_path = path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&meta.texture_file);
+        let texture_id = sources
+            .try_load_source(&texture_path)
+            .with_context(|| format!("Failed to load '{}'", texture_path.display()))?;
+
+        let atlas = meta
+            .to_texture_atlas(texture_id, sources)
+            .with_context(|| format!("Failed to load atlas '{}'", path.display()))?;
+
+        Ok((meta, atlas))
+    }
+
+    /// Saves the metadata as JSON, or as MessagePack if the extension is
+    /// `.rmp`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let bytes = self.to_bytes(MetaFormat::from_path(path))?;
+
+        hashing::write_if_changed(path, bytes)
+            .with_context(|| format!("Failed to write atlas meta '{}'", path.display()))
+    }
+
+    /// JSON is pretty-printed. All rects are written in [`Self::rect_layout`].
+    pub fn to_bytes(&self, format: MetaFormat) -> anyhow::Result<Vec<u8>> {
+        math::with_rect_layout(self.rect_layout, || match format {
+            MetaFormat::Json => serde_json::to_vec_pretty(self).map_err(anyhow::Error::from),
+            MetaFormat::MessagePack => rmp_serde::to_vec(self).map_err(anyhow::Error::from),
+        })
+        .with_context(|| format!("Failed to serialize atlas meta as {format}"))
+    }
+
+    /// Checks that every sprite lies inside of the texture, packed sprites
+    /// don't overlap, all sprite indices exist, and the metadata survives a
+    /// round trip through every [`MetaFormat`] unchanged.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let inner = IRect::new(0, 0, self.width as i32, self.height as i32)
+            .shrink(IMargins::uniform(self.atlas_margin as i32));
+
+        for (index, &rect) in self.sprites.iter().enumerate() {
+            if rect.width() < 0 || rect.height() < 0 {
+                Ewwow
+                    .raise()
+                    .with_context(|| format!("Sprite #{index} has a negative size: {rect:?}"))?;
+            }
+
+            if !rect.is_empty() && !inner.contains(rect) {
+                Ewwow.raise().with_context(|| {
+                    format!("Sprite #{index} {rect:?} is outside of the texture or in its margin")
+                })?;
+            }
+        }
+
+        let slots = self.fonts.iter().flat_map(|font| {
+            font.dynamic_slots
+                .iter()
+                .map(move |&rect| (format!("dynamic slot of font '{}'", font.name), rect))
+        });
+
+        // The padding frames of ragged chars repeat other sprites
+        let padding: Vec<usize> = self
+            .fonts
+            .iter()
+            .flat_map(|font| {
+                font.chars.iter().flat_map(|ch| {
+                    let first = ch.first_sprite_index;
+                    let num_frames = ch.num_frames.unwrap_or(font.num_animation_frames);
+                    (first + num_frames..first + font.num_animation_frames).map(|i| i as usize)
+                })
+            })
+            .collect();
+
+        for (&duplicate, &original) in self.duplicate_of.iter() {
+            let rect = |index: u32| self.sprites.get(index as usize).copied();
+            let layer = |index: u32| self.layers.get(index as usize).copied();
+
+            if original >= duplicate
+                || rect(duplicate).is_none()
+                || rect(duplicate) != rect(original)
+                || layer(duplicate) != layer(original)
+            {
+                Ewwow.raise().with_context(|| {
+                    format!("Sprite #{duplicate} isn't a duplicate of sprite #{original}")
+                })?;
+            }
+        }
+
+        let mut packed: Vec<(String, u32, IRect)> = self
+            .sprites
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !padding.contains(index))
+            .filter(|(index, _)| !self.duplicate_of.contains_key(&(*index as u32)))
+            .map(|(index, &rect)| {
+                let layer = self.layers.get(index).copied().unwrap_or(0);
+                (format!("sprite #{index}"), layer, rect)
+            })
+            .chain(slots.map(|(name, rect)| (name, 0, rect)))
+            .filter(|(_, _, rect)| !rect.is_empty())
+            .collect();
+        packed.sort_by_key(|(_, layer, rect)| (*layer, rect.min.x, rect.min.y));
+
+        // Sorted by layer and x, so only rects of the same layer starting
+        // before the end of a rect can overlap it
+        for (i, (a_name, a_layer, a)) in packed.iter().enumerate() {
+            for (b_name, b_layer, b) in packed[i + 1..].iter() {
+                if b_layer != a_layer || b.min.x >= a.max.x {
+                    break;
+                }
+
+                if a.intersects(*b) {
+                    Ewwow.raise().with_context(|| {
+                        format!("The {a_name} {a:?} and the {b_name} {b:?} overlap")
+                    })?;
+                }
+            }
+        }
+
+        if self.layer_count > 0 && self.layers.len() != self.sprites.len() {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "There are {} layer indices for {} sprites",
+                    self.layers.len(),
+                    self.sprites.len()
+                )
+            })?;
+        }
+
+        if let Some(layer) = self.layers.iter().find(|&&layer| layer >= self.layer_count) {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Layer #{layer} is out of range for {} layers",
+                    self.layer_count
+                )
+            })?;
+        }
+
+        if !self.trims.is_empty() && self.trims.len() != self.sprites.len() {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "There are {} trim rects for {} sprites",
+                    self.trims.len(),
+                    self.sprites.len()
+                )
+            })?;
+        }
+
+        for (index, (rect, trim)) in self.sprites.iter().zip(self.trims.iter()).enumerate() {
+            let fits = trim.source_x >= 0
+                && trim.source_y >= 0
+                && trim.source_x + rect.width() <= trim.original_w
+                && trim.source_y + rect.height() <= trim.original_h;
+
+            if !rect.is_empty() && !fits {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "Sprite #{index} {rect:?} doesn't fit into its untrimmed frame {trim:?}"
+                    )
+                })?;
+            }
+        }
+
+        if !self.uvs.is_empty() && self.uvs.len() != self.sprites.len() {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "There are {} UV rects for {} sprites",
+                    self.uvs.len(),
+                    self.sprites.len()
+                )
+            })?;
+        }
+
+        let num_sprites = self.sprites.len() as u64;
+        let check_range = |what: String, first: u32, count: u32| {
+            if first as u64 + count as u64 > num_sprites {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "The sprites #{first}..#{} of {what} don't exist",
+                        first + count
+                    )
+                })?;
+            }
+            anyhow::Ok(())
+        };
+
+        for font in self.fonts.iter() {
+            for ch in font.chars.iter() {
+                check_range(
+                    format!("char #{} of font '{}'", ch.char_code, font.name),
+                    ch.first_sprite_index,
+                    font.num_animation_frames,
+                )?;
+            }
+        }
+
+        for label in self.labels.iter() {
+            for variant in label.variants.iter() {
+                check_range(
+                    format!("label '{}'", variant.sprite_name),
+                    variant.sprite_index,
+                    1,
+                )?;
+            }
+        }
+
+        for sprite in self.named_sprites.iter() {
+            check_range(
+                format!("sprite '{}'", sprite.name),
+                sprite.first_sprite_index,
+                sprite.num_frames,
+            )?;
+        }
+
+        self.verify_round_trip()
+    }
+
+    /// Checks that serializing and parsing the metadata again gives the same
+    /// metadata in every [`MetaFormat`], so all formats describe the same
+    /// atlas.
+    pub fn verify_round_trip(&self) -> anyhow::Result<()> {
+        for format in MetaFormat::ALL {
+            let bytes = self.to_bytes(format)?;
+            let parsed = Self::from_bytes(&bytes, format)
+                .with_context(|| format!("Failed to parse own {format}"))?;
+
+            if &parsed != self {
+                Ewwow.raise().with_context(|| {
+                    format!("The atlas meta changes when serialized as {format} and parsed again")
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the intermediates of the atlas, with all sprites referencing
+    /// the atlas texture `texture_src_id`, e.g. to add more sprites to an
+    /// existing atlas. Label texts aren't stored, so they are empty.
+    pub fn to_texture_atlas(
+        &self,
+        texture_src_id: SourceId,
+        srcs: &Sources,
+    ) -> anyhow::Result<TextureAtlas> {
+        self.verify().context("Invalid atlas meta")?;
+
+        if self.layer_count > 0 {
+            Ewwow
+                .raise()
+                .context("Texture arrays can't be loaded back into an atlas")?;
+        }
+
+        let texture = srcs
+            .get_image(texture_src_id)
+            .context("Failed to get the atlas texture")?;
+
+        if texture.dimensions() != (self.width, self.height) {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "The atlas texture is {}x{} but the atlas meta expects {}x{}",
+                    texture.width(),
+                    texture.height(),
+                    self.width,
+                    self.height
+                )
+            })?;
+        }
+
+        let source_sprite = |rect: IRect| SourceSprite {
+            image_source_id: texture_src_id,
+            x: rect.min.x,
+            y: rect.min.y,
+            width: rect.width(),
+            height: rect.height(),
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.atlas_margin = self.atlas_margin as i32;
+        atlas.font_regions = self.fonts.iter().any(|font| font.region.is_some());
+        atlas.alpha_glyphs = self.fonts.iter().any(|font| font.alpha_only);
+        atlas.materials = self.materials.clone();
+
+        for font_meta in self.fonts.iter() {
+            let mut chars = vec![];
+
+            for frame in 0..font_meta.num_animation_frames {
+                // Padding frames of ragged chars aren't loaded back
+                let chars_with_frame = font_meta
+                    .chars
+                    .iter()
+                    .filter(|ch| ch.num_frames.is_none_or(|num_frames| frame < num_frames));
+
+                for ch in chars_with_frame {
+                    let rect = self.sprites[(ch.first_sprite_index + frame) as usize];
+
+                    chars.push(CharacterSprite {
+                        char_code: ch.char_code,
+                        sprite: source_sprite(rect),
+                        frame,
+                        x_offset: ch.x_offset,
+                        y_offset: ch.y_offset,
+                        x_advance: ch.x_advance,
+                        baked: None,
+                    });
+                }
+            }
+
+            atlas.with_font(FontIntermediate {
+                name: font_meta.name.clone(),
+                family: font_meta.family.clone(),
+                animation: font_meta.animation,
+                num_frames: font_meta.num_animation_frames,
+                line_height: font_meta.line_height as i32,
+                base: font_meta.base_line_y as i32,
+                chars,
+                dynamic_slots: font_meta.dynamic_slots.first().map(|slot| DynamicSlots {
+                    count: font_meta.dynamic_slots.len() as u32,
+                    cell_size: slot.size(),
+                }),
+                effect: font_meta.effect,
+                cell_width: font_meta.cell_width.map(|width| width as i32),
+                metrics: font_meta.metrics,
+                decorations: font_meta.decorations,
+                ragged_frames: font_meta.ragged_frames,
+                line_shelves: font_meta.shelf_stride.is_some(),
+                filtering_hint: font_meta.filtering_hint,
+                distance_field: font_meta.distance_field,
+            });
+        }
+
+        for label in self.labels.iter() {
+            let index = match atlas
+                .label_sets
+                .iter()
+                .position(|label_set| label_set.font_name == label.font)
+            {
+                Some(index) => index,
+                None => {
+                    atlas.with_label_set(LabelSetIntermediate {
+                        font_name: label.font.clone(),
+                        labels: vec![],
+                    });
+                    atlas.label_sets.len() - 1
+                }
+            };
+
+            for variant in label.variants.iter() {
+                let rect = self.sprites[variant.sprite_index as usize];
+                let (x, y) = rect.checked_umin().unwrap_or_default();
+
+                atlas.label_sets[index].labels.push(BakedLabel {
+                    key: label.key.clone(),
+                    language: variant.language.clone(),
+                    text: String::new(),
+                    image: texture.view(x, y, rect.uwidth(), rect.uheight()).to_image(),
+                });
+            }
+        }
+
+        for sprite_meta in self.named_sprites.iter() {
+            if !sprite_meta.flip.is_none() {
+                atlas.flipped_sprites.push(FlippedSprite {
+                    name: sprite_meta.name.clone(),
+                    sprite_name: sprite_meta.derived_from.clone().unwrap_or_default(),
+                    flip: sprite_meta.flip,
+                });
+                continue;
+            }
+
+            let first = sprite_meta.first_sprite_index as usize;
+            let frames = self.sprites[first..first + sprite_meta.num_frames as usize]
+                .iter()
+                .map(|&rect| SpriteFrame::Source(source_sprite(rect)))
+                .collect();
+
+            let derived_from = sprite_meta
+                .derived_from
+                .clone()
+                .zip(sprite_meta.derivation)
+                .map(|(sprite_name, derivation)| DerivedFrom {
+                    sprite_name,
+                    derivation,
+                    offset: sprite_meta.offset,
+                });
+
+            atlas.with_sprite(SpriteIntermediate {
+                name: sprite_meta.name.clone(),
+                frames,
+                derived_from,
+                scale: sprite_meta.scale,
+                user_data: sprite_meta.user_data.clone(),
+                slicing: sprite_meta.slicing,
+                trim: sprite_meta.trim.clone(),
+            });
+        }
+
+        Ok(atlas)
+    }
+
+    fn push_sprite(&mut self, bounds: IRect, layer: u32) {
+        self.sprites.push(bounds);
+
+        if self.layer_count > 0 {
+            self.layers.push(layer);
+        }
+    }
+
     fn insert_font(
         &mut self,
         font: &FontIntermediate,
         asset_id: usize,
-        bounds_map: &HashMap<(usize, usize), IRect>,
+        placements: &HashMap<(usize, usize), SpritePlacement>,
     ) -> anyhow::Result<()> {
         let mut font_meta = FontMeta {
             name: font.name.clone(),
+            family: font.family.clone(),
             animation: font.animation,
             num_animation_frames: font.num_frames,
             line_height: font.line_height as u32,
             base_line_y: font.base as u32,
             chars: vec![],
+            effect: font.effect,
+            dynamic_slots: vec![],
+            cell_width: font.cell_width.map(|width| width as u32),
+            metrics: font.metrics,
+            decorations: font.decorations,
+            region: None,
+            ragged_frames: font.ragged_frames,
+            shelf_stride: None,
+            alpha_only: false,
+            filtering_hint: font.filtering_hint,
+            distance_field: font.distance_field,
         };
 
         #[derive(Debug, Clone)]
@@ -100,14 +978,33 @@ impl AtlasMeta {
             frame_indices: Vec<(usize, u32)>,
         }
 
-        // We need to combine the info from char sprites of the same character
-        let mut char_builders: HashMap<u32, CharMetaBuilder> = HashMap::new();
+        // We need to combine the info from char sprites of the same character,
+        // ordered by char code no matter the order of the fnt file
+        let mut char_builders: BTreeMap<u32, CharMetaBuilder> = BTreeMap::new();
 
         for (index, char_sprite) in font.chars.iter().enumerate() {
             if let Some(builder) = char_builders.get_mut(&char_sprite.char_code) {
-                assert_eq!(builder.x_offset, char_sprite.x_offset);
-                assert_eq!(builder.y_offset, char_sprite.y_offset);
-                assert_eq!(builder.x_advance, char_sprite.x_advance);
+                let expected = (builder.x_offset, builder.y_offset, builder.x_advance);
+                let actual = (
+                    char_sprite.x_offset,
+                    char_sprite.y_offset,
+                    char_sprite.x_advance,
+                );
+
+                if actual != expected {
+                    Ewwow.raise().with_context(|| {
+                        format!(
+                            "The frames of character #{} disagree on their metrics: offset ({}, {}) and advance {} vs. offset ({}, {}) and advance {} (see --metric-conflicts)",
+                            char_sprite.char_code,
+                            expected.0,
+                            expected.1,
+                            expected.2,
+                            actual.0,
+                            actual.1,
+                            actual.2,
+                        )
+                    })?;
+                }
 
                 builder.frame_indices.push((index, char_sprite.frame));
             } else {
@@ -123,19 +1020,22 @@ impl AtlasMeta {
             }
         }
 
-        let mut char_builders: Vec<_> = char_builders
-            .iter()
-            .map(|(code, builder)| (*code, builder.clone()))
-            .collect();
-
-        char_builders.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-
         // Build the CharMetas from the CharMetaBuilders
         for (char_code, builder) in char_builders.iter_mut() {
             // Sort the frame indices
-            builder.frame_indices.sort_by(|a, b| a.1.cmp(&b.1));
+            builder.frame_indices.sort_by_key(|a| a.1);
+
+            let num_frames = builder.frame_indices.len() as u32;
+            let ragged = num_frames < font.num_frames && font.ragged_frames != RaggedFrames::Error;
 
-            assert_eq!(builder.frame_indices.len(), font.num_frames as usize);
+            if num_frames != font.num_frames && !ragged {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "Character #{char_code} has {num_frames} frame(s), but the font has {} (see --ragged-frames)",
+                        font.num_frames
+                    )
+                })?;
+            }
 
             let first_sprite_index = self.sprites.len() as u32;
 
@@ -145,15 +1045,50 @@ impl AtlasMeta {
                 x_offset: builder.x_offset,
                 y_offset: builder.y_offset,
                 x_advance: builder.x_advance,
+                num_frames: ragged.then_some(num_frames),
             });
 
             // Now actually push these sprites
             for (i, &(sprite_index, frame_index)) in builder.frame_indices.iter().enumerate() {
-                assert_eq!(i as u32, frame_index);
+                if i as u32 != frame_index {
+                    Ewwow
+                        .raise()
+                        .with_context(|| format!("Character #{char_code} is missing frame {i}"))?;
+                }
+
+                let size = font.chars[sprite_index].size();
+                let (bounds, layer) = get_sprite_bounds(placements, asset_id, sprite_index, size)
+                    .with_context(|| {
+                    format!("Failed to get bounds of character #{char_code} (frame {frame_index})")
+                })?;
+
+                self.push_sprite(bounds, layer);
+            }
+
+            // Padding frames point at the sprites of the existing ones
+            for frame in num_frames..font.num_frames {
+                let source = (first_sprite_index
+                    + font.ragged_frames.source_frame(frame, num_frames))
+                    as usize;
+                let layer = self.layers.get(source).copied().unwrap_or(0);
+
+                self.push_sprite(self.sprites[source], layer);
+            }
+        }
+
+        if let Some(slots) = font.dynamic_slots {
+            let first_index = font.first_dynamic_slot_index();
 
-                let &bounds = bounds_map.get(&(asset_id, sprite_index)).unwrap();
+            for slot_index in 0..slots.count as usize {
+                let (bounds, _) = get_sprite_bounds(
+                    placements,
+                    asset_id,
+                    first_index + slot_index,
+                    slots.cell_size,
+                )
+                .with_context(|| format!("Failed to get bounds of dynamic slot #{slot_index}"))?;
 
-                self.sprites.push(bounds);
+                font_meta.dynamic_slots.push(bounds);
             }
         }
 
@@ -161,4 +1096,737 @@ impl AtlasMeta {
 
         Ok(())
     }
+
+    fn insert_sprite(
+        &mut self,
+        sprite: &SpriteIntermediate,
+        asset_id: usize,
+        placements: &HashMap<(usize, usize), SpritePlacement>,
+    ) -> anyhow::Result<()> {
+        let first_sprite_index = self.sprites.len() as u32;
+
+        for (frame_index, frame) in sprite.frames.iter().enumerate() {
+            let (bounds, layer) =
+                get_sprite_bounds(placements, asset_id, frame_index, frame.size())
+                    .with_context(|| format!("Failed to get bounds of frame #{frame_index}"))?;
+
+            self.push_sprite(bounds, layer);
+        }
+
+        let derived_from = sprite.derived_from.as_ref();
+
+        self.named_sprites.push(SpriteMeta {
+            name: sprite.name.clone(),
+            first_sprite_index,
+            num_frames: sprite.frames.len() as u32,
+            derived_from: derived_from.map(|derived| derived.sprite_name.clone()),
+            offset: derived_from.map_or(IVec2::ZERO, |derived| derived.offset),
+            derivation: derived_from.map(|derived| derived.derivation),
+            derived: vec![],
+            scale: sprite.scale,
+            logical_size: sprite.frames.first().map_or(Vec2::ZERO, |frame| {
+                let size = frame.size();
+                Vec2::new(size.width as f32, size.height as f32) / sprite.scale
+            }),
+            user_data: sprite.user_data.clone(),
+            slicing: sprite.slicing,
+            trim: sprite.trim.clone(),
+            flip: Flip::default(),
+        });
+
+        Ok(())
+    }
+
+    fn insert_label_set(
+        &mut self,
+        label_set: &LabelSetIntermediate,
+        asset_id: usize,
+        placements: &HashMap<(usize, usize), SpritePlacement>,
+    ) -> anyhow::Result<()> {
+        // Group the variants of all languages by their key
+        let mut groups: BTreeMap<&str, Vec<LabelVariantMeta>> = BTreeMap::new();
+
+        for (sprite_id, label) in label_set.labels.iter().enumerate() {
+            let size = ISize::new(label.image.width() as i32, label.image.height() as i32);
+            let (bounds, layer) = get_sprite_bounds(placements, asset_id, sprite_id, size)
+                .with_context(|| {
+                    format!("Failed to get bounds of label '{}'", label.sprite_name())
+                })?;
+
+            let sprite_index = self.sprites.len() as u32;
+            self.push_sprite(bounds, layer);
+
+            groups
+                .entry(&label.key)
+                .or_default()
+                .push(LabelVariantMeta {
+                    language: label.language.clone(),
+                    sprite_name: label.sprite_name(),
+                    sprite_index,
+                });
+        }
+
+        for (key, variants) in groups {
+            self.labels.push(LabelMeta {
+                key: key.to_string(),
+                font: label_set.font_name.clone(),
+                variants,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Empty sprites aren't packed, so they are exported as metric-only entries
+/// with an empty rect at the origin.
+fn get_sprite_bounds(
+    placements: &HashMap<(usize, usize), SpritePlacement>,
+    asset_id: usize,
+    sprite_id: usize,
+    size: ISize,
+) -> anyhow::Result<(IRect, u32)> {
+    if let Some(placement) = placements.get(&(asset_id, sprite_id)) {
+        return Ok((placement.bounds, placement.layer));
+    }
+
+    if size.is_empty() {
+        return Ok((IRect::default(), 0));
+    }
+
+    Ewwow
+        .raise()
+        .with_context(|| format!("Sprite #{sprite_id} of asset #{asset_id} was not packed"))?;
+
+    unreachable!()
+}
+
+#[cfg(test)]
+impl AtlasMeta {
+    /// A `width` x `height` atlas `test.png` of `sprites` and nothing else.
+    /// Tests set what they need on top of it.
+    pub(crate) fn for_test(width: u32, height: u32, sprites: Vec<IRect>) -> Self {
+        Self {
+            atlas_name: "test".to_string(),
+            texture_file: "test.png".to_string(),
+            width,
+            height,
+            atlas_margin: 0,
+            pixel_format: Default::default(),
+            rect_layout: Default::default(),
+            sprites,
+            uvs: vec![],
+            uv_inset: 0.0,
+            trims: vec![],
+            layer_count: 0,
+            layers: vec![],
+            layer_files: vec![],
+            duplicate_of: Default::default(),
+            fonts: vec![],
+            labels: vec![],
+            named_sprites: vec![],
+            materials: Default::default(),
+            build_info: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl SpriteMeta {
+    /// A sprite of `num_frames` frames, neither derived nor trimmed.
+    pub(crate) fn for_test(name: &str, first_sprite_index: u32, num_frames: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            first_sprite_index,
+            num_frames,
+            derived_from: None,
+            offset: Default::default(),
+            derivation: None,
+            derived: vec![],
+            scale: 1.0,
+            logical_size: Default::default(),
+            user_data: Default::default(),
+            slicing: None,
+            trim: None,
+            flip: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use glam::Vec2;
+    use image::{Rgba, RgbaImage};
+
+    use super::{AtlasMeta, MetaFormat, TrimRect, UvRect};
+    use crate::error::Error;
+    use crate::{
+        cancellation::CancellationToken,
+        font_shared::{DecorationMetrics, DistanceField, DistanceFieldType, FontMetrics},
+        inputs::{flips::FlipsFile, sprite_references::SpriteReferences, user_data::UserDataFile},
+        intermediates::{
+            effects::SpriteDerivation,
+            font::{CharacterSprite, DynamicSlots, FontIntermediate, RaggedFrames},
+            label::{BakedLabel, LabelSetIntermediate},
+            sprite::{SpriteFrame, SpriteIntermediate, TrimMode},
+            texture_atlas::TextureAtlas,
+        },
+        math::{IMargins, IRect, ISize, RectLayout},
+        outputs::build_info::BuildInfo,
+        sampling::Filtering,
+        sources::{SharedSources, SourceId, SourceSprite, Sources},
+    };
+
+    fn noise(width: u32, height: u32, seed: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                (x * 31 + seed) as u8,
+                (y * 17 + seed) as u8,
+                seed as u8,
+                255,
+            ])
+        })
+    }
+
+    fn test_atlas(srcs: &Sources) -> anyhow::Result<TextureAtlas> {
+        let glyph = |char_code: u32, width: u32, height: u32| CharacterSprite {
+            char_code,
+            sprite: SourceSprite {
+                image_source_id: SourceId::Image(0),
+                x: 0,
+                y: 0,
+                width: width as i32,
+                height: height as i32,
+            },
+            frame: 0,
+            x_offset: 1,
+            y_offset: -2,
+            x_advance: width as i32 + 1,
+            baked: Some(noise(width, height, char_code)),
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.atlas_margin = 2;
+
+        atlas.with_font(FontIntermediate {
+            family: "test".to_string(),
+            dynamic_slots: Some(DynamicSlots {
+                count: 2,
+                cell_size: ISize::new(4, 6),
+            }),
+            cell_width: Some(5),
+            metrics: FontMetrics {
+                ascent: 8,
+                descent: 1,
+                cap_height: Some(8),
+                x_height: None,
+            },
+            decorations: DecorationMetrics {
+                underline_position: 1,
+                underline_thickness: 2,
+                strikethrough_position: -3,
+            },
+            filtering_hint: Filtering::Linear,
+            distance_field: Some(DistanceField {
+                field_type: DistanceFieldType::Sdf,
+                distance_range: 2,
+            }),
+            ..FontIntermediate::from_chars(
+                "ui_small".to_string(),
+                8,
+                6,
+                vec![glyph(32, 0, 0), glyph(65, 3, 5), glyph(66, 4, 4)],
+            )
+        });
+
+        atlas.with_label_set(LabelSetIntermediate {
+            font_name: "ui_small".to_string(),
+            labels: ["en", "de"]
+                .into_iter()
+                .enumerate()
+                .map(|(i, language)| BakedLabel {
+                    key: "label.title".to_string(),
+                    language: language.to_string(),
+                    text: String::new(),
+                    image: noise(9 + i as u32, 5, 100 + i as u32),
+                })
+                .collect(),
+        });
+
+        atlas.with_sprite(SpriteIntermediate::from_frames(
+            "hero".to_string(),
+            vec![
+                SpriteFrame::Baked(noise(6, 7, 200)),
+                SpriteFrame::Baked(noise(6, 7, 201)),
+            ],
+        ));
+
+        atlas.derive_sprites(
+            SpriteDerivation::Shadow {
+                blur_radius: 1,
+                color: [0, 0, 0, 128],
+            },
+            &SpriteReferences::parse("hero"),
+            srcs,
+        )?;
+        atlas.apply_user_data(&UserDataFile::try_parse("[hero]\nmaterial = \"metal\"")?);
+        atlas.flip_sprites(&FlipsFile::try_parse("hero x\nhero y baked")?, srcs)?;
+
+        Ok(atlas)
+    }
+
+    fn pack(atlas: &mut TextureAtlas, srcs: &Sources) -> anyhow::Result<(AtlasMeta, RgbaImage)> {
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let image = atlas.build_image(&atlas.layout, srcs, &CancellationToken::new())?;
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "atlas.png".to_string(),
+            atlas,
+            &atlas.layout,
+        )?;
+
+        Ok((meta, image))
+    }
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        let (mut meta, image) = pack(&mut test_atlas(&Sources::new())?, &Sources::new())?;
+        meta.set_uv_inset(0.5)?;
+        meta.verify()?;
+
+        // Only the baked flip has frames of its own
+        let sprite = |name: &str| meta.named_sprites.iter().find(|s| s.name == name).unwrap();
+        assert_eq!(
+            sprite("hero#flip-x").first_sprite_index,
+            sprite("hero").first_sprite_index
+        );
+        assert!(sprite("hero#flip-x").flip.x);
+        assert!(sprite("hero#flip-y").flip.is_none());
+        assert!(sprite("hero").derived.contains(&"hero#flip-x".to_string()));
+        assert_eq!(meta.fonts[0].filtering_hint, Filtering::Linear);
+        assert_eq!(meta.fonts[0].distance_field.unwrap().distance_range, 2);
+
+        // Both file formats
+        let dir = std::env::temp_dir().join(format!("texture-packer-meta-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        for file_name in ["atlas.json", "atlas.rmp"] {
+            meta.save(dir.join(file_name))?;
+            assert_eq!(AtlasMeta::load(dir.join(file_name))?, meta);
+        }
+        std::fs::remove_dir_all(&dir)?;
+
+        // Repacking the reconstructed intermediates gives the same atlas
+        let srcs = SharedSources::default();
+        let texture_id = srcs.try_load_source_bytes(
+            PathBuf::from("atlas.png"),
+            "atlas.png".to_string(),
+            &crate::image_io::encode_png(&image, &Default::default())?,
+        )?;
+        let srcs = srcs.into_inner();
+
+        let mut atlas = meta.to_texture_atlas(texture_id, &srcs)?;
+        atlas.padding = IMargins::uniform(1);
+
+        let (mut repacked_meta, repacked_image) = pack(&mut atlas, &srcs)?;
+        repacked_meta.set_uv_inset(0.5)?;
+        assert_eq!(repacked_meta, meta);
+        assert_eq!(repacked_image, image);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_output() -> anyhow::Result<()> {
+        let srcs = Sources::new();
+        let (meta, image) = pack(&mut test_atlas(&srcs)?, &srcs)?;
+        let json = serde_json::to_vec(&meta)?;
+
+        for _ in 0..4 {
+            let (other_meta, other_image) = pack(&mut test_atlas(&srcs)?, &srcs)?;
+            assert_eq!(serde_json::to_vec(&other_meta)?, json);
+            assert_eq!(other_image, image);
+        }
+
+        // Chars are ordered by code, whatever the order of the fnt file
+        let mut atlas = test_atlas(&srcs)?;
+        atlas.fonts[0].chars.reverse();
+        let (reversed, _) = pack(&mut atlas, &srcs)?;
+        let codes = |meta: &AtlasMeta| -> Vec<u32> {
+            meta.fonts[0].chars.iter().map(|ch| ch.char_code).collect()
+        };
+        assert_eq!(codes(&reversed), [32, 65, 66]);
+        assert_eq!(codes(&reversed), codes(&meta));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ragged_frames() -> anyhow::Result<()> {
+        let glyph = |char_code: u32, frame: u32| CharacterSprite {
+            char_code,
+            sprite: SourceSprite {
+                image_source_id: SourceId::Image(0),
+                x: 0,
+                y: 0,
+                width: 3,
+                height: 4,
+            },
+            frame,
+            x_offset: 0,
+            y_offset: 0,
+            x_advance: 4,
+            baked: Some(noise(3, 4, char_code + frame)),
+        };
+
+        // Only the letter is animated
+        let font = FontIntermediate {
+            num_frames: 3,
+            ..FontIntermediate::from_chars(
+                "animated".to_string(),
+                6,
+                5,
+                vec![glyph(33, 0), glyph(65, 0), glyph(65, 1), glyph(65, 2)],
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_font(font.clone());
+        assert!(pack(&mut atlas, &Sources::new()).is_err());
+
+        for policy in [RaggedFrames::RepeatLast, RaggedFrames::Loop] {
+            let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+            atlas.with_font(font.clone().with_ragged_frames(policy));
+            let (meta, image) = pack(&mut atlas, &Sources::new())?;
+            meta.verify()?;
+
+            let chars = &meta.fonts[0].chars;
+            assert_eq!(chars[0].num_frames, Some(1));
+            assert_eq!(chars[1].num_frames, None);
+            assert_eq!(meta.sprites.len(), 6);
+            assert_eq!(meta.sprites[1], meta.sprites[0]);
+            assert_eq!(meta.sprites[2], meta.sprites[0]);
+
+            // The padding frames aren't loaded back
+            let srcs = SharedSources::default();
+            let texture_id = srcs.try_load_source_bytes(
+                PathBuf::from("atlas.png"),
+                "atlas.png".to_string(),
+                &crate::image_io::encode_png(&image, &Default::default())?,
+            )?;
+            let atlas = meta.to_texture_atlas(texture_id, &srcs.into_inner())?;
+            assert_eq!(atlas.fonts[0].chars.len(), 4);
+            assert_eq!(atlas.fonts[0].ragged_frames, policy);
+        }
+
+        assert_eq!(RaggedFrames::RepeatLast.source_frame(4, 2), 1);
+        assert_eq!(RaggedFrames::Loop.source_frame(4, 3), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> anyhow::Result<()> {
+        let (meta, _) = pack(&mut test_atlas(&Sources::new())?, &Sources::new())?;
+
+        let mut overlapping = meta.clone();
+        overlapping.sprites[2] = overlapping.sprites[1];
+        assert!(overlapping.verify().is_err());
+
+        let mut outside = meta.clone();
+        outside.sprites[1] = IRect::new(0, 0, 2, 2);
+        assert!(outside.verify().is_err());
+
+        let mut missing = meta.clone();
+        missing.named_sprites[0].num_frames = 100;
+        assert!(missing.verify().is_err());
+
+        let mut slot = meta.clone();
+        slot.fonts[0].dynamic_slots[0] = meta.sprites[1];
+        assert!(slot.verify().is_err());
+
+        // Only identical rects can be shared
+        let mut shared = meta.clone();
+        shared.sprites[2] = shared.sprites[1];
+        shared.duplicate_of.insert(2, 1);
+        shared.verify()?;
+        shared.duplicate_of.insert(3, 1);
+        assert!(shared.verify().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim_rects() -> anyhow::Result<()> {
+        let framed = |x: u32, y: u32| {
+            let mut image = RgbaImage::new(8, 8);
+            image.put_pixel(x, y, Rgba([255; 4]));
+            image.put_pixel(x + 1, y + 2, Rgba([255; 4]));
+            SpriteFrame::Baked(image)
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_sprite(SpriteIntermediate::from_frames(
+            "coin".to_string(),
+            vec![framed(1, 3), framed(4, 0)],
+        ));
+        atlas.with_sprite(SpriteIntermediate::from_frames(
+            "gem".to_string(),
+            vec![SpriteFrame::Baked(noise(3, 3, 0))],
+        ));
+
+        let (meta, _) = pack(&mut atlas, &Sources::new())?;
+        assert!(meta.trims.is_empty());
+
+        atlas.trim_sprites(TrimMode::PerFrame, &Sources::new())?;
+        let (meta, _) = pack(&mut atlas, &Sources::new())?;
+        meta.verify_round_trip()?;
+
+        let trim = |x, y, w, h| TrimRect {
+            source_x: x,
+            source_y: y,
+            original_w: w,
+            original_h: h,
+        };
+        assert_eq!(
+            meta.trims,
+            [trim(1, 3, 8, 8), trim(4, 0, 8, 8), trim(0, 0, 3, 3)]
+        );
+        assert_eq!(meta.sprites[0].size(), ISize::new(2, 3));
+
+        let mut outside = meta.clone();
+        outside.trims[0].source_x = 7;
+        assert!(outside.verify().is_err());
+
+        let mut missing = meta.clone();
+        missing.trims.pop();
+        assert!(missing.verify().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_formats() -> anyhow::Result<()> {
+        let mut atlas = test_atlas(&Sources::new())?;
+        let (meta, _) = pack(&mut atlas, &Sources::new())?;
+
+        let mut inset = meta.clone();
+        inset.set_uv_inset(0.5)?;
+        inset.build_info = Some(BuildInfo::new(&atlas, &Sources::new()));
+
+        let mut layered = meta.clone();
+        layered.layer_count = 2;
+        layered.layers = (0..meta.sprites.len() as u32).map(|i| i % 2).collect();
+        layered.layer_files = vec!["atlas-0.png".to_string(), "atlas-1.png".to_string()];
+
+        let mut legacy = meta.clone();
+        legacy.rect_layout = RectLayout::MinMax;
+        let json: serde_json::Value = serde_json::from_slice(&legacy.to_bytes(MetaFormat::Json)?)?;
+        assert!(json["sprites"][0].get("min").is_some());
+        assert!(serde_json::to_value(&legacy)?["sprites"][0]
+            .get("w")
+            .is_some());
+
+        let dir = std::env::temp_dir().join(format!(
+            "texture-packer-meta-formats-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir)?;
+
+        for meta in [meta, inset, layered, legacy] {
+            meta.verify_round_trip()?;
+
+            let parsed: Vec<AtlasMeta> = MetaFormat::ALL
+                .into_iter()
+                .map(|format| AtlasMeta::from_bytes(&meta.to_bytes(format)?, format))
+                .collect::<anyhow::Result<_>>()?;
+            assert!(parsed.iter().all(|parsed| *parsed == meta));
+
+            for file_name in ["atlas.json", "atlas.rmp", "atlas.meta"] {
+                meta.save(dir.join(file_name))?;
+                assert_eq!(AtlasMeta::load(dir.join(file_name))?, meta);
+            }
+        }
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(
+            MetaFormat::from_path(Path::new("atlas.rmp")),
+            MetaFormat::MessagePack
+        );
+        assert_eq!(MetaFormat::from_path(Path::new("atlas")), MetaFormat::Json);
+
+        // NaN never equals itself, so it can't survive any round trip
+        let (mut nan, _) = pack(&mut test_atlas(&Sources::new())?, &Sources::new())?;
+        nan.uv_inset = f32::NAN;
+        assert!(nan.verify_round_trip().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dynamic_slots() -> anyhow::Result<()> {
+        let font = FontIntermediate::from_chars("dynamic".to_string(), 12, 10, vec![])
+            .with_dynamic_slots(DynamicSlots {
+                count: 5,
+                cell_size: ISize::new(8, 12),
+            });
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_font(font);
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let meta = AtlasMeta::from_texture_atlas(
+            "test".to_string(),
+            "test.png".to_string(),
+            &atlas,
+            &atlas.layout,
+        )?;
+
+        let slots = &meta.fonts[0].dynamic_slots;
+        assert_eq!(slots.len(), 5);
+        assert!(meta.sprites.is_empty());
+
+        for (i, slot) in slots.iter().enumerate() {
+            assert_eq!(slot.size(), ISize::new(8, 12));
+            assert!(slots[i + 1..].iter().all(|other| !slot.intersects(*other)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_groups() -> anyhow::Result<()> {
+        let label_set = |font_name: &str, labels: &[(&str, &str)]| LabelSetIntermediate {
+            font_name: font_name.to_string(),
+            labels: labels
+                .iter()
+                .enumerate()
+                .map(|(i, &(key, language))| BakedLabel {
+                    key: key.to_string(),
+                    language: language.to_string(),
+                    text: String::new(),
+                    image: noise(4 + i as u32, 3, i as u32),
+                })
+                .collect(),
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_label_set(label_set(
+            "small",
+            &[
+                ("label.start", "en"),
+                ("label.back", "en"),
+                ("label.start", "de"),
+            ],
+        ));
+        atlas.with_label_set(label_set("large", &[("label.start", "en")]));
+
+        let srcs = Sources::new();
+        let (meta, _) = pack(&mut atlas, &srcs)?;
+
+        // One entry per key and font, sorted by key within a label set
+        let groups: Vec<_> = meta
+            .labels
+            .iter()
+            .map(|label| {
+                let languages: Vec<_> = label
+                    .variants
+                    .iter()
+                    .map(|variant| variant.language.as_str())
+                    .collect();
+                (label.key.as_str(), label.font.as_str(), languages)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("label.back", "small", vec!["en"]),
+                ("label.start", "small", vec!["en", "de"]),
+                ("label.start", "large", vec!["en"]),
+            ]
+        );
+
+        // Every variant points at its own packed sprite
+        let mut indices = vec![];
+        for label in meta.labels.iter() {
+            let label_set = atlas
+                .label_sets
+                .iter()
+                .find(|label_set| label_set.font_name == label.font)
+                .unwrap();
+
+            for variant in label.variants.iter() {
+                let baked = label_set
+                    .labels
+                    .iter()
+                    .find(|baked| baked.sprite_name() == variant.sprite_name)
+                    .unwrap();
+                assert_eq!(
+                    meta.sprites[variant.sprite_index as usize].size(),
+                    ISize::new(baked.image.width() as i32, baked.image.height() as i32)
+                );
+                indices.push(variant.sprite_index);
+            }
+        }
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), meta.sprites.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uv_inset() {
+        let rect = IRect::new(4, 8, 4, 2);
+
+        let exact = UvRect::from_rect(rect, 16, 16, 0.0);
+        assert_eq!(exact.min, Vec2::new(0.25, 0.5));
+        assert_eq!(exact.max, Vec2::new(0.5, 0.625));
+
+        let inset = UvRect::from_rect(rect, 16, 16, 0.5);
+        assert_eq!(inset.min, Vec2::new(4.5 / 16.0, 8.5 / 16.0));
+        assert_eq!(inset.max, Vec2::new(7.5 / 16.0, 9.5 / 16.0));
+
+        // A 2 px high rect can only shrink by 1 px vertically
+        let collapsed = UvRect::from_rect(rect, 16, 16, 1.5);
+        assert_eq!(collapsed.min.y, collapsed.max.y);
+        assert_eq!(collapsed.min.y, 9.0 / 16.0);
+
+        assert_eq!(
+            UvRect::from_rect(IRect::default(), 16, 16, 0.5),
+            UvRect::default()
+        );
+    }
+
+    #[test]
+    fn test_set_uv_inset() -> anyhow::Result<()> {
+        let mut meta = AtlasMeta::for_test(
+            16,
+            16,
+            vec![
+                IRect::new(0, 0, 8, 8),
+                IRect::new(8, 0, 2, 4),
+                IRect::default(),
+            ],
+        );
+
+        assert_eq!(meta.set_uv_inset(0.5)?, 0);
+        assert_eq!(meta.uvs[1].min.x, 8.5 / 16.0);
+
+        // The 2 px wide sprite collapses, the empty one doesn't count
+        assert_eq!(meta.set_uv_inset(1.0)?, 1);
+        assert_eq!(meta.uvs[1].min.x, meta.uvs[1].max.x);
+
+        for inset in [-0.5, f32::NAN] {
+            let err = meta.set_uv_inset(inset).unwrap_err();
+            assert!(matches!(Error::find(&err), Some(Error::InvalidOption(_))));
+        }
+        assert_eq!(meta.uv_inset, 1.0);
+
+        Ok(())
+    }
 }
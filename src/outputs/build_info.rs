@@ -0,0 +1,20 @@
+use crate::{hashing, intermediates::texture_atlas::TextureAtlas, sources::Sources};
+
+/// Identifies the packer version, settings, and inputs that produced an atlas.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BuildInfo {
+    pub tool_version: String,
+    pub settings_hash: String,
+    /// A hash over the names and contents of all source files.
+    pub input_manifest_hash: String,
+}
+
+impl BuildInfo {
+    pub fn new(atlas: &TextureAtlas, srcs: &Sources) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            settings_hash: hashing::to_hex(atlas.settings_hash()),
+            input_manifest_hash: hashing::to_hex(hashing::hash_value(&srcs.input_manifest())),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::{error::Ewwow, math::IRect};
+
+use super::atlas_meta::{AtlasMeta, SpriteMeta, TrimRect, UvRect};
+
+/// Selects the part of an atlas a single output should include, so one pack
+/// can feed several differently scoped artifacts.
+///
+/// Parsed from comma separated terms, content is included if any of them
+/// matches:
+/// - `fonts`, `labels` or `sprites` include everything of that kind,
+/// - `tag:<tag>` includes sprites whose `tags` user data contains `<tag>`,
+/// - anything else is a name pattern for fonts, label keys and sprites,
+///   where `*` matches any text and `?` any single character, e.g. `ui/*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentFilter {
+    terms: Vec<FilterTerm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FilterTerm {
+    Fonts,
+    Labels,
+    Sprites,
+    Tag(String),
+    Name(String),
+}
+
+impl std::str::FromStr for ContentFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let terms = s
+            .split(',')
+            .map(|term| term.trim())
+            .map(|term| {
+                if term.is_empty() {
+                    Ewwow.raise().with_context(|| {
+                        format!(
+                            "Invalid filter '{s}', expected comma separated names, tags or kinds"
+                        )
+                    })?;
+                }
+
+                Ok(match term {
+                    "fonts" => FilterTerm::Fonts,
+                    "labels" => FilterTerm::Labels,
+                    "sprites" => FilterTerm::Sprites,
+                    _ => match term.strip_prefix("tag:") {
+                        Some(tag) => FilterTerm::Tag(tag.to_string()),
+                        None => FilterTerm::Name(term.to_string()),
+                    },
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { terms })
+    }
+}
+
+impl ContentFilter {
+    pub fn includes_font(&self, name: &str) -> bool {
+        self.terms.iter().any(|term| match term {
+            FilterTerm::Fonts => true,
+            FilterTerm::Name(pattern) => glob_match(pattern, name),
+            _ => false,
+        })
+    }
+
+    pub fn includes_label(&self, key: &str) -> bool {
+        self.terms.iter().any(|term| match term {
+            FilterTerm::Labels => true,
+            FilterTerm::Name(pattern) => glob_match(pattern, key),
+            _ => false,
+        })
+    }
+
+    pub fn includes_sprite(&self, sprite: &SpriteMeta) -> bool {
+        self.includes_sprite_named(&sprite.name, &sprite.user_data)
+    }
+
+    /// Like [`Self::includes_sprite`], for sprites that aren't exported yet.
+    pub fn includes_sprite_named(&self, name: &str, user_data: &BTreeMap<String, String>) -> bool {
+        self.terms.iter().any(|term| match term {
+            FilterTerm::Sprites => true,
+            FilterTerm::Tag(tag) => user_data
+                .get("tags")
+                .is_some_and(|tags| tags.split([' ', ',']).any(|t| t == tag)),
+            FilterTerm::Name(pattern) => glob_match(pattern, name),
+            _ => false,
+        })
+    }
+
+    /// A copy of `meta` with only the included fonts, labels and named
+    /// sprites. The sprite rects of everything else are emptied, so sprite
+    /// indices stay the same.
+    pub fn apply(&self, meta: &AtlasMeta) -> AtlasMeta {
+        let mut filtered = meta.clone();
+
+        filtered.fonts.retain(|font| self.includes_font(&font.name));
+        filtered
+            .labels
+            .retain(|label| self.includes_label(&label.key));
+        filtered
+            .named_sprites
+            .retain(|sprite| self.includes_sprite(sprite));
+        for sprite_names in filtered.materials.values_mut() {
+            sprite_names.retain(|name| {
+                filtered
+                    .named_sprites
+                    .iter()
+                    .any(|sprite| &sprite.name == name)
+            });
+        }
+        filtered
+            .materials
+            .retain(|_, sprite_names| !sprite_names.is_empty());
+
+        let mut used = vec![false; meta.sprites.len()];
+        let mut mark = |first: u32, count: u32| {
+            for index in first..first + count {
+                if let Some(used) = used.get_mut(index as usize) {
+                    *used = true;
+                }
+            }
+        };
+
+        for font in filtered.fonts.iter() {
+            for char in font.chars.iter() {
+                mark(char.first_sprite_index, font.num_animation_frames);
+            }
+        }
+        for variant in filtered
+            .labels
+            .iter()
+            .flat_map(|label| label.variants.iter())
+        {
+            mark(variant.sprite_index, 1);
+        }
+        for sprite in filtered.named_sprites.iter() {
+            mark(sprite.first_sprite_index, sprite.num_frames);
+        }
+
+        // Emptied sprites don't share their rect anymore
+        filtered.duplicate_of.retain(|&duplicate, &mut original| {
+            [duplicate, original]
+                .iter()
+                .all(|&index| used.get(index as usize) == Some(&true))
+        });
+
+        for (index, used) in used.into_iter().enumerate() {
+            if !used {
+                filtered.sprites[index] = IRect::default();
+                if let Some(uv) = filtered.uvs.get_mut(index) {
+                    *uv = UvRect::default();
+                }
+                if let Some(trim) = filtered.trims.get_mut(index) {
+                    *trim = TrimRect::default();
+                }
+            }
+        }
+
+        filtered
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any (possibly empty)
+/// text and `?` any single character.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to continue after the last `*` if the rest doesn't match
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, star_t)) => {
+                    p = star + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, ContentFilter};
+    use crate::{
+        math::IRect,
+        outputs::atlas_meta::{AtlasMeta, LabelMeta, LabelVariantMeta, SpriteMeta},
+    };
+
+    fn sprite_meta(name: &str, first_sprite_index: u32, tags: Option<&str>) -> SpriteMeta {
+        SpriteMeta {
+            user_data: tags
+                .map(|tags| [("tags".to_string(), tags.to_string())].into())
+                .unwrap_or_default(),
+            ..SpriteMeta::for_test(name, first_sprite_index, 1)
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("ui/*", "ui/button"));
+        assert!(glob_match("ui/*", "ui/"));
+        assert!(!glob_match("ui/*", "game/ui/button"));
+        assert!(glob_match("*#shadow", "hero#shadow"));
+        assert!(glob_match("h?ro*w", "hero#shadow"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("*a*b", "xxaxxbxx"));
+        assert!(glob_match("hero", "hero"));
+        assert!(!glob_match("hero", "heroes"));
+    }
+
+    #[test]
+    fn test_apply() -> anyhow::Result<()> {
+        let meta = AtlasMeta {
+            labels: vec![LabelMeta {
+                key: "label.title".to_string(),
+                font: "m5x7".to_string(),
+                variants: vec![LabelVariantMeta {
+                    language: "en".to_string(),
+                    sprite_name: "label.title.en".to_string(),
+                    sprite_index: 0,
+                }],
+            }],
+            named_sprites: vec![
+                sprite_meta("ui/button", 1, None),
+                sprite_meta("hero", 2, Some("player, animated")),
+                sprite_meta("tree", 3, Some("props")),
+            ],
+            ..AtlasMeta::for_test(16, 16, vec![IRect::new(0, 0, 2, 2); 4])
+        };
+
+        let filtered = "ui/*,tag:player".parse::<ContentFilter>()?.apply(&meta);
+        let names: Vec<_> = filtered.named_sprites.iter().map(|s| &s.name).collect();
+        assert_eq!(names, ["ui/button", "hero"]);
+        assert!(filtered.labels.is_empty());
+
+        // Indices stay valid, excluded sprites are empty
+        assert!(filtered.sprites[0].is_empty());
+        assert!(!filtered.sprites[2].is_empty());
+        assert!(filtered.sprites[3].is_empty());
+
+        let filtered = "labels".parse::<ContentFilter>()?.apply(&meta);
+        assert_eq!(filtered.labels.len(), 1);
+        assert!(filtered.named_sprites.is_empty());
+        assert!(!filtered.sprites[0].is_empty());
+
+        assert!("ui/*,".parse::<ContentFilter>().is_err());
+
+        Ok(())
+    }
+}
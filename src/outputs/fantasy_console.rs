@@ -0,0 +1,324 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    error::Ewwow,
+    hashing,
+    image_io::{self, PngOptions},
+    inputs::sprite_references::SpriteReferences,
+};
+
+use super::atlas_meta::AtlasMeta;
+
+/// The side length of a fantasy console spritesheet in pixels.
+pub const SHEET_SIZE: u32 = 128;
+const CELL_SIZE: u32 = 8;
+const CELLS: u32 = SHEET_SIZE / CELL_SIZE;
+
+const PICO8_PALETTE: [u32; 16] = [
+    0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8, 0xff004d,
+    0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+];
+
+/// The default "Sweetie 16" palette.
+const TIC80_PALETTE: [u32; 16] = [
+    0x1a1c2c, 0x5d275d, 0xb13e53, 0xef7d57, 0xffcd75, 0xa7f070, 0x38b764, 0x257179, 0x29366f,
+    0x3b5dc9, 0x41a6f6, 0x73eff7, 0xf4f4f4, 0x94b0c2, 0x566c86, 0x333c57,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FantasyConsole {
+    Pico8,
+    Tic80,
+}
+
+impl FantasyConsole {
+    /// The fixed palette, color 0 is the default transparent color.
+    pub fn palette(self) -> [u32; 16] {
+        match self {
+            FantasyConsole::Pico8 => PICO8_PALETTE,
+            FantasyConsole::Tic80 => TIC80_PALETTE,
+        }
+    }
+}
+
+impl std::str::FromStr for FantasyConsole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pico8" => Ok(Self::Pico8),
+            "tic80" => Ok(Self::Tic80),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Invalid fantasy console '{s}', expected pico8 or tic80")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// Where a frame of a sprite ended up on the sheet.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SheetSprite {
+    pub name: String,
+    pub frame: u32,
+    /// The number of the top left 8x8 cell, as `spr` expects it.
+    pub sprite_number: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The size in cells, the `w` and `h` arguments of `spr`.
+    pub cells_wide: u32,
+    pub cells_high: u32,
+}
+
+/// Sprites quantized to the palette of a fantasy console and arranged on its
+/// fixed size spritesheet.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FantasySheet {
+    pub console: FantasyConsole,
+    pub sprites: Vec<SheetSprite>,
+    /// The palette index of every pixel, row by row.
+    #[serde(skip)]
+    pub indices: Vec<u8>,
+}
+
+/// Arranges the frames of the named sprites of `meta` (all of them, or the
+/// ones `selection` references) on a sheet, each starting at an 8x8 cell,
+/// left to right and top to bottom in the first free spot.
+///
+/// Pixels with less than half alpha get color 0, all others the closest
+/// other palette color.
+pub fn build_sheet(
+    meta: &AtlasMeta,
+    atlas_image: &RgbaImage,
+    console: FantasyConsole,
+    selection: Option<&SpriteReferences>,
+) -> anyhow::Result<FantasySheet> {
+    let palette = console.palette().map(|color| color.to_be_bytes());
+
+    let mut occupied = [[false; CELLS as usize]; CELLS as usize];
+    let mut indices = vec![0u8; (SHEET_SIZE * SHEET_SIZE) as usize];
+    let mut sprites = vec![];
+
+    let selected = meta
+        .named_sprites
+        .iter()
+        .filter(|sprite| selection.is_none_or(|selection| selection.contains(&sprite.name)));
+
+    for sprite in selected {
+        let first = sprite.first_sprite_index as usize;
+
+        for frame in 0..sprite.num_frames {
+            let rect = meta
+                .sprites
+                .get(first + frame as usize)
+                .ok_or(Ewwow)
+                .with_context(|| format!("Frames of sprite '{}' are out of range", sprite.name))?;
+            let (width, height) = (rect.uwidth(), rect.uheight());
+            let (cells_wide, cells_high) = (width.div_ceil(CELL_SIZE), height.div_ceil(CELL_SIZE));
+
+            let free = |col: u32, row: u32| {
+                col + cells_wide <= CELLS
+                    && row + cells_high <= CELLS
+                    && (row..row + cells_high)
+                        .all(|r| (col..col + cells_wide).all(|c| !occupied[r as usize][c as usize]))
+            };
+
+            let Some((col, row)) = (0..CELLS)
+                .flat_map(|row| (0..CELLS).map(move |col| (col, row)))
+                .find(|&(col, row)| free(col, row))
+            else {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "Frame #{frame} of sprite '{}' ({width}x{height}) doesn't fit onto the {SHEET_SIZE}x{SHEET_SIZE} sheet anymore",
+                        sprite.name
+                    )
+                })?;
+                unreachable!()
+            };
+
+            for r in row..row + cells_high {
+                for c in col..col + cells_wide {
+                    occupied[r as usize][c as usize] = true;
+                }
+            }
+
+            let (x, y) = (col * CELL_SIZE, row * CELL_SIZE);
+            if let Some((rect_x, rect_y)) = rect.checked_umin().filter(|_| !rect.is_empty()) {
+                for py in 0..height {
+                    for px in 0..width {
+                        let pixel = atlas_image.get_pixel(rect_x + px, rect_y + py);
+                        indices[((y + py) * SHEET_SIZE + x + px) as usize] =
+                            closest_color(&palette, pixel);
+                    }
+                }
+            }
+
+            sprites.push(SheetSprite {
+                name: sprite.name.clone(),
+                frame,
+                sprite_number: row * CELLS + col,
+                x,
+                y,
+                width,
+                height,
+                cells_wide,
+                cells_high,
+            });
+        }
+    }
+
+    Ok(FantasySheet {
+        console,
+        sprites,
+        indices,
+    })
+}
+
+fn closest_color(palette: &[[u8; 4]; 16], pixel: &Rgba<u8>) -> u8 {
+    if pixel[3] < 128 {
+        return 0;
+    }
+
+    let distance = |color: &[u8; 4]| -> i32 {
+        (1..4)
+            .map(|channel| {
+                let diff = color[channel] as i32 - pixel[channel - 1] as i32;
+                diff * diff
+            })
+            .sum()
+    };
+
+    (1..palette.len())
+        .min_by_key(|&index| distance(&palette[index]))
+        .unwrap() as u8
+}
+
+impl FantasySheet {
+    /// The sheet in the palette colors, e.g. for importing it.
+    pub fn image(&self) -> RgbaImage {
+        let palette = self.console.palette().map(|color| color.to_be_bytes());
+
+        RgbaImage::from_fn(SHEET_SIZE, SHEET_SIZE, |x, y| {
+            let [_, r, g, b] = palette[self.indices[(y * SHEET_SIZE + x) as usize] as usize];
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    /// A PICO-8 cartridge with only the `__gfx__` section.
+    pub fn pico8_cartridge(&self) -> String {
+        let mut cartridge =
+            "pico-8 cartridge // http://www.pico-8.com\nversion 41\n__gfx__\n".to_string();
+
+        for row in self.indices.chunks(SHEET_SIZE as usize) {
+            cartridge.extend(
+                row.iter()
+                    .map(|&index| char::from_digit(index as u32, 16).unwrap()),
+            );
+            cartridge.push('\n');
+        }
+
+        cartridge
+    }
+
+    /// Writes `sheet.png`, the mapping report `sheet.json` and for PICO-8 the
+    /// cartridge `sheet.p8` to `dir`. Returns the written files.
+    pub fn write(&self, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+
+        let png_path = dir.join("sheet.png");
+        let png = image_io::encode_png(&self.image(), &PngOptions::default())
+            .with_context(|| format!("Failed to encode '{}'", png_path.display()))?;
+        hashing::write_if_changed(&png_path, png)
+            .with_context(|| format!("Failed to save '{}'", png_path.display()))?;
+
+        let json_path = dir.join("sheet.json");
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize sheet mapping")?;
+        hashing::write_if_changed(&json_path, json)
+            .with_context(|| format!("Failed to write '{}'", json_path.display()))?;
+
+        let mut files = vec![png_path, json_path];
+
+        if self.console == FantasyConsole::Pico8 {
+            let cartridge_path = dir.join("sheet.p8");
+            hashing::write_if_changed(&cartridge_path, self.pico8_cartridge())
+                .with_context(|| format!("Failed to write '{}'", cartridge_path.display()))?;
+            files.push(cartridge_path);
+        }
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{build_sheet, FantasyConsole, SHEET_SIZE};
+    use crate::{
+        inputs::sprite_references::SpriteReferences,
+        math::IRect,
+        outputs::atlas_meta::{AtlasMeta, SpriteMeta},
+    };
+
+    #[test]
+    fn test_build_sheet() -> anyhow::Result<()> {
+        // Almost PICO-8 red, with a transparent pixel
+        let mut atlas_image = RgbaImage::from_pixel(64, 64, Rgba([250, 10, 70, 255]));
+        atlas_image.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+
+        let meta = AtlasMeta {
+            named_sprites: vec![
+                SpriteMeta::for_test("wide", 0, 1),
+                SpriteMeta::for_test("anim", 1, 2),
+            ],
+            ..AtlasMeta::for_test(
+                64,
+                64,
+                vec![
+                    IRect::new(0, 0, 12, 4),
+                    IRect::new(0, 0, 8, 8),
+                    IRect::new(0, 0, 8, 8),
+                ],
+            )
+        };
+
+        let sheet = build_sheet(&meta, &atlas_image, FantasyConsole::Pico8, None)?;
+        assert_eq!(sheet.sprites.len(), 3);
+
+        let numbers: Vec<_> = sheet.sprites.iter().map(|s| s.sprite_number).collect();
+        assert_eq!(numbers, vec![0, 2, 3]);
+        assert_eq!(
+            (sheet.sprites[0].cells_wide, sheet.sprites[0].cells_high),
+            (2, 1)
+        );
+
+        assert_eq!(sheet.indices[0], 0);
+        assert_eq!(sheet.indices[1], 8);
+        // Outside of the 12x4 sprite
+        assert_eq!(sheet.indices[(5 * SHEET_SIZE) as usize], 0);
+
+        let cartridge = sheet.pico8_cartridge();
+        assert!(cartridge.contains("__gfx__\n088888888888"));
+        assert_eq!(cartridge.lines().count(), 3 + SHEET_SIZE as usize);
+
+        // Only the selected sprites
+        let selection = SpriteReferences::parse("anim");
+        let sheet = build_sheet(&meta, &atlas_image, FantasyConsole::Tic80, Some(&selection))?;
+        assert_eq!(sheet.sprites.len(), 2);
+        assert_eq!(sheet.sprites[0].sprite_number, 0);
+        // Sweetie 16 has its red at index 2
+        assert_eq!(sheet.indices[1], 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::Context;
+use image::{GenericImageView, RgbaImage};
+
+use crate::{blit::blit, error::Ewwow, math::IRect};
+
+use super::atlas_meta::{AtlasMeta, SpriteMeta};
+
+/// The frames of an animated sprite side by side, left to right, for
+/// particle systems and shaders that consume flipbooks instead of atlases.
+#[derive(Debug, Clone)]
+pub struct FlipbookStrip {
+    pub sprite_name: String,
+    pub num_frames: u32,
+    /// The size of every cell of the strip, the largest frame size.
+    pub cell_width: u32,
+    pub cell_height: u32,
+    pub image: RgbaImage,
+}
+
+/// The frames of an animated sprite in a grid of equally sized cells, left
+/// to right and top to bottom, as GPU flipbook nodes sample them.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FlipbookGrid {
+    pub sprite_name: String,
+    pub num_frames: u32,
+    pub rows: u32,
+    pub cols: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    #[serde(skip)]
+    pub image: RgbaImage,
+    /// Every frame on its own, e.g. for the slices of a volume texture.
+    #[serde(skip)]
+    pub frames: Vec<RgbaImage>,
+}
+
+/// Cuts a strip out of `atlas_image` for every named sprite of `meta` with
+/// more than one frame. Frames smaller than the cell are placed at its top
+/// left corner.
+pub fn build_strips(
+    meta: &AtlasMeta,
+    atlas_image: &RgbaImage,
+) -> anyhow::Result<Vec<FlipbookStrip>> {
+    let mut strips = vec![];
+
+    for sprite in animated_sprites(meta) {
+        let frames = sprite_frames(meta, sprite)?;
+
+        let cell_width = frames.iter().map(|frame| frame.uwidth()).max().unwrap_or(0);
+        let cell_height = frames
+            .iter()
+            .map(|frame| frame.uheight())
+            .max()
+            .unwrap_or(0);
+
+        let mut image = RgbaImage::new(cell_width * sprite.num_frames, cell_height);
+
+        for (index, &frame) in frames.iter().enumerate() {
+            copy_frame(&mut image, atlas_image, frame, index as u32 * cell_width, 0).with_context(
+                || format!("Failed to copy frame #{index} of sprite '{}'", sprite.name),
+            )?;
+        }
+
+        strips.push(FlipbookStrip {
+            sprite_name: sprite.name.clone(),
+            num_frames: sprite.num_frames,
+            cell_width,
+            cell_height,
+            image,
+        });
+    }
+
+    Ok(strips)
+}
+
+/// Cuts a grid out of `atlas_image` for every named sprite of `meta` with
+/// more than one frame. The grid is as close to square as possible and all
+/// frames of a sprite need to have the same size.
+pub fn build_grids(meta: &AtlasMeta, atlas_image: &RgbaImage) -> anyhow::Result<Vec<FlipbookGrid>> {
+    let mut grids = vec![];
+
+    for sprite in animated_sprites(meta) {
+        let frames = sprite_frames(meta, sprite)?;
+
+        let cell = frames[0].size();
+        if let Some(index) = frames.iter().position(|frame| frame.size() != cell) {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Frame #{index} of sprite '{}' isn't {}x{} like the first one, flipbook grids need equally sized frames",
+                    sprite.name, cell.width, cell.height
+                )
+            })?;
+        }
+
+        let (cell_width, cell_height) = (cell.width as u32, cell.height as u32);
+        let cols = (sprite.num_frames as f64).sqrt().ceil() as u32;
+        let rows = sprite.num_frames.div_ceil(cols);
+
+        let mut image = RgbaImage::new(cell_width * cols, cell_height * rows);
+        let mut frame_images = Vec::with_capacity(frames.len());
+
+        for (index, &frame) in frames.iter().enumerate() {
+            let mut frame_image = RgbaImage::new(cell_width, cell_height);
+            copy_frame(&mut frame_image, atlas_image, frame, 0, 0).with_context(|| {
+                format!("Failed to copy frame #{index} of sprite '{}'", sprite.name)
+            })?;
+
+            let (col, row) = (index as u32 % cols, index as u32 / cols);
+            blit(
+                &mut image,
+                &frame_image.view(0, 0, cell_width, cell_height),
+                col * cell_width,
+                row * cell_height,
+            )?;
+
+            frame_images.push(frame_image);
+        }
+
+        grids.push(FlipbookGrid {
+            sprite_name: sprite.name.clone(),
+            num_frames: sprite.num_frames,
+            rows,
+            cols,
+            cell_width,
+            cell_height,
+            image,
+            frames: frame_images,
+        });
+    }
+
+    Ok(grids)
+}
+
+fn animated_sprites(meta: &AtlasMeta) -> impl Iterator<Item = &SpriteMeta> {
+    meta.named_sprites
+        .iter()
+        .filter(|sprite| sprite.num_frames > 1)
+}
+
+fn sprite_frames<'m>(meta: &'m AtlasMeta, sprite: &SpriteMeta) -> anyhow::Result<&'m [IRect]> {
+    let first = sprite.first_sprite_index as usize;
+
+    meta.sprites
+        .get(first..first + sprite.num_frames as usize)
+        .ok_or(Ewwow)
+        .with_context(|| format!("Frames of sprite '{}' are out of range", sprite.name))
+}
+
+/// Copies the `frame` of `atlas_image` to `x`, `y` of `image`. Empty frames
+/// leave it transparent.
+fn copy_frame(
+    image: &mut RgbaImage,
+    atlas_image: &RgbaImage,
+    frame: IRect,
+    x: u32,
+    y: u32,
+) -> anyhow::Result<()> {
+    if frame.is_empty() {
+        return Ok(());
+    }
+
+    let (frame_x, frame_y) = frame
+        .checked_umin()
+        .ok_or(Ewwow)
+        .context("The frame is at negative coordinates")?;
+    let view = atlas_image.view(frame_x, frame_y, frame.uwidth(), frame.uheight());
+
+    blit(image, &view, x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    use super::{build_grids, build_strips};
+    use crate::{
+        math::IRect,
+        outputs::atlas_meta::{AtlasMeta, SpriteMeta},
+    };
+
+    /// A still sprite made of the first rect and an animation of the others.
+    fn test_meta(sprites: Vec<IRect>) -> AtlasMeta {
+        let num_frames = sprites.len() as u32 - 1;
+
+        AtlasMeta {
+            named_sprites: vec![
+                SpriteMeta::for_test("still", 0, 1),
+                SpriteMeta::for_test("anim", 1, num_frames),
+            ],
+            ..AtlasMeta::for_test(16, 16, sprites)
+        }
+    }
+
+    #[test]
+    fn test_build_strips() -> anyhow::Result<()> {
+        let atlas_image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        let meta = test_meta(vec![
+            IRect::new(0, 0, 2, 2),
+            IRect::new(4, 4, 3, 2),
+            IRect::new(8, 0, 2, 3),
+            IRect::default(),
+        ]);
+
+        let strips = build_strips(&meta, &atlas_image)?;
+        assert_eq!(strips.len(), 1);
+
+        let strip = &strips[0];
+        assert_eq!(strip.sprite_name, "anim");
+        assert_eq!((strip.cell_width, strip.cell_height), (3, 3));
+        assert_eq!(strip.image.dimensions(), (9, 3));
+
+        assert_eq!(
+            strip.image.view(0, 0, 3, 2).to_image(),
+            atlas_image.view(4, 4, 3, 2).to_image()
+        );
+        assert_eq!(
+            strip.image.view(3, 0, 2, 3).to_image(),
+            atlas_image.view(8, 0, 2, 3).to_image()
+        );
+        // Unused parts of cells and empty frames stay transparent
+        assert_eq!(strip.image.get_pixel(5, 0)[3], 0);
+        assert_eq!(strip.image.get_pixel(7, 1)[3], 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_grids() -> anyhow::Result<()> {
+        let atlas_image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        let frames = (0..5).map(|i| IRect::new(i * 3, 8, 2, 3));
+        let meta = test_meta([IRect::new(0, 0, 1, 1)].into_iter().chain(frames).collect());
+
+        let grids = build_grids(&meta, &atlas_image)?;
+        assert_eq!(grids.len(), 1);
+
+        let grid = &grids[0];
+        assert_eq!((grid.cols, grid.rows), (3, 2));
+        assert_eq!(grid.image.dimensions(), (6, 6));
+        assert_eq!(grid.frames.len(), 5);
+
+        // The fifth frame is in the middle of the second row
+        assert_eq!(
+            grid.image.view(2, 3, 2, 3).to_image(),
+            atlas_image.view(12, 8, 2, 3).to_image()
+        );
+        assert_eq!(grid.frames[4], atlas_image.view(12, 8, 2, 3).to_image());
+        assert_eq!(grid.image.get_pixel(4, 3)[3], 0);
+
+        // Frames of different sizes can't be sampled as a grid
+        let meta = test_meta(vec![
+            IRect::new(0, 0, 1, 1),
+            IRect::new(0, 0, 2, 2),
+            IRect::new(4, 0, 2, 3),
+        ]);
+        assert!(build_grids(&meta, &atlas_image).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::path::Path;
+
+use anyhow::Context;
+use image::RgbaImage;
+
+use crate::{error::Ewwow, hashing};
+
+use super::atlas_meta::AtlasMeta;
+
+/// The hardware sprite shapes by their OAM shape and size bits.
+const OAM_SIZES: [(u8, u8, u32, u32); 12] = [
+    (0, 0, 8, 8),
+    (0, 1, 16, 16),
+    (0, 2, 32, 32),
+    (0, 3, 64, 64),
+    (1, 0, 16, 8),
+    (1, 1, 32, 8),
+    (1, 2, 32, 16),
+    (1, 3, 64, 32),
+    (2, 0, 8, 16),
+    (2, 1, 8, 32),
+    (2, 2, 16, 32),
+    (2, 3, 32, 64),
+];
+
+/// How many bits every pixel of a tile takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileDepth {
+    /// 16 palette banks of 15 colors each, every sprite uses one bank.
+    Bpp4,
+    /// A single palette of 255 colors.
+    Bpp8,
+}
+
+impl TileDepth {
+    fn bits(self) -> u32 {
+        match self {
+            TileDepth::Bpp4 => 4,
+            TileDepth::Bpp8 => 8,
+        }
+    }
+}
+
+impl std::str::FromStr for TileDepth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4bpp" => Ok(Self::Bpp4),
+            "8bpp" => Ok(Self::Bpp8),
+            _ => {
+                Ewwow
+                    .raise()
+                    .with_context(|| format!("Invalid tile depth '{s}', expected 4bpp or 8bpp"))?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// A sprite of the atlas as a GBA/NDS hardware sprite, with what its OAM
+/// attributes need.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct TileSprite {
+    /// The index into [`AtlasMeta::sprites`].
+    pub sprite_index: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Attribute 0 bits 14-15.
+    pub shape: u8,
+    /// Attribute 1 bits 14-15.
+    pub size: u8,
+    /// Attribute 2 bits 0-9: the first tile in 32 byte units, tiles are laid
+    /// out for 1D mapping.
+    pub tile_index: u32,
+    /// Attribute 2 bits 12-15, always 0 for 8bpp.
+    pub palette_bank: u8,
+}
+
+/// The sprites of an atlas converted to 8x8 tiles and BGR555 palettes.
+/// Palette index 0 is transparent.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TileExport {
+    pub bits_per_pixel: u32,
+    pub sprites: Vec<TileSprite>,
+    #[serde(skip)]
+    pub tiles: Vec<u8>,
+    /// 16 banks of 16 colors for 4bpp, 256 colors for 8bpp.
+    #[serde(skip)]
+    pub palette: Vec<u16>,
+}
+
+/// Converts every non-empty sprite of `meta` into tiles. Each sprite is put
+/// in the top left corner of the smallest hardware sprite shape it fits.
+/// Pixels with less than half alpha are transparent.
+pub fn build_tiles(
+    meta: &AtlasMeta,
+    atlas_image: &RgbaImage,
+    depth: TileDepth,
+) -> anyhow::Result<TileExport> {
+    // The colors of every bank, without the transparent index 0
+    let (max_banks, bank_len) = match depth {
+        TileDepth::Bpp4 => (16, 15),
+        TileDepth::Bpp8 => (1, 255),
+    };
+    let mut banks: Vec<Vec<u16>> = vec![];
+
+    let mut sprites = vec![];
+    let mut tiles = vec![];
+
+    for (sprite_index, rect) in meta.sprites.iter().enumerate() {
+        if rect.is_empty() {
+            continue;
+        }
+
+        let (x, y) = rect
+            .checked_umin()
+            .ok_or(Ewwow)
+            .context("The sprite is at negative coordinates")?;
+        let (width, height) = (rect.uwidth(), rect.uheight());
+
+        let Some(&(shape, size, oam_width, oam_height)) = OAM_SIZES
+            .iter()
+            .filter(|&&(_, _, w, h)| w >= width && h >= height)
+            .min_by_key(|&&(_, _, w, h)| w * h)
+        else {
+            Ewwow.raise().with_context(|| {
+                format!("Sprite #{sprite_index} is {width}x{height}, hardware sprites can be at most 64x64")
+            })?;
+            unreachable!()
+        };
+
+        let pixel = |px: u32, py: u32| -> Option<u16> {
+            if px >= width || py >= height {
+                return None;
+            }
+
+            let [r, g, b, a] = atlas_image.get_pixel(x + px, y + py).0;
+            (a >= 128).then_some((r as u16 >> 3) | ((g as u16 >> 3) << 5) | ((b as u16 >> 3) << 10))
+        };
+
+        let mut colors: Vec<u16> = (0..height)
+            .flat_map(|py| (0..width).filter_map(move |px| pixel(px, py)))
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+
+        // The first bank that still has room for the colors of the sprite
+        let bank_index = banks.iter().position(|bank| {
+            let missing = colors.iter().filter(|color| !bank.contains(color)).count();
+            bank.len() + missing <= bank_len
+        });
+        let bank_index = match bank_index {
+            Some(index) => index,
+            None if banks.len() < max_banks && colors.len() <= bank_len => {
+                banks.push(vec![]);
+                banks.len() - 1
+            }
+            None => {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "Sprite #{sprite_index} has {} colors, which don't fit into the {}bpp palette anymore",
+                        colors.len(),
+                        depth.bits()
+                    )
+                })?;
+                unreachable!()
+            }
+        };
+
+        let bank = &mut banks[bank_index];
+        for color in colors {
+            if !bank.contains(&color) {
+                bank.push(color);
+            }
+        }
+
+        sprites.push(TileSprite {
+            sprite_index: sprite_index as u32,
+            width,
+            height,
+            shape,
+            size,
+            tile_index: (tiles.len() / 32) as u32,
+            palette_bank: bank_index as u8,
+        });
+
+        // Tiles row by row, pixels inside of a tile too
+        for tile_y in (0..oam_height).step_by(8) {
+            for tile_x in (0..oam_width).step_by(8) {
+                for py in tile_y..tile_y + 8 {
+                    let indices = (tile_x..tile_x + 8).map(|px| {
+                        pixel(px, py).map_or(0, |color| {
+                            bank.iter().position(|&c| c == color).unwrap() as u8 + 1
+                        })
+                    });
+
+                    match depth {
+                        TileDepth::Bpp4 => {
+                            let indices: Vec<u8> = indices.collect();
+                            // The left pixel is in the low nibble
+                            tiles.extend(indices.chunks(2).map(|pair| pair[0] | (pair[1] << 4)));
+                        }
+                        TileDepth::Bpp8 => tiles.extend(indices),
+                    }
+                }
+            }
+        }
+    }
+
+    let mut palette = vec![0u16; max_banks * (bank_len + 1)];
+    for (bank_index, bank) in banks.iter().enumerate() {
+        let start = bank_index * (bank_len + 1) + 1;
+        palette[start..start + bank.len()].copy_from_slice(bank);
+    }
+
+    Ok(TileExport {
+        bits_per_pixel: depth.bits(),
+        sprites,
+        tiles,
+        palette,
+    })
+}
+
+impl TileExport {
+    /// Writes `tiles.bin`, `palette.bin` (both little endian) and the OAM
+    /// attributes of every sprite in `tiles.json` to `dir`. Returns the
+    /// written files.
+    pub fn write(&self, dir: &Path) -> anyhow::Result<Vec<std::path::PathBuf>> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+
+        let palette: Vec<u8> = self
+            .palette
+            .iter()
+            .flat_map(|color| color.to_le_bytes())
+            .collect();
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize tile sprites")?;
+
+        let files = [
+            (dir.join("tiles.bin"), self.tiles.as_slice()),
+            (dir.join("palette.bin"), palette.as_slice()),
+            (dir.join("tiles.json"), json.as_slice()),
+        ];
+
+        for (path, contents) in files.iter() {
+            hashing::write_if_changed(path, contents)
+                .with_context(|| format!("Failed to write '{}'", path.display()))?;
+        }
+
+        Ok(files.into_iter().map(|(path, _)| path).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{build_tiles, TileDepth};
+    use crate::{math::IRect, outputs::atlas_meta::AtlasMeta};
+
+    #[test]
+    fn test_build_tiles() -> anyhow::Result<()> {
+        let mut atlas_image = RgbaImage::new(128, 128);
+        // A red 5x12 sprite with a white pixel at its top left
+        for y in 0..12 {
+            for x in 0..5 {
+                atlas_image.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+            }
+        }
+        atlas_image.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        // A green 9x3 sprite
+        for y in 20..23 {
+            for x in 0..9 {
+                atlas_image.put_pixel(x, y, Rgba([0, 255, 0, 255]));
+            }
+        }
+
+        let meta = AtlasMeta::for_test(
+            128,
+            128,
+            vec![
+                IRect::new(0, 0, 5, 12),
+                IRect::default(),
+                IRect::new(0, 20, 9, 3),
+            ],
+        );
+
+        let export = build_tiles(&meta, &atlas_image, TileDepth::Bpp4)?;
+        assert_eq!(export.sprites.len(), 2);
+
+        // 8x16 is the smallest shape for 5x12, 16x8 for 9x3
+        let (tall, wide) = (&export.sprites[0], &export.sprites[1]);
+        assert_eq!((tall.shape, tall.size, tall.tile_index), (2, 0, 0));
+        assert_eq!((wide.shape, wide.size, wide.tile_index), (1, 0, 2));
+        assert_eq!(wide.sprite_index, 2);
+        assert_eq!(export.tiles.len(), 4 * 32);
+
+        // Both sprites share the first bank: white, red and green
+        assert_eq!(wide.palette_bank, 0);
+        assert_eq!(&export.palette[..4], &[0, 0x001f, 0x7fff, 0x03e0]);
+        assert_eq!(export.palette.len(), 256);
+
+        // White then red in the low and high nibble, transparent after x 5
+        assert_eq!(export.tiles[..4], [0x12, 0x11, 0x01, 0x00]);
+
+        let export = build_tiles(&meta, &atlas_image, TileDepth::Bpp8)?;
+        assert_eq!(export.tiles.len(), 4 * 64);
+        assert_eq!(export.sprites[1].tile_index, 4);
+        assert_eq!(export.tiles[..6], [2, 1, 1, 1, 1, 0]);
+
+        // Too large for a hardware sprite
+        let meta = AtlasMeta::for_test(128, 128, vec![IRect::new(0, 0, 65, 8)]);
+        assert!(build_tiles(&meta, &atlas_image, TileDepth::Bpp4).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::Context;
+use image::RgbaImage;
+
+use crate::{error::Ewwow, hashing};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+const HEADER_LEN: usize = 80;
+const LEVEL_INDEX_LEN: usize = 24;
+/// The total size field plus a basic data format descriptor with 4 samples.
+const DFD_LEN: usize = 4 + 24 + 4 * 16;
+
+/// Encodes `layers` as an uncompressed sRGB RGBA8 KTX2 array texture with a
+/// single mip level. All layers need to have the same size.
+pub fn encode_array(layers: &[RgbaImage]) -> anyhow::Result<Vec<u8>> {
+    encode(layers, false)
+}
+
+/// Encodes `slices` as an uncompressed sRGB RGBA8 KTX2 volume (3D) texture,
+/// front to back, with a single mip level. All slices need to have the same
+/// size.
+pub fn encode_volume(slices: &[RgbaImage]) -> anyhow::Result<Vec<u8>> {
+    encode(slices, true)
+}
+
+fn encode(layers: &[RgbaImage], volume: bool) -> anyhow::Result<Vec<u8>> {
+    let Some(first) = layers.first() else {
+        Ewwow
+            .raise()
+            .context("A texture needs at least one layer")?;
+        unreachable!()
+    };
+
+    let (width, height) = first.dimensions();
+    if let Some(index) = layers
+        .iter()
+        .position(|layer| layer.dimensions() != (width, height))
+    {
+        Ewwow.raise().with_context(|| {
+            format!("Layer #{index} isn't {width}x{height} like the first layer")
+        })?;
+    }
+
+    let dfd_offset = HEADER_LEN + LEVEL_INDEX_LEN;
+    // The level data only has to be aligned to the 4 byte texel size
+    let data_offset = dfd_offset + DFD_LEN;
+    let data_len = layers
+        .iter()
+        .map(|layer| layer.as_raw().len())
+        .sum::<usize>();
+
+    let mut out = Vec::with_capacity(data_offset + data_len);
+    let u32 = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&value.to_le_bytes());
+    let u64 = |out: &mut Vec<u8>, value: u64| out.extend_from_slice(&value.to_le_bytes());
+
+    // Header
+    out.extend_from_slice(&IDENTIFIER);
+    u32(&mut out, VK_FORMAT_R8G8B8A8_SRGB);
+    u32(&mut out, 1); // typeSize
+    u32(&mut out, width);
+    u32(&mut out, height);
+    // Volume textures have slices instead of layers
+    let (depth, layer_count) = match volume {
+        true => (layers.len() as u32, 0),
+        false => (0, layers.len() as u32),
+    };
+    u32(&mut out, depth);
+    u32(&mut out, layer_count);
+    u32(&mut out, 1); // faceCount
+    u32(&mut out, 1); // levelCount
+    u32(&mut out, 0); // supercompressionScheme
+
+    // Index, without key/value or supercompression data
+    u32(&mut out, dfd_offset as u32);
+    u32(&mut out, DFD_LEN as u32);
+    u32(&mut out, 0);
+    u32(&mut out, 0);
+    u64(&mut out, 0);
+    u64(&mut out, 0);
+
+    // Level index
+    u64(&mut out, data_offset as u64);
+    u64(&mut out, data_len as u64);
+    u64(&mut out, data_len as u64);
+
+    // Data format descriptor
+    u32(&mut out, DFD_LEN as u32);
+    u32(&mut out, 0); // vendorId (Khronos), descriptorType (basic)
+    u32(&mut out, 2 | ((DFD_LEN as u32 - 4) << 16)); // versionNumber, descriptorBlockSize
+    u32(&mut out, 1 | (1 << 8) | (2 << 16)); // RGBSDA, BT.709 primaries, sRGB transfer, straight alpha
+    u32(&mut out, 0); // texelBlockDimension
+    u32(&mut out, 4); // bytesPlane0
+    u32(&mut out, 0);
+
+    // R, G, B and linear A, 8 bits each
+    for (channel, channel_type) in [0u32, 1, 2, 15 | 0x10].into_iter().enumerate() {
+        u32(
+            &mut out,
+            (channel as u32 * 8) | (7 << 16) | (channel_type << 24),
+        );
+        u32(&mut out, 0); // samplePosition
+        u32(&mut out, 0); // sampleLower
+        u32(&mut out, 255); // sampleUpper
+    }
+
+    debug_assert_eq!(out.len(), data_offset);
+
+    for layer in layers {
+        out.extend_from_slice(layer.as_raw());
+    }
+
+    Ok(out)
+}
+
+/// Encodes `layers` and writes them to `path` as a KTX2 array texture.
+pub fn write_array(path: &std::path::Path, layers: &[RgbaImage]) -> anyhow::Result<()> {
+    let bytes = encode_array(layers)?;
+
+    hashing::write_if_changed(path, bytes)
+        .with_context(|| format!("Failed to write '{}'", path.display()))
+}
+
+/// Encodes `slices` and writes them to `path` as a KTX2 volume texture.
+pub fn write_volume(path: &std::path::Path, slices: &[RgbaImage]) -> anyhow::Result<()> {
+    let bytes = encode_volume(slices)?;
+
+    hashing::write_if_changed(path, bytes)
+        .with_context(|| format!("Failed to write '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{encode_array, encode_volume, DFD_LEN, HEADER_LEN, IDENTIFIER, LEVEL_INDEX_LEN};
+
+    #[test]
+    fn test_encode_array() -> anyhow::Result<()> {
+        let layers = [
+            RgbaImage::from_pixel(4, 2, Rgba([1, 2, 3, 4])),
+            RgbaImage::from_pixel(4, 2, Rgba([5, 6, 7, 8])),
+        ];
+
+        let bytes = encode_array(&layers)?;
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(bytes[..12], IDENTIFIER);
+        assert_eq!((u32_at(20), u32_at(24)), (4, 2));
+        assert_eq!(u32_at(32), 2);
+
+        let data_offset = HEADER_LEN + LEVEL_INDEX_LEN + DFD_LEN;
+        assert_eq!(bytes.len(), data_offset + 2 * 4 * 2 * 4);
+        assert_eq!(bytes[data_offset..data_offset + 4], [1, 2, 3, 4]);
+        assert_eq!(bytes[bytes.len() - 4..], [5, 6, 7, 8]);
+
+        // Volumes have a depth instead of layers
+        let volume = encode_volume(&layers)?;
+        assert_eq!(volume[28..36], [2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(volume[HEADER_LEN..], bytes[HEADER_LEN..]);
+
+        assert!(encode_array(&[]).is_err());
+        assert!(encode_array(&[layers[0].clone(), RgbaImage::new(2, 2)]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+//! The state of `texture-packer tui`: the fonts, labels and sprites of an
+//! exported atlas as a tree, the page shown next to it and the occupancy of
+//! every page and asset. The terminal front end lives in `crate::tui` behind
+//! the `tui` feature, this part works without a terminal.
+
+use std::collections::HashSet;
+
+use crate::math::{IRect, ISize};
+
+use super::atlas_meta::AtlasMeta;
+
+/// A font, label or named sprite with the packed rects of its glyphs,
+/// languages or frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrowsedAsset {
+    /// `font`, `label` or `sprite`, like the `kind` of the reports.
+    pub kind: &'static str,
+    pub name: String,
+    pub items: Vec<BrowsedItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrowsedItem {
+    /// E.g. `'A' (#65)`, `de` or `frame 2`.
+    pub name: String,
+    /// The layer and rect of every sprite of the item, e.g. the animation
+    /// frames of a glyph.
+    pub rects: Vec<(u32, IRect)>,
+}
+
+/// How much of a page its sprites use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageStats {
+    pub packed_rects: usize,
+    pub used_area: u64,
+    pub occupancy: f64,
+}
+
+/// A line of the tree, see [`LayoutBrowser::rows`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Row {
+    Asset(usize),
+    /// An item of an expanded asset, by asset and item index.
+    Item(usize, usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct LayoutBrowser {
+    pub atlas_name: String,
+    pub page_size: ISize,
+    pub pages: Vec<PageStats>,
+    pub assets: Vec<BrowsedAsset>,
+    /// Every packed rect by layer, duplicates only once.
+    pub page_rects: Vec<Vec<IRect>>,
+    expanded: Vec<bool>,
+    selected: usize,
+    page: usize,
+}
+
+impl LayoutBrowser {
+    pub fn new(meta: &AtlasMeta) -> Self {
+        let rects = |first: u32, count: u32| -> Vec<(u32, IRect)> {
+            (first..first + count)
+                .filter_map(|index| {
+                    let rect = *meta.sprites.get(index as usize)?;
+                    let layer = meta.layers.get(index as usize).copied().unwrap_or(0);
+                    Some((layer, rect))
+                })
+                .collect()
+        };
+
+        let fonts = meta.fonts.iter().map(|font| BrowsedAsset {
+            kind: "font",
+            name: font.name.clone(),
+            items: font
+                .chars
+                .iter()
+                .map(|ch| BrowsedItem {
+                    name: match char::from_u32(ch.char_code).filter(|c| !c.is_control()) {
+                        Some(c) => format!("'{c}' (#{})", ch.char_code),
+                        None => format!("#{}", ch.char_code),
+                    },
+                    rects: rects(ch.first_sprite_index, font.num_animation_frames),
+                })
+                .collect(),
+        });
+
+        let labels = meta.labels.iter().map(|label| BrowsedAsset {
+            kind: "label",
+            name: label.key.clone(),
+            items: label
+                .variants
+                .iter()
+                .map(|variant| BrowsedItem {
+                    name: variant.language.clone(),
+                    rects: rects(variant.sprite_index, 1),
+                })
+                .collect(),
+        });
+
+        let sprites = meta.named_sprites.iter().map(|sprite| BrowsedAsset {
+            kind: "sprite",
+            name: sprite.name.clone(),
+            items: (0..sprite.num_frames)
+                .map(|frame| BrowsedItem {
+                    name: format!("frame {frame}"),
+                    rects: rects(sprite.first_sprite_index + frame, 1),
+                })
+                .collect(),
+        });
+
+        let assets: Vec<BrowsedAsset> = fonts.chain(labels).chain(sprites).collect();
+
+        // Aliased sprites share their rect (and layer)
+        let layers = meta.layer_count.max(1) as usize;
+        let mut page_rects = vec![vec![]; layers];
+        let mut seen = HashSet::new();
+        for (index, &rect) in meta.sprites.iter().enumerate() {
+            let layer = meta.layers.get(index).copied().unwrap_or(0);
+
+            if !rect.is_empty() && (layer as usize) < layers && seen.insert((layer, rect)) {
+                page_rects[layer as usize].push(rect);
+            }
+        }
+
+        let page_area = meta.width as u64 * meta.height as u64;
+        let pages = page_rects
+            .iter()
+            .map(|rects| {
+                let used_area = rects.iter().map(rect_area).sum();
+
+                PageStats {
+                    packed_rects: rects.len(),
+                    used_area,
+                    occupancy: match page_area {
+                        0 => 0.0,
+                        _ => used_area as f64 / page_area as f64,
+                    },
+                }
+            })
+            .collect();
+
+        Self {
+            atlas_name: meta.atlas_name.clone(),
+            page_size: ISize::new(meta.width as i32, meta.height as i32),
+            pages,
+            expanded: vec![false; assets.len()],
+            assets,
+            page_rects,
+            selected: 0,
+            page: 0,
+        }
+    }
+
+    /// The visible lines of the tree, the items of expanded assets below
+    /// them.
+    pub fn rows(&self) -> Vec<Row> {
+        let mut rows = vec![];
+
+        for (asset, expanded) in self.expanded.iter().enumerate() {
+            rows.push(Row::Asset(asset));
+
+            if *expanded {
+                rows.extend((0..self.assets[asset].items.len()).map(|item| Row::Item(asset, item)));
+            }
+        }
+
+        rows
+    }
+
+    /// The index of the selected line of [`Self::rows`].
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_row(&self) -> Option<Row> {
+        self.rows().get(self.selected).copied()
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1).min(self.rows().len().saturating_sub(1));
+        self.show_selection();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+        self.show_selection();
+    }
+
+    /// Expands or collapses the selected asset.
+    pub fn toggle(&mut self) {
+        if let Some(Row::Asset(asset)) = self.selected_row() {
+            self.expanded[asset] = !self.expanded[asset];
+        }
+    }
+
+    /// Collapses the asset the selection is in and selects it.
+    pub fn collapse(&mut self) {
+        let asset = match self.selected_row() {
+            Some(Row::Asset(asset) | Row::Item(asset, _)) => asset,
+            None => return,
+        };
+
+        self.expanded[asset] = false;
+        self.selected = self
+            .rows()
+            .iter()
+            .position(|row| *row == Row::Asset(asset))
+            .unwrap_or(0);
+    }
+
+    /// The page the layout view shows.
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    pub fn next_page(&mut self) {
+        self.page = (self.page + 1) % self.pages.len();
+    }
+
+    pub fn previous_page(&mut self) {
+        self.page = (self.page + self.pages.len() - 1) % self.pages.len();
+    }
+
+    /// The layer and rect of every sprite of the selected asset or item.
+    pub fn highlighted(&self) -> Vec<(u32, IRect)> {
+        match self.selected_row() {
+            Some(Row::Asset(asset)) => self.assets[asset]
+                .items
+                .iter()
+                .flat_map(|item| item.rects.iter().copied())
+                .collect(),
+            Some(Row::Item(asset, item)) => self.assets[asset].items[item].rects.clone(),
+            None => vec![],
+        }
+    }
+
+    /// The pixels the sprites of `asset` cover on every page, shared rects
+    /// only once.
+    pub fn asset_area(&self, asset: usize) -> Vec<u64> {
+        let mut area = vec![0; self.pages.len()];
+        let mut seen = HashSet::new();
+
+        for item in self.assets[asset].items.iter() {
+            for &(layer, rect) in item.rects.iter() {
+                if (layer as usize) < area.len() && seen.insert((layer, rect)) {
+                    area[layer as usize] += rect_area(&rect);
+                }
+            }
+        }
+
+        area
+    }
+
+    /// Turns to the page of the selection if none of it is on this one.
+    fn show_selection(&mut self) {
+        let highlighted = self.highlighted();
+
+        if !highlighted
+            .iter()
+            .any(|&(layer, _)| layer as usize == self.page)
+        {
+            if let Some(&(layer, _)) = highlighted.first() {
+                self.page = (layer as usize).min(self.pages.len() - 1);
+            }
+        }
+    }
+}
+
+fn rect_area(rect: &IRect) -> u64 {
+    rect.uwidth() as u64 * rect.uheight() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LayoutBrowser, Row};
+    use crate::{
+        font_shared::TextCharacterAnimation,
+        math::IRect,
+        outputs::atlas_meta::{
+            AtlasMeta, CharMeta, FontMeta, LabelMeta, LabelVariantMeta, SpriteMeta,
+        },
+    };
+
+    fn char_meta(char_code: u32, first_sprite_index: u32) -> CharMeta {
+        CharMeta {
+            first_sprite_index,
+            char_code,
+            x_offset: 0,
+            y_offset: 0,
+            x_advance: 4,
+            num_frames: None,
+        }
+    }
+
+    #[test]
+    fn test_layout_browser() {
+        let font = FontMeta {
+            name: "small".to_string(),
+            family: "small".to_string(),
+            animation: TextCharacterAnimation::NoAnimation,
+            num_animation_frames: 1,
+            line_height: 4,
+            base_line_y: 3,
+            chars: vec![char_meta(65, 0), char_meta(10, 1)],
+            effect: None,
+            dynamic_slots: vec![],
+            cell_width: None,
+            metrics: Default::default(),
+            decorations: Default::default(),
+            region: None,
+            alpha_only: false,
+            ragged_frames: Default::default(),
+            shelf_stride: None,
+            filtering_hint: Default::default(),
+            distance_field: None,
+        };
+        let label = LabelMeta {
+            key: "label.title".to_string(),
+            font: "small".to_string(),
+            variants: vec![LabelVariantMeta {
+                language: "en".to_string(),
+                sprite_name: "label.title.en".to_string(),
+                sprite_index: 2,
+            }],
+        };
+        let sprite = SpriteMeta::for_test("hero", 3, 1);
+        let meta = AtlasMeta {
+            atlas_name: "ui".to_string(),
+            texture_file: "ui.png".to_string(),
+            layer_count: 2,
+            layers: vec![0, 0, 1, 1],
+            duplicate_of: [(3, 2)].into(),
+            fonts: vec![font],
+            labels: vec![label],
+            named_sprites: vec![sprite],
+            ..AtlasMeta::for_test(
+                16,
+                8,
+                vec![
+                    IRect::new(0, 0, 4, 4),
+                    IRect::new(4, 0, 2, 4),
+                    IRect::new(0, 0, 8, 2),
+                    IRect::new(0, 0, 8, 2),
+                ],
+            )
+        };
+
+        let mut browser = LayoutBrowser::new(&meta);
+
+        assert_eq!(browser.pages.len(), 2);
+        assert_eq!(browser.pages[0].packed_rects, 2);
+        assert_eq!(browser.pages[0].used_area, 24);
+        assert_eq!(browser.pages[0].occupancy, 24.0 / 128.0);
+        // The hero is a duplicate of the label
+        assert_eq!(browser.pages[1].packed_rects, 1);
+        assert_eq!(browser.asset_area(0), [24, 0]);
+
+        assert_eq!(
+            browser.rows(),
+            [Row::Asset(0), Row::Asset(1), Row::Asset(2)]
+        );
+        assert_eq!(browser.assets[0].items[0].name, "'A' (#65)");
+        assert_eq!(browser.assets[0].items[1].name, "#10");
+        assert_eq!(browser.highlighted().len(), 2);
+
+        browser.toggle();
+        assert_eq!(browser.rows().len(), 5);
+        browser.select_next();
+        browser.select_next();
+        assert_eq!(browser.selected_row(), Some(Row::Item(0, 1)));
+        assert_eq!(browser.highlighted(), [(0, IRect::new(4, 0, 2, 4))]);
+
+        browser.collapse();
+        assert_eq!(browser.selected_row(), Some(Row::Asset(0)));
+        assert_eq!(browser.rows().len(), 3);
+
+        // Selecting the label turns to its page
+        browser.select_next();
+        assert_eq!(browser.page(), 1);
+        browser.select_next();
+        browser.select_next();
+        assert_eq!(browser.selected_row(), Some(Row::Asset(2)));
+        browser.next_page();
+        assert_eq!(browser.page(), 0);
+        browser.previous_page();
+        assert_eq!(browser.page(), 1);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
+
+use crate::{error::Ewwow, hashing, sources::Sources};
+
+/// Records the content hash of every input at pack time, so later builds can
+/// verify that nothing changed (see [`Lockfile::check_frozen`]).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Lockfile {
+    pub version: u32,
+    pub inputs: Vec<LockedInput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LockedInput {
+    pub file: String,
+    pub hash: String,
+}
+
+impl Lockfile {
+    const VERSION: u32 = 1;
+
+    pub fn from_sources(srcs: &Sources) -> Self {
+        Self {
+            version: Self::VERSION,
+            inputs: srcs
+                .input_manifest()
+                .into_iter()
+                .map(|(file, hash)| LockedInput {
+                    file: file.to_string(),
+                    hash: hashing::to_hex(hash),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read lockfile '{}'", path.display()))?;
+
+        let lockfile: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse lockfile '{}'", path.display()))?;
+
+        if lockfile.version != Self::VERSION {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Unsupported lockfile version {} in '{}'",
+                    lockfile.version,
+                    path.display()
+                )
+            })?;
+        }
+
+        Ok(lockfile)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let contents =
+            serde_json::to_string_pretty(self).context("Failed to serialize lockfile")?;
+
+        hashing::write_if_changed(path, contents)
+            .with_context(|| format!("Failed to write lockfile '{}'", path.display()))
+    }
+
+    /// The locked content hashes by file name.
+    pub fn hashes(&self) -> anyhow::Result<HashMap<String, u64>> {
+        self.inputs
+            .iter()
+            .map(|input| {
+                let hash = u64::from_str_radix(&input.hash, 16).with_context(|| {
+                    format!(
+                        "Invalid hash '{}' of '{}' in lockfile",
+                        input.hash, input.file
+                    )
+                })?;
+
+                Ok((input.file.clone(), hash))
+            })
+            .collect()
+    }
+
+    /// Fails if the inputs in `srcs` differ in any way from the locked ones.
+    pub fn check_frozen(&self, srcs: &Sources) -> anyhow::Result<()> {
+        let current = Self::from_sources(srcs);
+
+        let mut problems = vec![];
+
+        for locked in self.inputs.iter() {
+            match current
+                .inputs
+                .iter()
+                .find(|input| input.file == locked.file)
+            {
+                Some(input) if input.hash != locked.hash => problems.push(format!(
+                    "'{}' changed (locked {}, found {})",
+                    locked.file, locked.hash, input.hash
+                )),
+                Some(_) => {}
+                None => problems.push(format!("'{}' is no longer an input", locked.file)),
+            }
+        }
+
+        for input in current.inputs.iter() {
+            if !self.inputs.iter().any(|locked| locked.file == input.file) {
+                problems.push(format!("'{}' is not in the lockfile", input.file));
+            }
+        }
+
+        if !problems.is_empty() {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "The inputs don't match the lockfile:\n  {}",
+                    problems.join("\n  ")
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockedInput, Lockfile};
+
+    fn locked(file: &str, hash: &str) -> LockedInput {
+        LockedInput {
+            file: file.to_string(),
+            hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check_frozen() -> anyhow::Result<()> {
+        let srcs = crate::sources::Sources::new();
+
+        let empty = Lockfile::from_sources(&srcs);
+        assert!(empty.inputs.is_empty());
+        empty.check_frozen(&srcs)?;
+
+        let lockfile = Lockfile {
+            inputs: vec![locked("a.fnt", "0000000000000001")],
+            ..empty
+        };
+        let err = lockfile.check_frozen(&srcs).unwrap_err();
+        assert!(format!("{err:#}").contains("'a.fnt' is no longer an input"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_frozen_detects_changes() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("texture-packer-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("en.lang");
+
+        std::fs::write(&path, "label.title = \"Title\"")?;
+        let mut srcs = crate::sources::Sources::new();
+        srcs.try_load_source(&path)?;
+
+        let lockfile = Lockfile::from_sources(&srcs);
+        assert_eq!(lockfile.hashes()?["en.lang"], srcs.input_manifest()[0].1);
+        lockfile.save(dir.join("test.lock"))?;
+        assert_eq!(Lockfile::load(dir.join("test.lock"))?, lockfile);
+        lockfile.check_frozen(&srcs)?;
+
+        std::fs::write(&path, "label.title = \"Other title\"")?;
+        let mut srcs = crate::sources::Sources::new();
+        srcs.try_load_source(&path)?;
+
+        let err = lockfile.check_frozen(&srcs).unwrap_err();
+        assert!(format!("{err:#}").contains("'en.lang' changed"));
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
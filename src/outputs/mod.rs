@@ -1 +1,19 @@
 pub mod atlas_meta;
+pub mod build_info;
+pub mod content_filter;
+pub mod fantasy_console;
+pub mod flipbook;
+pub mod gba;
+pub mod ktx2;
+pub mod layout_browser;
+pub mod lockfile;
+pub mod pack_manifest;
+pub mod phaser;
+pub mod pixel_format;
+pub mod raw_texture;
+pub mod repack;
+pub mod report;
+pub mod source_usage;
+pub mod template;
+pub mod texture_files;
+pub mod thumbnails;
@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::{error::Ewwow, hashing};
+
+/// Every file a run produced, so build systems can declare the outputs
+/// precisely and remove artifacts a previous run left behind (see
+/// [`PackManifest::stale_files`]).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PackManifest {
+    pub version: u32,
+    pub files: Vec<EmittedFile>,
+    /// What the recorded paths are relative to, see [`Self::relative_to`].
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EmittedFile {
+    pub file: String,
+    /// In bytes.
+    pub size: u64,
+    pub hash: String,
+}
+
+impl PackManifest {
+    const VERSION: u32 = 1;
+
+    pub fn new() -> Self {
+        Self {
+            version: Self::VERSION,
+            files: vec![],
+            base_dir: PathBuf::new(),
+        }
+    }
+
+    /// Records the paths of files in `dir` relative to it, e.g. for the
+    /// manifest of an atlas in a project file.
+    pub fn relative_to<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.base_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Hashes the file at `path`, which has just been written. Recording the
+    /// same path again replaces the previous entry.
+    pub fn record<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed to read emitted file '{}'", path.display()))?;
+
+        let file = EmittedFile {
+            file: path
+                .strip_prefix(&self.base_dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/"),
+            size: contents.len() as u64,
+            hash: hashing::to_hex(hashing::hash_bytes(&contents)),
+        };
+
+        match self
+            .files
+            .iter_mut()
+            .find(|existing| existing.file == file.file)
+        {
+            Some(existing) => *existing = file,
+            None => self.files.push(file),
+        }
+
+        Ok(())
+    }
+
+    /// The files `previous` lists that this run didn't produce.
+    pub fn stale_files<'p>(&self, previous: &'p Self) -> Vec<&'p str> {
+        previous
+            .files
+            .iter()
+            .filter(|old| !self.files.iter().any(|file| file.file == old.file))
+            .map(|old| old.file.as_str())
+            .collect()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read pack manifest '{}'", path.display()))?;
+
+        let manifest: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse pack manifest '{}'", path.display()))?;
+
+        if manifest.version != Self::VERSION {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Unsupported pack manifest version {} in '{}'",
+                    manifest.version,
+                    path.display()
+                )
+            })?;
+        }
+
+        Ok(manifest)
+    }
+
+    /// Saves the manifest with the files sorted by path.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let mut sorted = self.clone();
+        sorted.files.sort_by(|a, b| a.file.cmp(&b.file));
+
+        let contents =
+            serde_json::to_string_pretty(&sorted).context("Failed to serialize pack manifest")?;
+
+        hashing::write_if_changed(path, contents)
+            .with_context(|| format!("Failed to write pack manifest '{}'", path.display()))
+    }
+}
+
+impl Default for PackManifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PackManifest;
+    use crate::hashing;
+
+    #[test]
+    fn test_record_and_stale_files() -> anyhow::Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("texture-packer-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let (a, b) = (dir.join("a.png"), dir.join("b.json"));
+        std::fs::write(&a, "old")?;
+        std::fs::write(&b, "{}")?;
+
+        let mut previous = PackManifest::new();
+        previous.record(&a)?;
+        previous.record(&b)?;
+
+        // Recording a file again replaces its entry
+        std::fs::write(&a, "new!")?;
+        let mut manifest = PackManifest::new();
+        manifest.record(&a)?;
+        manifest.record(&a)?;
+
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(manifest.files[0].size, 4);
+        assert_eq!(
+            manifest.files[0].hash,
+            hashing::to_hex(hashing::hash_bytes(b"new!"))
+        );
+        assert_eq!(
+            manifest.stale_files(&previous),
+            vec![previous.files[1].file.as_str()]
+        );
+
+        let path = dir.join("pack-manifest.json");
+        manifest.save(&path)?;
+        assert_eq!(PackManifest::load(&path)?, manifest);
+
+        assert!(manifest.record(dir.join("missing.png")).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::{error::Ewwow, hashing, intermediates::sprite::SpriteSlicing, math::IRect};
+
+use super::atlas_meta::{AtlasMeta, TrimRect};
+
+/// The JSON Phaser 3 loads with `this.load.multiatlas(key, url)`: one entry
+/// in `textures` per page of the atlas, each with the frames packed on it.
+/// A single texture atlas has one entry.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PhaserMultiAtlas {
+    pub textures: Vec<PhaserTexture>,
+    pub meta: PhaserMeta,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PhaserTexture {
+    /// Relative to the JSON file, Phaser prepends the path of the loader.
+    pub image: String,
+    pub format: String,
+    pub size: PhaserSize,
+    pub scale: f32,
+    pub frames: Vec<PhaserFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhaserFrame {
+    /// The sprite name, `<name>_<frame>` for the frames of animations like
+    /// `--unpack` names them, so `generateFrameNames` finds them.
+    pub filename: String,
+    pub rotated: bool,
+    pub trimmed: bool,
+    /// The untrimmed size of the frame.
+    pub source_size: PhaserSize,
+    /// Where the packed pixels are in the untrimmed frame.
+    pub sprite_source_size: PhaserRect,
+    pub frame: PhaserRect,
+    /// The pivot relative to the untrimmed size, (0, 0) is the top left
+    /// corner.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<PhaserPoint>,
+    /// The stretching center of a 9-slice sprite, in pixels of the untrimmed
+    /// frame.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale9_borders: Option<PhaserRect>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PhaserSize {
+    pub w: i32,
+    pub h: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PhaserRect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PhaserPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PhaserMeta {
+    pub app: String,
+    pub version: String,
+}
+
+impl PhaserMultiAtlas {
+    /// The named sprites and label variants of `meta` by the texture they're
+    /// packed on, with the paths of the textures as `meta` has them. Fonts
+    /// aren't frames, Phaser needs them as bitmap fonts.
+    pub fn new(meta: &AtlasMeta) -> anyhow::Result<Self> {
+        let images = match meta.layer_count {
+            0 => vec![meta.texture_file.clone()],
+            _ if meta.layer_files.len() == meta.layer_count as usize => meta.layer_files.clone(),
+            _ => {
+                Ewwow.raise().context(
+                    "Phaser needs a png per page, texture arrays without --texture-array-pngs can't be exported",
+                )?;
+
+                unreachable!()
+            }
+        };
+
+        let mut textures: Vec<PhaserTexture> = images
+            .into_iter()
+            .map(|image| PhaserTexture {
+                image,
+                format: "RGBA8888".to_string(),
+                size: PhaserSize {
+                    w: meta.width as i32,
+                    h: meta.height as i32,
+                },
+                scale: 1.0,
+                frames: vec![],
+            })
+            .collect();
+
+        let mut push_frame = |filename: String, index: u32, slicing| -> anyhow::Result<()> {
+            let rect = *meta.sprites.get(index as usize).with_context(|| {
+                format!("Frame '{filename}' is sprite #{index}, which isn't packed")
+            })?;
+            let layer = meta.layers.get(index as usize).copied().unwrap_or(0);
+            let texture = textures.get_mut(layer as usize).with_context(|| {
+                format!("Frame '{filename}' is on page {layer}, which doesn't exist")
+            })?;
+
+            texture.frames.push(PhaserFrame::new(
+                filename,
+                rect,
+                meta.trims.get(index as usize).copied(),
+                slicing,
+            ));
+            Ok(())
+        };
+
+        for sprite in meta.named_sprites.iter() {
+            for frame in 0..sprite.num_frames {
+                let filename = match sprite.num_frames {
+                    1 => sprite.name.clone(),
+                    _ => format!("{}_{frame}", sprite.name),
+                };
+
+                push_frame(
+                    filename,
+                    sprite.first_sprite_index + frame,
+                    sprite.slicing.as_ref(),
+                )?;
+            }
+        }
+
+        for variant in meta.labels.iter().flat_map(|label| label.variants.iter()) {
+            push_frame(variant.sprite_name.clone(), variant.sprite_index, None)?;
+        }
+
+        Ok(Self {
+            textures,
+            meta: PhaserMeta {
+                app: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let json =
+            serde_json::to_vec_pretty(self).context("Failed to serialize the Phaser atlas")?;
+        hashing::write_if_changed(path, json)
+            .with_context(|| format!("Failed to write '{}'", path.display()))
+    }
+}
+
+impl PhaserFrame {
+    fn new(
+        filename: String,
+        rect: IRect,
+        trim: Option<TrimRect>,
+        slicing: Option<&SpriteSlicing>,
+    ) -> Self {
+        let trim = trim.unwrap_or(TrimRect::untrimmed(rect.size()));
+        let source_size = PhaserSize {
+            w: trim.original_w,
+            h: trim.original_h,
+        };
+
+        Self {
+            filename,
+            rotated: false,
+            trimmed: trim != TrimRect::untrimmed(rect.size()),
+            source_size,
+            sprite_source_size: PhaserRect {
+                x: trim.source_x,
+                y: trim.source_y,
+                w: rect.width(),
+                h: rect.height(),
+            },
+            frame: PhaserRect {
+                x: rect.min.x,
+                y: rect.min.y,
+                w: rect.width(),
+                h: rect.height(),
+            },
+            anchor: slicing.map(|slicing| PhaserPoint {
+                x: slicing.pivot.x,
+                y: slicing.pivot.y,
+            }),
+            scale9_borders: slicing
+                .filter(|slicing| slicing.border != Default::default())
+                .map(|slicing| PhaserRect {
+                    x: slicing.border.left,
+                    y: slicing.border.top,
+                    w: source_size.w - slicing.border.hori(),
+                    h: source_size.h - slicing.border.vert(),
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::{PhaserMultiAtlas, PhaserRect};
+    use crate::{
+        intermediates::sprite::SpriteSlicing,
+        math::{IMargins, IRect},
+        outputs::atlas_meta::{AtlasMeta, LabelMeta, LabelVariantMeta, SpriteMeta, TrimRect},
+    };
+
+    #[test]
+    fn test_phaser_multiatlas() -> anyhow::Result<()> {
+        let mut panel = SpriteMeta::for_test("panel", 2, 1);
+        panel.slicing = Some(SpriteSlicing {
+            pivot: Vec2::new(0.5, 1.0),
+            border: IMargins::new(2, 2, 3, 3),
+        });
+
+        let mut meta = AtlasMeta {
+            atlas_name: "ui".to_string(),
+            texture_file: "ui.png".to_string(),
+            trims: vec![
+                TrimRect {
+                    source_x: 1,
+                    source_y: 2,
+                    original_w: 6,
+                    original_h: 8,
+                },
+                TrimRect::untrimmed(IRect::new(4, 0, 4, 4).size()),
+                TrimRect::untrimmed(IRect::new(0, 0, 8, 8).size()),
+                TrimRect::untrimmed(IRect::new(8, 0, 16, 8).size()),
+            ],
+            layer_count: 2,
+            layers: vec![0, 0, 1, 1],
+            layer_files: vec!["ui-0.png".to_string(), "ui-1.png".to_string()],
+            labels: vec![LabelMeta {
+                key: "label.title".to_string(),
+                font: "small".to_string(),
+                variants: vec![LabelVariantMeta {
+                    language: "en".to_string(),
+                    sprite_name: "label.title.en".to_string(),
+                    sprite_index: 3,
+                }],
+            }],
+            named_sprites: vec![SpriteMeta::for_test("hero", 0, 2), panel],
+            ..AtlasMeta::for_test(
+                32,
+                16,
+                vec![
+                    IRect::new(0, 0, 4, 4),
+                    IRect::new(4, 0, 4, 4),
+                    IRect::new(0, 0, 8, 8),
+                    IRect::new(8, 0, 16, 8),
+                ],
+            )
+        };
+
+        let atlas = PhaserMultiAtlas::new(&meta)?;
+        assert_eq!(atlas.textures.len(), 2);
+        assert_eq!(atlas.textures[1].image, "ui-1.png");
+
+        let names = |texture: usize| -> Vec<&str> {
+            atlas.textures[texture]
+                .frames
+                .iter()
+                .map(|frame| frame.filename.as_str())
+                .collect()
+        };
+        assert_eq!(names(0), ["hero_0", "hero_1"]);
+        assert_eq!(names(1), ["panel", "label.title.en"]);
+
+        let json = serde_json::to_value(&atlas)?;
+        let hero = &json["textures"][0]["frames"][0];
+        assert_eq!(hero["trimmed"], true);
+        assert_eq!(hero["sourceSize"], serde_json::json!({ "w": 6, "h": 8 }));
+        assert_eq!(
+            hero["spriteSourceSize"],
+            serde_json::json!({ "x": 1, "y": 2, "w": 4, "h": 4 })
+        );
+        assert_eq!(json["textures"][0]["frames"][1]["trimmed"], false);
+        assert!(hero.get("anchor").is_none());
+
+        let panel = &atlas.textures[1].frames[0];
+        assert_eq!(panel.anchor.map(|anchor| anchor.y), Some(1.0));
+        assert_eq!(
+            panel.scale9_borders,
+            Some(PhaserRect {
+                x: 3,
+                y: 2,
+                w: 2,
+                h: 4
+            })
+        );
+
+        // A single texture
+        meta.layer_count = 0;
+        meta.layers = vec![];
+        meta.layer_files = vec![];
+        let atlas = PhaserMultiAtlas::new(&meta)?;
+        assert_eq!(atlas.textures.len(), 1);
+        assert_eq!(atlas.textures[0].image, "ui.png");
+        assert_eq!(atlas.textures[0].frames.len(), 4);
+
+        // A texture array in one file
+        meta.layer_count = 2;
+        assert!(PhaserMultiAtlas::new(&meta).is_err());
+
+        Ok(())
+    }
+}
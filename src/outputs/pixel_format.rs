@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use image::{GenericImageView, Rgba, RgbaImage};
+
+use crate::{
+    error::{Error, Ewwow},
+    intermediates::texture_atlas::TextureAtlas,
+};
+
+/// The texel format the atlas is meant to be uploaded as. Reducing to a
+/// smaller format (see [`reduce`]) keeps the image RGBA8, but every channel
+/// only uses as many levels as the format can store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    #[default]
+    Rgba8888,
+    Rgba4444,
+    /// Without alpha, every pixel is opaque.
+    Rgb565,
+    /// Only alpha, every pixel is white.
+    A8,
+    /// Compressed in 4x4 blocks by the engine or a texture tool, so the
+    /// texture is kept as RGBA8 and needs sides that are multiples of 4.
+    Bc7,
+}
+
+impl PixelFormat {
+    /// The bits of red, green, blue and alpha.
+    pub fn channel_bits(self) -> [u32; 4] {
+        match self {
+            PixelFormat::Rgba8888 => [8, 8, 8, 8],
+            PixelFormat::Rgba4444 => [4, 4, 4, 4],
+            PixelFormat::Rgb565 => [5, 6, 5, 0],
+            PixelFormat::A8 => [0, 0, 0, 8],
+            PixelFormat::Bc7 => [8, 8, 8, 8],
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            // 16 bytes per block of 16 pixels
+            PixelFormat::Bc7 => 1,
+            _ => self.channel_bits().iter().sum::<u32>() as usize / 8,
+        }
+    }
+
+    /// The texture sides need to be multiples of this.
+    pub fn block_size(self) -> u32 {
+        match self {
+            PixelFormat::Bc7 => 4,
+            _ => 1,
+        }
+    }
+
+    /// What of `pixels` this format can't store at all, if anything: A8 has
+    /// no colors and RGB565 no transparency. Fewer levels per channel are
+    /// fine, see [`reduce`].
+    pub fn incompatibility<I: GenericImageView<Pixel = Rgba<u8>>>(
+        self,
+        pixels: &I,
+    ) -> Option<&'static str> {
+        match self {
+            PixelFormat::A8 => pixels
+                .pixels()
+                .any(|(_, _, pixel)| pixel[3] > 0 && pixel.0[..3] != [255; 3])
+                .then_some("colors"),
+            PixelFormat::Rgb565 => pixels
+                .pixels()
+                .any(|(_, _, pixel)| pixel[3] < 255)
+                .then_some("transparency"),
+            PixelFormat::Rgba8888 | PixelFormat::Rgba4444 | PixelFormat::Bc7 => None,
+        }
+    }
+}
+
+impl std::str::FromStr for PixelFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgba8888" | "rgba8" => Ok(Self::Rgba8888),
+            "rgba4444" => Ok(Self::Rgba4444),
+            "rgb565" => Ok(Self::Rgb565),
+            "a8" => Ok(Self::A8),
+            "bc7" => Ok(Self::Bc7),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Invalid pixel format '{s}', expected rgba8888, rgba4444, rgb565, a8 or bc7")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PixelFormat::Rgba8888 => write!(f, "RGBA8888"),
+            PixelFormat::Rgba4444 => write!(f, "RGBA4444"),
+            PixelFormat::Rgb565 => write!(f, "RGB565"),
+            PixelFormat::A8 => write!(f, "A8"),
+            PixelFormat::Bc7 => write!(f, "BC7"),
+        }
+    }
+}
+
+/// Checks that `format` can store the packed sprites of `atlas` in `images`
+/// (one per layer) and the size of the images, before they are reduced to
+/// it. Incompatible sprites fail with [`Error::IncompatiblePixelFormat`],
+/// grouped by asset.
+pub fn validate(
+    atlas: &TextureAtlas,
+    images: &[RgbaImage],
+    format: PixelFormat,
+) -> anyhow::Result<()> {
+    let block_size = format.block_size();
+    if let Some(image) = images
+        .iter()
+        .find(|image| image.width() % block_size != 0 || image.height() % block_size != 0)
+    {
+        Error::InvalidOption(format!(
+            "{format} needs sides that are multiples of {block_size}, the atlas is {}x{}",
+            image.width(),
+            image.height()
+        ))
+        .raise()?;
+    }
+
+    // The number of incompatible sprites and what they have of every asset
+    let mut incompatible: BTreeMap<usize, (usize, &str)> = BTreeMap::new();
+
+    for placement in atlas.layout.placements.iter() {
+        // Dynamic slots are only filled at runtime
+        if atlas
+            .fonts
+            .get(placement.asset_id)
+            .is_some_and(|font| placement.sprite_id >= font.chars.len())
+        {
+            continue;
+        }
+
+        let Some(image) = images.get(placement.layer as usize) else {
+            continue;
+        };
+        let Some((width, height)) = placement.bounds.size().checked_u32() else {
+            continue;
+        };
+
+        let view = image.view(
+            placement.bounds.min.x as u32,
+            placement.bounds.min.y as u32,
+            width,
+            height,
+        );
+        if let Some(reason) = format.incompatibility(&*view) {
+            incompatible
+                .entry(placement.asset_id)
+                .or_insert((0, reason))
+                .0 += 1;
+        }
+    }
+
+    if !incompatible.is_empty() {
+        Error::IncompatiblePixelFormat {
+            sprites: incompatible
+                .into_iter()
+                .map(|(asset_id, (count, reason))| {
+                    format!(
+                        "{} has {reason} ({count} sprite(s))",
+                        atlas.get_asset_name(asset_id)
+                    )
+                })
+                .collect(),
+            format: format.to_string(),
+        }
+        .raise()?;
+    }
+
+    Ok(())
+}
+
+/// How [`reduce`] hides the banding of fewer color levels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dithering {
+    /// Round every channel to the closest level.
+    #[default]
+    None,
+    /// A 4x4 Bayer matrix, which keeps flat areas flat and tiles cleanly.
+    Ordered,
+    /// Floyd–Steinberg error diffusion, smoother gradients but noisier.
+    FloydSteinberg,
+}
+
+impl std::str::FromStr for Dithering {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "ordered" => Ok(Self::Ordered),
+            "floyd-steinberg" => Ok(Self::FloydSteinberg),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Invalid dithering '{s}', expected none, ordered or floyd-steinberg")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Reduces every channel of `image` to the levels `format` can store,
+/// expanded back to 8 bits. Channels the format doesn't have are set to 255.
+pub fn reduce(image: &mut RgbaImage, format: PixelFormat, dithering: Dithering) {
+    let bits = format.channel_bits();
+    if bits == [8; 4] {
+        return;
+    }
+
+    let (width, height) = image.dimensions();
+
+    // The quantization error still to be distributed, per channel
+    let mut errors = match dithering {
+        Dithering::FloydSteinberg => vec![[0f32; 4]; (width * height) as usize],
+        Dithering::None | Dithering::Ordered => vec![],
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel_mut(x, y);
+
+            for (channel, &bits) in bits.iter().enumerate() {
+                if bits == 0 {
+                    pixel[channel] = 255;
+                    continue;
+                }
+
+                let step = 255.0 / ((1 << bits) - 1) as f32;
+                let value = pixel[channel] as f32;
+
+                let target = match dithering {
+                    Dithering::None => value,
+                    Dithering::Ordered => {
+                        let threshold = BAYER_4X4[y as usize % 4][x as usize % 4] as f32;
+                        value + ((threshold + 0.5) / 16.0 - 0.5) * step
+                    }
+                    Dithering::FloydSteinberg => value + errors[(y * width + x) as usize][channel],
+                };
+
+                let level = (target / step).round().clamp(0.0, ((1 << bits) - 1) as f32);
+                let reduced = (level * step).round() as u8;
+
+                if dithering == Dithering::FloydSteinberg {
+                    let error = target - reduced as f32;
+
+                    for (dx, dy, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+                        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                        if nx >= 0 && nx < width as i64 && ny < height as i64 {
+                            errors[(ny as u32 * width + nx as u32) as usize][channel] +=
+                                error * weight / 16.0;
+                        }
+                    }
+                }
+
+                pixel[channel] = reduced;
+            }
+        }
+    }
+}
+
+/// The pixels of `image` packed as `format`, row by row. 16 bit formats are
+/// little endian with red in the most significant bits.
+pub fn encode_raw(image: &RgbaImage, format: PixelFormat) -> Vec<u8> {
+    if format == PixelFormat::Rgba8888 {
+        return image.as_raw().clone();
+    }
+
+    let bits = format.channel_bits();
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let mut out = Vec::with_capacity(image.pixels().len() * bytes_per_pixel);
+
+    for pixel in image.pixels() {
+        let mut packed = 0u32;
+
+        for (channel, &bits) in bits.iter().enumerate().filter(|&(_, &bits)| bits > 0) {
+            packed = (packed << bits) | (pixel[channel] >> (8 - bits)) as u32;
+        }
+
+        out.extend_from_slice(&packed.to_le_bytes()[..bytes_per_pixel]);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{encode_raw, reduce, validate, Dithering, PixelFormat};
+    use crate::{
+        cancellation::CancellationToken,
+        error::Error,
+        intermediates::{
+            sprite::{SpriteFrame, SpriteIntermediate},
+            texture_atlas::TextureAtlas,
+        },
+        math::IMargins,
+        sources::Sources,
+    };
+
+    #[test]
+    fn test_reduce() {
+        let gradient = RgbaImage::from_fn(16, 4, |x, _| Rgba([x as u8 * 16, 100, 255, 128]));
+
+        let mut plain = gradient.clone();
+        reduce(&mut plain, PixelFormat::Rgba4444, Dithering::None);
+        assert_eq!(*plain.get_pixel(3, 0), Rgba([51, 102, 255, 136]));
+        assert!(plain.pixels().all(|p| p.0.iter().all(|c| c % 17 == 0)));
+
+        for dithering in [Dithering::Ordered, Dithering::FloydSteinberg] {
+            let mut dithered = gradient.clone();
+            reduce(&mut dithered, PixelFormat::Rgba4444, dithering);
+
+            assert!(dithered.pixels().all(|p| p.0.iter().all(|c| c % 17 == 0)));
+            // Dithering mixes neighbouring levels, but keeps the average
+            assert_ne!(dithered, plain);
+            let sum = |image: &RgbaImage| image.pixels().map(|p| p[1] as i64).sum::<i64>();
+            assert!((sum(&dithered) - sum(&gradient)).abs() < 16 * 4 * 4);
+        }
+
+        let mut opaque = gradient.clone();
+        reduce(&mut opaque, PixelFormat::Rgb565, Dithering::Ordered);
+        assert!(opaque.pixels().all(|p| p[3] == 255));
+
+        let mut alpha = gradient.clone();
+        reduce(&mut alpha, PixelFormat::A8, Dithering::Ordered);
+        assert!(alpha.pixels().all(|p| p.0 == [255, 255, 255, 128]));
+
+        let mut unchanged = gradient.clone();
+        reduce(
+            &mut unchanged,
+            PixelFormat::Rgba8888,
+            Dithering::FloydSteinberg,
+        );
+        assert_eq!(unchanged, gradient);
+    }
+
+    #[test]
+    fn test_encode_raw() {
+        let image = RgbaImage::from_pixel(2, 1, Rgba([0xff, 0x80, 0x10, 0x40]));
+
+        assert_eq!(
+            encode_raw(&image, PixelFormat::Rgba8888),
+            image.as_raw().clone()
+        );
+        assert_eq!(
+            encode_raw(&image, PixelFormat::Rgba4444),
+            [0x14, 0xf8, 0x14, 0xf8]
+        );
+        assert_eq!(encode_raw(&image, PixelFormat::A8), [0x40, 0x40]);
+        assert_eq!(
+            encode_raw(&image, PixelFormat::Rgb565),
+            [0x02, 0xfc, 0x02, 0xfc]
+        );
+    }
+
+    #[test]
+    fn test_validate() -> anyhow::Result<()> {
+        let sprite = |name: &str, color: [u8; 4]| {
+            SpriteIntermediate::from_frames(
+                name.to_string(),
+                vec![SpriteFrame::Baked(RgbaImage::from_pixel(4, 4, Rgba(color)))],
+            )
+        };
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(1));
+        atlas.with_sprite(sprite("glow", [255, 255, 255, 128]));
+        atlas.with_sprite(sprite("gem", [0, 128, 255, 255]));
+        atlas.load_sizes()?;
+        atlas.pack(&CancellationToken::new())?;
+
+        let images =
+            [atlas.build_image(&atlas.layout, &Sources::new(), &CancellationToken::new())?];
+
+        validate(&atlas, &images, PixelFormat::Rgba4444)?;
+        validate(&atlas, &images, PixelFormat::Bc7)?;
+
+        let incompatible =
+            |format| match Error::find(&validate(&atlas, &images, format).unwrap_err()) {
+                Some(Error::IncompatiblePixelFormat { sprites, .. }) => sprites.clone(),
+                other => panic!("Expected an incompatible pixel format, got {other:?}"),
+            };
+        assert_eq!(
+            incompatible(PixelFormat::A8),
+            ["sprite 'gem' has colors (1 sprite(s))"]
+        );
+        assert_eq!(
+            incompatible(PixelFormat::Rgb565),
+            ["sprite 'glow' has transparency (1 sprite(s))"]
+        );
+
+        // 4x4 blocks
+        let odd = [RgbaImage::new(6, 8)];
+        assert!(validate(
+            &TextureAtlas::new(IMargins::uniform(0)),
+            &odd,
+            PixelFormat::Bc7
+        )
+        .is_err());
+        assert_eq!("bc7".parse::<PixelFormat>()?, PixelFormat::Bc7);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Context;
+use image::RgbaImage;
+
+use crate::{
+    error::{Error, Ewwow},
+    hashing,
+};
+
+use super::pixel_format::{self, PixelFormat};
+
+const MAGIC: [u8; 4] = *b"TPRW";
+const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 20;
+
+/// Encodes `layers` as a raw pixel dump in `format` for targets that can't
+/// decode png at runtime, e.g. homebrew consoles.
+///
+/// The data starts with a little endian header of [`HEADER_LEN`] bytes:
+///
+/// | Offset | Size | Contents                                             |
+/// |--------|------|------------------------------------------------------|
+/// | 0      | 4    | `TPRW`                                               |
+/// | 4      | 1    | Version, currently 1                                 |
+/// | 5      | 1    | Format: 0 RGBA8888, 1 RGBA4444, 2 RGB565, 3 A8       |
+/// | 6      | 1    | Bytes per pixel                                      |
+/// | 7      | 1    | Reserved, 0                                          |
+/// | 8      | 4    | Width                                                |
+/// | 12     | 4    | Height                                               |
+/// | 16     | 4    | Number of layers, 1 for a single texture             |
+///
+/// The layers follow one after another without any padding, see
+/// [`pixel_format::encode_raw`].
+pub fn encode(layers: &[RgbaImage], format: PixelFormat) -> anyhow::Result<Vec<u8>> {
+    let Some(first) = layers.first() else {
+        Ewwow
+            .raise()
+            .context("A texture needs at least one layer")?;
+        unreachable!()
+    };
+
+    let (width, height) = first.dimensions();
+    if let Some(index) = layers
+        .iter()
+        .position(|layer| layer.dimensions() != (width, height))
+    {
+        Ewwow.raise().with_context(|| {
+            format!("Layer #{index} isn't {width}x{height} like the first layer")
+        })?;
+    }
+
+    let format_id: u8 = match format {
+        PixelFormat::Rgba8888 => 0,
+        PixelFormat::Rgba4444 => 1,
+        PixelFormat::Rgb565 => 2,
+        PixelFormat::A8 => 3,
+        PixelFormat::Bc7 => {
+            Error::InvalidOption(
+                "BC7 is compressed by the engine or a texture tool, a raw texture needs an uncompressed format"
+                    .to_string(),
+            )
+            .raise()?;
+
+            unreachable!()
+        }
+    };
+
+    let data_len = (width * height) as usize * format.bytes_per_pixel() * layers.len();
+    let mut out = Vec::with_capacity(HEADER_LEN + data_len);
+
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&[VERSION, format_id, format.bytes_per_pixel() as u8, 0]);
+    for value in [width, height, layers.len() as u32] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for layer in layers {
+        out.extend_from_slice(&pixel_format::encode_raw(layer, format));
+    }
+
+    Ok(out)
+}
+
+/// Encodes `layers` and writes them to `path`.
+pub fn write(
+    path: &std::path::Path,
+    layers: &[RgbaImage],
+    format: PixelFormat,
+) -> anyhow::Result<()> {
+    let bytes = encode(layers, format)?;
+
+    hashing::write_if_changed(path, bytes)
+        .with_context(|| format!("Failed to write '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{encode, HEADER_LEN};
+    use crate::outputs::pixel_format::PixelFormat;
+
+    #[test]
+    fn test_encode() -> anyhow::Result<()> {
+        let layers = [
+            RgbaImage::from_pixel(3, 2, Rgba([255, 255, 255, 7])),
+            RgbaImage::from_pixel(3, 2, Rgba([255, 255, 255, 9])),
+        ];
+
+        let bytes = encode(&layers, PixelFormat::A8)?;
+        assert_eq!(bytes[..8], *b"TPRW\x01\x03\x01\x00");
+        assert_eq!(bytes[8..HEADER_LEN], [3, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * 6);
+        assert_eq!(bytes[HEADER_LEN], 7);
+        assert_eq!(bytes[bytes.len() - 1], 9);
+
+        let bytes = encode(&layers[..1], PixelFormat::Rgba4444)?;
+        assert_eq!(bytes.len(), HEADER_LEN + 6 * 2);
+
+        assert!(encode(&[], PixelFormat::A8).is_err());
+        assert!(encode(&layers[..1], PixelFormat::Bc7).is_err());
+        assert!(encode(&[layers[0].clone(), RgbaImage::new(1, 1)], PixelFormat::A8).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use image::{GenericImageView, RgbaImage};
+
+use crate::{
+    blit,
+    error::{Error, Ewwow},
+    math::{IMargins, IRect},
+    outputs::{
+        atlas_meta::AtlasMeta,
+        content_filter::glob_match,
+        pixel_format::{self, Dithering},
+    },
+};
+
+/// The packed sprite index of `name` in `meta`: a single frame sprite by its
+/// name, or a frame of an animated one as `<name>_<frame>`, the file names
+/// `--unpack` writes.
+pub fn find_frame(meta: &AtlasMeta, name: &str) -> Option<u32> {
+    let sprite = |name: &str| meta.named_sprites.iter().find(|sprite| sprite.name == name);
+
+    if let Some(sprite) = sprite(name).filter(|sprite| sprite.num_frames == 1) {
+        return Some(sprite.first_sprite_index);
+    }
+
+    let (name, frame) = name.rsplit_once('_')?;
+    let frame: u32 = frame.parse().ok()?;
+    sprite(name)
+        .filter(|sprite| frame < sprite.num_frames)
+        .map(|sprite| sprite.first_sprite_index + frame)
+}
+
+/// Draws `image` over the packed rect of sprite `index` of an exported atlas,
+/// leaving everything else where it is, and repeats its border `extrude`
+/// pixels outward like `--extrude` did.
+///
+/// `image` needs the size of the packed rect, or for trimmed sprites the
+/// untrimmed size with nothing visible outside of the trimmed rect. Anything
+/// else, like a sprite that grew, needs a full pack.
+pub fn repack_sprite(
+    meta: &AtlasMeta,
+    texture: &mut RgbaImage,
+    index: u32,
+    image: &RgbaImage,
+    extrude: i32,
+) -> anyhow::Result<()> {
+    if meta.layer_count > 0 {
+        Ewwow
+            .raise()
+            .context("Sprites of texture arrays and pages can't be repacked in place")?;
+    }
+
+    let rect = *meta
+        .sprites
+        .get(index as usize)
+        .with_context(|| format!("No sprite #{index} in the atlas"))?;
+
+    let shared = meta.duplicate_of.contains_key(&index)
+        || meta
+            .duplicate_of
+            .values()
+            .any(|&original| original == index);
+    if shared {
+        Ewwow.raise().with_context(|| {
+            format!("Sprite #{index} shares its pixels with a deduplicated sprite, which needs a full pack")
+        })?;
+    }
+
+    let (width, height) = image.dimensions();
+    let size = rect.size();
+
+    // The whole image, or the trimmed part of it
+    let source = match meta.trims.get(index as usize) {
+        _ if (width as i32, height as i32) == (size.width, size.height) => {
+            IRect::new(0, 0, size.width, size.height)
+        }
+        Some(trim) if (width as i32, height as i32) == (trim.original_w, trim.original_h) => {
+            let trimmed = IRect::new(trim.source_x, trim.source_y, size.width, size.height);
+            let visible_outside = image.enumerate_pixels().any(|(x, y, pixel)| {
+                pixel[3] > 0 && !trimmed.contains(IRect::new(x as i32, y as i32, 1, 1))
+            });
+
+            if visible_outside {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "Sprite #{index} doesn't fit its trimmed {}x{} rect anymore, which needs a full pack",
+                        size.width, size.height
+                    )
+                })?;
+            }
+
+            trimmed
+        }
+        _ => {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Sprite #{index} is {width}x{height} but was packed as {}x{}, resizing it needs a full pack",
+                    size.width, size.height
+                )
+            })?;
+
+            unreachable!()
+        }
+    };
+
+    let mut pixels = image
+        .view(
+            source.min.x as u32,
+            source.min.y as u32,
+            source.uwidth(),
+            source.uheight(),
+        )
+        .to_image();
+
+    if let Some(what) = meta.pixel_format.incompatibility(&pixels) {
+        Error::IncompatiblePixelFormat {
+            sprites: vec![format!("#{index} ({what})")],
+            format: meta.pixel_format.to_string(),
+        }
+        .raise()?;
+    }
+    pixel_format::reduce(&mut pixels, meta.pixel_format, Dithering::default());
+
+    blit::blit(
+        texture,
+        &pixels.view(0, 0, pixels.width(), pixels.height()),
+        rect.min.x as u32,
+        rect.min.y as u32,
+    )?;
+    blit::extrude(texture, rect, IMargins::uniform(extrude));
+
+    Ok(())
+}
+
+/// The files matching `pattern`, sorted, where only the file name may
+/// contain `*` and `?`, e.g. `sprites/hero/*.png`.
+pub fn expand_glob(pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let pattern = Path::new(pattern);
+    let dir = match pattern.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_pattern = pattern
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Invalid pattern '{}'", pattern.display()))?;
+
+    let mut files = vec![];
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Failed to list '{}'", dir.display()))?
+    {
+        let path = entry?.path();
+        let matches = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| glob_match(file_pattern, name));
+
+        if matches && path.is_file() {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{find_frame, repack_sprite};
+    use crate::{
+        math::IRect,
+        outputs::atlas_meta::{AtlasMeta, SpriteMeta, TrimRect},
+    };
+
+    #[test]
+    fn test_repack_sprite() -> anyhow::Result<()> {
+        let mut meta = AtlasMeta {
+            named_sprites: vec![
+                SpriteMeta::for_test("coin", 0, 1),
+                SpriteMeta::for_test("hero", 1, 2),
+            ],
+            ..AtlasMeta::for_test(
+                8,
+                4,
+                vec![
+                    IRect::new(0, 0, 2, 2),
+                    IRect::new(4, 0, 2, 2),
+                    IRect::new(4, 2, 2, 2),
+                ],
+            )
+        };
+
+        assert_eq!(find_frame(&meta, "coin"), Some(0));
+        assert_eq!(find_frame(&meta, "hero_1"), Some(2));
+        assert_eq!(find_frame(&meta, "hero"), None);
+        assert_eq!(find_frame(&meta, "hero_2"), None);
+        assert_eq!(find_frame(&meta, "coin_0"), Some(0));
+
+        let red = Rgba([255, 0, 0, 255]);
+        let mut texture = RgbaImage::new(8, 4);
+        repack_sprite(&meta, &mut texture, 2, &RgbaImage::from_pixel(2, 2, red), 1)?;
+
+        // Extruded into the column left of it, clamped to the texture below
+        assert_eq!(texture[(4, 2)], red);
+        assert_eq!(texture[(3, 3)], red);
+        assert_eq!(texture[(3, 1)], red);
+        assert_eq!(texture[(2, 2)], Rgba([0; 4]));
+        assert_eq!(texture[(6, 3)], red);
+        assert_eq!(texture[(7, 3)], Rgba([0; 4]));
+
+        assert!(repack_sprite(&meta, &mut texture, 0, &RgbaImage::new(3, 2), 0).is_err());
+
+        // The untrimmed frame works as long as it stays inside the trimmed rect
+        meta.trims = vec![
+            TrimRect {
+                source_x: 1,
+                source_y: 0,
+                original_w: 4,
+                original_h: 2,
+            },
+            TrimRect::default(),
+            TrimRect::default(),
+        ];
+        let mut frame = RgbaImage::new(4, 2);
+        frame[(2, 1)] = red;
+        repack_sprite(&meta, &mut texture, 0, &frame, 0)?;
+        assert_eq!(texture[(1, 1)], red);
+
+        frame[(3, 1)] = red;
+        assert!(repack_sprite(&meta, &mut texture, 0, &frame, 0).is_err());
+
+        meta.duplicate_of.insert(1, 0);
+        assert!(repack_sprite(&meta, &mut texture, 0, &RgbaImage::new(2, 2), 0).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+//! Human and machine readable summaries of exported atlases, for the
+//! `inspect`, `stats` and `diff` commands.
+//!
+//! With `--json` every report is printed as a single JSON object:
+//!
+//! ```json
+//! { "schema_version": 1, "report": "inspect" | "stats" | "diff", ... }
+//! ```
+//!
+//! followed by the fields of [`InspectReport`], [`StatsReport`] or
+//! [`DiffReport`]. Fonts, labels and sprites are [`Entry`] objects tagged
+//! with `"kind": "font" | "label" | "sprite"`. Fields may be added without
+//! bumping [`SCHEMA_VERSION`], it only changes if one is removed, renamed or
+//! changes its meaning.
+
+use std::{collections::HashSet, fmt};
+
+use anyhow::Context;
+
+use super::{atlas_meta::AtlasMeta, pixel_format::PixelFormat};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Entry {
+    Font {
+        name: String,
+        family: String,
+        glyphs: usize,
+        animation_frames: u32,
+        line_height: u32,
+    },
+    Label {
+        key: String,
+        font: String,
+        languages: Vec<String>,
+    },
+    /// `width` and `height` are the size of the first frame.
+    Sprite {
+        name: String,
+        frames: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Entry {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Entry::Font { .. } => "font",
+            Entry::Label { .. } => "label",
+            Entry::Sprite { .. } => "sprite",
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Entry::Font { name, .. } | Entry::Sprite { name, .. } => name,
+            Entry::Label { key, .. } => key,
+        }
+    }
+
+    /// Every font, label and named sprite of `meta`, in that order.
+    pub fn collect(meta: &AtlasMeta) -> Vec<Entry> {
+        let fonts = meta.fonts.iter().map(|font| Entry::Font {
+            name: font.name.clone(),
+            family: font.family.clone(),
+            glyphs: font.chars.len(),
+            animation_frames: font.num_animation_frames,
+            line_height: font.line_height,
+        });
+
+        let labels = meta.labels.iter().map(|label| Entry::Label {
+            key: label.key.clone(),
+            font: label.font.clone(),
+            languages: label
+                .variants
+                .iter()
+                .map(|variant| variant.language.clone())
+                .collect(),
+        });
+
+        let sprites = meta.named_sprites.iter().map(|sprite| {
+            let rect = meta
+                .sprites
+                .get(sprite.first_sprite_index as usize)
+                .copied()
+                .unwrap_or_default();
+
+            Entry::Sprite {
+                name: sprite.name.clone(),
+                frames: sprite.num_frames,
+                width: rect.uwidth(),
+                height: rect.uheight(),
+            }
+        });
+
+        fonts.chain(labels).chain(sprites).collect()
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Font {
+                name,
+                family,
+                glyphs,
+                animation_frames,
+                line_height,
+            } => write!(
+                f,
+                "font '{name}' ({family}): {glyphs} glyph(s), {animation_frames} frame(s), line height {line_height}"
+            ),
+            Entry::Label {
+                key,
+                font,
+                languages,
+            } => write!(f, "label '{key}' in '{font}': {}", languages.join(", ")),
+            Entry::Sprite {
+                name,
+                frames,
+                width,
+                height,
+            } => write!(f, "sprite '{name}': {frames} frame(s) of {width}x{height}"),
+        }
+    }
+}
+
+/// The versioned envelope every report is printed in with `--json`.
+#[derive(serde::Serialize)]
+struct Versioned<'a, T> {
+    schema_version: u32,
+    report: &'static str,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+fn to_json<T: serde::Serialize>(report: &'static str, data: &T) -> anyhow::Result<String> {
+    serde_json::to_string_pretty(&Versioned {
+        schema_version: SCHEMA_VERSION,
+        report,
+        data,
+    })
+    .with_context(|| format!("Failed to serialize {report} report"))
+}
+
+/// What an atlas contains.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct InspectReport {
+    pub atlas_name: String,
+    pub texture_file: String,
+    pub width: u32,
+    pub height: u32,
+    /// 0 if the atlas isn't a texture array.
+    pub layer_count: u32,
+    pub pixel_format: PixelFormat,
+    pub entries: Vec<Entry>,
+}
+
+impl InspectReport {
+    pub fn new(meta: &AtlasMeta) -> Self {
+        Self {
+            atlas_name: meta.atlas_name.clone(),
+            texture_file: meta.texture_file.clone(),
+            width: meta.width,
+            height: meta.height,
+            layer_count: meta.layer_count,
+            pixel_format: meta.pixel_format,
+            entries: Entry::collect(meta),
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        to_json("inspect", self)
+    }
+}
+
+impl fmt::Display for InspectReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "atlas '{}' ('{}'): {}x{}",
+            self.atlas_name, self.texture_file, self.width, self.height
+        )?;
+        if self.layer_count > 0 {
+            write!(f, " x {} layers", self.layer_count)?;
+        }
+
+        for entry in self.entries.iter() {
+            write!(f, "\n  {entry}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How well an atlas is used.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct StatsReport {
+    pub width: u32,
+    pub height: u32,
+    /// 1 if the atlas isn't a texture array.
+    pub layers: u32,
+    /// The number of non-empty sprite rects, aliased sprites count once.
+    pub packed_rects: usize,
+    /// In pixels, over all layers.
+    pub used_area: u64,
+    pub total_area: u64,
+    /// `used_area / total_area`, from 0 to 1.
+    pub occupancy: f64,
+    pub fonts: usize,
+    pub glyphs: usize,
+    pub labels: usize,
+    pub named_sprites: usize,
+}
+
+impl StatsReport {
+    pub fn new(meta: &AtlasMeta) -> Self {
+        let layers = meta.layer_count.max(1);
+
+        // Aliased sprites share their rect (and layer)
+        let packed: HashSet<_> = meta
+            .sprites
+            .iter()
+            .enumerate()
+            .filter(|(_, rect)| !rect.is_empty())
+            .map(|(index, rect)| (*rect, meta.layers.get(index).copied().unwrap_or(0)))
+            .collect();
+
+        let used_area = packed
+            .iter()
+            .map(|(rect, _)| rect.uwidth() as u64 * rect.uheight() as u64)
+            .sum();
+        let total_area = meta.width as u64 * meta.height as u64 * layers as u64;
+
+        Self {
+            width: meta.width,
+            height: meta.height,
+            layers,
+            packed_rects: packed.len(),
+            used_area,
+            total_area,
+            occupancy: match total_area {
+                0 => 0.0,
+                _ => used_area as f64 / total_area as f64,
+            },
+            fonts: meta.fonts.len(),
+            glyphs: meta.fonts.iter().map(|font| font.chars.len()).sum(),
+            labels: meta.labels.len(),
+            named_sprites: meta.named_sprites.len(),
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        to_json("stats", self)
+    }
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}x{} x {} layer(s), {} packed rect(s)",
+            self.width, self.height, self.layers, self.packed_rects
+        )?;
+        writeln!(
+            f,
+            "{} of {} pixels used ({:.1}%)",
+            self.used_area,
+            self.total_area,
+            self.occupancy * 100.0
+        )?;
+        write!(
+            f,
+            "{} font(s) with {} glyph(s), {} label(s), {} named sprite(s)",
+            self.fonts, self.glyphs, self.labels, self.named_sprites
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ChangedEntry {
+    pub before: Entry,
+    pub after: Entry,
+}
+
+/// What changed between two exports of an atlas. Entries are matched by kind
+/// and name.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct DiffReport {
+    /// Width and height.
+    pub size_before: [u32; 2],
+    pub size_after: [u32; 2],
+    pub added: Vec<Entry>,
+    pub removed: Vec<Entry>,
+    pub changed: Vec<ChangedEntry>,
+}
+
+impl DiffReport {
+    pub fn new(before: &AtlasMeta, after: &AtlasMeta) -> Self {
+        let (old, new) = (Entry::collect(before), Entry::collect(after));
+        let find = |entries: &[Entry], entry: &Entry| {
+            entries
+                .iter()
+                .find(|other| other.kind() == entry.kind() && other.name() == entry.name())
+                .cloned()
+        };
+
+        let added = new
+            .iter()
+            .filter(|entry| find(&old, entry).is_none())
+            .cloned()
+            .collect();
+
+        let mut removed = vec![];
+        let mut changed = vec![];
+        for entry in old.iter() {
+            match find(&new, entry) {
+                None => removed.push(entry.clone()),
+                Some(after) if after != *entry => changed.push(ChangedEntry {
+                    before: entry.clone(),
+                    after,
+                }),
+                Some(_) => {}
+            }
+        }
+
+        Self {
+            size_before: [before.width, before.height],
+            size_after: [after.width, after.height],
+            added,
+            removed,
+            changed,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size_before == self.size_after
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        to_json("diff", self)
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no changes");
+        }
+
+        let [width_before, height_before] = self.size_before;
+        let [width_after, height_after] = self.size_after;
+        write!(
+            f,
+            "{width_before}x{height_before} -> {width_after}x{height_after}"
+        )?;
+
+        for entry in self.added.iter() {
+            write!(f, "\n+ {entry}")?;
+        }
+        for entry in self.removed.iter() {
+            write!(f, "\n- {entry}")?;
+        }
+        for ChangedEntry { before, after } in self.changed.iter() {
+            write!(f, "\n~ {before}\n  -> {after}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiffReport, Entry, InspectReport, StatsReport};
+    use crate::{
+        math::IRect,
+        outputs::atlas_meta::{AtlasMeta, SpriteMeta},
+    };
+
+    fn test_meta(sprites: Vec<IRect>, named_sprites: Vec<SpriteMeta>) -> AtlasMeta {
+        AtlasMeta {
+            named_sprites,
+            ..AtlasMeta::for_test(16, 16, sprites)
+        }
+    }
+
+    #[test]
+    fn test_reports() -> anyhow::Result<()> {
+        let before = test_meta(
+            vec![
+                IRect::new(0, 0, 4, 4),
+                IRect::new(4, 0, 4, 4),
+                IRect::new(4, 0, 4, 4),
+            ],
+            vec![
+                SpriteMeta::for_test("a", 0, 1),
+                SpriteMeta::for_test("b", 1, 2),
+            ],
+        );
+
+        let stats = StatsReport::new(&before);
+        // The aliased frames of `b` only count once
+        assert_eq!((stats.packed_rects, stats.used_area), (2, 32));
+        assert_eq!(stats.occupancy, 0.125);
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()?)?;
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["report"], "stats");
+        assert_eq!(json["used_area"], 32);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&InspectReport::new(&before).to_json()?)?;
+        assert_eq!(json["report"], "inspect");
+        assert_eq!(json["entries"][1]["kind"], "sprite");
+        assert_eq!(json["entries"][1]["frames"], 2);
+
+        let mut after = test_meta(
+            vec![IRect::new(0, 0, 8, 4), IRect::new(0, 4, 2, 2)],
+            vec![
+                SpriteMeta::for_test("a", 0, 1),
+                SpriteMeta::for_test("c", 1, 1),
+            ],
+        );
+        after.width = 32;
+
+        let diff = DiffReport::new(&before, &after);
+        assert_eq!(diff.size_after, [32, 16]);
+        assert_eq!(
+            diff.added.iter().map(Entry::name).collect::<Vec<_>>(),
+            ["c"]
+        );
+        assert_eq!(
+            diff.removed.iter().map(Entry::name).collect::<Vec<_>>(),
+            ["b"]
+        );
+        assert_eq!(diff.changed.len(), 1);
+        assert!(diff.to_string().contains("sprite 'a': 1 frame(s) of 8x4"));
+
+        assert!(DiffReport::new(&before, &before).is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::{fmt, path::PathBuf};
+
+use crate::{
+    intermediates::{sprite::SpriteFrame, texture_atlas::TextureAtlas},
+    sources::{SourceId, SourceSprite, Sources},
+};
+
+/// How much of every source image ends up in the atlas, for `--source-usage`.
+/// Pixels no glyph or sprite frame references are dead weight in the source
+/// file, e.g. glyphs dropped with `--keep-chars` or a sheet with a lot of
+/// empty space.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SourceUsageReport {
+    /// In the order the images were loaded.
+    pub images: Vec<ImageUsage>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ImageUsage {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// Pixels covered by at least one glyph or frame.
+    pub used_area: u64,
+    /// Unused pixels that aren't fully transparent, i.e. art nothing refers
+    /// to anymore.
+    pub unused_opaque_area: u64,
+    /// Unused fully transparent pixels.
+    pub unused_empty_area: u64,
+}
+
+impl ImageUsage {
+    pub fn unused_area(&self) -> u64 {
+        self.unused_opaque_area + self.unused_empty_area
+    }
+}
+
+impl SourceUsageReport {
+    /// Looks at the glyphs and source frames left in `atlas`, so it should be
+    /// called after filtering and pruning. Baked glyphs still count as using
+    /// the pixels they were generated from.
+    pub fn new(atlas: &TextureAtlas, sources: &Sources) -> Self {
+        let mut coverage: Vec<Vec<bool>> = sources
+            .images
+            .iter()
+            .map(|(_, image)| vec![false; image.width() as usize * image.height() as usize])
+            .collect();
+
+        let glyphs = atlas
+            .fonts
+            .iter()
+            .flat_map(|font| font.chars.iter())
+            .map(|char| &char.sprite);
+
+        let frames = atlas
+            .sprites
+            .iter()
+            .flat_map(|sprite| sprite.frames.iter())
+            .filter_map(|frame| match frame {
+                SpriteFrame::Source(source) => Some(source),
+                SpriteFrame::Baked(_) => None,
+            });
+
+        for source in glyphs.chain(frames) {
+            let SourceSprite {
+                image_source_id: SourceId::Image(index),
+                x,
+                y,
+                width,
+                height,
+            } = *source
+            else {
+                continue;
+            };
+
+            let image = &sources.images[index].1;
+            let (image_width, image_height) = (image.width() as i32, image.height() as i32);
+
+            for py in y.max(0)..(y + height).min(image_height) {
+                for px in x.max(0)..(x + width).min(image_width) {
+                    coverage[index][(py * image_width + px) as usize] = true;
+                }
+            }
+        }
+
+        let images = sources
+            .images
+            .iter()
+            .zip(coverage)
+            .map(|((path, image), covered)| {
+                let mut usage = ImageUsage {
+                    path: path.clone(),
+                    width: image.width(),
+                    height: image.height(),
+                    used_area: 0,
+                    unused_opaque_area: 0,
+                    unused_empty_area: 0,
+                };
+
+                for (pixel, covered) in image.pixels().zip(covered) {
+                    match (covered, pixel[3]) {
+                        (true, _) => usage.used_area += 1,
+                        (false, 0) => usage.unused_empty_area += 1,
+                        (false, _) => usage.unused_opaque_area += 1,
+                    }
+                }
+
+                usage
+            })
+            .collect();
+
+        Self { images }
+    }
+}
+
+impl fmt::Display for SourceUsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unused = || self.images.iter().filter(|image| image.unused_area() > 0);
+
+        write!(
+            f,
+            "{} source image(s) fully used",
+            self.images.len() - unused().count()
+        )?;
+
+        for image in unused() {
+            let total_area = image.width as u64 * image.height as u64;
+            write!(
+                f,
+                "\n'{}' ({}x{}): {} of {} px unused ({:.1}%), {} px of it not empty",
+                image.path.display(),
+                image.width,
+                image.height,
+                image.unused_area(),
+                total_area,
+                image.unused_area() as f64 / total_area as f64 * 100.0,
+                image.unused_opaque_area,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::SourceUsageReport;
+    use crate::{
+        intermediates::{
+            sprite::{SpriteFrame, SpriteIntermediate},
+            texture_atlas::TextureAtlas,
+        },
+        math::IMargins,
+        sources::{SourceId, SourceSprite, Sources},
+    };
+
+    #[test]
+    fn test_source_usage() {
+        let mut sources = Sources::new();
+
+        // Left half opaque, right half transparent
+        let sheet = RgbaImage::from_fn(8, 4, |x, _| match x < 4 {
+            true => Rgba([255, 255, 255, 255]),
+            false => Rgba([0, 0, 0, 0]),
+        });
+        sources.images.push(("sheet.png".into(), sheet));
+        sources
+            .images
+            .push(("unused.png".into(), RgbaImage::new(2, 2)));
+
+        let mut atlas = TextureAtlas::new(IMargins::uniform(0));
+        atlas.with_sprite(SpriteIntermediate::from_frames(
+            "hero".to_string(),
+            vec![SpriteFrame::Source(SourceSprite {
+                image_source_id: SourceId::Image(0),
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 4,
+            })],
+        ));
+
+        let report = SourceUsageReport::new(&atlas, &sources);
+
+        assert_eq!(report.images.len(), 2);
+        assert_eq!(report.images[0].used_area, 8);
+        assert_eq!(report.images[0].unused_opaque_area, 8);
+        assert_eq!(report.images[0].unused_empty_area, 16);
+        assert_eq!(report.images[1].used_area, 0);
+        assert_eq!(report.images[1].unused_empty_area, 4);
+    }
+}
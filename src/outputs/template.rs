@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::error::Ewwow;
+
+use super::atlas_meta::AtlasMeta;
+
+/// Renders a user provided Handlebars template with `meta` as its data, e.g.
+/// `{{#each named_sprites}}{{name}} {{first_sprite_index}}{{/each}}`, for
+/// engine formats the packer doesn't support itself. Nothing is HTML escaped,
+/// rects have the fields of [`AtlasMeta::rect_layout`].
+#[cfg(feature = "templates")]
+pub fn render_template(template: &str, meta: &AtlasMeta) -> anyhow::Result<String> {
+    let mut handlebars = handlebars::Handlebars::new();
+    handlebars.set_strict_mode(true);
+    handlebars.register_escape_fn(handlebars::no_escape);
+
+    crate::math::with_rect_layout(meta.rect_layout, || {
+        handlebars.render_template(template, meta)
+    })
+    .context("Failed to render template")
+}
+
+#[cfg(not(feature = "templates"))]
+pub fn render_template(_template: &str, _meta: &AtlasMeta) -> anyhow::Result<String> {
+    Ewwow
+        .raise()
+        .context("Rendering templates requires the `templates` feature")?;
+
+    unreachable!()
+}
+
+/// Where the output of the template at `template_path` goes: next to it,
+/// without the `.hbs` extension, e.g. `atlas.xml.hbs` renders to `atlas.xml`.
+pub fn output_path(template_path: &Path) -> anyhow::Result<PathBuf> {
+    if template_path.extension().is_none_or(|ext| ext != "hbs") {
+        Ewwow.raise().with_context(|| {
+            format!(
+                "Template '{}' needs a `.hbs` extension, e.g. `atlas.xml.hbs`",
+                template_path.display()
+            )
+        })?;
+    }
+
+    Ok(template_path.with_extension(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::output_path;
+
+    #[test]
+    fn test_output_path() -> anyhow::Result<()> {
+        assert_eq!(
+            output_path(Path::new("templates/atlas.xml.hbs"))?,
+            Path::new("templates/atlas.xml")
+        );
+        assert!(output_path(Path::new("atlas.xml")).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "templates")]
+    #[test]
+    fn test_render_template() -> anyhow::Result<()> {
+        use super::render_template;
+        use crate::{
+            math::{IRect, RectLayout},
+            outputs::atlas_meta::AtlasMeta,
+        };
+
+        let mut meta = AtlasMeta {
+            atlas_name: "ui".to_string(),
+            texture_file: "ui.png".to_string(),
+            ..AtlasMeta::for_test(64, 32, vec![IRect::new(1, 2, 3, 4)])
+        };
+
+        let rendered = render_template(
+            "<atlas file=\"{{texture_file}}\" size=\"{{width}}x{{height}}\">\
+             {{#each sprites}}<rect x=\"{{x}}\" w=\"{{w}}\"/>{{/each}}</atlas>",
+            &meta,
+        )?;
+        assert_eq!(
+            rendered,
+            "<atlas file=\"ui.png\" size=\"64x32\"><rect x=\"1\" w=\"3\"/></atlas>"
+        );
+
+        // Templates written for --legacy-rects see the rects as min and max
+        meta.rect_layout = RectLayout::MinMax;
+        let legacy = "{{#each sprites}}{{min.[0]}}..{{max.[0]}}{{/each}}";
+        assert_eq!(render_template(legacy, &meta)?, "1..4");
+        assert!(render_template("{{#each sprites}}{{w}}{{/each}}", &meta).is_err());
+
+        // Typos in field names are errors instead of empty output
+        assert!(render_template("{{texture_fiel}}", &meta).is_err());
+
+        Ok(())
+    }
+}
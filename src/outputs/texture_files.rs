@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use anyhow::Context;
+use image::RgbaImage;
+
+use crate::{
+    cancellation::CancellationToken,
+    image_io::{self, PngOptions},
+    intermediates::texture_atlas::TextureAtlas,
+    sources::Sources,
+};
+
+use super::{
+    ktx2,
+    pack_manifest::PackManifest,
+    pixel_format::{self, Dithering, PixelFormat},
+    raw_texture,
+};
+
+/// Where and how [`write_textures`] writes the atlas texture.
+#[derive(Clone, Copy)]
+pub struct TextureOutput<'a> {
+    /// The atlas png, also the name of the other texture files.
+    pub texture_path: &'a Path,
+    pub texture_array: bool,
+    /// `<name>-<layer>.png` for texture arrays written as pngs, empty
+    /// otherwise.
+    pub layer_files: &'a [String],
+    pub png_options: &'a PngOptions,
+    pub pixel_format: PixelFormat,
+    pub dithering: Dithering,
+    /// Also write the pixels in `pixel_format` to `<name>.bin`, see
+    /// [`raw_texture::encode`].
+    pub raw_texture: bool,
+}
+
+/// Builds the atlas image, or one per layer of a texture array, reduced to
+/// the pixel format of `output` after checking it can store them, see
+/// [`pixel_format::validate`].
+pub fn build_textures(
+    atlas: &TextureAtlas,
+    sources: &Sources,
+    output: &TextureOutput,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Vec<RgbaImage>> {
+    let mut images = match atlas.layout.layer_count {
+        0 => vec![atlas.build_image(&atlas.layout, sources, cancel)?],
+        layer_count => (0..layer_count)
+            .map(|layer| atlas.build_image(&atlas.layout.layer(layer), sources, cancel))
+            .collect::<anyhow::Result<_>>()?,
+    };
+
+    pixel_format::validate(atlas, &images, output.pixel_format)?;
+
+    for image in images.iter_mut() {
+        pixel_format::reduce(image, output.pixel_format, output.dithering);
+    }
+
+    Ok(images)
+}
+
+/// Writes the atlas image, or the layers of a texture array, records them in
+/// `manifest` and returns the path of the file the meta should reference.
+pub fn write_textures(
+    atlas_images: &[RgbaImage],
+    output: &TextureOutput,
+    cancel: &CancellationToken,
+    manifest: &mut PackManifest,
+) -> anyhow::Result<String> {
+    let TextureOutput {
+        texture_path,
+        texture_array,
+        layer_files,
+        png_options,
+        ..
+    } = *output;
+
+    if output.raw_texture {
+        let raw_path = texture_path.with_extension("bin");
+        raw_texture::write(&raw_path, atlas_images, output.pixel_format)
+            .context("Failed to save raw atlas texture")?;
+        manifest.record(&raw_path)?;
+    }
+
+    let texture_file = match (texture_array, layer_files.first()) {
+        (false, _) => {
+            image_io::write_png(texture_path, &atlas_images[0], png_options, cancel)
+                .context("Failed to save atlas image")?;
+            manifest.record(texture_path)?;
+            texture_path.display().to_string()
+        }
+        (true, None) => {
+            let array_path = texture_path.with_extension("ktx2");
+            ktx2::write_array(&array_path, atlas_images).context("Failed to save texture array")?;
+            manifest.record(&array_path)?;
+            array_path.display().to_string()
+        }
+        (true, Some(first_file)) => {
+            for (file, image) in layer_files.iter().zip(atlas_images.iter()) {
+                image_io::write_png(Path::new(file), image, png_options, cancel)
+                    .with_context(|| format!("Failed to save layer '{file}'"))?;
+                manifest.record(file)?;
+            }
+            first_file.clone()
+        }
+    };
+
+    Ok(texture_file)
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use anyhow::Context;
+use base64::Engine;
+use image::{imageops, RgbaImage};
+
+use crate::{
+    hashing,
+    image_io::{self, PngOptions},
+};
+
+use super::atlas_meta::AtlasMeta;
+
+/// The longest side of a thumbnail if not configured otherwise.
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 16;
+
+/// A tiny preview of the first frame of a named sprite.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Thumbnail {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// The base64 encoded png file.
+    pub png: String,
+}
+
+/// Previews of the named sprites of an atlas, written next to it as
+/// `atlas.thumbs.json` so editor asset pickers don't need to decode the
+/// whole atlas texture.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Thumbnails {
+    pub atlas_name: String,
+    /// The longest side of every thumbnail. Smaller sprites aren't scaled
+    /// up.
+    pub size: u32,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+impl Thumbnails {
+    /// Scales the first frame of every named sprite of `meta` down to fit
+    /// into `size` x `size`, keeping its aspect ratio. Empty sprites are left
+    /// out.
+    pub fn new(meta: &AtlasMeta, atlas_image: &RgbaImage, size: u32) -> anyhow::Result<Self> {
+        let mut thumbnails = vec![];
+
+        for sprite in meta.named_sprites.iter() {
+            let Some(rect) = meta
+                .sprites
+                .get(sprite.first_sprite_index as usize)
+                .filter(|rect| !rect.is_empty())
+            else {
+                continue;
+            };
+
+            let (x, y) = rect.checked_umin().with_context(|| {
+                format!("Sprite '{}' is outside of the atlas texture", sprite.name)
+            })?;
+            let frame = imageops::crop_imm(atlas_image, x, y, rect.uwidth(), rect.uheight());
+
+            let (width, height) = thumbnail_size(rect.uwidth(), rect.uheight(), size);
+            let image = match (width, height) == (rect.uwidth(), rect.uheight()) {
+                true => frame.to_image(),
+                false => imageops::thumbnail(&*frame, width, height),
+            };
+
+            let png = image_io::encode_png(&image, &PngOptions::default())
+                .with_context(|| format!("Failed to encode the thumbnail of '{}'", sprite.name))?;
+
+            thumbnails.push(Thumbnail {
+                name: sprite.name.clone(),
+                width,
+                height,
+                png: base64::engine::general_purpose::STANDARD.encode(png),
+            });
+        }
+
+        Ok(Self {
+            atlas_name: meta.atlas_name.clone(),
+            size,
+            thumbnails,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize thumbnails")?;
+        hashing::write_if_changed(path, json)
+            .with_context(|| format!("Failed to write '{}'", path.display()))
+    }
+}
+
+/// `width` x `height` scaled down to fit into `size` x `size`, at least one
+/// pixel on each side.
+fn thumbnail_size(width: u32, height: u32, size: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= size {
+        return (width, height);
+    }
+
+    let scale = |side: u32| ((side as u64 * size as u64) / longest as u64).max(1) as u32;
+
+    (scale(width), scale(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use image::{Rgba, RgbaImage};
+
+    use super::{thumbnail_size, Thumbnails};
+    use crate::{
+        image_io,
+        math::IRect,
+        outputs::atlas_meta::{AtlasMeta, SpriteMeta},
+    };
+
+    #[test]
+    fn test_thumbnails() -> anyhow::Result<()> {
+        assert_eq!(thumbnail_size(64, 32, 16), (16, 8));
+        assert_eq!(thumbnail_size(3, 200, 16), (1, 16));
+        assert_eq!(thumbnail_size(12, 5, 16), (12, 5));
+
+        let mut atlas_image = RgbaImage::from_pixel(64, 64, Rgba([0, 0, 255, 255]));
+        for pixel in atlas_image.rows_mut().take(32).flatten() {
+            *pixel = Rgba([255, 0, 0, 255]);
+        }
+
+        let meta = AtlasMeta {
+            named_sprites: vec![
+                SpriteMeta::for_test("banner", 0, 1),
+                SpriteMeta::for_test("empty", 1, 1),
+                SpriteMeta::for_test("gem", 2, 1),
+            ],
+            ..AtlasMeta::for_test(
+                64,
+                64,
+                vec![
+                    IRect::new(0, 0, 64, 32),
+                    IRect::default(),
+                    IRect::new(2, 40, 6, 4),
+                ],
+            )
+        };
+
+        let thumbnails = Thumbnails::new(&meta, &atlas_image, 16)?;
+        let names: Vec<_> = thumbnails.thumbnails.iter().map(|t| &t.name).collect();
+        assert_eq!(names, ["banner", "gem"]);
+
+        let decode = |png: &str| -> anyhow::Result<RgbaImage> {
+            let bytes = base64::engine::general_purpose::STANDARD.decode(png)?;
+            image_io::decode_png(&bytes)
+        };
+
+        let banner = decode(&thumbnails.thumbnails[0].png)?;
+        assert_eq!(banner.dimensions(), (16, 8));
+        assert_eq!(*banner.get_pixel(8, 4), Rgba([255, 0, 0, 255]));
+
+        let gem = decode(&thumbnails.thumbnails[1].png)?;
+        assert_eq!(gem.dimensions(), (6, 4));
+        assert!(gem.pixels().all(|pixel| *pixel == Rgba([0, 0, 255, 255])));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1176 @@
+use anyhow::Context;
+use rayon::prelude::*;
+
+use crate::{
+    error::{Error, Ewwow},
+    math::{IMargins, IRect, ISize},
+};
+
+/// The size of the image to pack into and how much space to keep around
+/// sprites.
+///
+/// Every sprite gets a cell of its size grown by `padding`. The padding
+/// belongs to the sprite (it's also kept at the edges of the image) and is
+/// always left empty. Between neighbouring cells there are additionally
+/// `spacing` empty pixels, but not between a cell and the image edge.
+///
+/// So two sprites next to each other are at least `a.padding.right + spacing +
+/// b.padding.left` pixels apart and a sprite is at least `padding.left` pixels
+/// away from the left edge of the image.
+///
+/// On top of that, a `margin` of empty pixels is reserved along all edges of
+/// the image, e.g. for engines that wrap around when sampling at UV 0 or 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackConfig {
+    pub width: i32,
+    pub height: i32,
+    pub padding: IMargins,
+    pub spacing: i32,
+    pub margin: i32,
+}
+
+impl PackConfig {
+    pub fn new(width: i32, height: i32, padding: IMargins) -> Self {
+        Self {
+            width,
+            height,
+            padding,
+            spacing: 0,
+            margin: 0,
+        }
+    }
+
+    pub fn with_spacing(self, spacing: i32) -> Self {
+        Self { spacing, ..self }
+    }
+
+    pub fn with_margin(self, margin: i32) -> Self {
+        Self { margin, ..self }
+    }
+}
+
+/// How [`PackAlgorithm::pack`] arranges the sprites.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PackAlgorithm {
+    /// Rows of sprites sorted by height, see [`pack`].
+    #[default]
+    Rows,
+    /// Sprites dropped onto a skyline, see [`pack_skyline`].
+    Skyline,
+    /// The smallest result of the first `attempts` of [`HEURISTICS`], see
+    /// [`pack_best`].
+    Best { attempts: u32 },
+}
+
+impl std::str::FromStr for PackAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rows" => Ok(Self::Rows),
+            "skyline" => Ok(Self::Skyline),
+            "best" => Ok(Self::Best {
+                attempts: HEURISTICS.len() as u32,
+            }),
+            _ => {
+                Error::InvalidOption(format!(
+                    "Invalid packing algorithm '{s}', expected rows, skyline or best"
+                ))
+                .raise()?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl PackAlgorithm {
+    pub fn pack(self, sizes: &[ISize], config: &PackConfig) -> anyhow::Result<Vec<IRect>> {
+        match self {
+            Self::Rows => pack(sizes, config),
+            Self::Skyline => pack_skyline(sizes, config),
+            Self::Best { attempts } => pack_best(sizes, config, attempts),
+        }
+    }
+}
+
+/// The packers [`pack_best`] picks from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packer {
+    /// See [`pack`].
+    Rows,
+    /// See [`pack_skyline`].
+    Skyline,
+    /// See [`pack_max_rects`].
+    MaxRects,
+}
+
+/// The order [`pack_best`] hands the sprites to a [`Packer`] in, largest
+/// first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// By height, the order the packers use on their own.
+    Height,
+    /// By area, then height.
+    Area,
+    /// By the longer side, then the shorter one.
+    MaxSide,
+}
+
+impl SortOrder {
+    /// The indices of `sizes` in this order. Equal sizes keep their order.
+    pub fn order(self, sizes: &[ISize]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&index| {
+            let size = sizes[index];
+            std::cmp::Reverse(match self {
+                SortOrder::Height => (size.height, size.width),
+                SortOrder::Area => (size.area(), size.height),
+                SortOrder::MaxSide => (size.width.max(size.height), size.width.min(size.height)),
+            })
+        });
+        order
+    }
+}
+
+impl Packer {
+    pub fn pack(
+        self,
+        sizes: &[ISize],
+        order: SortOrder,
+        config: &PackConfig,
+    ) -> anyhow::Result<Vec<IRect>> {
+        match (self, order) {
+            (Packer::Rows, SortOrder::Height) => pack(sizes, config),
+            (Packer::Skyline, SortOrder::Height) => pack_skyline(sizes, config),
+            (Packer::Rows, order) => pack_in_order(sizes, order.order(sizes), config),
+            (Packer::Skyline, order) => skyline_in_order(sizes, order.order(sizes), config),
+            (Packer::MaxRects, order) => max_rects_in_order(sizes, order.order(sizes), config),
+        }
+    }
+}
+
+/// The packers and sort orders [`pack_best`] tries, the ones that do best on
+/// their own first.
+pub const HEURISTICS: [(Packer, SortOrder); 9] = [
+    (Packer::Rows, SortOrder::Height),
+    (Packer::Skyline, SortOrder::Height),
+    (Packer::MaxRects, SortOrder::Area),
+    (Packer::MaxRects, SortOrder::MaxSide),
+    (Packer::MaxRects, SortOrder::Height),
+    (Packer::Skyline, SortOrder::MaxSide),
+    (Packer::Skyline, SortOrder::Area),
+    (Packer::Rows, SortOrder::MaxSide),
+    (Packer::Rows, SortOrder::Area),
+];
+
+/// Packs `sizes` with the first `attempts` (at least one) of [`HEURISTICS`],
+/// in parallel, and keeps the result whose padded sprites span the smallest area from
+/// the top left corner, the first one of equally small ones. Fails with the
+/// error of the last attempt if none of them fit.
+///
+/// On success, the returned rects are in the same order as `sizes`.
+pub fn pack_best(
+    sizes: &[ISize],
+    config: &PackConfig,
+    attempts: u32,
+) -> anyhow::Result<Vec<IRect>> {
+    // The attempts are independent, only picking the best one is in order
+    let results: Vec<anyhow::Result<Vec<IRect>>> = HEURISTICS
+        [..(attempts as usize).clamp(1, HEURISTICS.len())]
+        .par_iter()
+        .map(|&(packer, order)| packer.pack(sizes, order, config))
+        .collect();
+
+    let mut best: Option<(i64, Vec<IRect>)> = None;
+    let mut error = None;
+
+    for result in results {
+        match result {
+            Ok(rects) => {
+                let extent = rects
+                    .iter()
+                    .map(|rect| rect.grow(config.padding).max)
+                    .fold(glam::IVec2::ZERO, |a, b| a.max(b));
+                let area = extent.x as i64 * extent.y as i64;
+
+                if best.as_ref().is_none_or(|&(best_area, _)| area < best_area) {
+                    best = Some((area, rects));
+                }
+            }
+            Err(err) => error = Some(err),
+        }
+    }
+
+    match (best, error) {
+        (Some((_, rects)), _) => Ok(rects),
+        (None, Some(err)) => Err(err),
+        (None, None) => unreachable!(),
+    }
+}
+
+/// Packs `sizes` into a `config.width` x `config.height` image using rows of
+/// sprites sorted by decreasing height. If a sprite doesn't fit into the
+/// current row anymore, later (lower) sprites are used to fill the gap.
+/// Sprites of the same height keep their order, so the same `sizes` always
+/// pack the same.
+///
+/// On success, the returned rects are in the same order as `sizes`.
+pub fn pack(sizes: &[ISize], config: &PackConfig) -> anyhow::Result<Vec<IRect>> {
+    // Sort the sprites by height
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+
+    // Use reverse cmp to get decreasing heights
+    order.sort_by(|&a, &b| sizes[b].height.cmp(&sizes[a].height));
+
+    pack_in_order(sizes, order, config)
+}
+
+/// Like [`pack`], but keeps sprites of the same cluster next to each other:
+/// the clusters are packed one after another in the order of their keys,
+/// each by decreasing height. Similar pixels close together compress
+/// better, but rows of mixed heights leave more space empty.
+///
+/// `clusters` has a key for every sprite of `sizes`.
+pub fn pack_clustered(
+    sizes: &[ISize],
+    clusters: &[u64],
+    config: &PackConfig,
+) -> anyhow::Result<Vec<IRect>> {
+    if clusters.len() != sizes.len() {
+        Ewwow.raise().with_context(|| {
+            format!(
+                "Got {} cluster keys for {} sprites",
+                clusters.len(),
+                sizes.len()
+            )
+        })?;
+    }
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| (clusters[index], std::cmp::Reverse(sizes[index].height)));
+
+    pack_in_order(sizes, order, config)
+}
+
+/// Packs `sizes` in their order into rows that are all exactly
+/// `shelf_height` high (plus padding), so row `i` starts `i` strides below
+/// the top, where the stride is `shelf_height + padding.vert() + spacing`.
+/// Lets a runtime treat the rows as lines, e.g. to upload glyphs row by row.
+///
+/// Fails if a sprite is taller than `shelf_height`.
+pub fn pack_shelves(
+    sizes: &[ISize],
+    shelf_height: i32,
+    config: &PackConfig,
+) -> anyhow::Result<Vec<IRect>> {
+    let margin = config.margin;
+    let width = config.width - 2 * margin;
+    let height = config.height - 2 * margin;
+    let padding = config.padding;
+    let stride = shelf_height + padding.vert() + config.spacing;
+
+    let mut output = Vec::with_capacity(sizes.len());
+    let mut current_x = 0;
+    let mut current_y = 0;
+
+    for (index, size) in sizes.iter().enumerate() {
+        if size.height > shelf_height {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Sprite #{index} of size {}x{} is taller than the shelves of {shelf_height}",
+                    size.width, size.height
+                )
+            })?;
+        }
+
+        if size.width + padding.hori() > width {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Sprite #{index} of size {}x{} is wider than the {width}x{height} image",
+                    size.width, size.height
+                )
+            })?;
+        }
+
+        // Continue on the next shelf
+        if current_x + padding.hori() + size.width > width {
+            current_x = 0;
+            current_y += stride;
+        }
+
+        if current_y + shelf_height + padding.vert() > height {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Ran out of shelves packing {} sprites into a {width}x{height} image",
+                    sizes.len()
+                )
+            })?;
+        }
+
+        output.push(IRect::new(
+            margin + current_x + padding.left,
+            margin + current_y + padding.top,
+            size.width,
+            size.height,
+        ));
+
+        current_x += size.width + padding.hori() + config.spacing;
+    }
+
+    Ok(output)
+}
+
+/// Packs `sizes` bottom-left onto a skyline: the outline of the tops of
+/// everything packed so far. Every sprite, by decreasing height, goes where
+/// its top ends up highest, i.e. closest to the top of the image, and then
+/// furthest left. Unlike rows, short sprites next to a tall one stack on top
+/// of each other, which packs many thin and tall sprites (e.g. glyphs) a lot
+/// tighter.
+///
+/// On success, the returned rects are in the same order as `sizes`.
+pub fn pack_skyline(sizes: &[ISize], config: &PackConfig) -> anyhow::Result<Vec<IRect>> {
+    skyline_in_order(sizes, SortOrder::Height.order(sizes), config)
+}
+
+/// Packs the sprites in `order` onto a skyline, see [`pack_skyline`].
+fn skyline_in_order(
+    sizes: &[ISize],
+    order: Vec<usize>,
+    config: &PackConfig,
+) -> anyhow::Result<Vec<IRect>> {
+    let margin = config.margin;
+    let width = config.width - 2 * margin;
+    let height = config.height - 2 * margin;
+    let padding = config.padding;
+    let spacing = config.spacing;
+
+    // Every cell is followed by `spacing`, so the cells are packed into an
+    // area that is `spacing` larger to not need it at the far edges.
+    let area_width = width + spacing;
+    let area_height = height + spacing;
+
+    // `(x, y, width)` of every segment of the skyline, from left to right
+    let mut skyline: Vec<(i32, i32, i32)> = vec![(0, 0, area_width)];
+    let mut output = vec![IRect::default(); sizes.len()];
+
+    for index in order {
+        let size = sizes[index];
+        let cell_width = size.width + padding.hori() + spacing;
+        let cell_height = size.height + padding.vert() + spacing;
+
+        if cell_width > area_width {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Sprite #{index} of size {}x{} is wider than the {width}x{height} image",
+                    size.width, size.height
+                )
+            })?;
+        }
+
+        // The lowest `(y, x)` any segment can start the cell at
+        let mut best: Option<(i32, i32)> = None;
+
+        for (start, &(x, _, _)) in skyline.iter().enumerate() {
+            if x + cell_width > area_width {
+                break;
+            }
+
+            // The cell rests on the highest segment below it
+            let y = skyline[start..]
+                .iter()
+                .take_while(|&&(seg_x, _, _)| seg_x < x + cell_width.max(1))
+                .map(|&(_, seg_y, _)| seg_y)
+                .max()
+                .unwrap_or(0);
+
+            if y + cell_height <= area_height && best.is_none_or(|best| (y, x) < best) {
+                best = Some((y, x));
+            }
+        }
+
+        let Some((y, x)) = best else {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Ran out of space packing {} sprites into a {width}x{height} image",
+                    sizes.len()
+                )
+            })?;
+
+            unreachable!()
+        };
+
+        output[index] = IRect::new(
+            margin + x + padding.left,
+            margin + y + padding.top,
+            size.width,
+            size.height,
+        );
+
+        if cell_width > 0 {
+            raise_skyline(&mut skyline, x, y + cell_height, cell_width);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Replaces the part of `skyline` covered by `x..x + width` with a segment
+/// at `y` and merges neighbours of the same height.
+fn raise_skyline(skyline: &mut Vec<(i32, i32, i32)>, x: i32, y: i32, width: i32) {
+    let end = x + width;
+    let mut raised = Vec::with_capacity(skyline.len() + 2);
+
+    for &(seg_x, seg_y, seg_width) in skyline.iter() {
+        let seg_end = seg_x + seg_width;
+
+        // Keep the parts of the segment left and right of the new one
+        if seg_x < x {
+            raised.push((seg_x, seg_y, seg_end.min(x) - seg_x));
+        }
+        if seg_x < end && seg_end > x && seg_x <= x {
+            raised.push((x, y, width));
+        }
+        if seg_end > end {
+            let start = seg_x.max(end);
+            raised.push((start, seg_y, seg_end - start));
+        }
+    }
+
+    raised.dedup_by(|next, prev| {
+        let merge = next.1 == prev.1;
+        if merge {
+            prev.2 += next.2;
+        }
+        merge
+    });
+
+    *skyline = raised;
+}
+
+/// The largest rect inside of `bounds` that none of `occupied` reach into,
+/// e.g. to see how much room a packed atlas has left. Empty if there is
+/// none.
+///
+/// The edges of the rects split `bounds` into a grid of cells that are
+/// either free or occupied as a whole. Every row of cells is then the bottom
+/// of a histogram of the free cells above it, whose largest rect is found
+/// with a stack.
+pub fn largest_empty_rect(bounds: IRect, occupied: &[IRect]) -> IRect {
+    let clipped: Vec<IRect> = occupied
+        .iter()
+        .map(|rect| IRect {
+            min: rect.min.clamp(bounds.min, bounds.max),
+            max: rect.max.clamp(bounds.min, bounds.max),
+        })
+        .filter(|rect| !rect.is_empty())
+        .collect();
+
+    let edges = |axis: fn(glam::IVec2) -> i32| {
+        let mut edges: Vec<i32> = [bounds.min, bounds.max]
+            .into_iter()
+            .chain(clipped.iter().flat_map(|rect| [rect.min, rect.max]))
+            .map(axis)
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        edges
+    };
+    let xs = edges(|v| v.x);
+    let ys = edges(|v| v.y);
+    let columns = xs.len().saturating_sub(1);
+
+    let mut free = vec![vec![true; columns]; ys.len().saturating_sub(1)];
+    for rect in clipped.iter() {
+        let index = |edges: &[i32], value: i32| edges.binary_search(&value).unwrap_or_default();
+
+        for row in free[index(&ys, rect.min.y)..index(&ys, rect.max.y)].iter_mut() {
+            row[index(&xs, rect.min.x)..index(&xs, rect.max.x)].fill(false);
+        }
+    }
+
+    let mut largest = IRect::default();
+    let mut heights = vec![0; columns];
+
+    for (row, free_row) in free.iter().enumerate() {
+        let bottom = ys[row + 1];
+        for (height, &free) in heights.iter_mut().zip(free_row) {
+            *height = match free {
+                true => *height + bottom - ys[row],
+                false => 0,
+            };
+        }
+
+        // Bars as their left edge and height, with rising heights
+        let mut stack: Vec<(i32, i32)> = vec![];
+        for column in 0..=columns {
+            let (x, height) = match column < columns {
+                true => (xs[column], heights[column]),
+                false => (xs[columns], 0),
+            };
+
+            let mut start = x;
+            while let Some(&(left, bar)) = stack.last().filter(|&&(_, bar)| bar >= height) {
+                stack.pop();
+                start = left;
+
+                let rect = IRect::new(left, bottom - bar, x - left, bar);
+                if rect.size().area() > largest.size().area() {
+                    largest = rect;
+                }
+            }
+            stack.push((start, height));
+        }
+    }
+
+    largest
+}
+
+/// Packs `sizes` with MaxRects: the free space is kept as the list of all
+/// maximal empty rects, and every sprite goes into the one it fits best,
+/// i.e. that leaves the shortest side over. The placed sprite then splits
+/// every free rect it overlaps into the up to four rects around it. Slower
+/// than rows or a skyline, but it fills the holes they leave.
+///
+/// On success, the returned rects are in the same order as `sizes`.
+pub fn pack_max_rects(sizes: &[ISize], config: &PackConfig) -> anyhow::Result<Vec<IRect>> {
+    max_rects_in_order(sizes, SortOrder::Area.order(sizes), config)
+}
+
+/// Packs the sprites in `order` with MaxRects, see [`pack_max_rects`].
+fn max_rects_in_order(
+    sizes: &[ISize],
+    order: Vec<usize>,
+    config: &PackConfig,
+) -> anyhow::Result<Vec<IRect>> {
+    let margin = config.margin;
+    let width = config.width - 2 * margin;
+    let height = config.height - 2 * margin;
+    let padding = config.padding;
+    let spacing = config.spacing;
+
+    // Like the skyline, cells are followed by `spacing` in a larger area
+    let mut free = vec![IRect::new(0, 0, width + spacing, height + spacing)];
+    let mut output = vec![IRect::default(); sizes.len()];
+
+    for index in order {
+        let size = sizes[index];
+        let cell = ISize::new(
+            size.width + padding.hori() + spacing,
+            size.height + padding.vert() + spacing,
+        );
+
+        // The shortest and longest side left over, then the top left-most
+        let best = free
+            .iter()
+            .filter(|rect| rect.width() >= cell.width && rect.height() >= cell.height)
+            .map(|rect| {
+                let left_over = (rect.width() - cell.width, rect.height() - cell.height);
+                (
+                    left_over.0.min(left_over.1),
+                    left_over.0.max(left_over.1),
+                    rect.min.y,
+                    rect.min.x,
+                )
+            })
+            .min();
+
+        let Some((_, _, y, x)) = best else {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Ran out of space packing {} sprites into a {width}x{height} image",
+                    sizes.len()
+                )
+            })?;
+
+            unreachable!()
+        };
+
+        output[index] = IRect::new(
+            margin + x + padding.left,
+            margin + y + padding.top,
+            size.width,
+            size.height,
+        );
+
+        split_free_rects(&mut free, IRect::new(x, y, cell.width, cell.height));
+    }
+
+    Ok(output)
+}
+
+/// Cuts `placed` out of the free rects of [`pack_max_rects`], keeping only
+/// the maximal ones.
+fn split_free_rects(free: &mut Vec<IRect>, placed: IRect) {
+    let mut split = Vec::with_capacity(free.len() + 4);
+
+    for &rect in free.iter() {
+        if !rect.intersects(placed) {
+            split.push(rect);
+            continue;
+        }
+
+        let (min, max) = (rect.min, rect.max);
+        split.extend(
+            [
+                IRect {
+                    min,
+                    max: glam::IVec2::new(placed.min.x, max.y),
+                },
+                IRect {
+                    min: glam::IVec2::new(placed.max.x, min.y),
+                    max,
+                },
+                IRect {
+                    min,
+                    max: glam::IVec2::new(max.x, placed.min.y),
+                },
+                IRect {
+                    min: glam::IVec2::new(min.x, placed.max.y),
+                    max,
+                },
+            ]
+            .into_iter()
+            .filter(|part| !part.is_empty()),
+        );
+    }
+
+    // Rects inside of others (or equal to an earlier one) are redundant
+    *free = split
+        .iter()
+        .enumerate()
+        .filter(|&(i, rect)| {
+            !split
+                .iter()
+                .enumerate()
+                .any(|(j, other)| j != i && other.contains(*rect) && (other != rect || j < i))
+        })
+        .map(|(_, &rect)| rect)
+        .collect();
+}
+
+/// Packs the sprites in `order` into rows, see [`pack`]. Rows are as high as
+/// their tallest sprite, which is the first one if `order` is by height.
+fn pack_in_order(
+    sizes: &[ISize],
+    mut order: Vec<usize>,
+    config: &PackConfig,
+) -> anyhow::Result<Vec<IRect>> {
+    if sizes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Pack into the area inside of the margin
+    let margin = config.margin;
+    let width = config.width - 2 * margin;
+    let height = config.height - 2 * margin;
+    let padding = config.padding;
+
+    let mut output = vec![IRect::default(); sizes.len()];
+
+    let mut current_x: i32 = 0;
+    let mut current_y: i32 = 0;
+    let mut next_y: i32 = 0;
+
+    let mut index = 0;
+
+    let pad_h = padding.hori();
+    let pad_v = padding.vert();
+    let spacing = config.spacing;
+
+    while index < order.len() {
+        let size = sizes[order[index]];
+
+        // Sanity check -- if we didn't check this we could get an endless loop
+        if size.width + pad_h > width {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Sprite #{} of size {}x{} is wider than the {width}x{height} image",
+                    order[index], size.width, size.height
+                )
+            })?;
+        }
+
+        // Start of a new row
+        if current_x == 0 {
+            // Check that the sprites actually fit in the row
+            if current_y + size.height + pad_v > height {
+                Ewwow.raise().with_context(|| {
+                    format!(
+                        "Ran out of space packing {} sprites into a {width}x{height} image",
+                        sizes.len()
+                    )
+                })?;
+            }
+
+            next_y = current_y;
+        }
+
+        // Check that this sprite still fits in the row
+        if current_x + pad_h + size.width > width {
+            // The sprite doesn't fit anymore. See if we can fit a later sprite in
+            if let Some(other_index_offset) = order[index + 1..]
+                .iter()
+                .position(|&other| current_x + pad_h + sizes[other].width <= width)
+            {
+                // The sprite fits!
+                let other = order.remove(index + 1 + other_index_offset);
+                let other_size = sizes[other];
+
+                if current_y + other_size.height + pad_v > height {
+                    Ewwow.raise().with_context(|| {
+                        format!(
+                            "Ran out of space packing {} sprites into a {width}x{height} image",
+                            sizes.len()
+                        )
+                    })?;
+                }
+                next_y = next_y.max(current_y + other_size.height + pad_v + spacing);
+
+                output[other] = IRect::new(
+                    margin + current_x + padding.left,
+                    margin + current_y + padding.top,
+                    other_size.width,
+                    other_size.height,
+                );
+
+                current_x += other_size.width + pad_h + spacing;
+
+                continue;
+            }
+
+            current_x = 0;
+            current_y = next_y;
+            continue;
+        }
+
+        // The sprite fits!
+        if current_y + size.height + pad_v > height {
+            Ewwow.raise().with_context(|| {
+                format!(
+                    "Ran out of space packing {} sprites into a {width}x{height} image",
+                    sizes.len()
+                )
+            })?;
+        }
+        next_y = next_y.max(current_y + size.height + pad_v + spacing);
+
+        output[order[index]] = IRect::new(
+            margin + current_x + padding.left,
+            margin + current_y + padding.top,
+            size.width,
+            size.height,
+        );
+
+        current_x += size.width + pad_h + spacing;
+
+        index += 1;
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        largest_empty_rect, pack, pack_best, pack_clustered, pack_max_rects, pack_shelves,
+        pack_skyline, PackAlgorithm, PackConfig, Packer, SortOrder,
+    };
+    use crate::math::{IMargins, IRect, ISize};
+
+    fn config(width: i32, height: i32, padding: i32) -> PackConfig {
+        PackConfig::new(width, height, IMargins::uniform(padding))
+    }
+
+    fn assert_valid(sizes: &[ISize], rects: &[IRect], config: &PackConfig) {
+        assert_eq!(sizes.len(), rects.len());
+
+        for (size, rect) in sizes.iter().zip(rects) {
+            assert_eq!(rect.width(), size.width);
+            assert_eq!(rect.height(), size.height);
+            assert!(rect.min.x >= config.margin + config.padding.left);
+            assert!(rect.min.y >= config.margin + config.padding.top);
+            assert!(rect.max.x + config.padding.right + config.margin <= config.width);
+            assert!(rect.max.y + config.padding.bottom + config.margin <= config.height);
+        }
+
+        // Padded cells need to be at least `spacing` apart
+        let cells: Vec<IRect> = rects
+            .iter()
+            .filter(|rect| !rect.is_empty())
+            .map(|rect| rect.grow(config.padding))
+            .collect();
+
+        for (i, a) in cells.iter().enumerate() {
+            for b in cells[i + 1..].iter() {
+                let spaced = a.grow(IMargins::uniform(config.spacing));
+                assert!(!spaced.intersects(*b), "{a:?} is too close to {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_pack_nothing() -> anyhow::Result<()> {
+        assert!(pack(&[], &config(0, 0, 0))?.is_empty());
+        assert!(pack(&[], &config(16, 16, 1))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_exact_fit() -> anyhow::Result<()> {
+        let sizes = vec![ISize::new(8, 8); 4];
+        let config = config(16, 16, 0);
+
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        // One more pixel of padding and they don't fit anymore
+        assert!(pack(&sizes, &PackConfig::new(16, 16, IMargins::new(0, 0, 0, 1))).is_err());
+        assert!(pack(&sizes, &PackConfig::new(16, 16, IMargins::new(0, 1, 0, 0))).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_single_sprite_filling_image() -> anyhow::Result<()> {
+        let sizes = [ISize::new(30, 10)];
+        let config = config(32, 12, 1);
+
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+        assert_eq!(rects[0], IRect::new(1, 1, 30, 10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_too_large() {
+        assert!(pack(&[ISize::new(17, 1)], &config(16, 16, 0)).is_err());
+        assert!(pack(&[ISize::new(1, 17)], &config(16, 16, 0)).is_err());
+        assert!(pack(&[ISize::new(16, 16)], &config(16, 16, 1)).is_err());
+        assert!(pack(&[ISize::new(8, 8); 5], &config(16, 16, 0)).is_err());
+        assert!(pack(&[ISize::new(1, 1)], &config(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_pack_spacing() -> anyhow::Result<()> {
+        let sizes = vec![ISize::new(7, 7); 4];
+
+        // Spacing is only needed between sprites, not at the edges
+        let config = PackConfig::new(15, 15, IMargins::uniform(0)).with_spacing(1);
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+        assert!(rects.contains(&IRect::new(8, 8, 7, 7)));
+
+        let config = PackConfig::new(19, 19, IMargins::uniform(1)).with_spacing(1);
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+        assert!(rects.contains(&IRect::new(1, 1, 7, 7)));
+        assert!(rects.contains(&IRect::new(11, 11, 7, 7)));
+
+        let config = PackConfig::new(18, 18, IMargins::uniform(1)).with_spacing(1);
+        assert!(pack(&sizes, &config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_margin() -> anyhow::Result<()> {
+        let sizes = vec![ISize::new(7, 7); 4];
+
+        let config = PackConfig::new(18, 18, IMargins::uniform(0)).with_margin(2);
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+        assert!(rects.contains(&IRect::new(2, 2, 7, 7)));
+        assert!(rects.contains(&IRect::new(9, 9, 7, 7)));
+
+        // The margin adds to the padding
+        let config = PackConfig::new(20, 20, IMargins::uniform(1)).with_margin(1);
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+        assert!(rects.contains(&IRect::new(2, 2, 7, 7)));
+
+        let config = PackConfig::new(19, 19, IMargins::uniform(1)).with_margin(1);
+        assert!(pack(&sizes, &config).is_err());
+
+        // A margin larger than the image
+        let config = PackConfig::new(4, 4, IMargins::uniform(0)).with_margin(3);
+        assert!(pack(&[ISize::new(1, 1)], &config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_wide_padding_terminates() {
+        // Zero height sprites with horizontal padding used to never run out of rows
+        let sizes = [ISize::new(16, 0)];
+        let config = PackConfig::new(16, 16, IMargins::new(0, 0, 1, 0));
+
+        assert!(pack(&sizes, &config).is_err());
+    }
+
+    #[test]
+    fn test_pack_zero_area_sprites() -> anyhow::Result<()> {
+        let sizes = [
+            ISize::new(0, 0),
+            ISize::new(8, 8),
+            ISize::new(0, 5),
+            ISize::new(5, 0),
+            ISize::new(8, 8),
+        ];
+        let config = config(16, 8, 0);
+
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_keeps_input_order() -> anyhow::Result<()> {
+        let sizes = [ISize::new(2, 1), ISize::new(2, 3), ISize::new(2, 2)];
+        let config = config(6, 3, 0);
+
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        // Sprites are placed by decreasing height
+        assert_eq!(rects[1], IRect::new(0, 0, 2, 3));
+        assert_eq!(rects[2], IRect::new(2, 0, 2, 2));
+        assert_eq!(rects[0], IRect::new(4, 0, 2, 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_fills_row_gaps_with_later_sprites() -> anyhow::Result<()> {
+        let sizes = [ISize::new(6, 4), ISize::new(6, 3), ISize::new(2, 2)];
+        let config = config(8, 8, 0);
+
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        // The small sprite is pulled into the first row
+        assert_eq!(rects[2], IRect::new(6, 0, 2, 2));
+        assert_eq!(rects[1], IRect::new(0, 4, 6, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_clustered() -> anyhow::Result<()> {
+        let sizes = [ISize::new(2, 2); 4];
+        let config = config(4, 4, 0);
+
+        // Without clusters the input order is kept
+        let rects = pack(&sizes, &config)?;
+        assert_eq!(rects[1], IRect::new(2, 0, 2, 2));
+
+        let rects = pack_clustered(&sizes, &[1, 0, 1, 0], &config)?;
+        assert_valid(&sizes, &rects, &config);
+        assert_eq!(rects[1], IRect::new(0, 0, 2, 2));
+        assert_eq!(rects[3], IRect::new(2, 0, 2, 2));
+        assert_eq!(rects[0], IRect::new(0, 2, 2, 2));
+
+        assert!(pack_clustered(&sizes, &[0], &config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_shelves() -> anyhow::Result<()> {
+        let sizes = [
+            ISize::new(4, 7),
+            ISize::new(3, 2),
+            ISize::new(5, 8),
+            ISize::new(0, 0),
+            ISize::new(3, 1),
+        ];
+        let config = config(16, 32, 1).with_spacing(1);
+
+        let rects = pack_shelves(&sizes, 8, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        // Every sprite starts at the top of its shelf, 8 + 2 + 1 apart
+        let tops: Vec<_> = rects.iter().map(|rect| rect.min.y).collect();
+        assert_eq!(tops, [1, 1, 12, 12, 12]);
+        assert_eq!(rects[4], IRect::new(12, 12, 3, 1));
+
+        assert!(pack_shelves(&sizes, 7, &config).is_err());
+        assert!(pack_shelves(&sizes, 8, &config.with_spacing(0).with_margin(6)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_many_random_sizes() -> anyhow::Result<()> {
+        // Simple LCG to get deterministic "random" sizes
+        let mut state: u32 = 12345;
+        let mut next = |max: u32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) % max) as i32
+        };
+
+        let sizes: Vec<ISize> = (0..200).map(|_| ISize::new(next(12), next(12))).collect();
+        let config = config(256, 256, 1);
+
+        let rects = pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        let rects = pack_skyline(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        let config = config.with_spacing(2).with_margin(3);
+        let rects = pack_skyline(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_skyline() -> anyhow::Result<()> {
+        // Rows would put the short sprites into the row of the tall one and
+        // run out of space below it
+        let sizes = [
+            ISize::new(4, 8),
+            ISize::new(4, 2),
+            ISize::new(4, 2),
+            ISize::new(4, 2),
+            ISize::new(4, 2),
+        ];
+        let config = config(8, 8, 0);
+
+        assert!(pack(&sizes, &config).is_err());
+
+        let rects = pack_skyline(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+        assert_eq!(rects[0], IRect::new(0, 0, 4, 8));
+        let tops: Vec<_> = rects[1..].iter().map(|rect| rect.min.y).collect();
+        assert_eq!(tops, [0, 2, 4, 6]);
+
+        let config = config.with_spacing(1);
+        assert!(pack_skyline(&sizes, &config).is_err());
+        assert!(pack_skyline(&[ISize::new(9, 1)], &config).is_err());
+        assert!(pack_skyline(&[], &config)?.is_empty());
+
+        let sizes = [ISize::new(3, 7), ISize::new(3, 3), ISize::new(3, 3)];
+        let rects = pack_skyline(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        assert_eq!("skyline".parse::<PackAlgorithm>()?, PackAlgorithm::Skyline);
+        assert!("maxrects".parse::<PackAlgorithm>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_max_rects() -> anyhow::Result<()> {
+        let mut state: u32 = 54321;
+        let mut next = |max: u32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) % max) as i32
+        };
+
+        let sizes: Vec<ISize> = (0..200).map(|_| ISize::new(next(12), next(12))).collect();
+        let spaced = config(256, 256, 1).with_spacing(2).with_margin(3);
+
+        for order in [SortOrder::Height, SortOrder::Area, SortOrder::MaxSide] {
+            let rects = Packer::MaxRects.pack(&sizes, order, &spaced)?;
+            assert_valid(&sizes, &rects, &spaced);
+            let rects = Packer::Rows.pack(&sizes, order, &spaced)?;
+            assert_valid(&sizes, &rects, &spaced);
+            let rects = Packer::Skyline.pack(&sizes, order, &spaced)?;
+            assert_valid(&sizes, &rects, &spaced);
+        }
+
+        // Fills the hole next to the tall sprite that rows leave
+        let sizes = [ISize::new(4, 8), ISize::new(4, 4), ISize::new(4, 4)];
+        let config = config(8, 8, 0);
+        let rects = pack_max_rects(&sizes, &config)?;
+        assert_eq!(
+            rects,
+            [
+                IRect::new(0, 0, 4, 8),
+                IRect::new(4, 0, 4, 4),
+                IRect::new(4, 4, 4, 4)
+            ]
+        );
+
+        assert!(pack_max_rects(&[ISize::new(9, 1)], &config).is_err());
+        assert!(pack_max_rects(&[], &config)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_best() -> anyhow::Result<()> {
+        let sizes = [
+            ISize::new(4, 8),
+            ISize::new(4, 2),
+            ISize::new(4, 2),
+            ISize::new(4, 2),
+            ISize::new(4, 2),
+        ];
+        let config = config(8, 8, 0);
+
+        // Only the first attempt, rows, which runs out of space
+        assert!(pack_best(&sizes, &config, 1).is_err());
+        assert!(pack_best(&sizes, &config, 0).is_err());
+
+        let best = "best".parse::<PackAlgorithm>()?;
+        assert_eq!(best, PackAlgorithm::Best { attempts: 9 });
+        let rects = best.pack(&sizes, &config)?;
+        assert_valid(&sizes, &rects, &config);
+
+        // Rows leave the bottom row half empty, the other packers don't
+        let sizes = [
+            ISize::new(6, 2),
+            ISize::new(2, 6),
+            ISize::new(2, 6),
+            ISize::new(2, 6),
+        ];
+        let config = PackConfig::new(16, 16, IMargins::uniform(0));
+        let rows = pack(&sizes, &config)?;
+        let extent = |rects: &[IRect]| {
+            let max = rects.iter().fold(IRect::default().max, |a, b| a.max(b.max));
+            max.x * max.y
+        };
+        let best = pack_best(&sizes, &config, 9)?;
+        assert_valid(&sizes, &best, &config);
+        assert!(extent(&best) < extent(&rows));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_largest_empty_rect() {
+        let bounds = IRect::new(0, 0, 10, 8);
+        assert_eq!(largest_empty_rect(bounds, &[]), bounds);
+
+        // An L of sprites leaves the bottom right corner
+        let occupied = [IRect::new(0, 0, 10, 3), IRect::new(0, 3, 4, 5)];
+        assert_eq!(
+            largest_empty_rect(bounds, &occupied),
+            IRect::new(4, 3, 6, 5)
+        );
+
+        // Either the gap between two sprites or the row below them
+        let occupied = [IRect::new(0, 0, 4, 6), IRect::new(6, 0, 4, 7)];
+        assert_eq!(
+            largest_empty_rect(bounds, &occupied),
+            IRect::new(4, 0, 2, 8)
+        );
+        let occupied = [IRect::new(0, 0, 4, 7), IRect::new(5, 0, 5, 7)];
+        assert_eq!(
+            largest_empty_rect(bounds, &occupied),
+            IRect::new(0, 7, 10, 1)
+        );
+
+        // Rects reaching outside are clipped, full bounds leave nothing
+        assert_eq!(
+            largest_empty_rect(bounds, &[IRect::new(-5, -5, 20, 20)]),
+            IRect::default()
+        );
+    }
+}
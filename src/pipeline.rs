@@ -0,0 +1,2490 @@
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+#[cfg(feature = "cli")]
+use clap::Parser;
+
+use crate::{
+    cancellation, error, font_shared, hashing, image_io,
+    inputs::{self, sprite_references::SpriteReferences},
+    intermediates::{
+        self,
+        effects::{GlyphEffect, SpriteDerivation},
+        font,
+        resample::Resampler,
+        sprite::TrimMode,
+        texture_atlas::{AtlasGrowth, Clustering, Platform, SizeConstraint, TextureAtlas},
+    },
+    math,
+    outputs::{
+        self,
+        atlas_meta::AtlasMeta,
+        build_info::BuildInfo,
+        content_filter::ContentFilter,
+        fantasy_console::FantasyConsole,
+        gba::TileDepth,
+        lockfile::Lockfile,
+        pack_manifest::PackManifest,
+        pixel_format::{Dithering, PixelFormat},
+        texture_files::{build_textures, write_textures, TextureOutput},
+    },
+    packing::PackAlgorithm,
+    sampling, sources, warnings,
+    watch::{SourceWatcher, POLL_INTERVAL},
+};
+#[cfg(feature = "remote")]
+use crate::{remote, remote_cache};
+
+/// Where `--lockfile` records the input hashes.
+pub const LOCKFILE_PATH: &str = "texture-packer.lock";
+
+/// The png options of [`PackOptions`] and [`RepackOptions`].
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct PngArgs {
+    /// How hard to compress the pngs.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "LEVEL"))]
+    pub png_compression: Option<image_io::PngCompression>,
+    /// The png row filter.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "FILTER"))]
+    pub png_filter: Option<image_io::PngFilter>,
+    /// Runs the pngs through `oxipng` with this preset (needs the `oxipng`
+    /// feature).
+    #[cfg_attr(feature = "cli", arg(long, value_name = "0-6", value_parser = clap::value_parser!(u8).range(0..=6)))]
+    pub png_optimize: Option<u8>,
+}
+
+impl PngArgs {
+    pub fn options(&self) -> image_io::PngOptions {
+        image_io::PngOptions {
+            compression: self.png_compression.unwrap_or_default(),
+            filter: self.png_filter.unwrap_or_default(),
+            optimize: self.png_optimize,
+        }
+    }
+}
+
+/// Everything a [`build`] can do, parsed from the command line by the
+/// `texture-packer` binary, so the doc comments of the fields are its help.
+/// The defaults are the same as on the command line.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct PackOptions {
+    /// Builds every atlas of a `texture-packer.toml`, see `inputs::project`
+    /// for the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Projects")
+    )]
+    pub project: Option<PathBuf>,
+    /// Builds the projects of a `texture-packer-workspace.toml` and their
+    /// master atlas, see `inputs::workspace` for the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Projects")
+    )]
+    pub workspace: Option<PathBuf>,
+    /// Picks the platform sections of the project files to build with.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "PLATFORM", help_heading = "Projects")
+    )]
+    pub target: Option<String>,
+    /// Picks the feature sections of the project files to build with.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "feature", value_name = "NAME", help_heading = "Projects")
+    )]
+    pub features: Vec<String>,
+
+    /// Checks an exported atlas.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "META", help_heading = "Inspecting atlases")
+    )]
+    pub verify: Option<PathBuf>,
+    /// Writes every sprite and label of an exported atlas to a png.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "META", help_heading = "Inspecting atlases")
+    )]
+    pub unpack: Option<PathBuf>,
+    /// Where --unpack writes the pngs.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "DIR",
+            default_value = "unpacked",
+            help_heading = "Inspecting atlases"
+        )
+    )]
+    pub unpack_dir: PathBuf,
+    /// Renders --preview-text with every font of an exported atlas to
+    /// `preview-<font>.png`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "META", help_heading = "Inspecting atlases")
+    )]
+    pub preview_font: Option<PathBuf>,
+    /// The text of --preview-font.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "TEXT",
+            default_value = "The quick brown fox jumps over the lazy dog",
+            help_heading = "Inspecting atlases"
+        )
+    )]
+    pub preview_text: String,
+
+    /// Adds a font (`.fnt`) or a single frame sprite (`.png`, or `.svg` with
+    /// the `svg` feature, like --sprite).
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Inputs")
+    )]
+    pub input: Vec<PathBuf>,
+    /// Adds the image as a single frame sprite named after the file. A suffix
+    /// like `hero@2x.png` tags its design scale.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Inputs")
+    )]
+    pub sprite: Vec<PathBuf>,
+    /// Adds the sprites sliced in the Unity `<file>.png.meta` next to the
+    /// image, with their pivots and 9-slice borders.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Inputs")
+    )]
+    pub unity_sheet: Vec<PathBuf>,
+    /// Adds a sprite per region of the `<file>.slices.json` next to the image,
+    /// see `inputs::slices` for the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Inputs")
+    )]
+    pub slices: Vec<PathBuf>,
+    /// Generates solid, gradient and rounded rect sprites, see
+    /// `inputs::procedural` for the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Inputs")
+    )]
+    pub procedural: Option<PathBuf>,
+    /// Builds a font from SVG icons, see `inputs::icon_font` for the format.
+    /// It's named after the file, and `<name>.icons.json` lists the code
+    /// point of every icon.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Inputs")
+    )]
+    pub icon_font: Option<PathBuf>,
+    /// The height --icon-font is rasterized at.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "PX", default_value_t = 16, help_heading = "Inputs")
+    )]
+    pub icon_size: u32,
+    /// Adds a font downloaded over HTTP(S) (needs the `remote` feature).
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "URL", help_heading = "Inputs")
+    )]
+    pub remote_font: Vec<String>,
+    /// Keeps downloads around, revalidated by their ETag.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "DIR", help_heading = "Inputs")
+    )]
+    pub remote_cache: Option<PathBuf>,
+    /// Starts from a previously exported atlas. Fonts with the same name are
+    /// replaced, everything else is added.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "META", help_heading = "Inputs")
+    )]
+    pub append: Option<PathBuf>,
+    /// Adds everything of a previously exported atlas, renamed to
+    /// `<namespace>/<name>`.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "NAMESPACE=META", value_parser = key_value::<PathBuf>, help_heading = "Inputs"))]
+    pub merge: Vec<(String, PathBuf)>,
+    /// Records the input hashes in `texture-packer.lock`.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Inputs"))]
+    pub lockfile: bool,
+    /// Fails if the inputs don't match `texture-packer.lock`.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Inputs"))]
+    pub frozen: bool,
+
+    /// Exports the font with the face name from its fnt file under a logical
+    /// name, e.g. to pack two sizes of the same face.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "FACE=NAME", value_parser = key_value::<String>, help_heading = "Fonts"))]
+    pub font_name: Vec<(String, String)>,
+    /// Gives every char of the font the largest advance.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "NAME", help_heading = "Fonts")
+    )]
+    pub monospace: Vec<String>,
+    /// Fails if the advances of the font differ.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "NAME", help_heading = "Fonts")
+    )]
+    pub check_monospace: Vec<String>,
+    /// Packs the glyphs of the font into rows of exactly its line height,
+    /// exported as `shelf_stride`, for uploading glyphs a row at a time.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "NAME", help_heading = "Fonts")
+    )]
+    pub line_shelves: Vec<String>,
+    /// Overrides the text decoration metrics guessed for the font.
+    #[cfg_attr(feature = "cli", arg(
+        long,
+        value_name = "NAME=UNDERLINE:THICKNESS:STRIKETHROUGH",
+        value_parser = key_value::<font_shared::DecorationMetrics>,
+        help_heading = "Fonts"
+    ))]
+    pub decorations: Vec<(String, font_shared::DecorationMetrics)>,
+    /// Reserves empty cells in every font for glyphs rasterized at runtime.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "COUNT:WIDTHxHEIGHT", help_heading = "Fonts")
+    )]
+    pub dynamic_slots: Option<font::DynamicSlots>,
+    /// Adds a second frame with the effect applied to every glyph, e.g.
+    /// `outline:<thickness>:<rrggbb[aa]>`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "EFFECT", help_heading = "Fonts")
+    )]
+    pub glyph_effect: Option<GlyphEffect>,
+    /// What to do with frames of a char with other offsets or another advance
+    /// than its first one, `first-frame` gives them those of the first frame.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "MODE", help_heading = "Fonts")
+    )]
+    pub metric_conflicts: Option<font::MetricConflicts>,
+    /// Allows chars with fewer frames than their font, e.g. unanimated
+    /// punctuation, by padding them with `repeat-last` or `loop`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "MODE", help_heading = "Fonts")
+    )]
+    pub ragged_frames: Option<font::RaggedFrames>,
+    /// Packs control characters, byte order marks and zero-width code points
+    /// anyway, e.g. `10,U+2424`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "CODES", help_heading = "Fonts")
+    )]
+    pub keep_chars: Option<String>,
+    /// Adds a `<font>#line` sprite for drawing the text decorations of every
+    /// font.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Fonts"))]
+    pub line_sprites: bool,
+    /// Packs the glyphs of every font into their own block.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Fonts"))]
+    pub font_regions: bool,
+    /// Turns glyphs and their --font-regions white with the glyph coverage as
+    /// alpha, for fonts sampled from the alpha channel alone.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Fonts"))]
+    pub alpha_glyphs: bool,
+    /// Also packs the font on its own into `font-<name>.png` and
+    /// `font-<name>.json`, for engines that stream fonts independently of the
+    /// merged atlas.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "NAME", help_heading = "Fonts")
+    )]
+    pub standalone_font: Vec<String>,
+
+    /// Downscales sprites drawn at a higher design scale to it, e.g. 1 to pack
+    /// `@2x` assets next to `@1x` ones. Sprites below it are packed as they
+    /// are and reported.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "SCALE", help_heading = "Sprites")
+    )]
+    pub target_scale: Option<f32>,
+    /// Picks how a sprite is scaled by --target-scale, `coverage` keeps
+    /// foliage and hair from thinning out.
+    #[cfg_attr(feature = "cli", arg(
+        long,
+        value_name = "NAME=linear|lanczos|coverage",
+        value_parser = key_value::<Resampler>,
+        help_heading = "Sprites"
+    ))]
+    pub resampler: Vec<(String, Resampler)>,
+    /// Adds recolored variants of sprites like `knight#red`, or more frames,
+    /// see `inputs::palettes` for the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub palettes: Option<PathBuf>,
+    /// Shifts the hue, saturation, brightness and contrast of groups of
+    /// inputs, see `inputs::adjustments` for the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub adjustments: Option<PathBuf>,
+    /// Cuts the transparent borders off sprite frames, the meta's `trims`
+    /// keep their offset and untrimmed size. `group` crops all frames of a
+    /// sprite alike, so animations don't jitter without per-frame offsets.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "per-frame|group", help_heading = "Sprites")
+    )]
+    pub trim: Option<TrimMode>,
+    /// Bakes a shadow sprite (e.g. `hero#shadow`) for every sprite named in
+    /// the file.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub shadows: Option<PathBuf>,
+    /// The style of --shadows.
+    #[cfg_attr(feature = "cli", arg(
+        long,
+        value_name = "BLUR:RRGGBB[AA]",
+        value_parser = SpriteDerivation::parse_shadow,
+        default_value = "2:00000080",
+        help_heading = "Sprites"
+    ))]
+    pub shadow_style: SpriteDerivation,
+    /// Bakes an outline-only sprite (e.g. `hero#outline`) for every sprite
+    /// named in the file.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub outlines: Option<PathBuf>,
+    /// The style of --outlines.
+    #[cfg_attr(feature = "cli", arg(
+        long,
+        value_name = "THICKNESS:RRGGBB[AA]",
+        value_parser = SpriteDerivation::parse_outline,
+        default_value = "1:ffffff",
+        help_heading = "Sprites"
+    ))]
+    pub outline_style: SpriteDerivation,
+    /// Adds mirrored variants of sprites like `hero#flip-x`, see
+    /// `inputs::flips` for the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub flips: Option<PathBuf>,
+    /// Attaches game specific attributes to sprites.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub user_data: Option<PathBuf>,
+    /// Tags sprites with blend modes or materials, see `inputs::materials` for
+    /// the format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub materials: Option<PathBuf>,
+    /// Only packs sprites that are referenced in the file.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Sprites")
+    )]
+    pub prune: Option<PathBuf>,
+    /// Adds an opaque white 2x2 sprite named `white_pixel` for drawing
+    /// untextured quads, after pruning so it's always there.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Sprites"))]
+    pub white_pixel: bool,
+    /// Reports how much of every source image no glyph or sprite uses
+    /// anymore, after filtering and pruning.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Sprites"))]
+    pub source_usage: bool,
+
+    /// Enforces the certification limit of the platform (2048, 4096 or 8192),
+    /// which is also the default --max-size.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "handheld|console|pc", help_heading = "Packing")
+    )]
+    pub platform: Option<Platform>,
+    /// The longest side the atlas may grow to [default: 1024].
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "PX", help_heading = "Packing")
+    )]
+    pub max_size: Option<i32>,
+    /// Keeps the atlas from getting too long, e.g. 1 for square atlases.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "RATIO", help_heading = "Packing")
+    )]
+    pub max_aspect_ratio: Option<f32>,
+    /// Picks which sides of the atlas grow, `free` allows any power of two
+    /// shape like 2048x512 if it saves area.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "square|free", help_heading = "Packing")
+    )]
+    pub growth: Option<AtlasGrowth>,
+    /// Constrains the size of the atlas, `any` crops it to the area the
+    /// sprites use for engines without POT requirements.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "size", value_name = "pot|square|any", help_heading = "Packing")
+    )]
+    pub size_constraint: Option<SizeConstraint>,
+    /// Keeps empty pixels around every sprite.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "PX|TOP,BOTTOM,LEFT,RIGHT",
+            help_heading = "Packing"
+        )
+    )]
+    pub padding: Option<math::IMargins>,
+    /// Keeps empty pixels between neighbouring sprites.
+    #[cfg_attr(feature = "cli", arg(
+        long,
+        value_name = "PX",
+        value_parser = clap::value_parser!(i32).range(0..),
+        help_heading = "Packing"
+    ))]
+    pub spacing: Option<i32>,
+    /// Repeats the border pixels of every sprite that far into its padding
+    /// against bleeding with linear filtering, raising the padding to fit.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "PX", help_heading = "Packing")
+    )]
+    pub extrude: Option<i32>,
+    /// Packs sprites from the same source image or of a similar color next to
+    /// each other for better png compression.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "sheet|color", help_heading = "Packing")
+    )]
+    pub cluster: Option<Clustering>,
+    /// Picks how sprites are arranged, `skyline` packs many thin and tall
+    /// sprites tighter. `best` tries rows, skyline and MaxRects with several
+    /// sort orders and keeps the smallest result.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "rows|skyline|best", help_heading = "Packing")
+    )]
+    pub packer: Option<PackAlgorithm>,
+    /// How many of the attempts of `--packer best` to try.
+    #[cfg_attr(feature = "cli", arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help_heading = "Packing"
+    ))]
+    pub pack_attempts: Option<u32>,
+    /// Packs pixel-identical sprites only once, their entries in the meta
+    /// share a rect (see `duplicate_of`).
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Packing"))]
+    pub dedup: bool,
+    /// Spills into more atlases of --max-size, written as `atlas-<page>.png`,
+    /// instead of failing once everything doesn't fit.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Packing"))]
+    pub pages: bool,
+    /// Packs into as many equally sized layers as needed, written as
+    /// `atlas.ktx2` (named after --out).
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "WIDTHxHEIGHT", help_heading = "Packing")
+    )]
+    pub texture_array: Option<math::ISize>,
+    /// Writes the layers of --texture-array as `atlas-<layer>.png`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, requires = "texture_array", help_heading = "Packing")
+    )]
+    pub texture_array_pngs: bool,
+    /// Only puts sprites of one material on every layer of --texture-array.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Packing"))]
+    pub material_pages: bool,
+    /// Writes how well the atlas is used as JSON, with the waste of every row.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Packing")
+    )]
+    pub occupancy_report: Option<PathBuf>,
+
+    /// The atlas texture, the texture array and raw texture files are named
+    /// after it.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "FILE",
+            default_value = "atlas.png",
+            help_heading = "Output"
+        )
+    )]
+    pub out: PathBuf,
+    /// Where the metadata goes, repeated for more than one format
+    /// [default: --out with a `.json` extension].
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Output")
+    )]
+    pub meta: Vec<PathBuf>,
+    /// Reduces the colors of the atlas to what the format can store and
+    /// errors for sprites it would ruin.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FORMAT", help_heading = "Output")
+    )]
+    pub pixel_format: Option<PixelFormat>,
+    /// Hides the banding of --pixel-format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "MODE", help_heading = "Output")
+    )]
+    pub dither: Option<Dithering>,
+    /// Also writes the packed pixels with a small header to `atlas.bin`.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Output"))]
+    pub raw_texture: bool,
+    #[cfg_attr(feature = "cli", command(flatten, next_help_heading = "Output"))]
+    pub png: PngArgs,
+    /// Shrinks the exported UV rects, e.g. by 0.5. Sprites too small for it
+    /// collapse to their center, with a warning.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "TEXELS", help_heading = "Output")
+    )]
+    pub uv_inset: Option<f32>,
+    /// Declares how the runtime samples the atlas, which warns if that bleeds
+    /// between sprites.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "nearest|linear", help_heading = "Output")
+    )]
+    pub filtering: Option<sampling::Filtering>,
+    /// The mip levels --filtering samples.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "COUNT",
+            default_value_t = 0,
+            help_heading = "Output"
+        )
+    )]
+    pub mip_levels: u32,
+    /// Writes the rects of the meta, --template and --occupancy-report as
+    /// `{ min, max }` like older versions, for games that can't read
+    /// `{ x, y, w, h }` yet.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Output"))]
+    pub legacy_rects: bool,
+    /// Reads every written meta back and checks that it describes the same
+    /// atlas, in every format.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Output"))]
+    pub verify_roundtrip: bool,
+    /// Where the list of written files goes [default: --out with a
+    /// `.pack-manifest.json` extension].
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Output")
+    )]
+    pub pack_manifest: Option<PathBuf>,
+
+    /// Renders a Handlebars template with the atlas metadata into the file
+    /// without the `.hbs` (needs the `templates` feature).
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE.hbs", help_heading = "Exporters")
+    )]
+    pub template: Option<PathBuf>,
+    /// Limits --template to part of the atlas, e.g. `fonts,ui/*`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILTER", help_heading = "Exporters")
+    )]
+    pub template_include: Option<ContentFilter>,
+    /// Writes every animation as a strip png.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "DIR", help_heading = "Exporters")
+    )]
+    pub flipbook_strips: Option<PathBuf>,
+    /// Writes every animation as a grid png for GPU flipbook sampling, plus
+    /// the grid sizes in `flipbooks.json`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "DIR", help_heading = "Exporters")
+    )]
+    pub flipbook_grids: Option<PathBuf>,
+    /// Writes the --flipbook-grids as KTX2 volume textures instead.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, requires = "flipbook_grids", help_heading = "Exporters")
+    )]
+    pub flipbook_volumes: bool,
+    /// Limits both kinds of flipbooks to part of the atlas.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILTER", help_heading = "Exporters")
+    )]
+    pub flipbook_include: Option<ContentFilter>,
+    /// Converts every sprite into 8x8 tiles and palettes for GBA/NDS hardware
+    /// sprites.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "DIR", help_heading = "Exporters")
+    )]
+    pub gba_tiles: Option<PathBuf>,
+    /// The color depth of --gba-tiles.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "4bpp|8bpp",
+            default_value = "4bpp",
+            help_heading = "Exporters"
+        )
+    )]
+    pub gba_depth: TileDepth,
+    /// Limits --gba-tiles to part of the atlas.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILTER", help_heading = "Exporters")
+    )]
+    pub gba_include: Option<ContentFilter>,
+    /// Arranges the named sprites on a spritesheet with the fixed palette of
+    /// --fantasy-console.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "DIR", help_heading = "Exporters")
+    )]
+    pub fantasy_sheet: Option<PathBuf>,
+    /// The console whose palette and sheet size --fantasy-sheet uses.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long,
+            value_name = "pico8|tic80",
+            default_value = "pico8",
+            help_heading = "Exporters"
+        )
+    )]
+    pub fantasy_console: FantasyConsole,
+    /// Only puts the sprites named in the file on the --fantasy-sheet.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Exporters")
+    )]
+    pub fantasy_sprites: Option<PathBuf>,
+    /// Limits which sprites the --fantasy-sheet considers.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILTER", help_heading = "Exporters")
+    )]
+    pub fantasy_include: Option<ContentFilter>,
+    /// Writes previews of the named sprites to `atlas.thumbs.json` for editor
+    /// asset pickers, as base64 pngs.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Exporters"))]
+    pub thumbnails: bool,
+    /// The longest side of the --thumbnails.
+    #[cfg_attr(feature = "cli", arg(
+        long,
+        value_name = "PX",
+        default_value_t = outputs::thumbnails::DEFAULT_THUMBNAIL_SIZE,
+        help_heading = "Exporters"
+    ))]
+    pub thumbnail_size: u32,
+    /// Limits --thumbnails to part of the atlas.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILTER", help_heading = "Exporters")
+    )]
+    pub thumbnails_include: Option<ContentFilter>,
+    /// Writes the atlas as a Phaser 3 multiatlas, one texture per page, for
+    /// `this.load.multiatlas`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE.json", help_heading = "Exporters")
+    )]
+    pub phaser: Option<PathBuf>,
+    /// Limits --phaser to part of the atlas.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILTER", help_heading = "Exporters")
+    )]
+    pub phaser_include: Option<ContentFilter>,
+
+    /// Keeps running and rebuilds the atlas whenever one of the files it was
+    /// built from changes. Edits that only change the pixels of an image,
+    /// e.g. a page png of a font, only rebuild the texture.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Build"))]
+    pub watch: bool,
+    /// Prints how long every phase took.
+    #[cfg_attr(feature = "cli", arg(long, help_heading = "Build"))]
+    pub timings: bool,
+    /// Writes a trace for chrome://tracing (needs the `chrome-trace`
+    /// feature).
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "FILE", help_heading = "Build")
+    )]
+    pub trace_chrome: Option<PathBuf>,
+
+    /// The directory relative paths are resolved against, the working
+    /// directory if empty. Project builds set it to the directory of the
+    /// project file.
+    #[cfg_attr(feature = "cli", arg(skip))]
+    pub dir: PathBuf,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            project: Default::default(),
+            workspace: Default::default(),
+            target: Default::default(),
+            features: Default::default(),
+            verify: Default::default(),
+            unpack: Default::default(),
+            unpack_dir: PathBuf::from("unpacked"),
+            preview_font: Default::default(),
+            preview_text: "The quick brown fox jumps over the lazy dog".to_string(),
+            input: Default::default(),
+            sprite: Default::default(),
+            unity_sheet: Default::default(),
+            slices: Default::default(),
+            procedural: Default::default(),
+            icon_font: Default::default(),
+            icon_size: 16,
+            remote_font: Default::default(),
+            remote_cache: Default::default(),
+            append: Default::default(),
+            merge: Default::default(),
+            lockfile: Default::default(),
+            frozen: Default::default(),
+            font_name: Default::default(),
+            monospace: Default::default(),
+            check_monospace: Default::default(),
+            line_shelves: Default::default(),
+            decorations: Default::default(),
+            dynamic_slots: Default::default(),
+            glyph_effect: Default::default(),
+            metric_conflicts: Default::default(),
+            ragged_frames: Default::default(),
+            keep_chars: Default::default(),
+            line_sprites: Default::default(),
+            font_regions: Default::default(),
+            alpha_glyphs: Default::default(),
+            standalone_font: Default::default(),
+            target_scale: Default::default(),
+            resampler: Default::default(),
+            palettes: Default::default(),
+            adjustments: Default::default(),
+            trim: Default::default(),
+            shadows: Default::default(),
+            shadow_style: SpriteDerivation::Shadow {
+                blur_radius: 2,
+                color: [0, 0, 0, 0x80],
+            },
+            outlines: Default::default(),
+            outline_style: SpriteDerivation::Outline {
+                thickness: 1,
+                color: [0xff; 4],
+            },
+            flips: Default::default(),
+            user_data: Default::default(),
+            materials: Default::default(),
+            prune: Default::default(),
+            white_pixel: Default::default(),
+            source_usage: Default::default(),
+            platform: Default::default(),
+            max_size: Default::default(),
+            max_aspect_ratio: Default::default(),
+            growth: Default::default(),
+            size_constraint: Default::default(),
+            padding: Default::default(),
+            spacing: Default::default(),
+            extrude: Default::default(),
+            cluster: Default::default(),
+            packer: Default::default(),
+            pack_attempts: Default::default(),
+            dedup: Default::default(),
+            pages: Default::default(),
+            texture_array: Default::default(),
+            texture_array_pngs: Default::default(),
+            material_pages: Default::default(),
+            occupancy_report: Default::default(),
+            out: PathBuf::from("atlas.png"),
+            meta: Default::default(),
+            pixel_format: Default::default(),
+            dither: Default::default(),
+            raw_texture: Default::default(),
+            png: Default::default(),
+            uv_inset: Default::default(),
+            filtering: Default::default(),
+            mip_levels: 0,
+            legacy_rects: Default::default(),
+            verify_roundtrip: Default::default(),
+            pack_manifest: Default::default(),
+            template: Default::default(),
+            template_include: Default::default(),
+            flipbook_strips: Default::default(),
+            flipbook_grids: Default::default(),
+            flipbook_volumes: Default::default(),
+            flipbook_include: Default::default(),
+            gba_tiles: Default::default(),
+            gba_depth: TileDepth::Bpp4,
+            gba_include: Default::default(),
+            fantasy_sheet: Default::default(),
+            fantasy_console: FantasyConsole::Pico8,
+            fantasy_sprites: Default::default(),
+            fantasy_include: Default::default(),
+            thumbnails: Default::default(),
+            thumbnail_size: outputs::thumbnails::DEFAULT_THUMBNAIL_SIZE,
+            thumbnails_include: Default::default(),
+            phaser: Default::default(),
+            phaser_include: Default::default(),
+            watch: Default::default(),
+            timings: Default::default(),
+            trace_chrome: Default::default(),
+            dir: Default::default(),
+        }
+    }
+}
+
+impl PackOptions {
+    /// `file` relative to [`Self::dir`].
+    pub fn path<P: AsRef<Path>>(&self, file: P) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    /// Where the pack manifest goes, next to the texture unless
+    /// --pack-manifest says otherwise.
+    pub fn manifest_path(&self) -> PathBuf {
+        match &self.pack_manifest {
+            Some(file) => self.path(file),
+            None => self.path(&self.out).with_extension("pack-manifest.json"),
+        }
+    }
+}
+
+/// Draws every png matching a pattern over the sprite named after it in the
+/// texture of the exported atlas, `<name>_<frame>.png` for frames of
+/// animations. Nothing moves and the meta stays the same, so it's only for
+/// sprites that kept their size. A later --append of the atlas picks up the
+/// new pixels.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct RepackOptions {
+    /// The meta of the atlas.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "FILE"))]
+    pub meta: PathBuf,
+    /// The pngs to draw, e.g. `sprites/hero/*.png`.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "PATTERN", required = true))]
+    pub only: Vec<String>,
+    /// Should match the --extrude of the pack.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "PX", default_value_t = 0))]
+    pub extrude: i32,
+    /// The list of written files of the pack, updated with the new hash of
+    /// the texture [default: the texture with a `.pack-manifest.json`
+    /// extension].
+    #[cfg_attr(feature = "cli", arg(long, value_name = "FILE"))]
+    pub pack_manifest: Option<PathBuf>,
+    #[cfg_attr(feature = "cli", command(flatten))]
+    pub png: PngArgs,
+}
+
+/// Parses `<key>=<value>`, e.g. the `<face>=<name>` of --font-name.
+#[cfg(feature = "cli")]
+fn key_value<T>(s: &str) -> anyhow::Result<(String, T)>
+where
+    T: std::str::FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    let (key, value) = s
+        .split_once('=')
+        .with_context(|| format!("expected <key>=<value>, got '{s}'"))?;
+
+    Ok((key.to_string(), value.parse().map_err(Into::into)?))
+}
+
+/// An atlas [`build`] packed and exported, for [`watch`] to rebuild the
+/// texture of.
+pub struct Build {
+    pub atlas: TextureAtlas,
+    pub sources: sources::Sources,
+    /// Every file the build wrote.
+    pub manifest: PackManifest,
+    /// The pngs of the layers or pages, if they're written as pngs.
+    pub layer_files: Vec<String>,
+    /// What the sources and the atlas warned about, for the caller to show.
+    pub warnings: Vec<warnings::Warning>,
+}
+
+/// Builds what `options` asks for: a report on or the contents of an
+/// exported atlas, or a new atlas, which is returned for [`watch`]. The
+/// atlases of a `--project` or `--workspace` are built by [`build_project`]
+/// and [`build_workspace`] instead.
+pub fn build(
+    options: &PackOptions,
+    cancel: &cancellation::CancellationToken,
+) -> anyhow::Result<Option<Build>> {
+    if options.project.is_some() || options.workspace.is_some() {
+        error::Error::InvalidOption(
+            "Projects and workspaces are built by build_project and build_workspace".to_string(),
+        )
+        .raise()?;
+    }
+
+    let png_options = options.png.options();
+    // Every file with rects writes them like the meta
+    let rect_layout = match options.legacy_rects {
+        true => math::RectLayout::MinMax,
+        false => math::RectLayout::Sized,
+    };
+
+    if let Some(meta_file) = options.verify.as_deref() {
+        let meta_file = &options.path(meta_file);
+        // Texture arrays can't be loaded back, so only their meta is checked
+        let meta = AtlasMeta::load(meta_file)?;
+        if meta.layer_count > 0 {
+            meta.verify()
+                .with_context(|| format!("Invalid atlas meta '{}'", meta_file.display()))?;
+        } else {
+            AtlasMeta::load_atlas(meta_file, &mut sources::Sources::new())?;
+        }
+
+        println!("INFO: '{}' is valid", meta_file.display());
+        return Ok(None);
+    }
+
+    if let Some(meta_file) = options.unpack.as_deref() {
+        let meta_file = &options.path(meta_file);
+        let unpack_dir = options.path(&options.unpack_dir);
+        let mut sources = sources::Sources::new();
+        let (_, atlas) = AtlasMeta::load_atlas(meta_file, &mut sources)?;
+
+        std::fs::create_dir_all(&unpack_dir)
+            .with_context(|| format!("Failed to create '{}'", unpack_dir.display()))?;
+
+        for sprite in atlas.sprites.iter() {
+            for (index, frame) in sprite.frames.iter().enumerate() {
+                let file_name = match sprite.frames.len() {
+                    1 => format!("{}.png", sprite.name),
+                    _ => format!("{}_{index}.png", sprite.name),
+                };
+
+                image_io::write_png(
+                    &unpack_dir.join(file_name),
+                    &frame.get_image(&sources)?.to_image(),
+                    &png_options,
+                    cancel,
+                )
+                .with_context(|| format!("Failed to unpack sprite '{}'", sprite.name))?;
+            }
+        }
+
+        for label in atlas.label_sets.iter().flat_map(|set| set.labels.iter()) {
+            let file_name = format!("{}.{}.png", label.key, label.language);
+
+            image_io::write_png(
+                &unpack_dir.join(file_name),
+                &label.image,
+                &png_options,
+                cancel,
+            )
+            .with_context(|| format!("Failed to unpack label '{}'", label.key))?;
+        }
+
+        return Ok(None);
+    }
+
+    if let Some(meta_file) = options.preview_font.as_deref() {
+        let meta_file = &options.path(meta_file);
+        let mut sources = sources::Sources::new();
+        let (_, atlas) = AtlasMeta::load_atlas(meta_file, &mut sources)?;
+
+        for font in atlas.fonts.iter() {
+            font.render_text(&options.preview_text, &sources)?
+                .save(options.path(format!("preview-{}.png", font.name)))
+                .with_context(|| format!("Failed to save preview of font '{}'", font.name))?;
+        }
+
+        return Ok(None);
+    }
+
+    let has_inputs = !options.input.is_empty()
+        || !options.sprite.is_empty()
+        || !options.unity_sheet.is_empty()
+        || !options.slices.is_empty()
+        || options.procedural.is_some()
+        || options.icon_font.is_some()
+        || !options.remote_font.is_empty()
+        || options.append.is_some()
+        || !options.merge.is_empty();
+    if !has_inputs {
+        error::Error::InvalidOption("Nothing to pack, pass at least one --input".to_string())
+            .raise()?;
+    }
+
+    let load_span = tracing::info_span!("load").entered();
+
+    let mut sources = sources::Sources::new();
+    // Every file written from here on ends up in the pack manifest
+    let mut manifest = PackManifest::new().relative_to(&options.dir);
+
+    let appended_atlas = options
+        .append
+        .as_ref()
+        .map(|meta_file| {
+            AtlasMeta::load_atlas(options.path(meta_file), &mut sources).map(|(_, atlas)| atlas)
+        })
+        .transpose()?;
+    let merged_atlases: Vec<(&str, TextureAtlas)> = options
+        .merge
+        .iter()
+        .map(|(namespace, meta_file)| {
+            let (_, atlas) = AtlasMeta::load_atlas(options.path(meta_file), &mut sources)
+                .with_context(|| format!("Failed to merge '{}'", meta_file.display()))?;
+
+            Ok((namespace.as_str(), atlas))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut input_fonts = vec![];
+    let mut input_sprites = vec![];
+    for path in options.input.iter() {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("fnt") => {
+                let id = sources
+                    .try_load_source(options.path(path))
+                    .with_context(|| format!("Failed to load font '{}'", path.display()))?;
+                input_fonts.push(font::FontIntermediate::from_fnt(id, &sources)?);
+            }
+            Some("png" | "svg") => input_sprites.push(path.as_path()),
+            _ => {
+                error::Error::InvalidOption(format!(
+                    "Unsupported input '{}', expected a .fnt, .png or .svg file",
+                    path.display()
+                ))
+                .raise()?;
+            }
+        }
+    }
+
+    let mut icon_fonts = vec![];
+    if let Some(icon_font_path) = options.icon_font.as_deref() {
+        let icon_font_path = &options.path(icon_font_path);
+        let contents = std::fs::read_to_string(icon_font_path)
+            .with_context(|| format!("Failed to read icon font '{}'", icon_font_path.display()))?;
+        let icon_font = inputs::icon_font::IconFontFile::try_parse(&contents)
+            .with_context(|| format!("Failed to parse icon font '{}'", icon_font_path.display()))?;
+
+        let name = icon_font_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .with_context(|| {
+                format!("Invalid icon font file name '{}'", icon_font_path.display())
+            })?;
+
+        let mut icons = vec![];
+        for icon in icon_font.icons.iter() {
+            let id = sources
+                .try_load_source(icon_font_path.with_file_name(&icon.file))
+                .with_context(|| format!("Failed to load icon '{}'", icon.name))?;
+            icons.push((id, icon.code_point));
+        }
+
+        icon_fonts.push(font::FontIntermediate::from_icons(
+            name.to_string(),
+            &icons,
+            options.icon_size,
+            &sources,
+        )?);
+
+        let table_path = format!("{name}.icons.json");
+        let table = serde_json::to_vec_pretty(&icon_font.table(name))
+            .context("Failed to serialize icon table")?;
+        hashing::write_if_changed(options.path(&table_path), table)
+            .with_context(|| format!("Failed to write '{table_path}'"))?;
+        manifest.record(options.path(&table_path))?;
+    }
+
+    let remote_fonts = load_remote_fonts(
+        &mut sources,
+        &options.remote_font,
+        options
+            .remote_cache
+            .as_ref()
+            .map(|dir| options.path(dir))
+            .as_deref(),
+        &options.path(LOCKFILE_PATH),
+    )?;
+
+    let sprite_images: Vec<(&str, sources::SourceId)> = options
+        .sprite
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(input_sprites)
+        .map(|path| {
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .with_context(|| format!("Invalid sprite file name '{}'", path.display()))?;
+            let id = sources
+                .try_load_source(options.path(path))
+                .with_context(|| format!("Failed to load sprite '{}'", path.display()))?;
+
+            Ok((name, id))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let unity_sheets: Vec<(&Path, sources::SourceId)> = options
+        .unity_sheet
+        .iter()
+        .map(|file| {
+            let id = sources
+                .try_load_source(options.path(file))
+                .with_context(|| {
+                    format!("Failed to load Unity sprite sheet '{}'", file.display())
+                })?;
+
+            Ok((file.as_path(), id))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let sliced_images: Vec<(&Path, sources::SourceId)> = options
+        .slices
+        .iter()
+        .map(|file| {
+            let id = sources
+                .try_load_source(options.path(file))
+                .with_context(|| format!("Failed to load sliced image '{}'", file.display()))?;
+
+            Ok((file.as_path(), id))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    if options.frozen {
+        Lockfile::load(options.path(LOCKFILE_PATH))
+            .context("--frozen requires an existing lockfile")?
+            .check_frozen(&sources)
+            .context("Refusing to build with --frozen")?;
+    } else if options.lockfile {
+        Lockfile::from_sources(&sources).save(options.path(LOCKFILE_PATH))?;
+        manifest.record(options.path(LOCKFILE_PATH))?;
+    }
+
+    drop(load_span);
+
+    let mut atlas = appended_atlas.unwrap_or_else(|| TextureAtlas::new(math::IMargins::uniform(0)));
+    for (namespace, merged_atlas) in merged_atlases {
+        atlas.merge(merged_atlas, namespace);
+    }
+    atlas.max_aspect_ratio = options.max_aspect_ratio;
+    // The certification limit of the platform is also the default max size
+    if let Some(platform) = options.platform {
+        atlas.platform = Some(platform);
+        atlas.max_size = platform.max_texture_size();
+    }
+    if let Some(max_size) = options.max_size {
+        atlas.max_size = max_size;
+    }
+    if let Some(growth) = options.growth {
+        atlas.growth = growth;
+    }
+    if let Some(size_constraint) = options.size_constraint {
+        atlas.size_constraint = size_constraint;
+    }
+    if let Some(padding) = options.padding {
+        atlas.padding = padding;
+    }
+    if let Some(spacing) = options.spacing {
+        atlas.spacing = spacing;
+    }
+    // Extruded pixels need the padding to fit into
+    if let Some(extrude) = options.extrude {
+        atlas.extrude = extrude;
+
+        let padding = &mut atlas.padding;
+        for side in [
+            &mut padding.top,
+            &mut padding.bottom,
+            &mut padding.left,
+            &mut padding.right,
+        ] {
+            *side = (*side).max(atlas.extrude);
+        }
+    }
+
+    // Glyphs sampled from the alpha channel alone need their own block
+    atlas.alpha_glyphs = options.alpha_glyphs;
+    atlas.font_regions |= atlas.alpha_glyphs || options.font_regions;
+    atlas.clustering = options.cluster;
+    if let Some(packer) = options.packer {
+        atlas.pack_algorithm = packer;
+    }
+    if let Some(attempts) = options.pack_attempts {
+        if !matches!(atlas.pack_algorithm, PackAlgorithm::Best { .. }) {
+            error::Error::InvalidOption("--pack-attempts needs --packer best".to_string())
+                .raise()?;
+        }
+
+        atlas.pack_algorithm = PackAlgorithm::Best { attempts };
+    }
+
+    // Control characters, byte order marks and zero-width code points are
+    // skipped unless they're kept
+    let keep_chars = options
+        .keep_chars
+        .as_deref()
+        .map(font::parse_char_codes)
+        .transpose()?
+        .unwrap_or_default();
+
+    for mut font in input_fonts
+        .into_iter()
+        .chain(remote_fonts)
+        .chain(icon_fonts)
+    {
+        if let Some((_, name)) = options
+            .font_name
+            .iter()
+            .find(|(face, _)| *face == font.family)
+        {
+            font = font.with_name(name.clone());
+        }
+
+        if let Some(&(_, decorations)) = options
+            .decorations
+            .iter()
+            .find(|(name, _)| *name == font.name)
+        {
+            font = font.with_decorations(decorations);
+        }
+
+        if options.monospace.contains(&font.name) {
+            font = font.with_monospace(font::Monospace::Force)?;
+        } else if options.check_monospace.contains(&font.name) {
+            font = font.with_monospace(font::Monospace::Validate)?;
+        }
+
+        if options.line_shelves.contains(&font.name) {
+            font = font.with_line_shelves()?;
+        }
+
+        if let Some(effect) = options.glyph_effect {
+            font = font.with_effect_frames(effect, &sources)?;
+        }
+
+        font = font.with_ragged_frames(options.ragged_frames.unwrap_or_default());
+        atlas
+            .warnings
+            .extend(font.skip_invisible_chars(&keep_chars));
+
+        let conflicts = font.check_frame_metrics(options.metric_conflicts.unwrap_or_default())?;
+        atlas.warnings.extend(conflicts);
+
+        atlas.fonts.retain(|existing| existing.name != font.name);
+
+        match options.dynamic_slots {
+            Some(slots) => atlas.with_font(font.with_dynamic_slots(slots)),
+            None => atlas.with_font(font),
+        }
+    }
+
+    for &(file_name, id) in sprite_images.iter() {
+        let (name, scale) =
+            intermediates::sprite::SpriteIntermediate::split_scale_suffix(file_name);
+        let mut sprite =
+            intermediates::sprite::SpriteIntermediate::from_image(name.to_string(), id, &sources)?;
+        sprite.scale = scale;
+
+        atlas
+            .sprites
+            .retain(|existing| existing.name != sprite.name);
+        atlas.with_sprite(sprite);
+    }
+
+    if let Some(procedural_file) = options.procedural.as_deref() {
+        let procedural_file = &options.path(procedural_file);
+        let contents = std::fs::read_to_string(procedural_file).with_context(|| {
+            format!(
+                "Failed to read procedural sprites '{}'",
+                procedural_file.display()
+            )
+        })?;
+        let procedural =
+            inputs::procedural::ProceduralFile::try_parse(&contents).with_context(|| {
+                format!(
+                    "Failed to parse procedural sprites '{}'",
+                    procedural_file.display()
+                )
+            })?;
+
+        for sprite in procedural.sprites.iter() {
+            atlas
+                .sprites
+                .retain(|existing| existing.name != sprite.name);
+            atlas.with_sprite(intermediates::sprite::SpriteIntermediate::from_procedural(
+                sprite,
+            ));
+        }
+    }
+
+    for &(file, id) in unity_sheets.iter() {
+        let mut meta_file = OsString::from(options.path(file));
+        meta_file.push(".meta");
+        let meta_file = PathBuf::from(meta_file);
+
+        let contents = std::fs::read_to_string(&meta_file)
+            .with_context(|| format!("Failed to read Unity meta file '{}'", meta_file.display()))?;
+        let image = sources.get_image(id)?;
+        let texture_name = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .with_context(|| format!("Invalid sprite file name '{}'", file.display()))?;
+        let sheet = inputs::unity_meta::UnitySpriteSheet::try_parse(
+            &contents,
+            texture_name,
+            image.width() as i32,
+            image.height() as i32,
+        )
+        .with_context(|| format!("Failed to parse Unity meta file '{}'", meta_file.display()))?;
+
+        let sprites =
+            intermediates::sprite::SpriteIntermediate::from_unity_sheet(&sheet, id, &sources)
+                .with_context(|| format!("Invalid Unity sprite sheet '{}'", file.display()))?;
+        println!(
+            "INFO: Imported {} sprites from '{}'",
+            sprites.len(),
+            meta_file.display()
+        );
+
+        for sprite in sprites {
+            atlas
+                .sprites
+                .retain(|existing| existing.name != sprite.name);
+            atlas.with_sprite(sprite);
+        }
+    }
+
+    for &(file, id) in sliced_images.iter() {
+        let slices_path = options.path(file).with_extension("slices.json");
+        let contents = std::fs::read_to_string(&slices_path)
+            .with_context(|| format!("Failed to read slices file '{}'", slices_path.display()))?;
+        let slices = inputs::slices::SlicesFile::try_parse(&contents)
+            .with_context(|| format!("Failed to parse slices file '{}'", slices_path.display()))?;
+
+        let sprites = intermediates::sprite::SpriteIntermediate::from_slices(&slices, id, &sources)
+            .with_context(|| format!("Invalid slices of '{}'", file.display()))?;
+        println!(
+            "INFO: Imported {} sprites from '{}'",
+            sprites.len(),
+            slices_path.display()
+        );
+
+        for sprite in sprites {
+            atlas
+                .sprites
+                .retain(|existing| existing.name != sprite.name);
+            atlas.with_sprite(sprite);
+        }
+    }
+
+    if let Some(target_scale) = options.target_scale {
+        let resamplers: Vec<(&str, Resampler)> = options
+            .resampler
+            .iter()
+            .map(|(name, resampler)| (name.as_str(), *resampler))
+            .collect();
+
+        atlas.normalize_scales(target_scale, &resamplers, &sources)?;
+    }
+
+    if options.line_sprites {
+        let line_sprites: Vec<_> = atlas.fonts.iter().map(|font| font.line_sprite()).collect();
+
+        for sprite in line_sprites {
+            atlas
+                .sprites
+                .retain(|existing| existing.name != sprite.name);
+            atlas.with_sprite(sprite);
+        }
+    }
+
+    if let Some(palettes_file) = options.palettes.as_deref() {
+        let palettes_file = &options.path(palettes_file);
+        let contents = std::fs::read_to_string(palettes_file)
+            .with_context(|| format!("Failed to read palettes '{}'", palettes_file.display()))?;
+        let palettes = inputs::palettes::PalettesFile::try_parse(&contents)
+            .with_context(|| format!("Failed to parse palettes '{}'", palettes_file.display()))?;
+
+        let recolored = atlas.swap_palettes(&palettes, &sources)?;
+        println!("INFO: Generated {} recolored sprite(s)", recolored.len());
+    }
+
+    if let Some(adjustments_file) = options.adjustments.as_deref() {
+        let adjustments_file = &options.path(adjustments_file);
+        let contents = std::fs::read_to_string(adjustments_file).with_context(|| {
+            format!(
+                "Failed to read adjustments '{}'",
+                adjustments_file.display()
+            )
+        })?;
+        let adjustments =
+            inputs::adjustments::AdjustmentsFile::try_parse(&contents).with_context(|| {
+                format!(
+                    "Failed to parse adjustments '{}'",
+                    adjustments_file.display()
+                )
+            })?;
+
+        let adjusted = atlas.adjust_colors(&adjustments, &sources)?;
+        println!("INFO: Adjusted the colors of {adjusted} font(s), label(s) and sprite(s)");
+    }
+
+    if let Some(trim_mode) = options.trim {
+        let saved_area = atlas.trim_sprites(trim_mode, &sources)?;
+        println!("INFO: Trimming saved {saved_area} px of sprite frames");
+    }
+
+    let derivations = [
+        (&options.shadows, options.shadow_style),
+        (&options.outlines, options.outline_style),
+    ];
+
+    for (names_file, derivation) in derivations {
+        let Some(names_file) = names_file else {
+            continue;
+        };
+        let names_file = &options.path(names_file);
+
+        let contents = std::fs::read_to_string(names_file)
+            .with_context(|| format!("Failed to read sprite names '{}'", names_file.display()))?;
+        let derived =
+            atlas.derive_sprites(derivation, &SpriteReferences::parse(&contents), &sources)?;
+
+        println!(
+            "INFO: Generated {} {} sprite(s)",
+            derived.len(),
+            derivation.suffix()
+        );
+    }
+
+    if let Some(flips_file) = options.flips.as_deref() {
+        let flips_file = &options.path(flips_file);
+        let contents = std::fs::read_to_string(flips_file)
+            .with_context(|| format!("Failed to read flips '{}'", flips_file.display()))?;
+        let flips = inputs::flips::FlipsFile::try_parse(&contents)
+            .with_context(|| format!("Failed to parse flips '{}'", flips_file.display()))?;
+
+        let flipped = atlas.flip_sprites(&flips, &sources)?;
+        println!("INFO: Generated {} flipped sprite(s)", flipped.len());
+    }
+
+    if let Some(user_data_file) = options.user_data.as_deref() {
+        let user_data_file = &options.path(user_data_file);
+        let contents = std::fs::read_to_string(user_data_file)
+            .with_context(|| format!("Failed to read user data '{}'", user_data_file.display()))?;
+        let user_data = inputs::user_data::UserDataFile::try_parse(&contents)
+            .with_context(|| format!("Failed to parse user data '{}'", user_data_file.display()))?;
+
+        atlas.apply_user_data(&user_data);
+    }
+
+    if let Some(materials_file) = options.materials.as_deref() {
+        let materials_file = &options.path(materials_file);
+        let contents = std::fs::read_to_string(materials_file)
+            .with_context(|| format!("Failed to read materials '{}'", materials_file.display()))?;
+        let materials = inputs::materials::MaterialsFile::try_parse(&contents)
+            .with_context(|| format!("Failed to parse materials '{}'", materials_file.display()))?;
+
+        atlas.apply_materials(&materials);
+    }
+    atlas.material_pages = options.material_pages;
+
+    if let Some(prune_file) = options.prune.as_deref() {
+        let prune_file = &options.path(prune_file);
+        let _span = tracing::info_span!("prune").entered();
+
+        let contents = std::fs::read_to_string(prune_file).with_context(|| {
+            format!(
+                "Failed to read sprite references '{}'",
+                prune_file.display()
+            )
+        })?;
+        let report = atlas.prune(&SpriteReferences::parse(&contents));
+
+        println!(
+            "INFO: Pruned {} unreferenced sprite(s), saving {} px (~{} KiB of RGBA8)",
+            report.removed.len(),
+            report.saved_area,
+            report.saved_area * 4 / 1024,
+        );
+
+        for name in report.removed.iter() {
+            println!("INFO:   {name}");
+        }
+    }
+
+    // After pruning, so it's always there
+    if options.white_pixel {
+        atlas.inject_white_pixel();
+    }
+
+    // After filtering and pruning
+    if options.source_usage {
+        let report = outputs::source_usage::SourceUsageReport::new(&atlas, &sources);
+
+        for line in report.to_string().lines() {
+            println!("INFO: {line}");
+        }
+    }
+
+    let pack_span = tracing::info_span!("pack").entered();
+
+    atlas.load_sizes().context("Failed to load sprite sizes")?;
+    if options.dedup {
+        let num_duplicates = atlas
+            .dedup_sprites(&sources)
+            .context("Failed to deduplicate sprites")?;
+        println!(
+            "INFO: Found {num_duplicates} duplicate sprite(s) among {}",
+            atlas.sprite_sizes.len() + num_duplicates
+        );
+    }
+    let num_clusters = atlas
+        .load_clusters(&sources)
+        .context("Failed to cluster sprites")?;
+    if atlas.clustering.is_some() {
+        println!(
+            "INFO: Grouped {} sprites into {num_clusters} cluster(s)",
+            atlas.sprite_sizes.len()
+        );
+    }
+
+    atlas.pages = options.pages;
+
+    if let Some(layer_size) = options.texture_array {
+        let layer_count = atlas.pack_layers(layer_size, cancel)?;
+        println!(
+            "INFO: Packed into {layer_count} layer(s) of {}x{}",
+            layer_size.width, layer_size.height
+        );
+    } else {
+        atlas.pack(cancel)?;
+
+        // Pages are all of the largest size anyway
+        let pot_cost = atlas.pot_cost_report();
+        if pot_cost.wasted_bytes > 0 && atlas.layout.layer_count == 0 {
+            println!(
+                "INFO: Rounding {}x{} up to {}x{} costs {} KiB of RGBA8",
+                pot_cost.used_size.width,
+                pot_cost.used_size.height,
+                pot_cost.size.width,
+                pot_cost.size.height,
+                pot_cost.wasted_bytes / 1024,
+            );
+
+            for suggestion in pot_cost.suggestions.iter() {
+                println!("INFO:   {suggestion}");
+            }
+        }
+    }
+
+    // Every build prints how well the atlas is used
+    let occupancy = atlas.occupancy_report();
+    let total_area = (occupancy.size.area() as i64 * occupancy.layers as i64).max(1) as f64;
+    println!(
+        "INFO: Sprites cover {:.1}% of the atlas, their padding another {:.1}%",
+        occupancy.occupancy * 100.0,
+        occupancy.padding_area as f64 / total_area * 100.0,
+    );
+    let (layer, largest_empty) = occupancy.largest_empty;
+    println!(
+        "INFO: The largest empty area is {}x{} at {},{}{}",
+        largest_empty.width(),
+        largest_empty.height(),
+        largest_empty.min.x,
+        largest_empty.min.y,
+        match occupancy.layers {
+            1 => String::new(),
+            _ => format!(" of layer {layer}"),
+        },
+    );
+    if let Some(row) = occupancy.rows.iter().max_by_key(|row| row.empty_area) {
+        println!(
+            "INFO: The emptiest of {} row(s) starts at y {} and leaves {} px empty",
+            occupancy.rows.len(),
+            row.y,
+            row.empty_area,
+        );
+    }
+    for suggestion in occupancy.suggestions.iter() {
+        println!("INFO:   {suggestion}");
+    }
+
+    if let Some(report_file) = options.occupancy_report.as_deref() {
+        let report_file = &options.path(report_file);
+        let json = math::with_rect_layout(rect_layout, || serde_json::to_vec_pretty(&occupancy))
+            .context("Failed to serialize the occupancy report")?;
+        hashing::write_if_changed(report_file, json).with_context(|| {
+            format!(
+                "Failed to write occupancy report '{}'",
+                report_file.display()
+            )
+        })?;
+        manifest.record(report_file)?;
+    }
+
+    drop(pack_span);
+
+    let texture_path = options.path(&options.out);
+    let mut meta_files: Vec<PathBuf> = options.meta.iter().map(|file| options.path(file)).collect();
+    if meta_files.is_empty() {
+        meta_files = vec![texture_path.with_extension("json")];
+    }
+
+    // Pages are just a texture array written as pngs
+    let layered = atlas.layout.layer_count > 0;
+    let layer_pngs = match options.texture_array {
+        Some(_) => options.texture_array_pngs,
+        None => layered,
+    };
+
+    let layer_files: Vec<String> = match layer_pngs {
+        true => {
+            let stem = texture_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("atlas");
+
+            (0..atlas.layout.layer_count)
+                .map(|layer| {
+                    texture_path
+                        .with_file_name(format!("{stem}-{layer}.png"))
+                        .display()
+                        .to_string()
+                })
+                .collect()
+        }
+        false => vec![],
+    };
+
+    let texture_output =
+        texture_output(options, &texture_path, layered, &layer_files, &png_options);
+
+    let atlas_images = build_textures(&atlas, &sources, &texture_output, cancel)?;
+
+    let export_span = tracing::info_span!("export").entered();
+
+    let texture_file = write_textures(&atlas_images, &texture_output, cancel, &mut manifest)?;
+
+    // Texture paths in the meta are relative to it
+    let meta_path = &meta_files[0];
+    let atlas_name = meta_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("atlas");
+
+    let mut atlas_meta = AtlasMeta::from_texture_atlas(
+        atlas_name.to_string(),
+        relative_to_meta(Path::new(&texture_file), meta_path),
+        &atlas,
+        &atlas.layout,
+    )
+    .context("Failed to generate AtlasMeta from texture atlas")?;
+    atlas_meta.layer_files = layer_files
+        .iter()
+        .map(|file| relative_to_meta(Path::new(file), meta_path))
+        .collect();
+    atlas_meta.pixel_format = texture_output.pixel_format;
+
+    if let Some(uv_inset) = options.uv_inset {
+        let count = atlas_meta.set_uv_inset(uv_inset)?;
+        if count > 0 {
+            atlas.warnings.push(warnings::Warning::ClampedUvInset {
+                inset: uv_inset,
+                count,
+            });
+        }
+    }
+
+    if let Some(filtering) = options.filtering {
+        let sampling = sampling::Sampling {
+            filtering,
+            mip_levels: options.mip_levels,
+        };
+
+        atlas.warnings.extend(sampling::lint(
+            &sampling,
+            atlas.padding,
+            atlas.spacing,
+            atlas_meta.uv_inset,
+        ));
+    }
+
+    atlas_meta.build_info = Some(BuildInfo::new(&atlas, &sources));
+
+    atlas_meta.rect_layout = rect_layout;
+
+    for meta_file in meta_files.iter() {
+        atlas_meta.save(meta_file)?;
+        manifest.record(meta_file)?;
+    }
+
+    if options.verify_roundtrip {
+        atlas_meta.verify_round_trip()?;
+
+        for meta_file in meta_files.iter() {
+            if AtlasMeta::load(meta_file)? != atlas_meta {
+                error::Ewwow.raise().with_context(|| {
+                    format!("'{}' doesn't match the packed atlas", meta_file.display())
+                })?;
+            }
+        }
+
+        println!("INFO: The atlas meta survives a round trip in every format");
+    }
+
+    // The warnings of standalone fonts were already reported for the merged
+    // atlas
+    for name in options.standalone_font.iter() {
+        let mut font_atlas = atlas
+            .standalone_font(name)
+            .ok_or(error::Ewwow)
+            .with_context(|| format!("--standalone-font '{name}' is not a loaded font"))?;
+
+        font_atlas.load_sizes()?;
+        font_atlas
+            .pack(cancel)
+            .with_context(|| format!("Failed to pack standalone font '{name}'"))?;
+
+        let texture_file = format!("font-{name}.png");
+        let texture_path = options.path(&texture_file);
+        let output = TextureOutput {
+            texture_array: false,
+            layer_files: &[],
+            raw_texture: false,
+            texture_path: &texture_path,
+            ..texture_output
+        };
+        let images = build_textures(&font_atlas, &sources, &output, cancel)?;
+
+        image_io::write_png(&texture_path, &images[0], &png_options, cancel)
+            .with_context(|| format!("Failed to save standalone font '{name}'"))?;
+        manifest.record(&texture_path)?;
+
+        let mut font_meta = AtlasMeta::from_texture_atlas(
+            format!("font-{name}"),
+            texture_file.clone(),
+            &font_atlas,
+            &font_atlas.layout,
+        )?;
+        font_meta.pixel_format = output.pixel_format;
+        font_meta.build_info = Some(BuildInfo::new(&font_atlas, &sources));
+        font_meta.rect_layout = atlas_meta.rect_layout;
+
+        let meta_file = options.path(format!("font-{name}.json"));
+        font_meta.save(&meta_file)?;
+        manifest.record(&meta_file)?;
+    }
+
+    if let Some(template_file) = options.template.as_deref() {
+        let template_file = &options.path(template_file);
+        let template = std::fs::read_to_string(template_file)
+            .with_context(|| format!("Failed to read template '{}'", template_file.display()))?;
+        let output_path = outputs::template::output_path(template_file)?;
+
+        let template_meta = included_meta(options.template_include.as_ref(), &atlas_meta);
+        let rendered = outputs::template::render_template(&template, &template_meta)
+            .with_context(|| format!("Failed to render template '{}'", template_file.display()))?;
+
+        hashing::write_if_changed(&output_path, rendered)
+            .with_context(|| format!("Failed to write '{}'", output_path.display()))?;
+        manifest.record(&output_path)?;
+    }
+
+    let flipbook_meta = included_meta(options.flipbook_include.as_ref(), &atlas_meta);
+
+    if let Some(strips_dir) = options.flipbook_strips.as_deref() {
+        let strips_dir = &options.path(strips_dir);
+        if layered {
+            error::Ewwow
+                .raise()
+                .context("--flipbook-strips doesn't support texture arrays or pages")?;
+        }
+
+        std::fs::create_dir_all(strips_dir).with_context(|| {
+            format!(
+                "Failed to create flipbook directory '{}'",
+                strips_dir.display()
+            )
+        })?;
+
+        for strip in outputs::flipbook::build_strips(&flipbook_meta, &atlas_images[0])? {
+            let path = strips_dir.join(format!("{}.png", strip.sprite_name));
+
+            image_io::write_png(&path, &strip.image, &png_options, cancel)
+                .with_context(|| format!("Failed to save flipbook of '{}'", strip.sprite_name))?;
+            manifest.record(&path)?;
+        }
+    }
+
+    if let Some(grids_dir) = options.flipbook_grids.as_deref() {
+        let grids_dir = &options.path(grids_dir);
+        if layered {
+            error::Ewwow
+                .raise()
+                .context("--flipbook-grids doesn't support texture arrays or pages")?;
+        }
+
+        std::fs::create_dir_all(grids_dir).with_context(|| {
+            format!(
+                "Failed to create flipbook directory '{}'",
+                grids_dir.display()
+            )
+        })?;
+
+        let grids = outputs::flipbook::build_grids(&flipbook_meta, &atlas_images[0])?;
+
+        for grid in grids.iter() {
+            let (path, saved) = match options.flipbook_volumes {
+                true => {
+                    let path = grids_dir.join(format!("{}.ktx2", grid.sprite_name));
+                    let saved = outputs::ktx2::write_volume(&path, &grid.frames);
+                    (path, saved)
+                }
+                false => {
+                    let path = grids_dir.join(format!("{}.png", grid.sprite_name));
+                    let saved = image_io::write_png(&path, &grid.image, &png_options, cancel);
+                    (path, saved)
+                }
+            };
+            saved.with_context(|| format!("Failed to save flipbook of '{}'", grid.sprite_name))?;
+            manifest.record(&path)?;
+        }
+
+        let grids_json =
+            serde_json::to_vec_pretty(&grids).context("Failed to serialize flipbook grids")?;
+        let grids_json_path = grids_dir.join("flipbooks.json");
+        hashing::write_if_changed(&grids_json_path, grids_json)
+            .context("Failed to write flipbooks.json")?;
+        manifest.record(&grids_json_path)?;
+    }
+
+    if let Some(tiles_dir) = options.gba_tiles.as_deref() {
+        let tiles_dir = &options.path(tiles_dir);
+        if layered {
+            error::Ewwow
+                .raise()
+                .context("--gba-tiles doesn't support texture arrays or pages")?;
+        }
+
+        let gba_meta = included_meta(options.gba_include.as_ref(), &atlas_meta);
+        let export = outputs::gba::build_tiles(&gba_meta, &atlas_images[0], options.gba_depth)
+            .context("Failed to convert the atlas to tiles")?;
+
+        for path in export.write(tiles_dir)? {
+            manifest.record(&path)?;
+        }
+    }
+
+    if let Some(sheet_dir) = options.fantasy_sheet.as_deref() {
+        let sheet_dir = &options.path(sheet_dir);
+        if layered {
+            error::Ewwow
+                .raise()
+                .context("--fantasy-sheet doesn't support texture arrays or pages")?;
+        }
+
+        let selection = options
+            .fantasy_sprites
+            .as_ref()
+            .map(|file| {
+                std::fs::read_to_string(options.path(file))
+                    .map(|contents| SpriteReferences::parse(&contents))
+                    .with_context(|| format!("Failed to read sprite names '{}'", file.display()))
+            })
+            .transpose()?;
+
+        let fantasy_meta = included_meta(options.fantasy_include.as_ref(), &atlas_meta);
+        let sheet = outputs::fantasy_console::build_sheet(
+            &fantasy_meta,
+            &atlas_images[0],
+            options.fantasy_console,
+            selection.as_ref(),
+        )
+        .context("Failed to build the fantasy console spritesheet")?;
+
+        for path in sheet.write(sheet_dir)? {
+            manifest.record(&path)?;
+        }
+    }
+
+    if options.thumbnails {
+        if layered {
+            error::Ewwow
+                .raise()
+                .context("--thumbnails doesn't support texture arrays or pages")?;
+        }
+
+        let thumbnails_meta = included_meta(options.thumbnails_include.as_ref(), &atlas_meta);
+        let thumbnails = outputs::thumbnails::Thumbnails::new(
+            &thumbnails_meta,
+            &atlas_images[0],
+            options.thumbnail_size,
+        )
+        .context("Failed to build thumbnails")?;
+
+        thumbnails.save(options.path("atlas.thumbs.json"))?;
+        manifest.record(options.path("atlas.thumbs.json"))?;
+    }
+
+    if let Some(phaser_path) = options.phaser.as_deref() {
+        let phaser_path = &options.path(phaser_path);
+        // The images are relative to the Phaser file instead of the meta
+        let mut phaser_meta =
+            included_meta(options.phaser_include.as_ref(), &atlas_meta).into_owned();
+        phaser_meta.texture_file = relative_to_meta(Path::new(&texture_file), phaser_path);
+        phaser_meta.layer_files = layer_files
+            .iter()
+            .map(|file| relative_to_meta(Path::new(file), phaser_path))
+            .collect();
+
+        outputs::phaser::PhaserMultiAtlas::new(&phaser_meta)
+            .context("Failed to build the Phaser atlas")?
+            .save(phaser_path)?;
+        manifest.record(phaser_path)?;
+    }
+
+    // The pack manifest lists every file written above. Files the previous
+    // run wrote but this one didn't are reported, build systems can use the
+    // manifests to clean them up.
+    let manifest_path = options.manifest_path();
+    if manifest_path.exists() {
+        let previous = PackManifest::load(&manifest_path)?;
+
+        for file in manifest.stale_files(&previous) {
+            println!("INFO: '{file}' is left over from a previous run");
+        }
+    }
+    manifest.save(&manifest_path)?;
+
+    drop(export_span);
+
+    let mut warnings = sources.take_warnings();
+    warnings.extend(atlas.take_warnings());
+
+    Ok(Some(Build {
+        atlas,
+        sources,
+        manifest,
+        layer_files,
+        warnings,
+    }))
+}
+
+/// Builds the atlases of a project file one after another, each with the
+/// options of the atlas for `target` and `features`, relative to the
+/// directory of the file. Returns the warnings of every atlas by its name.
+#[cfg(feature = "cli")]
+pub fn build_project(
+    project_file: &Path,
+    target: Option<&str>,
+    features: &[&str],
+    cancel: &cancellation::CancellationToken,
+) -> anyhow::Result<Vec<(String, warnings::Warning)>> {
+    let project = load_project(project_file)?;
+    let dir = parent_dir(project_file);
+
+    let unused_target = target.filter(|&target| !project.has_target(target));
+    let unused_features = features
+        .iter()
+        .filter(|&&feature| !project.has_feature(feature));
+    for name in unused_target.iter().chain(unused_features) {
+        println!(
+            "INFO: No atlas of '{}' has a section for '{name}'",
+            project_file.display()
+        );
+    }
+
+    let mut warnings = vec![];
+    for (name, atlas_build) in project.atlases.iter() {
+        println!("INFO: Building atlas '{name}'");
+
+        let atlas_build = atlas_build
+            .resolve(target, features)
+            .with_context(|| format!("Failed to resolve atlas '{name}'"))?;
+        let options = parse_project_args(&atlas_build.to_args(name), dir)
+            .with_context(|| format!("Invalid options of atlas '{name}'"))?;
+        let build =
+            build(&options, cancel).with_context(|| format!("Failed to build atlas '{name}'"))?;
+
+        let atlas_warnings = build.map(|build| build.warnings).unwrap_or_default();
+        warnings.extend(
+            atlas_warnings
+                .into_iter()
+                .map(|warning| (name.clone(), warning)),
+        );
+    }
+
+    Ok(warnings)
+}
+
+/// Builds every project of a workspace file, then the master atlas merged
+/// from all of them if the workspace has one. Returns the warnings of every
+/// atlas by its name, `<project>/<atlas>` for those of projects.
+#[cfg(feature = "cli")]
+pub fn build_workspace(
+    workspace_file: &Path,
+    target: Option<&str>,
+    features: &[&str],
+    cancel: &cancellation::CancellationToken,
+) -> anyhow::Result<Vec<(String, warnings::Warning)>> {
+    let contents = std::fs::read_to_string(workspace_file).with_context(|| {
+        format!(
+            "Failed to read workspace file '{}'",
+            workspace_file.display()
+        )
+    })?;
+    let workspace = inputs::workspace::WorkspaceFile::try_parse(&contents).with_context(|| {
+        format!(
+            "Failed to parse workspace file '{}'",
+            workspace_file.display()
+        )
+    })?;
+    let dir = parent_dir(workspace_file);
+
+    let mut projects = vec![];
+    let mut warnings = vec![];
+    for (name, path) in workspace.projects.iter() {
+        println!("INFO: Building project '{name}'");
+
+        let project_file = dir.join(path);
+        let project_warnings = build_project(&project_file, target, features, cancel)
+            .with_context(|| format!("Failed to build project '{name}'"))?;
+        warnings.extend(
+            project_warnings
+                .into_iter()
+                .map(|(atlas, warning)| (format!("{name}/{atlas}"), warning)),
+        );
+
+        // The master atlas merges the atlases as they were just built
+        let mut project = load_project(&project_file)?;
+        for build in project.atlases.values_mut() {
+            *build = build.resolve(target, features)?;
+        }
+        projects.push((name.as_str(), Path::new(path), project));
+    }
+
+    if let Some(master) = &workspace.master {
+        println!("INFO: Building the master atlas");
+
+        let options = parse_project_args(&master.to_args(&projects)?, dir)
+            .context("Invalid options of the master atlas")?;
+        let build = build(&options, cancel).context("Failed to build the master atlas")?;
+
+        let master_warnings = build.map(|build| build.warnings).unwrap_or_default();
+        warnings.extend(
+            master_warnings
+                .into_iter()
+                .map(|warning| ("master".to_string(), warning)),
+        );
+    }
+
+    Ok(warnings)
+}
+
+#[cfg(feature = "cli")]
+fn load_project(project_file: &Path) -> anyhow::Result<inputs::project::ProjectFile> {
+    let contents = std::fs::read_to_string(project_file)
+        .with_context(|| format!("Failed to read project file '{}'", project_file.display()))?;
+
+    inputs::project::ProjectFile::try_parse(&contents)
+        .with_context(|| format!("Failed to parse project file '{}'", project_file.display()))
+}
+
+/// The directory relative paths in `file` are relative to.
+#[cfg(feature = "cli")]
+fn parent_dir(file: &Path) -> &Path {
+    file.parent().unwrap_or(Path::new(""))
+}
+
+/// The options of an atlas of a project file, parsed like the command line.
+#[cfg(feature = "cli")]
+#[derive(clap::Parser)]
+#[command(no_binary_name = true)]
+struct ProjectArgs {
+    #[command(flatten)]
+    options: PackOptions,
+}
+
+/// The options of an atlas of a project or workspace file in `dir`.
+#[cfg(feature = "cli")]
+fn parse_project_args(args: &[String], dir: &Path) -> anyhow::Result<PackOptions> {
+    let mut options = ProjectArgs::try_parse_from(args)?.options;
+    options.dir = dir.to_path_buf();
+
+    Ok(options)
+}
+
+/// Draws the pngs matching the `--only` patterns over the sprites of an
+/// exported atlas, see [`RepackOptions`].
+pub fn repack(
+    options: &RepackOptions,
+    cancel: &cancellation::CancellationToken,
+) -> anyhow::Result<()> {
+    let meta = AtlasMeta::load(&options.meta)?;
+    let texture_path = options
+        .meta
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(&meta.texture_file);
+    let mut texture = image_io::read_png(&texture_path)?.image;
+
+    let mut repacked = 0;
+    for pattern in options.only.iter() {
+        for file in outputs::repack::expand_glob(pattern)? {
+            let name = file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .with_context(|| format!("Invalid sprite file name '{}'", file.display()))?;
+            let index = outputs::repack::find_frame(&meta, name).with_context(|| {
+                format!(
+                    "'{}' isn't a sprite of the atlas, adding sprites needs a full pack",
+                    file.display()
+                )
+            })?;
+
+            let image = image_io::read_png(&file)?.image;
+            outputs::repack::repack_sprite(&meta, &mut texture, index, &image, options.extrude)
+                .with_context(|| format!("Failed to repack '{}'", file.display()))?;
+            repacked += 1;
+        }
+    }
+
+    if repacked == 0 {
+        error::Ewwow
+            .raise()
+            .context("No file matches the --only patterns")?;
+    }
+
+    image_io::write_png(&texture_path, &texture, &options.png.options(), cancel)
+        .context("Failed to save atlas image")?;
+    println!(
+        "INFO: Repacked {repacked} sprite(s) into '{}'",
+        texture_path.display()
+    );
+
+    // The texture has a new hash, if the pack that wrote it recorded it
+    let manifest_path = options
+        .pack_manifest
+        .clone()
+        .unwrap_or_else(|| texture_path.with_extension("pack-manifest.json"));
+    if manifest_path.exists() {
+        let mut manifest = PackManifest::load(&manifest_path)?;
+        let texture_file = texture_path.to_string_lossy().replace('\\', "/");
+
+        if manifest.files.iter().any(|file| file.file == texture_file) {
+            manifest.record(&texture_path)?;
+            manifest.save(&manifest_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Polls the source files of `build` until `cancel` is cancelled. If only
+/// images the atlas copies from changed, they are reloaded and the texture is
+/// rebuilt with the existing layout, without reconstructing any
+/// intermediates. Everything else runs [`build`] again with `options`,
+/// except for `--frozen`, and passes the new build to `on_rebuild`, e.g. to
+/// show its warnings.
+pub fn watch(
+    options: &PackOptions,
+    build: Build,
+    cancel: &cancellation::CancellationToken,
+    mut on_rebuild: impl FnMut(&Build),
+) -> anyhow::Result<()> {
+    let mut build = build;
+
+    let manifest_path = options.manifest_path();
+    let png_options = options.png.options();
+    let texture_path = options.path(&options.out);
+
+    // Effects and derived sprites were baked from the old pixels
+    let baked_from_sources = [
+        &options.flips,
+        &options.icon_font,
+        &options.palettes,
+        &options.adjustments,
+        &options.shadows,
+        &options.outlines,
+    ]
+    .iter()
+    .any(|file| file.is_some())
+        || options.glyph_effect.is_some()
+        // So are trim rects, duplicates and sprites scaled to the target
+        || options.trim.is_some()
+        || options.dedup
+        || options.target_scale.is_some();
+
+    // The sources are expected to change while watching, --frozen only holds
+    // for the first build
+    let rebuild_options = PackOptions {
+        watch: false,
+        frozen: false,
+        ..options.clone()
+    };
+
+    let mut watcher = SourceWatcher::new(&build.sources);
+    let mut atlas_image_ids = build.atlas.source_image_ids();
+
+    println!(
+        "INFO: Watching {} source file(s) for changes",
+        watcher.len()
+    );
+
+    while !cancel.is_cancelled() {
+        std::thread::sleep(POLL_INTERVAL);
+
+        let changed = watcher.poll();
+        if changed.is_empty() {
+            continue;
+        }
+
+        let only_images = changed
+            .iter()
+            .all(|id| matches!(id, sources::SourceId::Image(_)));
+        // Fonts and everything generated from sources can't be updated in place
+        let mut rebuild_all = !only_images || baked_from_sources;
+
+        let mut affected = false;
+        for &id in changed.iter() {
+            if rebuild_all {
+                break;
+            }
+
+            let path = build.sources.get_path(id)?.display().to_string();
+
+            match build.sources.reload_image(id) {
+                // The rects into it may not fit anymore
+                Ok(sources::ImageReload::Resized) => rebuild_all = true,
+                Ok(sources::ImageReload::Changed) if atlas_image_ids.contains(&id) => {
+                    let dependents: Vec<_> = build
+                        .sources
+                        .dependents(id)
+                        .into_iter()
+                        .map(|dependent| {
+                            build
+                                .sources
+                                .get_path(dependent)
+                                .map(|p| format!("'{}'", p.display()))
+                        })
+                        .collect::<anyhow::Result<_>>()?;
+
+                    match dependents.is_empty() {
+                        true => println!("INFO: Reloaded '{path}'"),
+                        false => {
+                            println!("INFO: Reloaded '{path}' used by {}", dependents.join(", "))
+                        }
+                    }
+                    affected = true;
+                }
+                Ok(_) => {}
+                Err(err) => println!("WARNING: {err:#}"),
+            }
+        }
+
+        if rebuild_all {
+            for id in changed.iter() {
+                println!("INFO: '{}' changed", build.sources.get_path(*id)?.display());
+            }
+
+            // A broken edit shouldn't end the session, the next save may fix it
+            match self::build(&rebuild_options, cancel) {
+                Ok(Some(rebuilt)) => {
+                    build = rebuilt;
+                    // Dependencies may be new now, like an added page of a font
+                    watcher = SourceWatcher::new(&build.sources);
+                    atlas_image_ids = build.atlas.source_image_ids();
+                    println!("INFO: Rebuilt the atlas");
+                    on_rebuild(&build);
+                }
+                Ok(None) => {}
+                Err(err) => println!("WARNING: Failed to rebuild the atlas: {err:#}"),
+            }
+            continue;
+        }
+
+        if !affected {
+            continue;
+        }
+
+        let output = texture_output(
+            options,
+            &texture_path,
+            build.atlas.layout.layer_count > 0,
+            &build.layer_files,
+            &png_options,
+        );
+
+        // A broken edit shouldn't end the session, the next save may fix it
+        let rebuilt =
+            build_textures(&build.atlas, &build.sources, &output, cancel).and_then(|images| {
+                write_textures(&images, &output, cancel, &mut build.manifest)?;
+
+                // The textures have new hashes
+                build.manifest.save(&manifest_path)
+            });
+
+        match rebuilt {
+            Ok(()) => println!("INFO: Rebuilt the atlas texture"),
+            Err(err) => println!("WARNING: Failed to rebuild the atlas: {err:#}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// How [`build`] writes the texture of the atlas.
+fn texture_output<'a>(
+    options: &PackOptions,
+    texture_path: &'a Path,
+    texture_array: bool,
+    layer_files: &'a [String],
+    png_options: &'a image_io::PngOptions,
+) -> TextureOutput<'a> {
+    TextureOutput {
+        texture_path,
+        texture_array,
+        layer_files,
+        png_options,
+        pixel_format: options.pixel_format.unwrap_or_default(),
+        dithering: options.dither.unwrap_or_default(),
+        raw_texture: options.raw_texture,
+    }
+}
+
+/// `path` relative to the directory of `meta_file`, as the meta references
+/// textures. Paths outside of it are kept as they are.
+fn relative_to_meta(path: &Path, meta_file: &Path) -> String {
+    let meta_dir = meta_file.parent().unwrap_or(Path::new(""));
+
+    path.strip_prefix(meta_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+#[cfg(feature = "remote")]
+fn load_remote_fonts(
+    sources: &mut sources::Sources,
+    urls: &[String],
+    cache_dir: Option<&Path>,
+    lockfile_path: &Path,
+) -> anyhow::Result<Vec<font::FontIntermediate>> {
+    if urls.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut remote = remote::RemoteSources::new();
+
+    if let Some(cache_dir) = cache_dir {
+        remote = remote.with_cache(remote_cache::RemoteCache::new(cache_dir)?);
+
+        // Cached files the lockfile knows don't even need to be revalidated
+        if lockfile_path.exists() {
+            remote = remote.with_expected_hashes(Lockfile::load(lockfile_path)?.hashes()?);
+        }
+    }
+
+    let shared = sources::SharedSources::new(std::mem::take(sources));
+    let urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+    let loaded = remote.load_blocking(&shared, &urls);
+    *sources = shared.into_inner();
+
+    let (ids, report) = loaded.context("Failed to load remote fonts")?;
+
+    for url in report.downloaded.iter() {
+        println!("INFO: Downloaded source file '{url}'");
+    }
+    for url in report.cached.iter() {
+        println!("INFO: Using cached source file '{url}'");
+    }
+    for url in report.already_loaded.iter() {
+        println!("INFO: Source file '{url}' has been loaded already");
+    }
+    if report.evicted > 0 {
+        println!(
+            "INFO: Evicted {} file(s) from the source cache",
+            report.evicted
+        );
+    }
+
+    ids.into_iter()
+        .map(|id| font::FontIntermediate::from_fnt(id, sources))
+        .collect()
+}
+
+#[cfg(not(feature = "remote"))]
+fn load_remote_fonts(
+    _sources: &mut sources::Sources,
+    urls: &[String],
+    _cache_dir: Option<&Path>,
+    _lockfile_path: &Path,
+) -> anyhow::Result<Vec<font::FontIntermediate>> {
+    if !urls.is_empty() {
+        error::Ewwow
+            .raise()
+            .context("--remote-font requires the `remote` feature")?;
+    }
+
+    Ok(vec![])
+}
+
+/// The part of `atlas_meta` an exporter should include, if its `--*-include`
+/// selects one.
+fn included_meta<'a>(
+    filter: Option<&ContentFilter>,
+    atlas_meta: &'a AtlasMeta,
+) -> Cow<'a, AtlasMeta> {
+    match filter {
+        Some(filter) => Cow::Owned(filter.apply(atlas_meta)),
+        None => Cow::Borrowed(atlas_meta),
+    }
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use clap::Parser;
+
+    use super::{PackOptions, ProjectArgs};
+
+    #[test]
+    fn test_default_options() -> anyhow::Result<()> {
+        let parsed = ProjectArgs::try_parse_from(Vec::<String>::new())?.options;
+        assert_eq!(
+            format!("{:?}", PackOptions::default()),
+            format!("{parsed:?}")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Context;
+use reqwest::{header, StatusCode, Url};
+
+use crate::{
+    error::Error,
+    hashing,
+    remote_cache::RemoteCache,
+    sources::{SharedSources, SourceId},
+    warnings::Warning,
+};
+
+/// Fetches source files over HTTP(S), e.g. from the artifact store of a build
+/// farm, straight into a [`SharedSources`].
+pub struct RemoteSources {
+    client: reqwest::Client,
+    cache: Option<RemoteCache>,
+    /// The content hashes the files are expected to have by file name, e.g.
+    /// from a lockfile. Cached files with the expected hash are used without
+    /// asking the server, all others are revalidated with their `ETag`.
+    expected_hashes: HashMap<String, u64>,
+}
+
+/// Where the files of [`RemoteSources::load`] came from.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteReport {
+    pub downloaded: Vec<String>,
+    /// Files used from the cache, because they had the expected hash or the
+    /// server confirmed they're still up to date.
+    pub cached: Vec<String>,
+    /// Files that were loaded into the sources before, by file name.
+    pub already_loaded: Vec<String>,
+    /// The number of files evicted from the cache afterwards.
+    pub evicted: usize,
+}
+
+/// Where a single file came from, see [`RemoteReport`].
+enum Origin {
+    Downloaded { etag: Option<String> },
+    Cached,
+    AlreadyLoaded,
+}
+
+/// The response to a (conditional) request.
+enum Response {
+    Downloaded {
+        bytes: Vec<u8>,
+        etag: Option<String>,
+    },
+    NotModified,
+}
+
+impl RemoteSources {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            cache: None,
+            expected_hashes: HashMap::new(),
+        }
+    }
+
+    pub fn with_cache(mut self, cache: RemoteCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_expected_hashes(mut self, expected_hashes: HashMap<String, u64>) -> Self {
+        self.expected_hashes = expected_hashes;
+        self
+    }
+
+    /// Like [`Self::load`], for callers that don't run an async runtime.
+    pub fn load_blocking(
+        &self,
+        srcs: &SharedSources,
+        urls: &[&str],
+    ) -> anyhow::Result<(Vec<SourceId>, RemoteReport)> {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start the async runtime")?
+            .block_on(self.load(srcs, urls))
+    }
+
+    /// Downloads the source files at `urls` and their dependencies, which are
+    /// resolved relative to the url of the file depending on them. All files
+    /// of one level of dependencies are downloaded concurrently.
+    ///
+    /// Corrupted cache files are downloaded again, with a
+    /// [`Warning::CorruptedCacheFile`] in the sources.
+    pub async fn load(
+        &self,
+        srcs: &SharedSources,
+        urls: &[&str],
+    ) -> anyhow::Result<(Vec<SourceId>, RemoteReport)> {
+        let mut pending = urls
+            .iter()
+            .map(|url| Url::parse(url).with_context(|| format!("Invalid source url '{url}'")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut ids = vec![];
+        let mut report = RemoteReport::default();
+        let mut is_top_level = true;
+
+        while !pending.is_empty() {
+            let mut next = vec![];
+
+            for (url, bytes) in self.fetch_all(srcs, pending, &mut report).await? {
+                let file_name = file_name(&url)?;
+
+                let id =
+                    srcs.try_load_source_bytes(PathBuf::from(url.as_str()), file_name, &bytes)?;
+
+                for dep in srcs.read().dependencies(id) {
+                    next.push(url.join(&dep).with_context(|| {
+                        format!("Failed to resolve dependency '{dep}' of '{url}'")
+                    })?);
+                }
+
+                if is_top_level {
+                    ids.push(id);
+                }
+            }
+
+            pending = next;
+            is_top_level = false;
+        }
+
+        if let Some(cache) = self.cache.as_ref() {
+            report.evicted = cache.evict().context("Failed to evict old cache files")?;
+        }
+
+        Ok((ids, report))
+    }
+
+    /// Downloads all `urls` whose file isn't loaded yet, keeping their order.
+    async fn fetch_all(
+        &self,
+        srcs: &SharedSources,
+        urls: Vec<Url>,
+        report: &mut RemoteReport,
+    ) -> anyhow::Result<Vec<(Url, Vec<u8>)>> {
+        let mut downloads = tokio::task::JoinSet::new();
+
+        for (index, url) in urls.into_iter().enumerate() {
+            let file_name = file_name(&url)?;
+
+            // Already loaded files are resolved by name, like local ones
+            if srcs.read().find_id(&file_name).is_ok() {
+                downloads.spawn(async move { Ok((index, url, vec![], Origin::AlreadyLoaded)) });
+                continue;
+            }
+
+            let mut cached = self
+                .cache
+                .as_ref()
+                .and_then(|cache| cache.get(url.as_str()));
+
+            if let (Some(cache), Some(file)) = (self.cache.as_ref(), cached.as_ref()) {
+                if !file.is_intact() {
+                    cache.remove(url.as_str());
+                    srcs.push_warning(Warning::CorruptedCacheFile {
+                        url: url.to_string(),
+                    });
+                    cached = None;
+                }
+            }
+
+            let expected = self.expected_hashes.get(&file_name).copied();
+
+            match cached {
+                Some(file) if Some(hashing::hash_bytes(&file.bytes)) == expected => {
+                    downloads.spawn(async move { Ok((index, url, file.bytes, Origin::Cached)) });
+                }
+                cached => {
+                    let client = self.client.clone();
+
+                    downloads.spawn(async move {
+                        let etag = cached.as_ref().and_then(|file| file.etag.as_deref());
+
+                        anyhow::Ok(match fetch(&client, &url, etag).await? {
+                            Response::Downloaded { bytes, etag } => {
+                                (index, url, bytes, Origin::Downloaded { etag })
+                            }
+                            Response::NotModified => {
+                                let bytes = cached.map(|file| file.bytes).unwrap_or_default();
+                                (index, url, bytes, Origin::Cached)
+                            }
+                        })
+                    });
+                }
+            }
+        }
+
+        let mut files = vec![];
+        while let Some(download) = downloads.join_next().await {
+            let (index, url, bytes, origin) = download.context("Download task failed")??;
+
+            match origin {
+                Origin::Downloaded { etag } => {
+                    if let Some(cache) = self.cache.as_ref() {
+                        cache.put(url.as_str(), etag.as_deref(), &bytes)?;
+                    }
+
+                    report.downloaded.push(url.to_string());
+                }
+                Origin::Cached => report.cached.push(url.to_string()),
+                Origin::AlreadyLoaded => report.already_loaded.push(url.to_string()),
+            }
+
+            files.push((index, url, bytes));
+        }
+
+        files.sort_by_key(|&(index, _, _)| index);
+
+        Ok(files
+            .into_iter()
+            .map(|(_, url, bytes)| (url, bytes))
+            .collect())
+    }
+}
+
+impl Default for RemoteSources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Downloads `url`, unless the server confirms that the file with the `ETag`
+/// `etag` is still up to date.
+async fn fetch(
+    client: &reqwest::Client,
+    url: &Url,
+    etag: Option<&str>,
+) -> anyhow::Result<Response> {
+    let mut request = client.get(url.clone());
+    if let Some(etag) = etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to download source file '{url}'"))?;
+
+    if etag.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Response::NotModified);
+    }
+
+    let response = response
+        .error_for_status()
+        .with_context(|| format!("Failed to download source file '{url}'"))?;
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let bytes = response
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to download source file '{url}'"))?;
+
+    Ok(Response::Downloaded {
+        bytes: bytes.to_vec(),
+        etag,
+    })
+}
+
+/// The last path segment of `url`, which is also the source file alias.
+fn file_name(url: &Url) -> anyhow::Result<String> {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| Error::SourceNotFound(url.to_string()))
+        .with_context(|| format!("Source url '{url}' has no file name"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::RemoteSources;
+    use crate::{hashing, remote_cache::RemoteCache, sources::SharedSources, warnings::Warning};
+
+    /// The `ETag` [`serve`] sends with every file.
+    const ETAG: &str = "\"v1\"";
+
+    /// Serves `files` over HTTP on a random port until the test ends. Files
+    /// requested with `If-None-Match: <ETAG>` are answered with 304 and the
+    /// number of such requests is counted in the returned counter.
+    fn serve(files: Vec<(&'static str, Vec<u8>)>) -> (String, Arc<AtomicUsize>) {
+        let num_not_modified = Arc::new(AtomicUsize::new(0));
+        let counter = num_not_modified.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 1024];
+                let len = stream.read(&mut request).unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..len]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/");
+                let is_unchanged = request
+                    .lines()
+                    .any(|line| line.eq_ignore_ascii_case(&format!("if-none-match: {ETAG}")));
+
+                let response = match files.iter().find(|(name, _)| path == format!("/{name}")) {
+                    Some(_) if is_unchanged => {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        b"HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_vec()
+                    }
+                    Some((_, body)) => {
+                        let mut response = format!(
+                            "HTTP/1.1 200 OK\r\nETag: {ETAG}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes();
+                        response.extend_from_slice(body);
+                        response
+                    }
+                    None => {
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_vec()
+                    }
+                };
+
+                let _ = stream.write_all(&response);
+            }
+        });
+
+        (format!("http://{address}"), num_not_modified)
+    }
+
+    #[test]
+    fn test_load_remote_sources() -> anyhow::Result<()> {
+        let page = image::RgbaImage::from_pixel(8, 8, image::Rgba([255, 255, 255, 255]));
+        let page = crate::image_io::encode_png(&page, &Default::default())?;
+
+        let fnt = "info face=\"remote\" size=8\n\
+                   common lineHeight=8 base=6 scaleW=8 scaleH=8 pages=1 packed=0\n\
+                   page id=0 file=\"remote.png\"\n\
+                   chars count=1\n\
+                   char id=65 x=0 y=0 width=4 height=6 xoffset=0 yoffset=0 xadvance=5 page=0 chnl=15\n";
+
+        let (base, num_not_modified) = serve(vec![
+            ("fonts/remote.fnt", fnt.as_bytes().to_vec()),
+            ("fonts/remote.png", page),
+            ("en.lang", b"label.title = \"Title\"".to_vec()),
+        ]);
+
+        let srcs = SharedSources::default();
+        let (ids, report) = RemoteSources::new().load_blocking(
+            &srcs,
+            &[
+                &format!("{base}/fonts/remote.fnt"),
+                &format!("{base}/en.lang"),
+            ],
+        )?;
+
+        let srcs = srcs.into_inner();
+        assert_eq!(srcs.get_fnt(ids[0])?.dependencies(), vec!["remote.png"]);
+        assert_eq!(
+            srcs.get_localization(ids[1])?.entries["label.title"],
+            "Title"
+        );
+        assert_eq!(
+            srcs.get_image(srcs.find_id(&"remote.png".to_string())?)?
+                .width(),
+            8
+        );
+
+        assert_eq!(report.downloaded.len(), 3);
+
+        let dir =
+            std::env::temp_dir().join(format!("texture-packer-remote-{}", std::process::id()));
+        let cache = RemoteCache::new(&dir)?;
+        let url = format!("{base}/en.lang");
+        let load = |remote: RemoteSources| -> anyhow::Result<_> {
+            let srcs = SharedSources::default();
+            let (_, report) = remote.load_blocking(&srcs, &[&url])?;
+            let mut srcs = srcs.into_inner();
+            let title = srcs.localizations[0].1.entries["label.title"].clone();
+
+            Ok((title, report, srcs.take_warnings()))
+        };
+
+        // Downloaded files are cached with their ETag and revalidated
+        let (title, report, _) = load(RemoteSources::new().with_cache(cache.clone()))?;
+        assert_eq!((title.as_str(), report.downloaded.len()), ("Title", 1));
+        assert_eq!(cache.get(&url).unwrap().etag.as_deref(), Some(ETAG));
+
+        let (title, report, _) = load(RemoteSources::new().with_cache(cache.clone()))?;
+        assert_eq!((title.as_str(), report.cached.len()), ("Title", 1));
+        assert_eq!(num_not_modified.load(Ordering::SeqCst), 1);
+
+        // Files with the expected hash come from the cache without a request
+        let bytes = b"label.title = \"Cached\"";
+        cache.put(&url, Some(ETAG), bytes)?;
+        let expected = HashMap::from([("en.lang".to_string(), hashing::hash_bytes(bytes))]);
+
+        let (title, report, _) = load(
+            RemoteSources::new()
+                .with_cache(cache.clone())
+                .with_expected_hashes(expected),
+        )?;
+        assert_eq!((title.as_str(), report.cached.len()), ("Cached", 1));
+        assert_eq!(num_not_modified.load(Ordering::SeqCst), 1);
+
+        // Corrupted files are downloaded again
+        let data_path = cache.data_path(&cache.get(&url).unwrap().sha256);
+        std::fs::write(data_path, b"label.title = \"Broken\"")?;
+
+        let (title, report, warnings) = load(RemoteSources::new().with_cache(cache.clone()))?;
+        assert_eq!((title.as_str(), report.downloaded.len()), ("Title", 1));
+        assert!(matches!(warnings[..], [Warning::CorruptedCacheFile { .. }]));
+
+        std::fs::remove_dir_all(&dir)?;
+
+        let missing = RemoteSources::new().load_blocking(
+            &SharedSources::default(),
+            &[&format!("{base}/missing.lang")],
+        );
+        assert!(format!("{:#}", missing.unwrap_err()).contains("404"));
+
+        Ok(())
+    }
+}
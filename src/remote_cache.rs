@@ -0,0 +1,280 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+/// A local directory of downloaded source files, named after the SHA-256 hash
+/// of their contents, so files with the same contents are stored once and
+/// corrupted files are detected. An index entry per URL in `index/` points to
+/// the hash of what was downloaded from there, with the `ETag` it was served
+/// with, so it can be revalidated with a conditional request instead of being
+/// downloaded again.
+///
+/// The least recently used files are evicted once the cache grows beyond
+/// [`Self::max_bytes`], together with the index entries pointing to them.
+#[derive(Debug, Clone)]
+pub struct RemoteCache {
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+}
+
+/// A file from the cache, see [`RemoteCache::get`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedFile {
+    pub bytes: Vec<u8>,
+    pub etag: Option<String>,
+    /// The SHA-256 hash the file had when it was cached, as hex.
+    pub sha256: String,
+}
+
+/// The index entry of a URL.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    sha256: String,
+}
+
+impl CachedFile {
+    /// Whether the contents still have the hash they were cached with.
+    pub fn is_intact(&self) -> bool {
+        sha256_hex(&self.bytes) == self.sha256
+    }
+}
+
+impl RemoteCache {
+    pub const DEFAULT_MAX_BYTES: u64 = 1 << 30;
+
+    pub fn new<P: AsRef<Path>>(dir: P) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+
+        std::fs::create_dir_all(dir.join("index"))
+            .with_context(|| format!("Failed to create cache directory '{}'", dir.display()))?;
+
+        Ok(Self {
+            dir,
+            max_bytes: Self::DEFAULT_MAX_BYTES,
+        })
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// The contents of the file with the SHA-256 hash `sha256`.
+    pub(crate) fn data_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(format!("{sha256}.data"))
+    }
+
+    /// The index entry of `url`.
+    fn entry_path(&self, url: &str) -> PathBuf {
+        let key = sha256_hex(url.as_bytes());
+        self.dir.join("index").join(format!("{key}.json"))
+    }
+
+    fn read_entry(path: &Path) -> Option<CacheEntry> {
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+    }
+
+    /// The cached file downloaded from `url`, if there is one. Check
+    /// [`CachedFile::is_intact`] before using it.
+    pub fn get(&self, url: &str) -> Option<CachedFile> {
+        let entry = Self::read_entry(&self.entry_path(url))?;
+        let data_path = self.data_path(&entry.sha256);
+        let bytes = std::fs::read(&data_path).ok()?;
+
+        // Two urls with the same hash are as good as impossible, but cheap
+        // to rule out
+        if entry.url != url {
+            return None;
+        }
+
+        // Mark the file as recently used for the eviction
+        if let Ok(file) = std::fs::File::options().append(true).open(&data_path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(CachedFile {
+            bytes,
+            etag: entry.etag,
+            sha256: entry.sha256,
+        })
+    }
+
+    /// Adds `bytes` downloaded from `url` with the `ETag` header `etag` to
+    /// the cache, pointing the index entry of `url` to them instead of to
+    /// what was cached for it before.
+    pub fn put(&self, url: &str, etag: Option<&str>, bytes: &[u8]) -> anyhow::Result<()> {
+        let sha256 = sha256_hex(bytes);
+        let data_path = self.data_path(&sha256);
+        let entry_path = self.entry_path(url);
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag: etag.map(str::to_string),
+            sha256,
+        };
+        let entry = serde_json::to_vec(&entry).context("Failed to serialize cache entry")?;
+
+        // Write to temporary files first, so other processes sharing the
+        // cache never see a partial file. The contents go first, so an index
+        // entry never points to a missing file.
+        for (path, contents) in [(&data_path, bytes), (&entry_path, &entry[..])] {
+            let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+
+            std::fs::write(&tmp_path, contents)
+                .and_then(|_| std::fs::rename(&tmp_path, path))
+                .with_context(|| format!("Failed to write cache file '{}'", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes what is cached for `url`, e.g. after it turned out to be
+    /// corrupted. Other URLs with the same contents download them again.
+    pub fn remove(&self, url: &str) {
+        let entry_path = self.entry_path(url);
+
+        if let Some(entry) = Self::read_entry(&entry_path) {
+            let _ = std::fs::remove_file(self.data_path(&entry.sha256));
+        }
+        let _ = std::fs::remove_file(entry_path);
+    }
+
+    /// Removes the least recently used files until the cache fits into
+    /// [`Self::max_bytes`], and the index entries of removed files. Returns
+    /// the number of removed files.
+    pub fn evict(&self) -> anyhow::Result<usize> {
+        let mut files = vec![];
+
+        let entries = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read cache directory '{}'", self.dir.display()))?;
+
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let path = entry.path();
+
+            if metadata.is_file() && path.extension().is_some_and(|ext| ext == "data") {
+                files.push((metadata.modified()?, metadata.len(), path));
+            }
+        }
+
+        let mut total: u64 = files.iter().map(|&(_, len, _)| len).sum();
+        let mut num_removed = 0;
+
+        // Oldest first
+        files.sort();
+
+        for (_, len, path) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to evict cache file '{}'", path.display()))?;
+
+            total -= len;
+            num_removed += 1;
+        }
+
+        if num_removed > 0 {
+            self.remove_dangling_entries()?;
+        }
+
+        Ok(num_removed)
+    }
+
+    /// Removes the index entries whose file isn't cached anymore.
+    fn remove_dangling_entries(&self) -> anyhow::Result<()> {
+        let index_dir = self.dir.join("index");
+        let entries = std::fs::read_dir(&index_dir)
+            .with_context(|| format!("Failed to read cache index '{}'", index_dir.display()))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            let is_dangling =
+                Self::read_entry(&path).is_none_or(|entry| !self.data_path(&entry.sha256).exists());
+            if is_dangling {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::RemoteCache;
+
+    #[test]
+    fn test_remote_cache() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("texture-packer-cache-{}", std::process::id()));
+        let cache = RemoteCache::new(&dir)?.with_max_bytes(10);
+
+        let (old, new) = (
+            "https://example.com/old.lang",
+            "https://example.com/new.lang",
+        );
+        cache.put(old, None, b"0123456")?;
+        cache.put(new, Some("\"v1\""), b"abcdef")?;
+
+        let cached = cache.get(new).unwrap();
+        assert_eq!(cached.bytes, b"abcdef");
+        assert_eq!(cached.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            cached.sha256,
+            "bef57ec7f53a6d40beb640a780a639c83bc29ac8a9816f1fc6c5c6dcd93c4721"
+        );
+        assert!(cached.is_intact());
+        assert_eq!(cache.get("https://example.com/missing.lang"), None);
+
+        // Files are named after their contents
+        assert!(cache.data_path(&cached.sha256).exists());
+
+        // Make sure the first file is the least recently used one
+        let old_data = cache.data_path(&cache.get(old).unwrap().sha256);
+        let file = std::fs::File::options().append(true).open(old_data)?;
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))?;
+
+        assert_eq!(cache.evict()?, 1);
+        assert_eq!(cache.get(old), None);
+        assert!(cache.get(new).is_some());
+        assert_eq!(std::fs::read_dir(dir.join("index"))?.count(), 1);
+
+        // Files with the same contents are stored once
+        let same = "https://example.com/same.lang";
+        cache.put(same, None, b"abcdef")?;
+        assert_eq!(cache.get(same).unwrap().sha256, cached.sha256);
+        assert_eq!(
+            std::fs::read_dir(&dir)?
+                .filter(|entry| entry.as_ref().unwrap().path().is_file())
+                .count(),
+            1
+        );
+
+        // Corrupted files are detected
+        std::fs::write(cache.data_path(&cached.sha256), b"abcdeF")?;
+        assert!(!cache.get(new).unwrap().is_intact());
+        cache.remove(new);
+        assert_eq!(cache.get(new), None);
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
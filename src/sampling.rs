@@ -0,0 +1,134 @@
+use anyhow::Context;
+
+use crate::{error::Ewwow, math::IMargins, warnings::Warning};
+
+/// How the runtime samples the atlas texture.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Filtering {
+    /// Point sampling, e.g. for pixel art drawn at integer scales.
+    #[default]
+    Nearest,
+    /// Bilinear filtering, which also reads the neighbouring texels.
+    Linear,
+}
+
+impl std::str::FromStr for Filtering {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "linear" => Ok(Self::Linear),
+            _ => {
+                Ewwow.raise().with_context(|| {
+                    format!("Invalid filtering '{s}', expected nearest or linear")
+                })?;
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Filtering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filtering::Nearest => write!(f, "nearest"),
+            Filtering::Linear => write!(f, "linear"),
+        }
+    }
+}
+
+/// How the runtime samples the atlas, declared so [`lint`] can check the
+/// packing settings against it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sampling {
+    pub filtering: Filtering,
+    /// The number of mip levels below the full size texture, 0 without
+    /// mipmaps.
+    pub mip_levels: u32,
+}
+
+impl Sampling {
+    /// How many texels past the edge of a sprite sampling may read, if its
+    /// UV rect is shrunk by `uv_inset` texels.
+    ///
+    /// Bilinear filtering reads half a texel past the edge. Every mip level
+    /// halves the resolution, so at level `n` a texel averages `2^n` texels
+    /// of the full size texture in every direction.
+    pub fn reach(&self, uv_inset: f32) -> i32 {
+        match (self.mip_levels, self.filtering) {
+            (0, Filtering::Nearest) => 0,
+            (0, Filtering::Linear) => (0.5 - uv_inset).max(0.0).ceil() as i32,
+            (levels, _) => 1 << levels.min(16),
+        }
+    }
+}
+
+/// Warns if sampling the atlas as `sampling` declares reads the pixels of
+/// neighbouring sprites: the padding of two neighbours plus `spacing` needs
+/// to be at least [`Sampling::reach`].
+pub fn lint(sampling: &Sampling, padding: IMargins, spacing: i32, uv_inset: f32) -> Vec<Warning> {
+    let needed = sampling.reach(uv_inset);
+
+    [
+        ("horizontally", padding.hori() + spacing),
+        ("vertically", padding.vert() + spacing),
+    ]
+    .into_iter()
+    .filter(|&(_, gap)| gap < needed)
+    .map(|(axis, gap)| Warning::SamplingBleed {
+        filtering: sampling.filtering.to_string(),
+        mip_levels: sampling.mip_levels,
+        axis: axis.to_string(),
+        gap,
+        needed,
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, Filtering, Sampling};
+    use crate::{math::IMargins, warnings::Warning};
+
+    #[test]
+    fn test_lint() -> anyhow::Result<()> {
+        let nearest = Sampling::default();
+        let linear = Sampling {
+            filtering: "linear".parse()?,
+            mip_levels: 0,
+        };
+        let mipmapped = Sampling {
+            filtering: Filtering::Linear,
+            mip_levels: 2,
+        };
+        assert!("trilinear".parse::<Filtering>().is_err());
+
+        // Point sampling never leaves the sprite
+        assert!(lint(&nearest, IMargins::uniform(0), 0, 0.0).is_empty());
+
+        // Bilinear needs a pixel between sprites, or half a texel of inset
+        assert_eq!(
+            lint(&linear, IMargins::new(0, 0, 1, 0), 0, 0.0),
+            vec![Warning::SamplingBleed {
+                filtering: "linear".to_string(),
+                mip_levels: 0,
+                axis: "vertically".to_string(),
+                gap: 0,
+                needed: 1,
+            }]
+        );
+        assert!(lint(&linear, IMargins::uniform(0), 1, 0.0).is_empty());
+        assert!(lint(&linear, IMargins::uniform(0), 0, 0.5).is_empty());
+
+        // Two mip levels average blocks of 4x4 texels
+        assert_eq!(lint(&mipmapped, IMargins::uniform(1), 1, 0.5).len(), 2);
+        assert!(lint(&mipmapped, IMargins::uniform(2), 0, 0.0).is_empty());
+
+        Ok(())
+    }
+}
@@ -1,34 +1,65 @@
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
+    sync::{RwLock, RwLockReadGuard},
 };
 
 use anyhow::Context;
 use image::{GenericImageView, SubImage};
 
-use crate::{error::Ewwow, inputs::fnt};
+use crate::{
+    error::Error,
+    hashing, image_io,
+    inputs::{fnt, localization},
+    math::ISize,
+    warnings::Warning,
+};
 
+/// A file loaded into [`Sources`].
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum SourceId {
     Image(usize),
     Fnt(usize),
+    Localization(usize),
 }
 
 impl SourceId {
-    fn index(self) -> usize {
+    /// The position among the loaded sources of the same kind.
+    pub fn index(self) -> usize {
         match self {
             SourceId::Image(i) => i,
             SourceId::Fnt(i) => i,
+            SourceId::Localization(i) => i,
         }
     }
 }
 
-#[derive(Debug)]
+/// What [`Sources::reload_image`] found on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageReload {
+    Unchanged,
+    /// The pixels were replaced.
+    Changed,
+    /// The image has another size now, so rects into it may be out of
+    /// bounds. The old pixels are kept, the atlas needs to be rebuilt.
+    Resized,
+}
+
+/// Every file a build reads, loaded once and referenced by [`SourceId`].
+///
+/// Loading a fnt file also loads its pages, files are known by their file
+/// name afterwards (see [`Self::find_id`]).
+#[derive(Debug, Default)]
 pub struct Sources {
     pub images: Vec<(PathBuf, image::RgbaImage)>,
     pub fnt_files: Vec<(PathBuf, fnt::FntFile)>,
+    pub localizations: Vec<(PathBuf, localization::LocalizationFile)>,
 
     pub source_file_aliases: HashMap<String, SourceId>,
+    /// Content hashes of the loaded files, see [`hashing::hash_bytes`].
+    pub source_hashes: HashMap<SourceId, u64>,
+
+    pub warnings: Vec<Warning>,
 }
 
 impl Sources {
@@ -36,25 +67,49 @@ impl Sources {
         Self {
             images: Vec::new(),
             fnt_files: Vec::new(),
+            localizations: Vec::new(),
             source_file_aliases: HashMap::new(),
+            source_hashes: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Removes and returns all warnings collected so far.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Called when `path` resolves to an already loaded source file.
+    fn check_duplicate_alias(&mut self, path: &Path, file_name: &str, id: SourceId) {
+        let Ok(loaded) = self.get_path(id) else {
+            return;
+        };
+
+        // Only different files with the same name are a problem
+        let ignored = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if ignored != loaded {
+            self.warnings.push(Warning::DuplicateAlias {
+                file_name: file_name.to_string(),
+                loaded: loaded.to_path_buf(),
+                ignored,
+            });
+        } else {
+            println!("INFO: Source file '{file_name}' has been loaded already");
         }
     }
 
     pub fn find_id(&self, alias: &String) -> anyhow::Result<SourceId> {
         self.source_file_aliases
             .get(alias)
-            .map(|&id| id)
-            .ok_or(Ewwow)
-            .with_context(|| format!("Could not resolve source file alias '{alias}'"))
+            .copied()
+            .ok_or_else(|| Error::SourceNotFound(alias.clone()).into())
     }
 
     pub fn get_image(&self, id: SourceId) -> anyhow::Result<&image::RgbaImage> {
         let index = match id {
             SourceId::Image(index) => index,
-            SourceId::Fnt(_) => {
-                Ewwow
-                    .raise()
-                    .with_context(|| format!("Incompatible source id {id:?} for image source"))?;
+            _ => {
+                Error::InvalidSourceId(id).raise()?;
 
                 unreachable!();
             }
@@ -66,10 +121,8 @@ impl Sources {
     pub fn get_fnt(&self, id: SourceId) -> anyhow::Result<&fnt::FntFile> {
         let index = match id {
             SourceId::Fnt(index) => index,
-            SourceId::Image(_) => {
-                Ewwow
-                    .raise()
-                    .with_context(|| format!("Incompatible source id {id:?} for image source"))?;
+            _ => {
+                Error::InvalidSourceId(id).raise()?;
 
                 unreachable!();
             }
@@ -78,6 +131,22 @@ impl Sources {
         Ok(&self.fnt_files[index].1)
     }
 
+    pub fn get_localization(
+        &self,
+        id: SourceId,
+    ) -> anyhow::Result<&localization::LocalizationFile> {
+        let index = match id {
+            SourceId::Localization(index) => index,
+            _ => {
+                Error::InvalidSourceId(id).raise()?;
+
+                unreachable!();
+            }
+        };
+
+        Ok(&self.localizations[index].1)
+    }
+
     pub fn get_path(&self, id: SourceId) -> anyhow::Result<&Path> {
         match id {
             SourceId::Image(index) => self
@@ -88,9 +157,26 @@ impl Sources {
                 .fnt_files
                 .get(index)
                 .map(|(path_buf, _)| path_buf.as_path()),
+            SourceId::Localization(index) => self
+                .localizations
+                .get(index)
+                .map(|(path_buf, _)| path_buf.as_path()),
         }
-        .ok_or(Ewwow)
-        .with_context(|| format!("Invalid source id {id:?}"))
+        .ok_or_else(|| Error::InvalidSourceId(id).into())
+    }
+
+    /// The file names and content hashes of all loaded source files, sorted
+    /// by file name.
+    pub fn input_manifest(&self) -> Vec<(&str, u64)> {
+        let mut manifest: Vec<_> = self
+            .source_file_aliases
+            .iter()
+            .filter_map(|(name, id)| Some((name.as_str(), *self.source_hashes.get(id)?)))
+            .collect();
+
+        manifest.sort();
+
+        manifest
     }
 
     pub fn get_relative_path(&self, id: SourceId, file: &str) -> anyhow::Result<PathBuf> {
@@ -100,123 +186,421 @@ impl Sources {
 
     pub fn try_load_source<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<SourceId> {
         let path: &Path = path.as_ref();
+        let _span = tracing::info_span!("load_source", path = %path.display()).entered();
+        let file_name = source_file_name(path)?;
 
-        // 1. Check the file extension
-        let ext = path
-            .extension()
-            .ok_or(Ewwow)
-            .with_context(|| {
-                format!(
-                    "Failed to determine extension of source file '{}'.",
-                    path.to_str().unwrap(),
-                )
-            })?
-            .to_str()
-            .unwrap();
+        // Check if the file has been loaded already
+        if let Some(&id) = self.source_file_aliases.get(&file_name) {
+            self.check_duplicate_alias(path, &file_name, id);
+            return Ok(id);
+        }
 
-        let path_str = path.to_str().unwrap();
+        let file = LoadedSourceFile::read(path, file_name)?;
+        let id = self.register_source_file(file);
 
-        let id = match ext {
-            "fnt" => self.try_load_fnt_source_file(path),
-            "png" => self.try_load_image_source_file(path),
-            _ => {
-                Ewwow
-                    .raise()
-                    .with_context(|| format!("Unrecognized source file extension '{ext}'"))?;
+        self.try_load_dependencies(id)?;
 
-                unreachable!();
+        Ok(id)
+    }
+
+    /// Loads all `paths` like [`Self::try_load_source`], but decodes all new
+    /// png files in parallel first.
+    pub fn try_load_sources<P: AsRef<Path>>(
+        &mut self,
+        paths: &[P],
+    ) -> anyhow::Result<Vec<SourceId>> {
+        let mut new_pngs: Vec<(PathBuf, String)> = vec![];
+
+        for path in paths.iter().map(|path| path.as_ref()) {
+            let (Some(ext), Some(file_name)) = (path.extension(), path.file_name()) else {
+                continue;
+            };
+
+            let file_name = file_name.to_str().unwrap().to_string();
+
+            if ext == "png"
+                && !self.source_file_aliases.contains_key(&file_name)
+                && !new_pngs.iter().any(|(_, name)| *name == file_name)
+            {
+                new_pngs.push((path.to_path_buf(), file_name));
             }
         }
-        .with_context(|| format!("Failed to load source file '{path_str}'"))?;
 
-        Ok(id)
-    }
+        let png_paths: Vec<PathBuf> = new_pngs.iter().map(|(path, _)| path.clone()).collect();
+        let decoded = image_io::read_pngs_parallel(&png_paths);
 
-    fn try_load_fnt_source_file(&mut self, path: &Path) -> anyhow::Result<SourceId> {
-        // Check if the file has been loaded already
-        let file_name = path
-            .file_name()
-            .expect(
-                "We already checked that the path has an extension, so it should have a file name.",
-            )
-            .to_str()
-            .unwrap()
-            .to_string();
+        let mut ids = Vec::with_capacity(paths.len());
+        let mut decoded_ids = Vec::with_capacity(new_pngs.len());
 
-        if let Some(id) = self.source_file_aliases.get(&file_name) {
-            println!("INFO: Source file '{file_name}' has been loaded already");
-            return Ok(*id);
+        for ((path, file_name), decoded) in new_pngs.into_iter().zip(decoded) {
+            let file = decoded
+                .and_then(|decoded| {
+                    let canonical_path = path.canonicalize().with_context(|| {
+                        format!("Failed to canonicalize path '{}'", path.display())
+                    })?;
+
+                    LoadedSourceFile::from_png(canonical_path, file_name, decoded)
+                })
+                .with_context(|| format!("Failed to load source file '{}'", path.display()))?;
+
+            decoded_ids.push((path, self.register_source_file(file)));
+        }
+
+        for path in paths.iter().map(|path| path.as_ref()) {
+            match decoded_ids.iter().find(|(decoded, _)| decoded == path) {
+                Some(&(_, id)) => ids.push(id),
+                None => ids.push(self.try_load_source(path)?),
+            }
         }
 
-        // Load the file
-        let file_contents = std::fs::read_to_string(path)?;
-        let fnt_file = fnt::FntFile::try_parse(&file_contents)?;
+        Ok(ids)
+    }
 
-        // Register the file in the vec
-        let id = SourceId::Fnt(self.fnt_files.len());
-        let canonical_path_name = PathBuf::from(path)
-            .canonicalize()
-            .with_context(|| format!("Failed to canonicalize path '{}'", path.to_str().unwrap()))?;
-        self.fnt_files.push((canonical_path_name, fnt_file));
+    /// Adds an already read source file. Its file name must not be registered
+    /// yet.
+    fn register_source_file(&mut self, file: LoadedSourceFile) -> SourceId {
+        let LoadedSourceFile {
+            canonical_path,
+            file_name,
+            content_hash,
+            contents,
+        } = file;
+
+        let id = match contents {
+            SourceContents::Image(image) => {
+                self.images.push((canonical_path, image));
+                SourceId::Image(self.images.len() - 1)
+            }
+            SourceContents::Fnt(mut fnt_file) => {
+                for message in std::mem::take(&mut fnt_file.parse_warnings) {
+                    self.warnings.push(Warning::LenientParse {
+                        file_name: file_name.clone(),
+                        message,
+                    });
+                }
+
+                self.fnt_files.push((canonical_path, fnt_file));
+                SourceId::Fnt(self.fnt_files.len() - 1)
+            }
+            SourceContents::Localization(localization) => {
+                self.localizations.push((canonical_path, localization));
+                SourceId::Localization(self.localizations.len() - 1)
+            }
+        };
 
         // Register the file name as an alias
         self.source_file_aliases.insert(file_name, id);
+        self.source_hashes.insert(id, content_hash);
 
-        // Recursively load dependencies
-        self.try_load_fnt_file_dependencies(id).with_context(|| {
+        id
+    }
+
+    /// The files a source file depends on, e.g. the pages of a fnt file,
+    /// relative to the source file.
+    pub fn dependencies(&self, id: SourceId) -> Vec<String> {
+        match id {
+            SourceId::Fnt(index) => self.fnt_files[index].1.dependencies(),
+            SourceId::Image(_) | SourceId::Localization(_) => vec![],
+        }
+    }
+
+    /// The loaded source files that depend on `id`, e.g. the fnt files that
+    /// use an image as one of their pages.
+    pub fn dependents(&self, id: SourceId) -> Vec<SourceId> {
+        (0..self.fnt_files.len())
+            .map(SourceId::Fnt)
+            .filter(|&fnt_id| {
+                self.dependencies(fnt_id).iter().any(|dep| {
+                    source_file_name(Path::new(dep))
+                        .is_ok_and(|name| self.source_file_aliases.get(&name) == Some(&id))
+                })
+            })
+            .collect()
+    }
+
+    /// Reads the image `id` again from its path, e.g. after it changed on
+    /// disk.
+    ///
+    /// Only the pixels are replaced, everything referencing the image (like
+    /// glyph rects of a fnt file) is left as it is. So an image of another
+    /// size isn't swapped in.
+    pub fn reload_image(&mut self, id: SourceId) -> anyhow::Result<ImageReload> {
+        let SourceId::Image(index) = id else {
+            Error::InvalidSourceId(id).raise()?;
+
+            unreachable!()
+        };
+
+        let path = self.get_path(id)?;
+        let decoded = match path.extension().is_some_and(|ext| ext == "svg") {
+            true => std::fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| {
+                    Ok(image_io::DecodedPng {
+                        content_hash: hashing::hash_bytes(&bytes),
+                        image: image_io::rasterize_svg(&bytes, None)?,
+                    })
+                }),
+            false => image_io::read_png(path),
+        }
+        .with_context(|| format!("Failed to reload source file '{}'", path.display()))?;
+
+        if self.source_hashes.get(&id) == Some(&decoded.content_hash) {
+            return Ok(ImageReload::Unchanged);
+        }
+        if decoded.image.dimensions() != self.images[index].1.dimensions() {
+            return Ok(ImageReload::Resized);
+        }
+
+        self.images[index].1 = decoded.image;
+        self.source_hashes.insert(id, decoded.content_hash);
+
+        Ok(ImageReload::Changed)
+    }
+
+    fn dependency_paths(&self, id: SourceId) -> anyhow::Result<Vec<PathBuf>> {
+        self.dependencies(id)
+            .iter()
+            .map(|dep| self.get_relative_path(id, dep))
+            .collect()
+    }
+
+    fn try_load_dependencies(&mut self, id: SourceId) -> anyhow::Result<()> {
+        let paths = self.dependency_paths(id)?;
+
+        // The page images are decoded in parallel
+        let _ = self.try_load_sources(&paths).with_context(|| {
             format!(
                 "Failed loading dependencies of '{}'",
-                path.to_str().unwrap()
+                self.get_path(id).unwrap().display()
             )
         })?;
 
-        Ok(id)
+        Ok(())
     }
+}
+
+fn source_file_name(path: &Path) -> anyhow::Result<String> {
+    Ok(path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::SourceNotFound(path.display().to_string()))
+        .with_context(|| format!("Source file '{}' has no file name", path.display()))?
+        .to_string())
+}
 
-    fn try_load_fnt_file_dependencies(&mut self, id: SourceId) -> anyhow::Result<()> {
-        let fnt_file = &self.fnt_files[id.index()].1;
+/// A source file that has been read and parsed, but isn't part of any
+/// [`Sources`] yet. Reading doesn't need access to the [`Sources`], so it can
+/// happen on any thread.
+struct LoadedSourceFile {
+    canonical_path: PathBuf,
+    file_name: String,
+    /// See [`hashing::hash_bytes`].
+    content_hash: u64,
+    contents: SourceContents,
+}
 
-        for dep in fnt_file.dependencies() {
-            let path = self.get_relative_path(id, &dep)?;
+enum SourceContents {
+    Image(image::RgbaImage),
+    Fnt(fnt::FntFile),
+    Localization(localization::LocalizationFile),
+}
 
-            let _ = self
-                .try_load_source(path.as_path())
-                .with_context(|| format!("Failed loading dependency '{dep}'"))?;
+impl LoadedSourceFile {
+    fn read(path: &Path, file_name: String) -> anyhow::Result<Self> {
+        let path_str = path.to_str().unwrap();
+
+        if !path.exists() {
+            Error::SourceNotFound(path_str.to_string()).raise()?;
         }
 
-        Ok(())
+        let canonical_path = PathBuf::from(path)
+            .canonicalize()
+            .with_context(|| format!("Failed to load source file '{path_str}'"))?;
+
+        // Pngs are hashed and decoded in one go
+        let file = if path.extension().is_some_and(|ext| ext == "png") {
+            image_io::read_png(path)
+                .and_then(|decoded| Self::from_png(canonical_path, file_name, decoded))
+        } else {
+            std::fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Self::parse(canonical_path, file_name, &bytes))
+        }
+        .with_context(|| format!("Failed to load source file '{path_str}'"))?;
+
+        Ok(file)
     }
 
-    fn try_load_image_source_file(&mut self, path: &Path) -> anyhow::Result<SourceId> {
-        // Check if the file has been loaded already
-        let file_name = path
-            .file_name()
-            .expect(
-                "We already checked that the path has an extension, so it should have a file name.",
-            )
+    /// Parses a source file that has been read already. `location` is where it
+    /// came from, which is also what its dependencies are resolved against.
+    fn parse(location: PathBuf, file_name: String, bytes: &[u8]) -> anyhow::Result<Self> {
+        // 1. Check the file extension
+        let ext = Path::new(&file_name)
+            .extension()
+            .ok_or_else(|| {
+                Error::ParseError(format!(
+                    "Failed to determine extension of source file '{file_name}'."
+                ))
+            })?
             .to_str()
-            .unwrap()
-            .to_string();
+            .unwrap();
 
-        if let Some(id) = self.source_file_aliases.get(&file_name) {
-            println!("INFO: Source file '{file_name}' has been loaded already");
-            return Ok(*id);
+        let content_hash = hashing::hash_bytes(bytes);
+
+        // 2. Parse the file
+        let contents = match ext {
+            "fnt" => SourceContents::Fnt(fnt::FntFile::try_parse(std::str::from_utf8(bytes)?)?),
+            "png" => SourceContents::Image(image_io::decode_png(bytes)?),
+            "svg" => SourceContents::Image(image_io::rasterize_svg(bytes, None)?),
+            "lang" => {
+                // The language code is the file stem, e.g. `de.lang`
+                let language = Path::new(&file_name)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .filter(|stem| !stem.is_empty())
+                    .ok_or_else(|| {
+                        Error::ParseError(format!("Failed to determine language of '{file_name}'"))
+                    })?;
+
+                SourceContents::Localization(localization::LocalizationFile::try_parse(
+                    language,
+                    std::str::from_utf8(bytes)?,
+                )?)
+            }
+            _ => {
+                Error::ParseError(format!("Unrecognized source file extension '{ext}'")).raise()?;
+
+                unreachable!();
+            }
+        };
+
+        Ok(Self {
+            canonical_path: location,
+            file_name,
+            content_hash,
+            contents,
+        })
+    }
+
+    /// Like [`Sources::dependency_paths`], before the file is registered.
+    fn dependency_paths(&self) -> Vec<PathBuf> {
+        match &self.contents {
+            SourceContents::Fnt(fnt_file) => fnt_file
+                .dependencies()
+                .iter()
+                .map(|dep| self.canonical_path.with_file_name(dep))
+                .collect(),
+            SourceContents::Image(_) | SourceContents::Localization(_) => vec![],
         }
+    }
 
-        // Load the image
-        let image = image::open(path)
-            .with_context(|| format!("Failed to read png image '{}'", &file_name))?
-            .to_rgba8();
+    fn from_png(
+        canonical_path: PathBuf,
+        file_name: String,
+        decoded: image_io::DecodedPng,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            canonical_path,
+            file_name,
+            content_hash: decoded.content_hash,
+            contents: SourceContents::Image(decoded.image),
+        })
+    }
+}
 
-        let id = SourceId::Image(self.images.len());
-        let canonical_path_name = PathBuf::from(path)
-            .canonicalize()
-            .with_context(|| format!("Failed to canonicalize path '{}'", path.to_str().unwrap()))?;
-        self.images.push((canonical_path_name, image));
+/// [`Sources`] that can be loaded into from several threads at once, e.g. to
+/// build the intermediates of different fonts in parallel.
+///
+/// Files are read and decoded without holding the lock; it is only taken to
+/// register them, after their dependencies are. Use [`Self::into_inner`]
+/// once everything is loaded.
+#[derive(Debug, Default)]
+pub struct SharedSources {
+    inner: RwLock<Sources>,
+}
 
-        self.source_file_aliases.insert(file_name, id);
+impl SharedSources {
+    pub fn new(srcs: Sources) -> Self {
+        Self {
+            inner: RwLock::new(srcs),
+        }
+    }
 
-        Ok(id)
+    /// Read access to everything loaded so far.
+    pub fn read(&self) -> RwLockReadGuard<'_, Sources> {
+        self.inner.read().unwrap()
+    }
+
+    pub fn into_inner(self) -> Sources {
+        self.inner.into_inner().unwrap()
+    }
+
+    /// Adds a warning to [`Sources::warnings`].
+    pub fn push_warning(&self, warning: Warning) {
+        self.inner.write().unwrap().warnings.push(warning);
+    }
+
+    /// Like [`Sources::try_load_source`].
+    pub fn try_load_source<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<SourceId> {
+        let path: &Path = path.as_ref();
+        let file_name = source_file_name(path)?;
+
+        let loaded = self.read().source_file_aliases.get(&file_name).copied();
+        if let Some(id) = loaded {
+            self.inner
+                .write()
+                .unwrap()
+                .check_duplicate_alias(path, &file_name, id);
+            return Ok(id);
+        }
+
+        let file = LoadedSourceFile::read(path, file_name)?;
+
+        // Other threads get the id as soon as it's registered, e.g. a fnt file
+        // has to come with its pages by then
+        for path in file.dependency_paths() {
+            self.try_load_source(&path).with_context(|| {
+                format!(
+                    "Failed loading dependencies of '{}'",
+                    file.canonical_path.display()
+                )
+            })?;
+        }
+
+        let mut srcs = self.inner.write().unwrap();
+
+        // Another thread might have loaded the same file in the meantime
+        match srcs.source_file_aliases.get(&file.file_name) {
+            Some(&id) => Ok(id),
+            None => Ok(srcs.register_source_file(file)),
+        }
+    }
+
+    /// Registers a source file that has been read into memory already, e.g.
+    /// after downloading it. Its dependencies are not loaded.
+    ///
+    /// `location` stands in for the file's path, see [`Sources::get_path`].
+    pub fn try_load_source_bytes(
+        &self,
+        location: PathBuf,
+        file_name: String,
+        bytes: &[u8],
+    ) -> anyhow::Result<SourceId> {
+        if let Some(&id) = self.read().source_file_aliases.get(&file_name) {
+            return Ok(id);
+        }
+
+        let location_str = location.display().to_string();
+        let file = LoadedSourceFile::parse(location, file_name, bytes)
+            .with_context(|| format!("Failed to load source file '{location_str}'"))?;
+
+        let mut srcs = self.inner.write().unwrap();
+
+        match srcs.source_file_aliases.get(&file.file_name) {
+            Some(&id) => Ok(id),
+            None => Ok(srcs.register_source_file(file)),
+        }
     }
 }
 
@@ -230,6 +614,10 @@ pub struct SourceSprite {
 }
 
 impl SourceSprite {
+    pub fn size(&self) -> ISize {
+        ISize::new(self.width, self.height)
+    }
+
     pub fn get_image<'s>(
         &self,
         srcs: &'s Sources,
@@ -241,6 +629,21 @@ impl SourceSprite {
             )
         })?;
 
+        let (width, height) = atlas.dimensions();
+        let in_bounds = self.x >= 0
+            && self.y >= 0
+            && self.width >= 0
+            && self.height >= 0
+            && self.x as u64 + self.width as u64 <= width as u64
+            && self.y as u64 + self.height as u64 <= height as u64;
+        if !in_bounds {
+            Error::ParseError(format!(
+                "The sprite at {},{} of size {}x{} is outside of the {width}x{height} image {:?}",
+                self.x, self.y, self.width, self.height, self.image_source_id
+            ))
+            .raise()?;
+        }
+
         Ok(atlas.view(
             self.x as u32,
             self.y as u32,
@@ -249,3 +652,201 @@ impl SourceSprite {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageReload, SharedSources, SourceSprite, Sources};
+    use crate::{error::Error, warnings::Warning};
+
+    #[test]
+    fn test_duplicate_alias_warning() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("texture-packer-dup-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("other"))?;
+        std::fs::write(dir.join("en.lang"), "label.title = \"Title\"")?;
+        std::fs::write(dir.join("other/en.lang"), "label.title = \"Other\"")?;
+
+        let mut srcs = Sources::new();
+        let id = srcs.try_load_source(dir.join("en.lang"))?;
+
+        // Loading the same file again is fine
+        assert_eq!(srcs.try_load_source(dir.join("en.lang"))?, id);
+        assert!(srcs.warnings.is_empty());
+
+        assert_eq!(srcs.try_load_source(dir.join("other/en.lang"))?, id);
+        assert_eq!(
+            srcs.take_warnings(),
+            vec![Warning::DuplicateAlias {
+                file_name: "en.lang".to_string(),
+                loaded: dir.join("en.lang").canonicalize()?,
+                ignored: dir.join("other/en.lang").canonicalize()?,
+            }]
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_image() -> anyhow::Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("texture-packer-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let path = dir.join("page.png");
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255])).save(&path)?;
+
+        let mut srcs = Sources::new();
+        let id = srcs.try_load_source(&path)?;
+        let fnt_id = srcs.try_load_source("assets/m5x7.fnt")?;
+
+        assert!(srcs.dependents(id).is_empty());
+        assert_eq!(
+            srcs.dependents(srcs.find_id(&"m5x7.png".to_string())?),
+            vec![fnt_id]
+        );
+
+        // Unchanged files are left alone
+        assert_eq!(srcs.reload_image(id)?, ImageReload::Unchanged);
+
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 255, 0, 255])).save(&path)?;
+        assert_eq!(srcs.reload_image(id)?, ImageReload::Changed);
+        assert_eq!(srcs.get_image(id)?.get_pixel(1, 1)[1], 255);
+
+        // Rects into the old pixels could be out of bounds now
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 255, 255])).save(&path)?;
+        assert_eq!(srcs.reload_image(id)?, ImageReload::Resized);
+        assert_eq!(srcs.get_image(id)?.dimensions(), (2, 2));
+
+        let sprite = SourceSprite {
+            image_source_id: id,
+            x: 1,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        assert!(matches!(
+            Error::find(&sprite.get_image(&srcs).err().unwrap()),
+            Some(Error::ParseError(_))
+        ));
+
+        assert!(matches!(
+            Error::find(&srcs.reload_image(fnt_id).unwrap_err()),
+            Some(Error::InvalidSourceId(_))
+        ));
+        assert!(matches!(
+            Error::find(&srcs.try_load_source(dir.join("missing.png")).unwrap_err()),
+            Some(Error::SourceNotFound(_))
+        ));
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_sources_load_concurrently() -> anyhow::Result<()> {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Sources>();
+        assert_send_sync::<SharedSources>();
+
+        let dir = std::env::temp_dir().join(format!("texture-packer-srcs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let paths: Vec<_> = ["en", "de", "fr", "es", "it", "ja"]
+            .iter()
+            .map(|language| {
+                let path = dir.join(format!("{language}.lang"));
+                std::fs::write(&path, format!("label.title = \"{language}\"")).unwrap();
+                path
+            })
+            .collect();
+
+        let shared = SharedSources::default();
+
+        // Every thread loads every file, starting at a different one
+        let ids: Vec<Vec<_>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..paths.len())
+                .map(|offset| {
+                    let (shared, paths) = (&shared, &paths);
+
+                    scope.spawn(move || {
+                        let mut ids = vec![None; paths.len()];
+                        for i in 0..paths.len() {
+                            let index = (i + offset) % paths.len();
+                            ids[index] = Some(shared.try_load_source(&paths[index]).unwrap());
+                        }
+                        ids
+                    })
+                })
+                .collect();
+
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert!(ids.iter().all(|thread_ids| *thread_ids == ids[0]));
+
+        let srcs = shared.into_inner();
+        assert_eq!(srcs.localizations.len(), paths.len());
+
+        for (path, id) in paths.iter().zip(ids[0].iter()) {
+            let localization = srcs.get_localization(id.unwrap())?;
+            let language = path.file_stem().unwrap().to_str().unwrap();
+
+            assert_eq!(localization.language, language);
+            assert_eq!(localization.entries["label.title"], language);
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_sources_load_fnt_concurrently() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("texture-packer-fnt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        // A large page takes a while to decode
+        image::RgbaImage::from_pixel(512, 512, image::Rgba([255; 4])).save(dir.join("tiny.png"))?;
+        let fnt_path = dir.join("tiny.fnt");
+        std::fs::write(
+            &fnt_path,
+            "common lineHeight=8 base=6 scaleW=512 scaleH=512 pages=1 packed=0\n\
+             page id=0 file=\"tiny.png\"\n\
+             char id=65 x=0 y=0 width=3 height=4 xoffset=0 yoffset=2 xadvance=4 page=0 chnl=15\n",
+        )?;
+
+        let shared = SharedSources::default();
+
+        // Whichever thread gets the id of the fnt file, its page is loaded
+        let ids: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    let (shared, fnt_path) = (&shared, &fnt_path);
+
+                    scope.spawn(move || {
+                        let id = shared.try_load_source(fnt_path).unwrap();
+                        let page = shared.read().find_id(&"tiny.png".to_string());
+                        (id, page.is_ok())
+                    })
+                })
+                .collect();
+
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert!(ids
+            .iter()
+            .all(|&(id, page_loaded)| id == ids[0].0 && page_loaded));
+
+        let srcs = shared.into_inner();
+        assert_eq!(srcs.fnt_files.len(), 1);
+        assert_eq!(srcs.images.len(), 1);
+        assert_eq!(srcs.get_fnt(ids[0].0)?.chars.len(), 1);
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    fmt::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+/// Sums up how long the `tracing` spans of every phase were entered, for the
+/// `--timings` summary.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    phases: Arc<Mutex<Vec<Phase>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phase {
+    pub name: &'static str,
+    /// The number of parent spans when the phase was first entered
+    pub depth: usize,
+    pub calls: u32,
+    pub total: Duration,
+}
+
+/// Span extension with the time the span was last entered.
+struct Entered(Instant);
+
+impl Timings {
+    /// All phases in the order they were first entered.
+    pub fn phases(&self) -> Vec<Phase> {
+        self.phases.lock().unwrap().clone()
+    }
+
+    pub fn summary_table(&self) -> String {
+        let phases = self.phases();
+
+        let name_width = phases
+            .iter()
+            .map(|phase| phase.name.len() + 2 * phase.depth)
+            .chain(["Phase".len()])
+            .max()
+            .unwrap();
+
+        let mut table = format!(
+            "{:<name_width$}  {:>6}  {:>10}\n",
+            "Phase", "Calls", "Total"
+        );
+
+        for phase in phases.iter() {
+            let name = format!("{}{}", "  ".repeat(phase.depth), phase.name);
+            let millis = phase.total.as_secs_f64() * 1000.0;
+
+            writeln!(
+                table,
+                "{name:<name_width$}  {:>6}  {millis:>7.2} ms",
+                phase.calls
+            )
+            .unwrap();
+        }
+
+        table
+    }
+}
+
+impl<S> Layer<S> for Timings
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut phases = self.phases.lock().unwrap();
+        if !phases.iter().any(|phase| phase.name == span.name()) {
+            phases.push(Phase {
+                name: span.name(),
+                depth: span.scope().skip(1).count(),
+                calls: 0,
+                total: Duration::ZERO,
+            });
+        }
+
+        span.extensions_mut().insert(Entered(Instant::now()));
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let Some(Entered(start)) = span.extensions_mut().remove::<Entered>() else {
+            return;
+        };
+
+        let mut phases = self.phases.lock().unwrap();
+        if let Some(phase) = phases.iter_mut().find(|phase| phase.name == span.name()) {
+            phase.calls += 1;
+            phase.total += start.elapsed();
+        }
+    }
+}
+
+/// Keeps the tracing outputs alive until the end of the build.
+pub struct TracingGuard {
+    timings: Option<Timings>,
+    #[cfg(feature = "chrome-trace")]
+    _chrome_guard: Option<tracing_chrome::FlushGuard>,
+}
+
+impl TracingGuard {
+    /// Prints the `--timings` summary, if it was requested, and flushes the
+    /// chrome trace.
+    pub fn finish(self) {
+        if let Some(timings) = self.timings.as_ref() {
+            print!("{}", timings.summary_table());
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber. `chrome_trace` is the file to
+/// write a chrome trace to (needs the `chrome-trace` feature).
+pub fn init(timings: bool, chrome_trace: Option<&Path>) -> anyhow::Result<TracingGuard> {
+    let timings = timings.then(Timings::default);
+
+    #[cfg(feature = "chrome-trace")]
+    let (chrome_layer, chrome_guard) = match chrome_trace {
+        Some(path) => {
+            let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+                .file(path)
+                .include_args(true)
+                .build();
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    #[cfg(not(feature = "chrome-trace"))]
+    if chrome_trace.is_some() {
+        use anyhow::Context;
+
+        crate::error::Ewwow
+            .raise()
+            .context("--trace-chrome requires the `chrome-trace` feature")?;
+    }
+
+    let registry = tracing_subscriber::registry().with(timings.clone());
+
+    #[cfg(feature = "chrome-trace")]
+    let registry = registry.with(chrome_layer);
+
+    tracing::subscriber::set_global_default(registry)?;
+
+    Ok(TracingGuard {
+        timings,
+        #[cfg(feature = "chrome-trace")]
+        _chrome_guard: chrome_guard,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::prelude::*;
+
+    use super::Timings;
+
+    #[test]
+    fn test_timings() {
+        let timings = Timings::default();
+        let subscriber = tracing_subscriber::registry().with(timings.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let load = tracing::info_span!("load").entered();
+            for _ in 0..3 {
+                let _decode = tracing::info_span!("decode").entered();
+            }
+            drop(load);
+
+            let _pack = tracing::info_span!("pack").entered();
+        });
+
+        let phases: Vec<_> = timings
+            .phases()
+            .into_iter()
+            .map(|phase| (phase.name, phase.depth, phase.calls))
+            .collect();
+        assert_eq!(
+            phases,
+            vec![("load", 0, 1), ("decode", 1, 3), ("pack", 0, 1)]
+        );
+
+        let table = timings.summary_table();
+        assert!(table.starts_with("Phase"));
+        assert!(table.contains("\n  decode       3"));
+    }
+}
@@ -0,0 +1,160 @@
+//! `texture-packer tui`: browses the layout of an exported atlas in the
+//! terminal, e.g. over SSH on a build machine. The tree of fonts, labels and
+//! sprites is on the left, the page with the selection highlighted on the
+//! right.
+
+use anyhow::Context;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    symbols::Marker,
+    text::Line,
+    widgets::{
+        canvas::{Canvas, Rectangle},
+        Block, List, ListState,
+    },
+    DefaultTerminal, Frame,
+};
+
+use crate::outputs::layout_browser::{LayoutBrowser, Row};
+
+const HELP: &str = "↑↓ move  enter expand  ← collapse  [ ] page  q quit";
+
+/// Runs until the user quits, restoring the terminal afterwards even if
+/// drawing failed.
+pub fn run(browser: LayoutBrowser) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = browse(&mut terminal, browser);
+    ratatui::restore();
+
+    result.context("Failed to draw to the terminal")
+}
+
+fn browse(terminal: &mut DefaultTerminal, mut browser: LayoutBrowser) -> std::io::Result<()> {
+    loop {
+        terminal.draw(|frame| draw(frame, &browser))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => browser.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => browser.select_next(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => browser.toggle(),
+            KeyCode::Left | KeyCode::Char('h') => browser.collapse(),
+            KeyCode::PageDown | KeyCode::Char(']') => browser.next_page(),
+            KeyCode::PageUp | KeyCode::Char('[') => browser.previous_page(),
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, browser: &LayoutBrowser) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [tree, layout] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(body);
+
+    let page = browser.page();
+    let stats = browser.pages[page];
+    let size = browser.page_size;
+    frame.render_widget(
+        Line::from(format!(
+            "{}  page {}/{}  {}x{}  {} rects  {:.1}% used",
+            browser.atlas_name,
+            page + 1,
+            browser.pages.len(),
+            size.width,
+            size.height,
+            stats.packed_rects,
+            stats.occupancy * 100.0,
+        ))
+        .style(Style::new().add_modifier(Modifier::BOLD)),
+        header,
+    );
+
+    let items: Vec<Line> = browser
+        .rows()
+        .into_iter()
+        .map(|row| match row {
+            Row::Asset(asset) => {
+                let asset = &browser.assets[asset];
+                Line::from(format!(
+                    "{} {} ({})",
+                    asset.kind,
+                    asset.name,
+                    asset.items.len()
+                ))
+            }
+            Row::Item(asset, item) => {
+                Line::from(format!("  {}", browser.assets[asset].items[item].name))
+            }
+        })
+        .collect();
+    let mut state = ListState::default().with_selected(Some(browser.selected()));
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::bordered().title("assets"))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+        tree,
+        &mut state,
+    );
+
+    // The canvas has y pointing up
+    let height = size.height as f64;
+    let to_shape = |rect: crate::math::IRect, color| Rectangle {
+        x: rect.min.x as f64,
+        y: height - rect.max.y as f64,
+        width: rect.width() as f64,
+        height: rect.height() as f64,
+        color,
+    };
+    let highlighted = browser.highlighted();
+    frame.render_widget(
+        Canvas::default()
+            .block(Block::bordered().title(format!("page {}", page + 1)))
+            .marker(Marker::Braille)
+            .x_bounds([0.0, size.width as f64])
+            .y_bounds([0.0, height])
+            .paint(|ctx| {
+                for &rect in browser.page_rects[page].iter() {
+                    ctx.draw(&to_shape(rect, Color::DarkGray));
+                }
+                ctx.layer();
+                for &(_, rect) in highlighted
+                    .iter()
+                    .filter(|(layer, _)| *layer as usize == page)
+                {
+                    ctx.draw(&to_shape(rect, Color::Yellow));
+                }
+            }),
+        layout,
+    );
+
+    let selection = match browser.selected_row() {
+        Some(Row::Asset(asset) | Row::Item(asset, _)) => {
+            let area = browser.asset_area(asset)[page];
+            let page_area = size.width as f64 * size.height as f64;
+            format!(
+                "{}: {area} px on this page ({:.1}%)  ",
+                browser.assets[asset].name,
+                match page_area {
+                    0.0 => 0.0,
+                    _ => area as f64 / page_area * 100.0,
+                }
+            )
+        }
+        None => String::new(),
+    };
+    frame.render_widget(Line::from(format!("{selection}{HELP}")), footer);
+}
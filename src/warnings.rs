@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+
+/// A problem that doesn't stop the build, but probably isn't what the user
+/// wants. Collected by [`crate::sources::Sources`] and
+/// [`crate::intermediates::texture_atlas::TextureAtlas`], so embedders can
+/// show them in their own UI; the CLI prints them at the end.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// Two different files with the same file name were loaded. Sources are
+    /// identified by their file name, so the first one is used for both.
+    DuplicateAlias {
+        file_name: String,
+        loaded: PathBuf,
+        ignored: PathBuf,
+    },
+    /// Empty sprites (e.g. the space glyph) aren't packed and only exported
+    /// as metrics.
+    SkippedEmptySprites { asset: String, count: usize },
+    /// The parser glossed over something in a source file.
+    LenientParse { file_name: String, message: String },
+    /// User data was given for a sprite that isn't in the atlas.
+    UnknownUserDataSprite { sprite_name: String },
+    /// A material was given for a sprite that isn't in the atlas.
+    UnknownMaterialSprite { sprite_name: String },
+    /// Control characters, byte order marks or zero-width code points of a
+    /// font weren't packed.
+    SkippedInvisibleChars {
+        font_name: String,
+        char_codes: Vec<u32>,
+    },
+    /// A frame of a char had other offsets or another advance than its first
+    /// frame, and was given those of the first frame.
+    MetricConflict {
+        font_name: String,
+        char_code: u32,
+        frame: u32,
+    },
+    /// The sprite was drawn at a lower design scale than the atlas targets
+    /// and is packed as is instead of being upscaled.
+    BelowTargetScale {
+        sprite_name: String,
+        scale: f32,
+        target_scale: f32,
+    },
+    /// The declared runtime sampling reads further past the edges of
+    /// sprites than they are apart, see [`crate::sampling::lint`].
+    SamplingBleed {
+        filtering: String,
+        mip_levels: u32,
+        axis: String,
+        gap: i32,
+        needed: i32,
+    },
+    /// A file in the remote cache didn't have the hash it was cached with
+    /// and was downloaded again.
+    CorruptedCacheFile { url: String },
+    /// Sprites too small for the UV inset, whose UV rects collapsed to their
+    /// center, see [`crate::outputs::atlas_meta::AtlasMeta::set_uv_inset`].
+    ClampedUvInset { inset: f32, count: usize },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::DuplicateAlias {
+                file_name,
+                loaded,
+                ignored,
+            } => write!(
+                f,
+                "'{}' has the same file name '{file_name}' as '{}' and was ignored",
+                ignored.display(),
+                loaded.display(),
+            ),
+            Warning::SkippedEmptySprites { asset, count } => write!(
+                f,
+                "Skipped packing {count} empty sprite(s) of {asset}; they are kept as metric-only entries"
+            ),
+            Warning::LenientParse { file_name, message } => write!(f, "'{file_name}': {message}"),
+            Warning::UnknownUserDataSprite { sprite_name } => {
+                write!(f, "Ignored user data of unknown sprite '{sprite_name}'")
+            }
+            Warning::UnknownMaterialSprite { sprite_name } => {
+                write!(f, "Ignored material of unknown sprite '{sprite_name}'")
+            }
+            Warning::SkippedInvisibleChars {
+                font_name,
+                char_codes,
+            } => {
+                let codes: Vec<_> = char_codes
+                    .iter()
+                    .map(|code| format!("U+{code:04X}"))
+                    .collect();
+                write!(
+                    f,
+                    "Skipped {} invisible char(s) of font '{font_name}': {} (see --keep-chars)",
+                    codes.len(),
+                    codes.join(", ")
+                )
+            }
+            Warning::MetricConflict {
+                font_name,
+                char_code,
+                frame,
+            } => write!(
+                f,
+                "Frame {frame} of character #{char_code} of font '{font_name}' had different metrics and uses those of the first frame"
+            ),
+            Warning::BelowTargetScale {
+                sprite_name,
+                scale,
+                target_scale,
+            } => write!(
+                f,
+                "Sprite '{sprite_name}' is @{scale}x, below the target scale @{target_scale}x; it's packed at its own scale"
+            ),
+            Warning::SamplingBleed {
+                filtering,
+                mip_levels,
+                axis,
+                gap,
+                needed,
+            } => write!(
+                f,
+                "Sprites are {gap} pixel(s) apart {axis}, but {filtering} filtering with {mip_levels} mip level(s) reads {needed} past their edges and will bleed (raise --padding or --spacing)"
+            ),
+            Warning::CorruptedCacheFile { url } => {
+                write!(f, "The cached copy of '{url}' was corrupted and was downloaded again")
+            }
+            Warning::ClampedUvInset { inset, count } => write!(
+                f,
+                "{count} sprite(s) are at most {} pixel(s) wide or high, their UV rects collapsed to their center (lower --uv-inset)",
+                2.0 * inset
+            ),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::sources::{SourceId, Sources};
+
+/// How long `--watch` waits between checking the source files.
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Notices changes to the files of loaded sources by polling their
+/// modification times, which works the same on every platform and for
+/// network drives.
+#[derive(Debug)]
+pub struct SourceWatcher {
+    files: Vec<(SourceId, PathBuf, Option<SystemTime>)>,
+}
+
+impl SourceWatcher {
+    /// Watches every file loaded into `srcs` so far.
+    pub fn new(srcs: &Sources) -> Self {
+        let images = srcs
+            .images
+            .iter()
+            .enumerate()
+            .map(|(i, (path, _))| (SourceId::Image(i), path));
+        let fnt_files = srcs
+            .fnt_files
+            .iter()
+            .enumerate()
+            .map(|(i, (path, _))| (SourceId::Fnt(i), path));
+        let localizations = srcs
+            .localizations
+            .iter()
+            .enumerate()
+            .map(|(i, (path, _))| (SourceId::Localization(i), path));
+
+        let files = images
+            .chain(fnt_files)
+            .chain(localizations)
+            // Remote sources only have a stand-in path
+            .filter(|(_, path)| path.is_file())
+            .map(|(id, path)| (id, path.clone(), modified(path)))
+            .collect();
+
+        Self { files }
+    }
+
+    /// The paths of every watched file.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(_, path, _)| path.as_path())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The sources whose files were modified (or removed) since the last
+    /// call.
+    pub fn poll(&mut self) -> Vec<SourceId> {
+        let mut changed = vec![];
+
+        for (id, path, last_modified) in self.files.iter_mut() {
+            let modified = modified(path);
+
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed.push(*id);
+            }
+        }
+
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::SourceWatcher;
+    use crate::sources::Sources;
+
+    #[test]
+    fn test_poll() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("texture-packer-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let paths = [dir.join("en.lang"), dir.join("de.lang")];
+        for path in paths.iter() {
+            std::fs::write(path, "label.title = \"Title\"")?;
+        }
+
+        let mut srcs = Sources::new();
+        let ids = paths
+            .iter()
+            .map(|path| srcs.try_load_source(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut watcher = SourceWatcher::new(&srcs);
+        assert_eq!(watcher.len(), 2);
+        assert!(watcher.paths().all(|path| path.starts_with(&dir)));
+        assert!(watcher.poll().is_empty());
+
+        // Set the time explicitly, file systems can have a coarse resolution
+        let file = std::fs::File::options().write(true).open(&paths[1])?;
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))?;
+        drop(file);
+
+        assert_eq!(watcher.poll(), vec![ids[1]]);
+        assert!(watcher.poll().is_empty());
+
+        std::fs::remove_file(&paths[0])?;
+        assert_eq!(watcher.poll(), vec![ids[0]]);
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+#[allow(dead_code)]
+#[path = "../examples/demo_scene.rs"]
+mod demo_scene;
+
+use image::{Rgba, RgbaImage};
+
+#[test]
+fn test_demo_scene() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("texture-packer-demo-{}", std::process::id()));
+    let (sprites_dir, out_dir) = (dir.join("sprites"), dir.join("out"));
+    std::fs::create_dir_all(&sprites_dir)?;
+
+    let red = Rgba([255, 0, 0, 255]);
+    let blue = Rgba([0, 0, 255, 255]);
+    RgbaImage::from_pixel(8, 8, red).save(sprites_dir.join("a_crate.png"))?;
+    RgbaImage::from_pixel(5, 12, blue).save(sprites_dir.join("b_lamp.png"))?;
+    std::fs::write(sprites_dir.join("notes.txt"), "not a sprite")?;
+
+    let demo = demo_scene::run(&sprites_dir, &out_dir)?;
+    demo.meta.verify()?;
+
+    let names: Vec<_> = demo.meta.named_sprites.iter().map(|s| &s.name).collect();
+    assert_eq!(names, ["a_crate", "b_lamp"]);
+    assert_eq!(demo.meta.fonts.len(), 2);
+
+    // The sprites in a row, the text below them
+    assert_eq!(*demo.scene.get_pixel(0, 0), red);
+    assert_eq!(*demo.scene.get_pixel(12, 11), blue);
+    assert!(demo.scene.height() > 12);
+    let text_pixels = (16..demo.scene.height())
+        .flat_map(|y| (0..demo.scene.width()).map(move |x| (x, y)))
+        .filter(|&(x, y)| demo.scene.get_pixel(x, y)[3] > 0)
+        .count();
+    assert!(text_pixels > 0);
+
+    for file in ["atlas.png", "atlas.json", "scene.png"] {
+        assert!(out_dir.join(file).exists());
+    }
+    assert_eq!(
+        image::open(out_dir.join("scene.png"))?.into_rgba8(),
+        demo.scene
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}